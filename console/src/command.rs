@@ -0,0 +1,511 @@
+//! Slash command registry for `StdinThread`-driven tools.
+//!
+//! ```
+//! use console::command::{Command, CommandRegistry, Dispatch, Flow};
+//!
+//! struct App {
+//!     count: u32,
+//! }
+//!
+//! let mut commands = CommandRegistry::new();
+//! commands.register(Command::new("q", "quit").alias("quit"), |_: &mut App, _| Flow::Quit);
+//! commands.register(
+//!     Command::new("add", "add n to the counter").arg("n"),
+//!     |app: &mut App, args| {
+//!         match args.parse::<u32>("n") {
+//!             Ok(n) => app.count += n,
+//!             Err(e) => println!("{}", e),
+//!         }
+//!         Flow::Continue
+//!     },
+//! );
+//!
+//! let mut app = App { count: 0 };
+//! assert_eq!(commands.dispatch(&mut app, "/add 2"), Dispatch::Continue);
+//! assert_eq!(app.count, 2);
+//! assert_eq!(commands.dispatch(&mut app, "hello"), Dispatch::Text("hello".into()));
+//! assert_eq!(commands.dispatch(&mut app, "/quit"), Dispatch::Quit);
+//! ```
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+pub const COMMAND_PREFIX: char = '/';
+
+/// What the main loop should do after a command ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Result of feeding one input line to the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dispatch {
+    Continue,
+    Quit,
+    /// The line is not a command; the app decides what to do with it.
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    Unknown(String),
+    MissingArg { command: String, arg: String },
+    TooManyArgs { command: String },
+    InvalidArg { arg: String, reason: String },
+    UnclosedQuote,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Unknown(name) => write!(f, "unknown command: {}{}", COMMAND_PREFIX, name),
+            CommandError::MissingArg { command, arg } => {
+                write!(f, "{}{}: missing <{}>", COMMAND_PREFIX, command, arg)
+            }
+            CommandError::TooManyArgs { command } => {
+                write!(f, "{}{}: too many arguments", COMMAND_PREFIX, command)
+            }
+            CommandError::InvalidArg { arg, reason } => write!(f, "invalid <{}>: {}", arg, reason),
+            CommandError::UnclosedQuote => write!(f, "unclosed quote"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgKind {
+    Required,
+    Optional,
+    /// Takes the rest of the line as one value.
+    Rest,
+}
+
+#[derive(Debug, Clone)]
+struct ArgSpec {
+    name: &'static str,
+    kind: ArgKind,
+}
+
+/// Command definition: name, aliases, arguments and help line.
+#[derive(Debug, Clone)]
+pub struct Command {
+    name: &'static str,
+    aliases: Vec<&'static str>,
+    args: Vec<ArgSpec>,
+    help: &'static str,
+}
+
+impl Command {
+    pub fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            aliases: Vec::new(),
+            args: Vec::new(),
+            help,
+        }
+    }
+
+    pub fn alias(mut self, alias: &'static str) -> Self {
+        self.aliases.push(alias);
+        self
+    }
+
+    pub fn arg(self, name: &'static str) -> Self {
+        self.push_arg(name, ArgKind::Required)
+    }
+
+    pub fn optional_arg(self, name: &'static str) -> Self {
+        self.push_arg(name, ArgKind::Optional)
+    }
+
+    /// Last argument, taking the rest of the line as is.
+    pub fn rest_arg(self, name: &'static str) -> Self {
+        self.push_arg(name, ArgKind::Rest)
+    }
+
+    fn push_arg(mut self, name: &'static str, kind: ArgKind) -> Self {
+        if let Some(last) = self.args.last() {
+            assert!(last.kind != ArgKind::Rest, "rest arg must be the last one");
+            assert!(
+                !(last.kind == ArgKind::Optional && kind == ArgKind::Required),
+                "required arg after optional arg"
+            );
+        }
+        self.args.push(ArgSpec { name, kind });
+        self
+    }
+
+    fn usage(&self) -> String {
+        let mut usage = format!("{}{}", COMMAND_PREFIX, self.name);
+        for arg in &self.args {
+            match arg.kind {
+                ArgKind::Required => usage += &format!(" <{}>", arg.name),
+                ArgKind::Optional => usage += &format!(" [{}]", arg.name),
+                ArgKind::Rest => usage += &format!(" <{}...>", arg.name),
+            }
+        }
+        usage
+    }
+
+    fn parse_args(&self, rest: &str) -> Result<CommandArgs, CommandError> {
+        let mut values = HashMap::new();
+        let mut rest = rest.trim_start();
+
+        for spec in &self.args {
+            if spec.kind == ArgKind::Rest {
+                let value = rest.trim();
+                if !value.is_empty() {
+                    values.insert(spec.name, value.to_string());
+                    rest = "";
+                }
+            } else if let Some((word, remain)) = next_word(rest)? {
+                values.insert(spec.name, word);
+                rest = remain;
+            }
+
+            if spec.kind != ArgKind::Optional && !values.contains_key(spec.name) {
+                return Err(CommandError::MissingArg {
+                    command: self.name.to_string(),
+                    arg: spec.name.to_string(),
+                });
+            }
+        }
+
+        if next_word(rest)?.is_some() {
+            return Err(CommandError::TooManyArgs {
+                command: self.name.to_string(),
+            });
+        }
+        Ok(CommandArgs { values })
+    }
+}
+
+/// Split off the next whitespace separated word. Double quotes group words
+/// and `\"` / `\\` are unescaped inside them.
+fn next_word(s: &str) -> Result<Option<(String, &str)>, CommandError> {
+    let s = s.trim_start();
+    if s.is_empty() {
+        return Ok(None);
+    }
+
+    let mut word = String::new();
+    let mut in_quote = false;
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => in_quote = !in_quote,
+            '\\' if in_quote => {
+                if let Some((_, escaped)) = chars.next() {
+                    word.push(escaped);
+                }
+            }
+            c if c.is_whitespace() && !in_quote => return Ok(Some((word, &s[i..]))),
+            c => word.push(c),
+        }
+    }
+    if in_quote {
+        return Err(CommandError::UnclosedQuote);
+    }
+    Ok(Some((word, "")))
+}
+
+/// Parsed arguments of one command invocation.
+#[derive(Debug, Default)]
+pub struct CommandArgs {
+    values: HashMap<&'static str, String>,
+}
+
+impl CommandArgs {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    pub fn parse<T>(&self, name: &str) -> Result<T, CommandError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.get(name).ok_or_else(|| CommandError::InvalidArg {
+            arg: name.to_string(),
+            reason: "not given".to_string(),
+        })?;
+        value.parse().map_err(|e: T::Err| CommandError::InvalidArg {
+            arg: name.to_string(),
+            reason: e.to_string(),
+        })
+    }
+}
+
+type Handler<C> = Box<dyn FnMut(&mut C, &CommandArgs) -> Flow>;
+
+enum Found {
+    Help,
+    Command(usize),
+}
+
+/// Maps `/name args...` lines to handlers. `/help` (`/h`, `/?`) is built in.
+pub struct CommandRegistry<C> {
+    commands: Vec<(Command, Handler<C>)>,
+}
+
+impl<C> Default for CommandRegistry<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> CommandRegistry<C> {
+    pub fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+        }
+    }
+
+    fn help_command() -> Command {
+        Command::new("help", "show this help").alias("h").alias("?")
+    }
+
+    /// Register a command. Panics if a name or alias is already taken.
+    pub fn register<F>(&mut self, command: Command, handler: F) -> &mut Self
+    where
+        F: FnMut(&mut C, &CommandArgs) -> Flow + 'static,
+    {
+        for name in std::iter::once(&command.name).chain(&command.aliases) {
+            assert!(
+                self.find(name).is_none(),
+                "command name is already registered: {}",
+                name
+            );
+        }
+        self.commands.push((command, Box::new(handler)));
+        self
+    }
+
+    fn find(&self, name: &str) -> Option<Found> {
+        let help = Self::help_command();
+        if help.name == name || help.aliases.contains(&name) {
+            return Some(Found::Help);
+        }
+        self.commands
+            .iter()
+            .position(|(command, _)| command.name == name || command.aliases.contains(&name))
+            .map(Found::Command)
+    }
+
+    fn usage_of(&self, name: &str) -> Option<String> {
+        match self.find(name)? {
+            Found::Help => Some(Self::help_command().usage()),
+            Found::Command(index) => Some(self.commands[index].0.usage()),
+        }
+    }
+
+    /// Run the command in `line`, printing errors and usage to stdout.
+    pub fn dispatch(&mut self, ctx: &mut C, line: &str) -> Dispatch {
+        match self.try_dispatch(ctx, line) {
+            Ok(dispatch) => dispatch,
+            Err(e) => {
+                println!("{}", e);
+                match &e {
+                    CommandError::Unknown(_) => print!("{}", self.help_text()),
+                    CommandError::MissingArg { command, .. }
+                    | CommandError::TooManyArgs { command } => {
+                        if let Some(usage) = self.usage_of(command) {
+                            println!("usage: {}", usage);
+                        }
+                    }
+                    _ => {}
+                }
+                Dispatch::Continue
+            }
+        }
+    }
+
+    pub fn try_dispatch(&mut self, ctx: &mut C, line: &str) -> Result<Dispatch, CommandError> {
+        let Some(command_line) = line.trim_start().strip_prefix(COMMAND_PREFIX) else {
+            return Ok(Dispatch::Text(line.to_string()));
+        };
+        let (name, rest) = match command_line.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest),
+            None => (command_line, ""),
+        };
+
+        let index = match self.find(name) {
+            Some(Found::Command(index)) => index,
+            Some(Found::Help) => {
+                print!("{}", self.help_text());
+                return Ok(Dispatch::Continue);
+            }
+            None => return Err(CommandError::Unknown(name.to_string())),
+        };
+
+        let (command, handler) = &mut self.commands[index];
+        let args = command.parse_args(rest)?;
+        Ok(match handler(ctx, &args) {
+            Flow::Continue => Dispatch::Continue,
+            Flow::Quit => Dispatch::Quit,
+        })
+    }
+
+    pub fn help_text(&self) -> String {
+        let help = Self::help_command();
+        let commands: Vec<&Command> = self
+            .commands
+            .iter()
+            .map(|(command, _)| command)
+            .chain(std::iter::once(&help))
+            .collect();
+
+        let lines: Vec<(String, &str)> = commands
+            .iter()
+            .map(|command| {
+                let mut usage = command.usage();
+                if !command.aliases.is_empty() {
+                    let aliases: Vec<String> = command
+                        .aliases
+                        .iter()
+                        .map(|alias| format!("{}{}", COMMAND_PREFIX, alias))
+                        .collect();
+                    usage += &format!(" ({})", aliases.join(", "));
+                }
+                (usage, command.help)
+            })
+            .collect();
+
+        let width = lines
+            .iter()
+            .map(|(usage, _)| usage.len())
+            .max()
+            .unwrap_or(0);
+        let mut text = String::from("commands:\n");
+        for (usage, help) in lines {
+            text += &format!("  {:width$}  {}\n", usage, help, width = width);
+        }
+        text
+    }
+
+    /// All command names and aliases with the prefix, for tab completion.
+    pub fn completions(&self) -> Vec<String> {
+        let help = Self::help_command();
+        self.commands
+            .iter()
+            .map(|(command, _)| command)
+            .chain(std::iter::once(&help))
+            .flat_map(|command| std::iter::once(&command.name).chain(&command.aliases))
+            .map(|name| format!("{}{}", COMMAND_PREFIX, name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Ctx {
+        calls: Vec<(Option<String>, Option<String>)>,
+    }
+
+    fn registry() -> CommandRegistry<Ctx> {
+        let mut commands = CommandRegistry::new();
+        commands
+            .register(Command::new("q", "quit").alias("quit"), |_, _| Flow::Quit)
+            .register(
+                Command::new("send", "send text to addr")
+                    .arg("addr")
+                    .rest_arg("text"),
+                |ctx: &mut Ctx, args| {
+                    ctx.calls.push((
+                        args.get("addr").map(Into::into),
+                        args.get("text").map(Into::into),
+                    ));
+                    Flow::Continue
+                },
+            )
+            .register(
+                Command::new("level", "set log level").optional_arg("level"),
+                |ctx: &mut Ctx, args| {
+                    ctx.calls.push((args.get("level").map(Into::into), None));
+                    Flow::Continue
+                },
+            );
+        commands
+    }
+
+    #[test]
+    fn dispatch_by_name_and_alias() {
+        let mut commands = registry();
+        let mut ctx = Ctx::default();
+
+        assert_eq!(commands.try_dispatch(&mut ctx, "/q"), Ok(Dispatch::Quit));
+        assert_eq!(commands.try_dispatch(&mut ctx, "/quit"), Ok(Dispatch::Quit));
+        assert_eq!(
+            commands.try_dispatch(&mut ctx, "plain text"),
+            Ok(Dispatch::Text("plain text".into()))
+        );
+        assert_eq!(
+            commands.try_dispatch(&mut ctx, "/nope"),
+            Err(CommandError::Unknown("nope".into()))
+        );
+    }
+
+    #[test]
+    fn argument_parsing() {
+        let mut commands = registry();
+        let mut ctx = Ctx::default();
+
+        commands
+            .try_dispatch(&mut ctx, "/send \"a b\"  hello  world ")
+            .unwrap();
+        commands.try_dispatch(&mut ctx, "/level").unwrap();
+        commands.try_dispatch(&mut ctx, "/level debug").unwrap();
+        assert_eq!(
+            ctx.calls,
+            vec![
+                (Some("a b".into()), Some("hello  world".into())),
+                (None, None),
+                (Some("debug".into()), None),
+            ]
+        );
+
+        assert_eq!(
+            commands.try_dispatch(&mut ctx, "/send"),
+            Err(CommandError::MissingArg {
+                command: "send".into(),
+                arg: "addr".into()
+            })
+        );
+        assert_eq!(
+            commands.try_dispatch(&mut ctx, "/level a b"),
+            Err(CommandError::TooManyArgs {
+                command: "level".into()
+            })
+        );
+        assert_eq!(
+            commands.try_dispatch(&mut ctx, "/send \"x"),
+            Err(CommandError::UnclosedQuote)
+        );
+    }
+
+    #[test]
+    fn help_and_completions() {
+        let commands = registry();
+        let help = commands.help_text();
+        assert!(help.contains("/q (/quit)"));
+        assert!(help.contains("/send <addr> <text...>"));
+        assert!(help.contains("/level [level]"));
+        assert!(help.contains("/help (/h, /?)"));
+
+        let completions = commands.completions();
+        assert!(completions.contains(&"/quit".to_string()));
+        assert!(completions.contains(&"/?".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "already registered")]
+    fn duplicate_names_panic() {
+        let mut commands = registry();
+        commands.register(Command::new("x", "").alias("q"), |_, _| Flow::Continue);
+    }
+}
@@ -0,0 +1,4 @@
+//! Slash commands and line input shared by the interactive tools.
+
+pub mod command;
+pub mod stdinthread;
@@ -0,0 +1,177 @@
+use std::io::{self, IsTerminal};
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Context, Editor, Helper,
+};
+
+pub struct StdinThread {
+    rx: Receiver<String>,
+    /// Terminal settings to put back on drop, as the line editor may still be
+    /// in raw mode when the app exits.
+    saved_termios: Option<libc::termios>,
+}
+
+impl Default for StdinThread {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StdinThread {
+    pub fn new() -> StdinThread {
+        Self::with_completions(Vec::new())
+    }
+
+    /// Read stdin with line editing, history and tab completion of `words`
+    /// when it is a terminal; plain line reads otherwise.
+    pub fn with_completions(words: Vec<String>) -> StdinThread {
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let saved_termios = if io::stdin().is_terminal() {
+            Self::get_termios()
+        } else {
+            None
+        };
+
+        Self::start_reader_thread(tx, saved_termios.is_some().then_some(words));
+        StdinThread { rx, saved_termios }
+    }
+
+    fn get_termios() -> Option<libc::termios> {
+        // SAFETY: an all-zero termios is valid storage for tcgetattr to fill.
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: valid fd and pointer.
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == 0 {
+            Some(termios)
+        } else {
+            None
+        }
+    }
+
+    fn start_reader_thread(
+        tx: Sender<String>,
+        completions: Option<Vec<String>>,
+    ) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            log::debug!("Start reader thread");
+            match completions {
+                Some(words) => Self::editor_loop(tx, words),
+                None => Self::reader_loop(tx),
+            }
+            log::debug!("Exit reader thread");
+        })
+    }
+
+    fn reader_loop(tx: Sender<String>) {
+        let mut input = String::new();
+        loop {
+            match io::stdin().read_line(&mut input) {
+                Ok(0) => break,
+                Ok(_) => {
+                    if tx.send(input.trim_end().to_string()).is_err() {
+                        break;
+                    }
+                    input.clear();
+                }
+                Err(e) => {
+                    log::error!("Failed to read stdin: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn editor_loop(tx: Sender<String>, words: Vec<String>) {
+        let mut editor = match Editor::<WordCompleter, DefaultHistory>::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                log::error!("Failed to start line editor: {}", e);
+                return Self::reader_loop(tx);
+            }
+        };
+        editor.set_helper(Some(WordCompleter { words }));
+
+        loop {
+            match editor.readline("") {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = editor.add_history_entry(line.as_str());
+                    }
+                    if tx.send(line.trim_end().to_string()).is_err() {
+                        break;
+                    }
+                }
+                Err(ReadlineError::Interrupted) => {
+                    // keep the usual Ctrl+C behavior now that the terminal is restored
+                    // SAFETY: raising a signal on ourselves.
+                    unsafe { libc::raise(libc::SIGINT) };
+                    break;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    log::error!("Failed to read stdin: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn read_line(&self) -> Option<String> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Wait for the next line. `None` once stdin is closed.
+    pub fn recv_line(&self) -> Option<String> {
+        self.rx.recv().ok()
+    }
+}
+
+impl Drop for StdinThread {
+    fn drop(&mut self) {
+        if let Some(termios) = &self.saved_termios {
+            // SAFETY: restoring settings previously read from the same fd.
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios) };
+        }
+    }
+}
+
+/// Completes the first word of the line from a fixed list.
+struct WordCompleter {
+    words: Vec<String>,
+}
+
+impl Completer for WordCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = &line[..pos];
+        if prefix.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = self
+            .words
+            .iter()
+            .filter(|word| word.starts_with(prefix))
+            .cloned()
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for WordCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for WordCompleter {}
+
+impl Validator for WordCompleter {}
+
+impl Helper for WordCompleter {}
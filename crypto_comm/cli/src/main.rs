@@ -1,14 +1,21 @@
-use std::{
-    io::{Read, Write},
-    net::TcpStream,
-};
+use std::net::TcpStream;
+
+use lib::command::{Command, CommandRegistry, Dispatch, Flow};
 
 fn main() {
     let _logger = lib::logger::start("debug", "", true);
 
     log::debug!("client");
 
-    let stdin = lib::stdinthread::StdinThread::new();
+    let mut commands = CommandRegistry::new();
+    commands.register(Command::new("q", "quit").alias("quit"), |_: &mut (), _| {
+        Flow::Quit
+    });
+    commands.register(Command::new("t", "bytes test"), |_, _| {
+        test_bytes();
+        Flow::Continue
+    });
+    let stdin = lib::stdinthread::StdinThread::with_completions(commands.completions());
     // test_bytes();
 
     let stream = TcpStream::connect("127.0.0.1:18181").unwrap();
@@ -20,19 +27,16 @@ fn main() {
     stream.read(&mut buf).unwrap();
     log::debug!("recv from server: [{}]", String::from_utf8_lossy(&buf));
 
-    log::debug!("/q : quit\n");
-    log::debug!("/t : bytes test\n");
+    for line in commands.help_text().lines() {
+        log::debug!("{}", line);
+    }
     loop {
         if let Some(cmd) = stdin.read_line() {
             println!("cmd from stdin: {}", cmd);
-            match cmd.as_str() {
-                "/q" => {
-                    break;
-                }
-                "/t" => {
-                    test_bytes();
-                }
-                _ => {
+            match commands.dispatch(&mut (), &cmd) {
+                Dispatch::Quit => break,
+                Dispatch::Continue => {}
+                Dispatch::Text(cmd) => {
                     stream.write(cmd.as_bytes()).unwrap();
                     let mut buf = [0; 1024];
                     stream.read(&mut buf).unwrap();
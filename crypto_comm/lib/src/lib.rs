@@ -1,5 +1,6 @@
-pub mod logger;
-pub mod stdinthread;
+
+pub use console::{command, stdinthread};
+pub use logger;
 
 // use std::{
 //     io::{Read, Write},
@@ -1,7 +1,4 @@
-use std::{
-    io::{Read, Write},
-    net::TcpListener,
-};
+use std::net::TcpListener;
 
 fn main() {
     let _logger = lib::logger::start("debug", "", true);
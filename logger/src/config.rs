@@ -0,0 +1,489 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use flexi_logger::{Age, Cleanup, Criterion, Duplicate, Naming, WriteMode};
+use serde::Deserialize;
+
+use crate::{
+    json_format, log_format,
+    syslog::{Facility, SyslogTarget, SyslogWriter},
+    LogPathInfo, Logger,
+};
+
+#[derive(Debug)]
+pub enum LogError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Invalid(String),
+    Logger(flexi_logger::FlexiLoggerError),
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogError::Io(e) => write!(f, "{}", e),
+            LogError::Toml(e) => write!(f, "invalid log config: {}", e),
+            LogError::Invalid(msg) => write!(f, "{}", msg),
+            LogError::Logger(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+impl From<std::io::Error> for LogError {
+    fn from(e: std::io::Error) -> Self {
+        LogError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for LogError {
+    fn from(e: toml::de::Error) -> Self {
+        LogError::Toml(e)
+    }
+}
+
+impl From<flexi_logger::FlexiLoggerError> for LogError {
+    fn from(e: flexi_logger::FlexiLoggerError) -> Self {
+        LogError::Logger(e)
+    }
+}
+
+/// When to start a new log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Rotation {
+    #[default]
+    Never,
+    Daily,
+    /// Rotate once the file grows past this many bytes.
+    Size(u64),
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    /// `never`, `daily`, or a size like `10M`, `512K`, `1G` or plain bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "never" | "" => return Ok(Rotation::Never),
+            "daily" => return Ok(Rotation::Daily),
+            _ => {}
+        }
+
+        let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+            Some(idx) => s.split_at(idx),
+            None => (s, ""),
+        };
+        let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" => 1 << 10,
+            "M" | "MB" => 1 << 20,
+            "G" | "GB" => 1 << 30,
+            _ => return Err(format!("invalid rotation '{}' (never, daily, 10M, ...)", s)),
+        };
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .filter(|&n| n > 0)
+            .map(Rotation::Size)
+            .ok_or_else(|| format!("invalid rotation size '{}'", s))
+    }
+}
+
+impl TryFrom<String> for Rotation {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Line format of the file and stderr output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, see `json_format`.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid log format '{}' (text, json)", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for LogFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Logger settings.
+///
+/// ```toml
+/// level = "info, lib::streamthread=debug"
+/// file = "/var/log/echo/server.log"
+/// stderr = false
+/// rotate = "10M"     # never, daily or a size
+/// keep = 7           # rotated files to keep, 0 keeps all
+/// compress = true    # gzip rotated files
+/// format = "json"    # text or json
+/// syslog = "local"   # also send to /dev/log, unix:/path or udp://host:port
+/// syslog_facility = "local0"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+    pub file: Option<PathBuf>,
+    pub stderr: bool,
+    pub rotate: Rotation,
+    pub keep: usize,
+    pub compress: bool,
+    pub format: LogFormat,
+    pub syslog: Option<SyslogTarget>,
+    pub syslog_facility: Facility,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            file: None,
+            stderr: true,
+            rotate: Rotation::Never,
+            keep: 7,
+            compress: false,
+            format: LogFormat::Text,
+            syslog: None,
+            syslog_facility: Facility::User,
+        }
+    }
+}
+
+/// Environment variables read by `LogConfig::apply_env`.
+pub const ENV_CONFIG: &str = "LOG_CONFIG";
+pub const ENV_LEVEL: &str = "LOG_LEVEL";
+pub const ENV_FILE: &str = "LOG_FILE";
+pub const ENV_STDERR: &str = "LOG_STDERR";
+pub const ENV_ROTATE: &str = "LOG_ROTATE";
+pub const ENV_KEEP: &str = "LOG_KEEP";
+pub const ENV_COMPRESS: &str = "LOG_COMPRESS";
+pub const ENV_FORMAT: &str = "LOG_FORMAT";
+pub const ENV_SYSLOG: &str = "LOG_SYSLOG";
+pub const ENV_SYSLOG_FACILITY: &str = "LOG_SYSLOG_FACILITY";
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, LogError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(LogError::Invalid(format!("invalid {}: {}", name, value))),
+    }
+}
+
+impl LogConfig {
+    pub fn from_toml_str(s: &str) -> Result<Self, LogError> {
+        Ok(toml::from_str(s)?)
+    }
+
+    pub fn from_toml_file(path: &Path) -> Result<Self, LogError> {
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| LogError::Invalid(format!("failed to read {}: {}", path.display(), e)))?;
+        Self::from_toml_str(&s)
+    }
+
+    /// Override settings from `LOG_LEVEL` (or `RUST_LOG`), `LOG_FILE`,
+    /// `LOG_STDERR`, `LOG_ROTATE`, `LOG_KEEP`, `LOG_COMPRESS`, `LOG_FORMAT`,
+    /// `LOG_SYSLOG` and `LOG_SYSLOG_FACILITY`.
+    pub fn apply_env(&mut self) -> Result<(), LogError> {
+        self.apply_vars(|name| std::env::var(name).ok())
+    }
+
+    fn apply_vars(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), LogError> {
+        if let Some(level) = var(ENV_LEVEL).or_else(|| var("RUST_LOG")) {
+            self.level = level;
+        }
+        if let Some(file) = var(ENV_FILE) {
+            self.file = (!file.is_empty()).then(|| PathBuf::from(file));
+        }
+        if let Some(stderr) = var(ENV_STDERR) {
+            self.stderr = parse_bool(ENV_STDERR, &stderr)?;
+        }
+        if let Some(rotate) = var(ENV_ROTATE) {
+            self.rotate = rotate.parse().map_err(LogError::Invalid)?;
+        }
+        if let Some(keep) = var(ENV_KEEP) {
+            self.keep = keep
+                .trim()
+                .parse()
+                .map_err(|_| LogError::Invalid(format!("invalid {}: {}", ENV_KEEP, keep)))?;
+        }
+        if let Some(compress) = var(ENV_COMPRESS) {
+            self.compress = parse_bool(ENV_COMPRESS, &compress)?;
+        }
+        if let Some(format) = var(ENV_FORMAT) {
+            self.format = format.parse().map_err(LogError::Invalid)?;
+        }
+        if let Some(syslog) = var(ENV_SYSLOG) {
+            self.syslog = if syslog.is_empty() {
+                None
+            } else {
+                Some(syslog.parse().map_err(LogError::Invalid)?)
+            };
+        }
+        if let Some(facility) = var(ENV_SYSLOG_FACILITY) {
+            self.syslog_facility = facility.parse().map_err(LogError::Invalid)?;
+        }
+        Ok(())
+    }
+
+    /// Defaults, then the TOML file named by `LOG_CONFIG`, then the environment.
+    pub fn from_env() -> Result<Self, LogError> {
+        let mut config = match std::env::var_os(ENV_CONFIG) {
+            Some(path) => Self::from_toml_file(Path::new(&path))?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn cleanup(&self) -> Cleanup {
+        match (self.keep, self.compress) {
+            (0, _) => Cleanup::Never,
+            (keep, true) => Cleanup::KeepCompressedFiles(keep),
+            (keep, false) => Cleanup::KeepLogFiles(keep),
+        }
+    }
+
+    pub fn start(&self) -> Result<Logger, LogError> {
+        let format = match self.format {
+            LogFormat::Text => log_format,
+            LogFormat::Json => json_format,
+        };
+        let mut logger = flexi_logger::Logger::try_with_str(&self.level)?.format(format);
+
+        let syslog = match &self.syslog {
+            Some(target) => Some(Box::new(
+                SyslogWriter::new(target, self.syslog_facility).map_err(|e| {
+                    LogError::Invalid(format!("failed to open syslog {:?}: {}", target, e))
+                })?,
+            )),
+            None => None,
+        };
+
+        match (&self.file, syslog) {
+            (Some(file), syslog) => {
+                let path_info = LogPathInfo::new(file).ok_or_else(|| {
+                    LogError::Invalid(format!("invalid log file {}", file.display()))
+                })?;
+                logger = match syslog {
+                    Some(syslog) => logger.log_to_file_and_writer(path_info.file_spec(), syslog),
+                    None => logger.log_to_file(path_info.file_spec()),
+                };
+                logger = logger.append().write_mode(WriteMode::BufferAndFlush);
+
+                logger = match self.rotate {
+                    Rotation::Never => logger,
+                    Rotation::Daily => logger.rotate(
+                        Criterion::Age(Age::Day),
+                        Naming::TimestampsCustomFormat {
+                            current_infix: Some("rCURRENT"),
+                            format: "r%Y-%m-%d",
+                        },
+                        self.cleanup(),
+                    ),
+                    Rotation::Size(size) => {
+                        logger.rotate(Criterion::Size(size), Naming::Numbers, self.cleanup())
+                    }
+                };
+                if self.stderr {
+                    logger = logger.duplicate_to_stderr(Duplicate::All);
+                }
+            }
+            (None, Some(syslog)) => {
+                logger = logger.log_to_writer(syslog);
+                if self.stderr {
+                    logger = logger.duplicate_to_stderr(Duplicate::All);
+                }
+            }
+            (None, None) if self.stderr => logger = logger.log_to_stderr(),
+            (None, None) => {
+                return Err(LogError::Invalid(
+                    "no log file, syslog or stderr output".into(),
+                ))
+            }
+        }
+
+        Ok(Logger {
+            handle: logger.start()?,
+        })
+    }
+}
+
+// Logging flags to `#[command(flatten)]` into an app's clap arguments. Not a
+// doc comment: clap would use it as the about text of the app.
+#[cfg(feature = "clap")]
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct LogArgs {
+    /// log config file (TOML), defaults to $LOG_CONFIG
+    #[arg(long)]
+    pub log_config: Option<PathBuf>,
+
+    /// log spec, e.g. "info" or "debug, lib::streamthread=trace"
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// log file path
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// also log to stderr
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub log_stderr: Option<bool>,
+
+    /// rotation: never, daily or a size like 10M
+    #[arg(long)]
+    pub log_rotate: Option<Rotation>,
+
+    /// rotated files to keep (0: keep all)
+    #[arg(long)]
+    pub log_keep: Option<usize>,
+
+    /// gzip rotated files
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub log_compress: Option<bool>,
+
+    /// line format: text or json
+    #[arg(long)]
+    pub log_format: Option<LogFormat>,
+
+    /// also send to syslog: local, unix:/path or udp://host:port
+    #[arg(long)]
+    pub log_syslog: Option<SyslogTarget>,
+
+    /// syslog facility, e.g. user, daemon or local0
+    #[arg(long)]
+    pub log_syslog_facility: Option<Facility>,
+}
+
+#[cfg(feature = "clap")]
+impl LogArgs {
+    /// `defaults` < config file < environment < flags.
+    pub fn config(&self, defaults: LogConfig) -> Result<LogConfig, LogError> {
+        let path = self
+            .log_config
+            .clone()
+            .or_else(|| std::env::var_os(ENV_CONFIG).map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => LogConfig::from_toml_file(&path)?,
+            None => defaults,
+        };
+        config.apply_env()?;
+        self.apply(&mut config);
+        Ok(config)
+    }
+
+    fn apply(&self, config: &mut LogConfig) {
+        if let Some(level) = &self.log_level {
+            config.level = level.clone();
+        }
+        if let Some(file) = &self.log_file {
+            config.file = Some(file.clone());
+        }
+        if let Some(stderr) = self.log_stderr {
+            config.stderr = stderr;
+        }
+        if let Some(rotate) = self.log_rotate {
+            config.rotate = rotate;
+        }
+        if let Some(keep) = self.log_keep {
+            config.keep = keep;
+        }
+        if let Some(compress) = self.log_compress {
+            config.compress = compress;
+        }
+        if let Some(format) = self.log_format {
+            config.format = format;
+        }
+        if let Some(syslog) = &self.log_syslog {
+            config.syslog = Some(syslog.clone());
+        }
+        if let Some(facility) = self.log_syslog_facility {
+            config.syslog_facility = facility;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rotation() {
+        assert_eq!("never".parse(), Ok(Rotation::Never));
+        assert_eq!("Daily".parse(), Ok(Rotation::Daily));
+        assert_eq!("10M".parse(), Ok(Rotation::Size(10 << 20)));
+        assert_eq!("512kb".parse(), Ok(Rotation::Size(512 << 10)));
+        assert_eq!("4096".parse(), Ok(Rotation::Size(4096)));
+        assert!("10X".parse::<Rotation>().is_err());
+        assert!("0".parse::<Rotation>().is_err());
+    }
+
+    #[test]
+    fn toml_then_env() {
+        let mut config = LogConfig::from_toml_str(
+            r#"
+            level = "debug"
+            file = "/tmp/server.log"
+            rotate = "daily"
+            compress = true
+            format = "json"
+            syslog = "udp://127.0.0.1:514"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rotate, Rotation::Daily);
+        assert_eq!(config.keep, 7);
+        assert_eq!(config.format, LogFormat::Json);
+        assert_eq!(
+            config.syslog,
+            Some(SyslogTarget::Udp("127.0.0.1:514".into()))
+        );
+        assert!(config.stderr);
+
+        let env = [
+            ("LOG_LEVEL", "warn"),
+            ("LOG_STDERR", "off"),
+            ("LOG_KEEP", "3"),
+        ];
+        config
+            .apply_vars(|name| {
+                env.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            })
+            .unwrap();
+        assert_eq!(config.level, "warn");
+        assert!(!config.stderr);
+        assert_eq!(config.keep, 3);
+        assert_eq!(config.file, Some(PathBuf::from("/tmp/server.log")));
+
+        assert!(LogConfig::from_toml_str("colour = true").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Logging setup shared by the socket tools, on top of flexi_logger.
+//!
+//! `start` keeps the old one-line setup on top of `LogConfig`, which adds
+//! rotation, retention, compression, JSON lines and a syslog sink, loaded
+//! from a TOML file, the environment and (with the `clap` feature) command
+//! line flags.
+
+mod config;
+pub mod syslog;
+
+#[cfg(feature = "clap")]
+pub use config::LogArgs;
+pub use config::{LogConfig, LogError, LogFormat, Rotation};
+
+use flexi_logger::LoggerHandle;
+
+const LOG_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S %:z";
+
+fn log_format(
+    w: &mut dyn std::io::Write,
+    now: &mut flexi_logger::DeferredNow,
+    record: &log::Record,
+) -> Result<(), std::io::Error> {
+    if record.level() == log::Level::Info {
+        write!(w, "{} {}", now.format(LOG_TIMESTAMP_FORMAT), &record.args())
+    } else if record.level() == log::Level::Debug {
+        write!(
+            w,
+            "{} {} [{}:{}] {}",
+            now.format(LOG_TIMESTAMP_FORMAT),
+            record.level(),
+            record.file().unwrap_or("unknown"),
+            record.line().unwrap_or(0),
+            &record.args()
+        )
+    } else {
+        write!(
+            w,
+            "{} {} {}",
+            now.format(LOG_TIMESTAMP_FORMAT),
+            record.level(),
+            &record.args()
+        )
+    }
+}
+
+/// One JSON object per line, for log shipping:
+///
+/// ```text
+/// {"ts":"2024-05-01T10:00:00.123+09:00","level":"INFO","module":"lib::server","file":"lib/src/server.rs","line":42,"thread":"main","msg":"accepted","fields":{"peer":"10.0.0.1:80"}}
+/// ```
+///
+/// `fields` holds the record's key-values (`log::info!(peer = addr; "accepted")`)
+/// and is left out when there are none.
+pub fn json_format(
+    w: &mut dyn std::io::Write,
+    now: &mut flexi_logger::DeferredNow,
+    record: &log::Record,
+) -> Result<(), std::io::Error> {
+    struct Fields(serde_json::Map<String, serde_json::Value>);
+
+    impl<'kvs> log::kv::VisitSource<'kvs> for Fields {
+        fn visit_pair(
+            &mut self,
+            key: log::kv::Key<'kvs>,
+            value: log::kv::Value<'kvs>,
+        ) -> Result<(), log::kv::Error> {
+            let value = if let Some(v) = value.to_bool() {
+                v.into()
+            } else if let Some(v) = value.to_i64() {
+                v.into()
+            } else if let Some(v) = value.to_u64() {
+                v.into()
+            } else if let Some(v) = value.to_f64() {
+                v.into()
+            } else {
+                value.to_string().into()
+            };
+            self.0.insert(key.to_string(), value);
+            Ok(())
+        }
+    }
+
+    #[derive(serde::Serialize)]
+    struct Line<'a> {
+        ts: String,
+        level: &'a str,
+        module: Option<&'a str>,
+        file: Option<&'a str>,
+        line: Option<u32>,
+        thread: String,
+        msg: String,
+        #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+        fields: serde_json::Map<String, serde_json::Value>,
+    }
+
+    let thread = std::thread::current();
+    let mut fields = Fields(serde_json::Map::new());
+    let _ = record.key_values().visit(&mut fields);
+    let line = Line {
+        ts: now.format_rfc3339(),
+        level: record.level().as_str(),
+        module: record.module_path(),
+        file: record.file(),
+        line: record.line(),
+        thread: match thread.name() {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", thread.id()),
+        },
+        msg: record.args().to_string(),
+        fields: fields.0,
+    };
+
+    serde_json::to_writer(&mut *w, &line)?;
+    Ok(())
+}
+
+/// Log to `log_file` (appending, no rotation) and/or stderr.
+///
+/// An empty or unusable `log_file` logs to stderr only. This is a shorthand
+/// for a `LogConfig` with these three settings, so the `LOG_*` environment
+/// variables apply on top (see `LogConfig::apply_env`); `LOG_SYSLOG` adds
+/// the syslog sink.
+pub fn start(log_level: &str, log_file: &str, to_stderr: bool) -> Result<Logger, LogError> {
+    if log_file.is_empty() && !to_stderr {
+        panic!("invalid log_file and to_stderr is not set");
+    }
+
+    let file = std::path::Path::new(log_file);
+    let file = if LogPathInfo::new(file).is_some() {
+        Some(file.to_path_buf())
+    } else if to_stderr {
+        None
+    } else {
+        panic!("log_file is invalid");
+    };
+
+    let mut config = LogConfig {
+        level: log_level.to_string(),
+        file,
+        stderr: to_stderr,
+        ..Default::default()
+    };
+    config.apply_env()?;
+    config.start()
+}
+
+/// Running logger. Logging stops when it is dropped.
+pub struct Logger {
+    handle: LoggerHandle,
+}
+
+impl Logger {
+    /// Replace the log spec, e.g. `"info"` or `"debug, lib::streamthread=trace"`.
+    pub fn set_spec(&self, spec: &str) -> Result<(), LogError> {
+        self.handle.parse_new_spec(spec)?;
+        Ok(())
+    }
+
+    /// Use `spec` until `pop_temp_spec` is called.
+    pub fn push_temp_spec(&mut self, spec: &str) -> Result<(), LogError> {
+        self.handle.parse_and_push_temp_spec(spec)?;
+        Ok(())
+    }
+
+    pub fn pop_temp_spec(&mut self) {
+        self.handle.pop_temp_spec();
+    }
+
+    /// Rotate now, regardless of the rotation criterion.
+    pub fn rotate(&self) -> Result<(), LogError> {
+        self.handle.trigger_rotation()?;
+        Ok(())
+    }
+
+    pub fn flush(&self) {
+        self.handle.flush();
+    }
+
+    pub fn handle(&self) -> &LoggerHandle {
+        &self.handle
+    }
+}
+
+#[derive(Debug)]
+struct LogPathInfo {
+    dir: std::path::PathBuf,
+    filename: String,
+    extension: String,
+}
+
+impl LogPathInfo {
+    fn new(path: &std::path::Path) -> Option<Self> {
+        let log_dir = path.parent()?;
+        let log_filename = path.file_stem()?;
+        let log_extension = path.extension();
+
+        if !log_dir.is_dir() {
+            return None;
+        }
+
+        Some(Self {
+            dir: log_dir.to_path_buf(),
+            filename: log_filename.to_str().unwrap().into(),
+            extension: if let Some(extension) = log_extension {
+                extension.to_str().unwrap().into()
+            } else {
+                // default extension
+                "log".into()
+            },
+        })
+    }
+
+    fn file_spec(&self) -> flexi_logger::FileSpec {
+        flexi_logger::FileSpec::default()
+            .suppress_timestamp()
+            .directory(&self.dir)
+            .basename(&self.filename)
+            .suffix(&self.extension)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_line() {
+        // the thread name is part of the line; the test harness only names
+        // its threads when tests run in parallel
+        let buf = std::thread::Builder::new()
+            .name("json-line".into())
+            .spawn(|| {
+                let fields = [("peer", "10.0.0.1:80")];
+                let record = log::Record::builder()
+                    .level(log::Level::Info)
+                    .target("lib::server")
+                    .module_path(Some("lib::server"))
+                    .file(Some("lib/src/server.rs"))
+                    .line(Some(42))
+                    .args(format_args!("accepted"))
+                    .key_values(&fields)
+                    .build();
+                let mut buf = Vec::new();
+                json_format(&mut buf, &mut flexi_logger::DeferredNow::new(), &record).unwrap();
+                buf
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        let line: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["module"], "lib::server");
+        assert_eq!(line["file"], "lib/src/server.rs");
+        assert_eq!(line["line"], 42);
+        assert_eq!(line["thread"], "json-line");
+        assert_eq!(line["msg"], "accepted");
+        assert_eq!(line["fields"], serde_json::json!({"peer": "10.0.0.1:80"}));
+        assert!(line["ts"].is_string());
+    }
+
+    #[test]
+    fn size_rotation_keeps_compressed_files() {
+        let dir = std::env::temp_dir().join(format!("logger-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let config = LogConfig {
+            level: "info".into(),
+            file: Some(dir.join("rotate.log")),
+            stderr: false,
+            rotate: Rotation::Size(1024),
+            keep: 2,
+            compress: true,
+            ..Default::default()
+        };
+        let mut logger = config.start().unwrap();
+        for i in 0..200 {
+            log::info!("line {} of the rotation test, long enough to fill files", i);
+        }
+        logger.push_temp_spec("error").unwrap();
+        log::info!("filtered out");
+        logger.pop_temp_spec();
+        logger.flush();
+        // cleanup runs in a background thread after each rotation
+        std::thread::sleep(std::time::Duration::from_millis(500));
+
+        let names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(
+            names.contains(&"rotate_rCURRENT.log".to_string()),
+            "{:?}",
+            names
+        );
+        let compressed = names.iter().filter(|name| name.ends_with(".gz")).count();
+        assert!((1..=2).contains(&compressed), "{:?}", names);
+        assert!(!names.iter().any(|name| name.ends_with("r00000.log")));
+
+        drop(logger);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,389 @@
+//! RFC 5424 syslog sink, sent over `/dev/log`, a Unix datagram socket or UDP.
+//!
+//! Key-value fields of a record go into a structured data element, the
+//! message text into MSG.
+
+use std::{
+    fmt::{self, Write as _},
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use flexi_logger::{writers::LogWriter, DeferredNow};
+use log::Record;
+use serde::Deserialize;
+
+/// Where `/dev/log` style syslog daemons listen.
+pub const LOCAL_SOCKET: &str = "/dev/log";
+
+/// SD-ID of the element holding key-value fields. 32473 is the private
+/// enterprise number reserved for examples (RFC 5612).
+const FIELDS_SD_ID: &str = "fields@32473";
+
+/// Where syslog messages are sent.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum SyslogTarget {
+    /// The local daemon at `/dev/log`.
+    Local,
+    /// A Unix datagram socket, e.g. a journald or test listener.
+    Unix(PathBuf),
+    /// A remote collector, `host:port`.
+    Udp(String),
+}
+
+impl FromStr for SyslogTarget {
+    type Err = String;
+
+    /// `local`, `unix:/path/to/socket` or `udp://host:port`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "local" {
+            Ok(SyslogTarget::Local)
+        } else if let Some(path) = s.strip_prefix("unix:") {
+            Ok(SyslogTarget::Unix(PathBuf::from(path)))
+        } else if let Some(addr) = s.strip_prefix("udp://") {
+            Ok(SyslogTarget::Udp(addr.to_string()))
+        } else {
+            Err(format!(
+                "invalid syslog target '{}' (local, unix:/path, udp://host:port)",
+                s
+            ))
+        }
+    }
+}
+
+impl TryFrom<String> for SyslogTarget {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Syslog facility; the default is `user`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Facility {
+    Kern,
+    #[default]
+    User,
+    Daemon,
+    Auth,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    fn code(self) -> u8 {
+        match self {
+            Facility::Kern => 0,
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            Facility::Auth => 4,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+impl FromStr for Facility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "kern" => Facility::Kern,
+            "user" => Facility::User,
+            "daemon" => Facility::Daemon,
+            "auth" => Facility::Auth,
+            "local0" => Facility::Local0,
+            "local1" => Facility::Local1,
+            "local2" => Facility::Local2,
+            "local3" => Facility::Local3,
+            "local4" => Facility::Local4,
+            "local5" => Facility::Local5,
+            "local6" => Facility::Local6,
+            "local7" => Facility::Local7,
+            _ => return Err(format!("invalid syslog facility '{}'", s)),
+        })
+    }
+}
+
+impl TryFrom<String> for Facility {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+fn severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+enum Socket {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+/// `LogWriter` that sends each record as one RFC 5424 datagram.
+pub struct SyslogWriter {
+    socket: Socket,
+    facility: Facility,
+    hostname: String,
+    app_name: String,
+    procid: u32,
+    max_level: log::LevelFilter,
+}
+
+impl SyslogWriter {
+    pub fn new(target: &SyslogTarget, facility: Facility) -> io::Result<Self> {
+        let socket = match target {
+            SyslogTarget::Local => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(LOCAL_SOCKET)?;
+                Socket::Unix(socket)
+            }
+            SyslogTarget::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Socket::Unix(socket)
+            }
+            SyslogTarget::Udp(addr) => {
+                let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("no address {}", addr))
+                })?;
+                let bind_addr = if addr.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(bind_addr)?;
+                socket.connect(addr)?;
+                Socket::Udp(socket)
+            }
+        };
+
+        Ok(Self {
+            socket,
+            facility,
+            hostname: header_field(&hostname(), 255),
+            app_name: header_field(&app_name(), 48),
+            procid: std::process::id(),
+            max_level: log::LevelFilter::Trace,
+        })
+    }
+
+    /// Records above `level` are not sent, whatever the log spec says.
+    pub fn max_level(mut self, level: log::LevelFilter) -> Self {
+        self.max_level = level;
+        self
+    }
+
+    fn message(&self, now: &mut DeferredNow, record: &Record) -> String {
+        let pri = self.facility.code() * 8 + severity(record.level());
+        let mut msg = format!(
+            "<{}>1 {} {} {} {} - ",
+            pri,
+            now.format_rfc3339(),
+            self.hostname,
+            self.app_name,
+            self.procid
+        );
+        structured_data(&mut msg, record);
+        let _ = write!(msg, " {}", record.args());
+        msg
+    }
+}
+
+impl LogWriter for SyslogWriter {
+    fn write(&self, now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+        let msg = self.message(now, record);
+        match &self.socket {
+            Socket::Unix(socket) => socket.send(msg.as_bytes())?,
+            Socket::Udp(socket) => socket.send(msg.as_bytes())?,
+        };
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn max_log_level(&self) -> log::LevelFilter {
+        self.max_level
+    }
+}
+
+/// `[fields@32473 key="value" ...]`, or `-` for a record without fields.
+fn structured_data(msg: &mut String, record: &Record) {
+    struct Params<'a>(&'a mut String);
+
+    impl<'kvs> log::kv::VisitSource<'kvs> for Params<'_> {
+        fn visit_pair(
+            &mut self,
+            key: log::kv::Key<'kvs>,
+            value: log::kv::Value<'kvs>,
+        ) -> Result<(), log::kv::Error> {
+            let name: String = key
+                .as_str()
+                .chars()
+                .filter(|c| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"'))
+                .take(32)
+                .collect();
+            if name.is_empty() {
+                return Ok(());
+            }
+            let _ = write!(self.0, " {}=\"", name);
+            escape_param(self.0, &value.to_string());
+            self.0.push('"');
+            Ok(())
+        }
+    }
+
+    let start = msg.len();
+    msg.push('[');
+    msg.push_str(FIELDS_SD_ID);
+    let params_start = msg.len();
+    let _ = record.key_values().visit(&mut Params(msg));
+    if msg.len() == params_start {
+        msg.truncate(start);
+        msg.push('-');
+    } else {
+        msg.push(']');
+    }
+}
+
+/// PARAM-VALUE escaping: `"`, `\` and `]` get a backslash.
+fn escape_param(out: &mut String, value: &str) {
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// Header fields are printable ASCII without spaces, `-` when empty.
+fn header_field(value: &str, max_len: usize) -> String {
+    let value: String = value
+        .chars()
+        .filter(char::is_ascii_graphic)
+        .take(max_len)
+        .collect();
+    if value.is_empty() {
+        "-".to_string()
+    } else {
+        value
+    }
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn app_name() -> String {
+    std::env::args_os()
+        .next()
+        .and_then(|arg0| {
+            std::path::Path::new(&arg0)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default()
+}
+
+impl fmt::Debug for SyslogWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyslogWriter")
+            .field("facility", &self.facility)
+            .field("hostname", &self.hostname)
+            .field("app_name", &self.app_name)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc5424_over_unix_datagram() {
+        let path = std::env::temp_dir().join(format!("logger-syslog-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let syslogd = UnixDatagram::bind(&path).unwrap();
+
+        let writer =
+            SyslogWriter::new(&SyslogTarget::Unix(path.clone()), Facility::Local0).unwrap();
+        let fields = [("peer", "10.0.0.1:80"), ("reason", "reset \"by\" peer]")];
+        let record = Record::builder()
+            .level(log::Level::Warn)
+            .args(format_args!("connection lost"))
+            .key_values(&fields)
+            .build();
+        writer.write(&mut DeferredNow::new(), &record).unwrap();
+
+        let mut buf = [0u8; 1024];
+        let n = syslogd.recv(&mut buf).unwrap();
+        let msg = std::str::from_utf8(&buf[..n]).unwrap();
+        // local0 (16) * 8 + warning (4)
+        assert!(msg.starts_with("<132>1 "), "{}", msg);
+        assert!(
+            msg.ends_with(&format!(
+                " {} - [fields@32473 peer=\"10.0.0.1:80\" reason=\"reset \\\"by\\\" peer\\]\"] connection lost",
+                std::process::id()
+            )),
+            "{}",
+            msg
+        );
+
+        let record = Record::builder()
+            .level(log::Level::Info)
+            .args(format_args!("plain"))
+            .build();
+        writer.write(&mut DeferredNow::new(), &record).unwrap();
+        let n = syslogd.recv(&mut buf).unwrap();
+        let msg = std::str::from_utf8(&buf[..n]).unwrap();
+        assert!(msg.starts_with("<134>1 "), "{}", msg);
+        assert!(msg.ends_with(" - - plain"), "{}", msg);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn parse_target() {
+        assert_eq!("local".parse(), Ok(SyslogTarget::Local));
+        assert_eq!(
+            "unix:/run/systemd/journal/syslog".parse(),
+            Ok(SyslogTarget::Unix("/run/systemd/journal/syslog".into()))
+        );
+        assert_eq!(
+            "udp://logs:514".parse(),
+            Ok(SyslogTarget::Udp("logs:514".into()))
+        );
+        assert!("tcp://logs:514".parse::<SyslogTarget>().is_err());
+        assert_eq!("LOCAL3".parse(), Ok(Facility::Local3));
+    }
+}
@@ -75,7 +75,7 @@ pub fn verify_hmac_message(json_data: &str, secret_key: &[u8]) -> Option<String>
     let expected_signature = hasher.finalize();
 
     // 서명 비교
-    if signature_bytes != expected_signature.as_slice() {
+    if signature_bytes != expected_signature[..] {
         return None;
     }
 
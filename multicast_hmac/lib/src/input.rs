@@ -1,8 +1,13 @@
 use std::{
-    io::{self, Write},
+    io,
     net::{IpAddr, SocketAddr, UdpSocket},
 };
 
+use console::{
+    command::{Command, CommandRegistry, Dispatch, Flow},
+    stdinthread::StdinThread,
+};
+
 use crate::{
     MULTICAST_ADDR, PORT,
     udpm::{send_multicast_message_with_hmac, send_udp_msg},
@@ -11,7 +16,7 @@ use crate::{
 /// 사용자 입력을 처리하는 함수
 ///
 /// 표준 입력에서 명령어를 읽고 처리합니다.
-/// 지원하는 명령어: /hello, /quit, /exit
+/// 지원하는 명령어: /hello, /quit, /exit, /help
 ///
 /// # Arguments
 /// * `socket_clone` - 메시지 전송에 사용할 UDP 소켓 참조
@@ -20,63 +25,34 @@ use crate::{
 /// * `io::Result<()>` - 처리 성공 시 Ok(()), 실패 시 Err
 ///
 /// # Examples
-/// ```
+/// ```no_run
 /// use std::net::UdpSocket;
-/// use lib::handle_user_input;
+/// use lib::input::handle_user_input;
 ///
 /// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
 /// let socket_clone = socket.try_clone().unwrap();
 /// let result = handle_user_input(&socket_clone);
 /// ```
 pub fn handle_user_input(socket_clone: &UdpSocket) -> io::Result<()> {
-    println!("Enter commands (press Ctrl+C to exit):");
-    println!("  /hello - Send 'hello' message to multicast group");
-    println!("  /quit  - Exit program");
-
-    let mut input = String::new();
-    loop {
-        input.clear();
-        print!("> ");
-        io::stdout().flush()?;
-
-        match io::stdin().read_line(&mut input) {
-            Ok(_) => {
-                let command = input.trim();
-
-                match command {
-                    "/hello" => {
-                        let multicast_addr = SocketAddr::new(IpAddr::V4(MULTICAST_ADDR), PORT);
-                        if let Err(e) = send_udp_msg(socket_clone, multicast_addr, "hello") {
-                            eprintln!("Failed to send message: {e}");
-                        }
-                    }
-                    "/quit" | "/exit" => {
-                        println!("Exiting program.");
-                        break;
-                    }
-                    "" => continue, // 빈 입력 무시
-                    _ => {
-                        println!("Unknown command: {command}");
-                        println!("Available commands:");
-                        println!("  /hello - Send 'hello' message to multicast group");
-                        println!("  /quit  - Exit program");
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("Input error: {e}");
-                break;
+    let mut commands = CommandRegistry::new();
+    commands.register(
+        Command::new("hello", "Send 'hello' message to multicast group"),
+        |socket: &mut UdpSocket, _| {
+            let multicast_addr = SocketAddr::new(IpAddr::V4(MULTICAST_ADDR), PORT);
+            if let Err(e) = send_udp_msg(socket, multicast_addr, "hello") {
+                eprintln!("Failed to send message: {e}");
             }
-        }
-    }
-
+            Flow::Continue
+        },
+    );
+    run_commands(commands, &mut socket_clone.try_clone()?);
     Ok(())
 }
 
 /// HMAC을 사용하는 사용자 입력 처리 함수
 ///
 /// 표준 입력에서 명령어를 읽고 HMAC 서명과 함께 처리합니다.
-/// 지원하는 명령어: /hello, /quit, /exit
+/// 지원하는 명령어: /hello, /quit, /exit, /help
 ///
 /// # Arguments
 /// * `socket_clone` - 메시지 전송에 사용할 UDP 소켓 참조
@@ -86,9 +62,9 @@ pub fn handle_user_input(socket_clone: &UdpSocket) -> io::Result<()> {
 /// * `io::Result<()>` - 처리 성공 시 Ok(()), 실패 시 Err
 ///
 /// # Examples
-/// ```
+/// ```no_run
 /// use std::net::UdpSocket;
-/// use lib::handle_user_input_with_hmac;
+/// use lib::input::handle_user_input_with_hmac;
 ///
 /// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
 /// let socket_clone = socket.try_clone().unwrap();
@@ -96,51 +72,46 @@ pub fn handle_user_input(socket_clone: &UdpSocket) -> io::Result<()> {
 /// let result = handle_user_input_with_hmac(&socket_clone, secret_key);
 /// ```
 pub fn handle_user_input_with_hmac(socket_clone: &UdpSocket, secret_key: &[u8]) -> io::Result<()> {
-    println!("Enter commands (press Ctrl+C to exit):");
-    println!("  /hello - Send 'hello' message with HMAC to multicast group");
-    println!("  /quit  - Exit program");
-
-    let mut input = String::new();
-    loop {
-        input.clear();
-        print!("> ");
-        io::stdout().flush()?;
+    let secret_key = secret_key.to_vec();
+    let mut commands = CommandRegistry::new();
+    commands.register(
+        Command::new("hello", "Send 'hello' message with HMAC to multicast group"),
+        move |socket: &mut UdpSocket, _| {
+            let multicast_addr = SocketAddr::new(IpAddr::V4(MULTICAST_ADDR), PORT);
+            if let Err(e) =
+                send_multicast_message_with_hmac(socket, "hello", multicast_addr, &secret_key)
+            {
+                eprintln!("Failed to send HMAC message: {e}");
+            }
+            Flow::Continue
+        },
+    );
+    run_commands(commands, &mut socket_clone.try_clone()?);
+    Ok(())
+}
 
-        match io::stdin().read_line(&mut input) {
-            Ok(_) => {
-                let command = input.trim();
+/// `/quit`, `/exit` 를 더해서 표준 입력이 닫히거나 종료 명령이 올 때까지 명령어 처리
+fn run_commands(mut commands: CommandRegistry<UdpSocket>, socket: &mut UdpSocket) {
+    commands.register(
+        Command::new("quit", "Exit program").alias("exit"),
+        |_, _| Flow::Quit,
+    );
+    let stdin = StdinThread::with_completions(commands.completions());
 
-                match command {
-                    "/hello" => {
-                        let multicast_addr = SocketAddr::new(IpAddr::V4(MULTICAST_ADDR), PORT);
-                        if let Err(e) = send_multicast_message_with_hmac(
-                            socket_clone,
-                            "hello",
-                            multicast_addr,
-                            secret_key,
-                        ) {
-                            eprintln!("Failed to send HMAC message: {e}");
-                        }
-                    }
-                    "/quit" | "/exit" => {
-                        println!("Exiting program.");
-                        break;
-                    }
-                    "" => continue, // 빈 입력 무시
-                    _ => {
-                        println!("Unknown command: {command}");
-                        println!("Available commands:");
-                        println!("  /hello - Send 'hello' message with HMAC to multicast group");
-                        println!("  /quit  - Exit program");
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("Input error: {e}");
+    println!("Enter commands (press Ctrl+C to exit):");
+    print!("{}", commands.help_text());
+    while let Some(line) = stdin.recv_line() {
+        match commands.dispatch(socket, &line) {
+            Dispatch::Quit => {
+                println!("Exiting program.");
                 break;
             }
+            Dispatch::Continue => {}
+            Dispatch::Text(text) if text.trim().is_empty() => {} // 빈 입력 무시
+            Dispatch::Text(text) => {
+                println!("Unknown command: {}", text.trim());
+                print!("{}", commands.help_text());
+            }
         }
     }
-
-    Ok(())
 }
@@ -0,0 +1,24 @@
+#!/bin/bash
+# Test CA and certificates for svr, svr_cn and cli, issued with tls_echo3/pki.
+# Run the binaries from the certs directory or pass -r/-c/-k.
+set -e
+
+DIR=$(cd "$(dirname "$0")" && pwd)
+WORK_DIR=$DIR/certs
+PKI_MANIFEST=$DIR/../../tls_echo3/pki/Cargo.toml
+
+pki() {
+    cargo run --quiet --manifest-path "$PKI_MANIFEST" -- --dir "$WORK_DIR" "$@"
+}
+
+if [ -f "$WORK_DIR/index.toml" ]; then
+    echo "Root CA already exists in $WORK_DIR. Skipping setup."
+    echo "Issue more with: cargo run --manifest-path $PKI_MANIFEST -- --dir $WORK_DIR issue-client --cn <CN>"
+    exit 1
+fi
+
+pki init-ca --cn "saibi CA" --cert ca.cert.pem --key ca.key.pem --crl ca.crl.pem
+# svr and cli share cert.pem, so it carries serverAuth and clientAuth
+pki issue-server --name cert --dns localhost --client-auth --cert cert.pem --key key.pem
+# svr_cn allows CN "client" by default
+pki issue-client --cn client --cert client.cert.pem --key client.key.pem
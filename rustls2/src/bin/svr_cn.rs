@@ -11,6 +11,7 @@ use rustls::{
 const BIND_ADDRESS: &str = "0.0.0.0:8443";
 
 use clap::Parser;
+use rustls2::identity::PeerIdentity;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -27,47 +28,10 @@ pub struct Args {
     #[arg(short = 'k', long, default_value = "key.pem")]
     pub key: String,
 
-    /// allowed client CN (Common Name)
-    #[arg(short = 'n', long, default_value = "client")]
-    pub allowed_cn: String,
-}
-
-// CN 값을 추출하는 함수
-fn extract_cn_from_cert(cert: &Certificate) -> Result<String, Box<dyn std::error::Error>> {
-    use std::io::Write;
-    use std::process::Command;
-    use tempfile::NamedTempFile;
-
-    // 임시 파일에 인증서 저장
-    let mut temp_file = NamedTempFile::new()?;
-    temp_file.write_all(&cert.0)?;
-    temp_file.flush()?;
-
-    // OpenSSL 명령어로 CN 추출
-    let output = Command::new("openssl")
-        .args(&[
-            "x509",
-            "-noout",
-            "-subject",
-            "-in",
-            temp_file.path().to_str().unwrap(),
-        ])
-        .output()?;
-
-    if output.status.success() {
-        let subject = String::from_utf8(output.stdout)?;
-        // "subject=CN = client" 형태에서 CN 값 추출
-        if let Some(cn_start) = subject.find("CN=") {
-            let cn_part = &subject[cn_start + 5..];
-            if let Some(cn_end) = cn_part.find('\n') {
-                return Ok(cn_part[..cn_end].trim().to_string());
-            } else {
-                return Ok(cn_part.trim().to_string());
-            }
-        }
-    }
-
-    Err("CN not found in certificate".into())
+    /// allowed client CN (Common Name), repeat or separate with commas;
+    /// `*` matches any characters (e.g. `client-*`)
+    #[arg(short = 'n', long, default_value = "client", value_delimiter = ',')]
+    pub allowed_cn: Vec<String>,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -121,7 +85,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // TCP 리스너 생성
     let listener = TcpListener::bind(BIND_ADDRESS)?;
     println!("서버가 {}에서 실행 중입니다...", BIND_ADDRESS);
-    println!("허용된 클라이언트 CN: {}", args.allowed_cn);
+    println!("허용된 클라이언트 CN: {}", args.allowed_cn.join(", "));
 
     for stream in listener.incoming() {
         match stream {
@@ -144,30 +108,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 // 클라이언트 인증서에서 CN 추출 및 검증
                                 if let Some(client_certs) = tls.conn.peer_certificates() {
                                     if let Some(client_cert) = client_certs.first() {
-                                        match extract_cn_from_cert(client_cert) {
-                                            Ok(cn) => {
-                                                if cn == args.allowed_cn {
+                                        match PeerIdentity::from_der(&client_cert.0) {
+                                            Ok(identity) => {
+                                                println!("클라이언트 인증서: {}", identity);
+                                                if let Some(cn) =
+                                                    identity.allowed_cn(&args.allowed_cn)
+                                                {
                                                     println!(
                                                         "클라이언트 인증서 CN 검증 성공: {}",
                                                         cn
                                                     );
                                                 } else {
                                                     println!(
-                                                        "클라이언트 인증서 CN 불일치: 예상={}, 실제={}",
-                                                        args.allowed_cn, cn
+                                                        "클라이언트 인증서 CN 불일치: 허용={}, 실제={}",
+                                                        args.allowed_cn.join(","),
+                                                        identity.common_names.join(",")
                                                     );
                                                     println!("연결을 거부합니다.");
                                                     // TLS 연결 종료 후 다음 연결로 넘어감
-                                                    let _ = tls.conn.send_close_notify();
+                                                    tls.conn.send_close_notify();
                                                     let _ = tls.conn.complete_io(&mut tls.sock);
                                                     continue;
                                                 }
                                             }
                                             Err(e) => {
-                                                println!("클라이언트 인증서 CN 추출 실패: {}", e);
+                                                println!("클라이언트 인증서 파싱 실패: {}", e);
                                                 println!("연결을 거부합니다.");
                                                 // TLS 연결 종료 후 다음 연결로 넘어감
-                                                let _ = tls.conn.send_close_notify();
+                                                tls.conn.send_close_notify();
                                                 let _ = tls.conn.complete_io(&mut tls.sock);
                                                 continue;
                                             }
@@ -175,14 +143,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     } else {
                                         println!("클라이언트 인증서가 없습니다.");
                                         // TLS 연결 종료 후 다음 연결로 넘어감
-                                        let _ = tls.conn.send_close_notify();
+                                        tls.conn.send_close_notify();
                                         let _ = tls.conn.complete_io(&mut tls.sock);
                                         continue;
                                     }
                                 } else {
                                     println!("클라이언트 인증서 정보를 가져올 수 없습니다.");
                                     // TLS 연결 종료 후 다음 연결로 넘어감
-                                    let _ = tls.conn.send_close_notify();
+                                    tls.conn.send_close_notify();
                                     let _ = tls.conn.complete_io(&mut tls.sock);
                                     continue;
                                 }
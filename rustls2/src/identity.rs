@@ -0,0 +1,200 @@
+//! What a peer certificate says about the peer, parsed from the DER with
+//! x509-parser.
+
+use std::fmt;
+use std::net::IpAddr;
+
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::FromDer;
+use x509_parser::time::ASN1Time;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerIdentity {
+    /// Whole subject, e.g. `CN=client, OU=backend, O=edger`.
+    pub subject: String,
+    /// Every CN in the subject, usually one.
+    pub common_names: Vec<String>,
+    pub dns_names: Vec<String>,
+    pub ip_addresses: Vec<IpAddr>,
+    pub uris: Vec<String>,
+    pub emails: Vec<String>,
+    /// Hex bytes separated by `:`.
+    pub serial: String,
+    pub not_before: ASN1Time,
+    pub not_after: ASN1Time,
+    /// SHA-256 of the DER, `AB:CD:...`.
+    pub sha256: String,
+}
+
+impl PeerIdentity {
+    pub fn from_der(der: &[u8]) -> Result<Self, Error> {
+        let (_, cert) =
+            X509Certificate::from_der(der).map_err(|e| format!("invalid certificate: {}", e))?;
+
+        let common_names = cert
+            .subject()
+            .iter_common_name()
+            .map(|cn| cn.as_str().map(str::to_string))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("invalid CN: {}", e))?;
+
+        let mut identity = Self {
+            subject: cert.subject().to_string(),
+            common_names,
+            dns_names: Vec::new(),
+            ip_addresses: Vec::new(),
+            uris: Vec::new(),
+            emails: Vec::new(),
+            serial: cert.raw_serial_as_string(),
+            not_before: cert.validity().not_before,
+            not_after: cert.validity().not_after,
+            sha256: hex(ring::digest::digest(&ring::digest::SHA256, der).as_ref()),
+        };
+        let san = cert
+            .subject_alternative_name()
+            .map_err(|e| format!("invalid subjectAltName: {}", e))?;
+        for name in san.iter().flat_map(|san| &san.value.general_names) {
+            match name {
+                GeneralName::DNSName(dns) => identity.dns_names.push(dns.to_string()),
+                GeneralName::URI(uri) => identity.uris.push(uri.to_string()),
+                GeneralName::RFC822Name(email) => identity.emails.push(email.to_string()),
+                GeneralName::IPAddress(bytes) => {
+                    let ip = if let Ok(v4) = <[u8; 4]>::try_from(*bytes) {
+                        IpAddr::from(v4)
+                    } else if let Ok(v6) = <[u8; 16]>::try_from(*bytes) {
+                        IpAddr::from(v6)
+                    } else {
+                        return Err("invalid IP address in subjectAltName".into());
+                    };
+                    identity.ip_addresses.push(ip);
+                }
+                _ => {}
+            }
+        }
+        Ok(identity)
+    }
+
+    /// The first CN that one of `patterns` allows.
+    pub fn allowed_cn(&self, patterns: &[String]) -> Option<&str> {
+        self.common_names
+            .iter()
+            .find(|cn| patterns.iter().any(|pattern| cn_matches(pattern, cn)))
+            .map(String::as_str)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// `subject=CN=client serial=10:00 san=DNS:a.edger.dev,IP:127.0.0.1 valid=... sha256=AB:...`
+impl fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "subject={} serial={}", self.subject, self.serial)?;
+        let san: Vec<String> = self
+            .dns_names
+            .iter()
+            .map(|dns| format!("DNS:{}", dns))
+            .chain(self.ip_addresses.iter().map(|ip| format!("IP:{}", ip)))
+            .chain(self.uris.iter().map(|uri| format!("URI:{}", uri)))
+            .chain(self.emails.iter().map(|email| format!("email:{}", email)))
+            .collect();
+        if !san.is_empty() {
+            write!(f, " san={}", san.join(","))?;
+        }
+        write!(
+            f,
+            " valid={} ~ {} sha256={}",
+            self.not_before, self.not_after, self.sha256
+        )
+    }
+}
+
+/// `*` matches any run of characters, including none, so `client-*`
+/// matches `client-1` and `*` matches every CN. Everything else is exact.
+pub fn cn_matches(pattern: &str, cn: &str) -> bool {
+    let (pattern, cn) = (pattern.as_bytes(), cn.as_bytes());
+    // backtrack to the last `*`, letting it take one more character
+    let (mut p, mut c) = (0, 0);
+    let mut star = None;
+    while c < cn.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, c));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == cn[c] {
+            p += 1;
+            c += 1;
+        } else if let Some((star_p, star_c)) = star {
+            p = star_p + 1;
+            c = star_c + 1;
+            star = Some((star_p, star_c + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_generated_certificate() {
+        let mut params = rcgen::CertificateParams::new(vec!["client.edger.dev".to_string()]);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "echo-client");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "edger");
+        params
+            .subject_alt_names
+            .push(rcgen::SanType::IpAddress("127.0.0.1".parse().unwrap()));
+        params.serial_number = Some(rcgen::SerialNumber::from(0x1001u64));
+        params.not_after = rcgen::date_time_ymd(2031, 5, 6);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let der = cert.serialize_der().unwrap();
+
+        let identity = PeerIdentity::from_der(&der).unwrap();
+        assert_eq!(identity.common_names, ["echo-client"]);
+        assert!(identity.subject.contains("CN=echo-client"));
+        assert!(identity.subject.contains("O=edger"));
+        assert_eq!(identity.dns_names, ["client.edger.dev"]);
+        assert_eq!(identity.ip_addresses, [IpAddr::from([127, 0, 0, 1])]);
+        assert_eq!(identity.serial, "10:01");
+        assert_eq!(identity.sha256.len(), 32 * 3 - 1);
+        assert!(
+            identity
+                .to_string()
+                .contains("san=DNS:client.edger.dev,IP:127.0.0.1")
+        );
+
+        assert_eq!(
+            identity.allowed_cn(&["admin".into(), "echo-*".into()]),
+            Some("echo-client")
+        );
+        assert_eq!(identity.allowed_cn(&["echo".into()]), None);
+        assert!(PeerIdentity::from_der(b"not a certificate").is_err());
+    }
+
+    #[test]
+    fn wildcards_match_any_run() {
+        assert!(cn_matches("client", "client"));
+        assert!(!cn_matches("client", "client2"));
+        assert!(cn_matches("*", "anything"));
+        assert!(cn_matches("client-*", "client-"));
+        assert!(cn_matches("*.svc.edger.dev", "api.svc.edger.dev"));
+        assert!(cn_matches("a*b*c", "aXbYbZc"));
+        assert!(!cn_matches("a*b*c", "aXbYbZ"));
+        assert!(!cn_matches("*-admin", "ops-admin-2"));
+    }
+}
@@ -0,0 +1,3 @@
+//! Code shared by the rustls2 binaries.
+
+pub mod identity;
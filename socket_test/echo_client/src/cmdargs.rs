@@ -1,9 +1,38 @@
 use clap::Parser;
+use lib::queue::OverflowPolicy;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    /// address
+    /// address (host:port or unix:/path/to/sock)
     #[arg(short = 'a', long, default_value = "127.0.0.1:12345")]
     pub addr: String,
+
+    /// max number of messages kept while disconnected
+    #[arg(short = 'o', long, default_value_t = 256)]
+    pub outbox: usize,
+
+    /// what to do when the outbox is full (block, drop-oldest, error)
+    #[arg(long, default_value = "error")]
+    pub overflow: OverflowPolicy,
+
+    /// connect with TLS
+    #[arg(long)]
+    pub tls: bool,
+
+    /// root CA to verify the server with
+    #[arg(long, default_value = "certs/rootCA.pem")]
+    pub ca: String,
+
+    /// server name to verify (defaults to the host part of addr)
+    #[arg(long)]
+    pub server_name: Option<String>,
+
+    /// client certificate for mutual TLS
+    #[arg(long, requires = "key")]
+    pub cert: Option<String>,
+
+    /// client private key for mutual TLS
+    #[arg(long, requires = "cert")]
+    pub key: Option<String>,
 }
@@ -1,37 +1,102 @@
 mod cmdargs;
 
 use clap::Parser;
-use std::net::TcpStream;
 
 use cmdargs::Args;
-use lib::{stdinthread::StdinThread, streamthread::StreamThread};
+use lib::command::{Command, CommandRegistry, Dispatch, Flow};
+use lib::queue::QueueConfig;
+use lib::reconnect::{ConnectionState, Endpoint, ReconnectConfig, ReconnectingStream};
+use lib::stdinthread::StdinThread;
+use lib::tls::{TlsClientFiles, TlsConnector};
 
 fn main() {
     let _logger = lib::logger::start("debug", "", true);
     let args = Args::parse();
-    let stdin = StdinThread::new();
+
+    let mut commands = CommandRegistry::new();
+    commands.register(
+        Command::new("q", "quit").alias("quit"),
+        |stream: &mut ReconnectingStream, _| {
+            stream.stop();
+            Flow::Quit
+        },
+    );
+    let stdin = StdinThread::with_completions(commands.completions());
     println!("echo client start");
 
-    let stream = TcpStream::connect(args.addr).unwrap();
-    let mut stream_thread = StreamThread::new(stream);
+    let endpoint: Endpoint = args.addr.parse().unwrap();
+    let tls = if args.tls {
+        match tls_connector(&args) {
+            Ok(connector) => Some(connector),
+            Err(e) => {
+                log::error!("failed to load TLS config : {}", e);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let config = ReconnectConfig {
+        outbox: QueueConfig {
+            capacity: args.outbox,
+            policy: args.overflow,
+        },
+        tls,
+        ..Default::default()
+    };
+    let mut stream = match ReconnectingStream::new(endpoint, config) {
+        Ok(stream) => stream,
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
 
-    println!("/q : quit\n");
+    print!("{}", commands.help_text());
     loop {
         if let Some(cmd) = stdin.read_line() {
             println!("cmd from stdin: {}", cmd);
-            match cmd.as_str() {
-                "/q" => {
-                    stream_thread.stop();
-                    break;
+            match commands.dispatch(&mut stream, &cmd) {
+                Dispatch::Quit => break,
+                Dispatch::Continue => {}
+                Dispatch::Text(msg) => {
+                    stream.send(msg + "\n");
                 }
-                _ => {
-                    let msg = cmd + "\n";
-                    stream_thread.send(msg);
+            }
+        }
+        if let Some(state) = stream.state_changed() {
+            match state {
+                ConnectionState::Connecting { attempt } => {
+                    log::debug!("connecting... (attempt {})", attempt)
                 }
+                ConnectionState::Connected => println!("connected to server"),
+                ConnectionState::Disconnected { retry_in } => {
+                    println!("disconnected, retry in {:?}", retry_in)
+                }
+                ConnectionState::Stopped => break,
             }
         }
-        if let Some(msg) = stream_thread.recv() {
+        if let Some(msg) = stream.recv() {
             println!("recv msg from server : {}", msg);
         }
+        std::thread::sleep(std::time::Duration::from_millis(10));
     }
 }
+
+fn tls_connector(args: &Args) -> std::io::Result<TlsConnector> {
+    let config = TlsClientFiles {
+        ca: args.ca.clone().into(),
+        cert: args.cert.clone().map(Into::into),
+        key: args.key.clone().map(Into::into),
+    }
+    .load()?;
+
+    let server_name = match &args.server_name {
+        Some(name) => name.clone(),
+        None => match args.addr.rsplit_once(':') {
+            Some((host, _)) => host.trim_matches(['[', ']']).to_string(),
+            None => args.addr.clone(),
+        },
+    };
+    TlsConnector::new(config, &server_name)
+}
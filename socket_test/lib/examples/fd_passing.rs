@@ -0,0 +1,90 @@
+//! The server hands a listening TCP socket over to a child process, which
+//! then accepts and serves connections on it.
+//!
+//! cargo run --example fd_passing
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+
+use lib::logger;
+use lib::server::SingleUnixServer;
+use lib::streamthread::StreamThread;
+
+const SOCK_PATH: &str = "/tmp/fd_passing.sock";
+
+fn main() {
+    let _logger = logger::start("debug", "", true);
+
+    match std::env::args().nth(1).as_deref() {
+        Some("child") => child(),
+        _ => parent(),
+    }
+}
+
+fn parent() {
+    let mut server = SingleUnixServer::new(SOCK_PATH);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    log::info!("parent: listening on {}", addr);
+
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .arg("child")
+        .spawn()
+        .unwrap();
+
+    let mut fds = Some(vec![OwnedFd::from(listener)]);
+    loop {
+        if let Some(listener_fd) = fds.take() {
+            match server.send_with_fds(format!("listener {}", addr), listener_fd) {
+                Ok(()) => log::info!("parent: listener passed to child"),
+                Err(listener_fd) => fds = Some(listener_fd),
+            }
+        }
+        if let Some(msg) = server.recv() {
+            log::info!("parent: child says '{}'", msg);
+            if msg == "ready" {
+                break;
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    // the listener is now only open in the child
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"hello from parent\n").unwrap();
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).unwrap();
+    log::info!("parent: reply over tcp '{}'", reply.trim_end());
+
+    child.wait().unwrap();
+}
+
+fn child() {
+    let stream = UnixStream::connect(SOCK_PATH).unwrap();
+    let mut stream_thread = StreamThread::new(stream);
+
+    let listener = loop {
+        if let Some(packet) = stream_thread.recv_with_fds() {
+            log::info!("child: got '{}' with {} fds", packet.msg, packet.fds.len());
+            if let Some(fd) = packet.fds.into_iter().next() {
+                break TcpListener::from(fd);
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    };
+    stream_thread.send("ready".to_string());
+
+    let (stream, peer) = listener.accept().unwrap();
+    log::info!("child: accepted {}", peer);
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).unwrap();
+    (&stream)
+        .write_all(format!("child echo: {}", line).as_bytes())
+        .unwrap();
+
+    stream_thread.stop();
+}
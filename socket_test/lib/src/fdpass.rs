@@ -0,0 +1,204 @@
+//! File descriptor passing over Unix stream sockets (SCM_RIGHTS).
+//!
+//! Received descriptors are returned as `OwnedFd`, so they are closed when
+//! dropped unless the caller takes them over. They are also received with
+//! `MSG_CMSG_CLOEXEC` and do not leak into spawned processes.
+
+use std::{
+    io,
+    os::{
+        fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+};
+
+/// Max number of fds in one message (SCM_MAX_FD in the kernel).
+pub const MAX_FDS: usize = 253;
+
+fn cmsg_space(fd_count: usize) -> usize {
+    // SAFETY: CMSG_SPACE is a pure size calculation.
+    unsafe { libc::CMSG_SPACE((fd_count * std::mem::size_of::<RawFd>()) as u32) as usize }
+}
+
+/// Control message buffer, aligned for `cmsghdr`.
+fn cmsg_buffer(fd_count: usize) -> Vec<u64> {
+    let len = cmsg_space(fd_count);
+    vec![0u64; len.div_ceil(std::mem::size_of::<u64>())]
+}
+
+/// Send `data` with `fds` attached. The fds are attached to the first byte,
+/// so callers should write the rest with `write_all` if this returns a short count.
+pub fn send_with_fds(sock: &UnixStream, data: &[u8], fds: &[BorrowedFd]) -> io::Result<usize> {
+    if data.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "at least one byte must be sent with fds",
+        ));
+    }
+    if fds.len() > MAX_FDS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("too many fds: {} > {}", fds.len(), MAX_FDS),
+        ));
+    }
+
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // SAFETY: an all-zero msghdr is a valid empty header.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    let mut cmsg_buf = cmsg_buffer(fds.len());
+    if !fds.is_empty() {
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = cmsg_space(fds.len()) as _;
+
+        // SAFETY: msg_control points to a buffer of msg_controllen bytes that
+        // is large enough for one cmsghdr carrying `fds.len()` descriptors.
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len =
+                libc::CMSG_LEN((fds.len() * std::mem::size_of::<RawFd>()) as u32) as _;
+            let data = libc::CMSG_DATA(cmsg) as *mut RawFd;
+            for (i, fd) in fds.iter().enumerate() {
+                std::ptr::write_unaligned(data.add(i), fd.as_raw_fd());
+            }
+        }
+    }
+
+    // SAFETY: msg and everything it points to outlive the call.
+    let ret = unsafe { libc::sendmsg(sock.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+/// Receive into `buf` and append up to `max_fds` received descriptors to `fds`.
+///
+/// If the sender attached more than `max_fds` descriptors, the descriptors that did arrive are closed and an
+/// `InvalidData` error is returned, since the message can't be trusted as a whole.
+pub fn recv_with_fds(
+    sock: &UnixStream,
+    buf: &mut [u8],
+    max_fds: usize,
+    fds: &mut Vec<OwnedFd>,
+) -> io::Result<usize> {
+    let max_fds = max_fds.min(MAX_FDS);
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // SAFETY: an all-zero msghdr is a valid empty header.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    let mut cmsg_buf = cmsg_buffer(max_fds.max(1));
+    msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = cmsg_space(max_fds.max(1)) as _;
+
+    // SAFETY: msg and everything it points to outlive the call.
+    let ret = unsafe { libc::recvmsg(sock.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut received = Vec::new();
+    // SAFETY: the kernel filled msg_control with msg_controllen bytes of
+    // well-formed cmsghdrs; every SCM_RIGHTS entry holds fds we now own.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+                for i in 0..len / std::mem::size_of::<RawFd>() {
+                    let fd = std::ptr::read_unaligned(data.add(i));
+                    received.push(OwnedFd::from_raw_fd(fd));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    // the control buffer is rounded up for alignment and may hold one more fd than asked for
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 || received.len() > max_fds {
+        log::error!(
+            "control message truncated, closing {} received fds",
+            received.len()
+        );
+        drop(received);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("control message truncated (more than {} fds)", max_fds),
+        ));
+    }
+
+    fds.append(&mut received);
+    Ok(ret as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, Write};
+    use std::os::fd::AsFd;
+
+    #[test]
+    fn pass_file_over_socketpair() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        let mut file = std::fs::File::open("/proc/self/stat").unwrap();
+        let mut expected = String::new();
+        file.read_to_string(&mut expected).unwrap();
+        file.rewind().unwrap();
+
+        let n = send_with_fds(&a, b"file\n", &[file.as_fd()]).unwrap();
+        assert_eq!(n, 5);
+
+        let mut buf = [0u8; 16];
+        let mut fds = Vec::new();
+        let n = recv_with_fds(&b, &mut buf, 4, &mut fds).unwrap();
+        assert_eq!(&buf[..n], b"file\n");
+        assert_eq!(fds.len(), 1);
+
+        let mut received = std::fs::File::from(fds.pop().unwrap());
+        let mut content = String::new();
+        received.read_to_string(&mut content).unwrap();
+        assert_eq!(content.split(' ').next(), expected.split(' ').next());
+    }
+
+    #[test]
+    fn too_many_fds_is_an_error() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (x, y) = UnixStream::pair().unwrap();
+        let (z, _w) = UnixStream::pair().unwrap();
+
+        send_with_fds(&a, b"x", &[x.as_fd(), y.as_fd(), z.as_fd()]).unwrap();
+
+        let mut buf = [0u8; 16];
+        let mut fds = Vec::new();
+        let err = recv_with_fds(&b, &mut buf, 1, &mut fds).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(fds.is_empty());
+
+        // the stream is still usable afterwards
+        (&a).write_all(b"y").unwrap();
+        let n = recv_with_fds(&b, &mut buf, 1, &mut fds).unwrap();
+        assert_eq!(&buf[..n], b"y");
+    }
+
+    #[test]
+    fn empty_data_is_rejected() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let err = send_with_fds(&a, b"", &[a.as_fd()]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
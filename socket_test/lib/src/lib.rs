@@ -1,4 +1,12 @@
-pub mod logger;
+pub mod fdpass;
+pub mod peer;
+pub mod queue;
+pub mod reconnect;
 pub mod server;
-pub mod stdinthread;
 pub mod streamthread;
+pub mod systemd;
+pub mod tls;
+pub mod unixsock;
+
+pub use console::{command, stdinthread};
+pub use logger;
@@ -0,0 +1,147 @@
+use std::{fmt, io, os::fd::AsRawFd, path::PathBuf};
+
+/// Address of the remote end of an accepted stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerAddr {
+    Inet(std::net::SocketAddr),
+    UnixPath(PathBuf),
+    /// Linux abstract namespace name, without the leading NUL byte.
+    UnixAbstract(Vec<u8>),
+    /// Unix socket peer that did not bind an address (the usual client case).
+    Unnamed,
+}
+
+impl From<std::net::SocketAddr> for PeerAddr {
+    fn from(addr: std::net::SocketAddr) -> Self {
+        PeerAddr::Inet(addr)
+    }
+}
+
+impl From<std::os::unix::net::SocketAddr> for PeerAddr {
+    fn from(addr: std::os::unix::net::SocketAddr) -> Self {
+        use std::os::linux::net::SocketAddrExt;
+
+        if let Some(path) = addr.as_pathname() {
+            PeerAddr::UnixPath(path.to_path_buf())
+        } else if let Some(name) = addr.as_abstract_name() {
+            PeerAddr::UnixAbstract(name.to_vec())
+        } else {
+            PeerAddr::Unnamed
+        }
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Inet(addr) => write!(f, "{}", addr),
+            PeerAddr::UnixPath(path) => write!(f, "unix:{}", path.display()),
+            PeerAddr::UnixAbstract(name) => {
+                write!(f, "unix:@{}", String::from_utf8_lossy(name))
+            }
+            PeerAddr::Unnamed => write!(f, "unix:(unnamed)"),
+        }
+    }
+}
+
+/// Credentials of the process on the other end of a Unix socket, as
+/// reported by the kernel at `connect()` time (SO_PEERCRED).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+impl PeerCred {
+    pub fn from_socket(socket: &impl AsRawFd) -> io::Result<Self> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+        // SAFETY: `cred` and `len` are valid for writes and `len` holds the
+        // size of `cred`, as getsockopt(SO_PEERCRED) requires.
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            uid: cred.uid,
+            gid: cred.gid,
+            // pid 0 means the peer was in another pid namespace
+            pid: if cred.pid > 0 { Some(cred.pid) } else { None },
+        })
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+}
+
+impl fmt::Display for PeerCred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "uid={} gid={}", self.uid, self.gid)?;
+        if let Some(pid) = self.pid {
+            write!(f, " pid={}", pid)?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything known about the peer of the current connection.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub addr: PeerAddr,
+    pub cred: Option<PeerCred>,
+}
+
+impl fmt::Display for PeerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.cred {
+            Some(cred) => write!(f, "{} ({})", self.addr, cred),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn peer_cred_of_socketpair_is_own_process() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let cred = PeerCred::from_socket(&a).unwrap();
+
+        assert_eq!(cred.uid, unsafe { libc::getuid() });
+        assert_eq!(cred.gid, unsafe { libc::getgid() });
+        assert_eq!(cred.pid, Some(std::process::id() as i32));
+    }
+
+    #[test]
+    fn unix_addr_kinds() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        assert_eq!(PeerAddr::from(a.peer_addr().unwrap()), PeerAddr::Unnamed);
+
+        use std::os::linux::net::SocketAddrExt;
+        let name = format!("peer-test-{}", std::process::id());
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        assert_eq!(
+            PeerAddr::from(addr),
+            PeerAddr::UnixAbstract(name.as_bytes().to_vec())
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Bounded packet queues between a stream loop and the app.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::{Condvar, Mutex},
+};
+
+/// What to do when a message is pushed onto a full queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Wait for room. On the receive side the stream stops reading, so the
+    /// peer is slowed down by TCP flow control.
+    #[default]
+    Block,
+    /// Make room by dropping the oldest queued message.
+    DropOldest,
+    /// Refuse the new message.
+    Error,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "error" => Ok(OverflowPolicy::Error),
+            _ => Err(format!(
+                "unknown overflow policy '{}' (block, drop-oldest, error)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            policy: OverflowPolicy::Block,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueStats {
+    /// Messages currently queued.
+    pub depth: usize,
+    pub capacity: usize,
+    /// Messages dropped to make room (`DropOldest`).
+    pub dropped: u64,
+    /// Messages refused because the queue was full (`Error`).
+    pub rejected: u64,
+}
+
+/// Returned with the message that could not be queued.
+#[derive(Debug)]
+pub enum PushError<T> {
+    Full(T),
+    /// The stream is gone.
+    Closed(T),
+}
+
+impl<T> PushError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            PushError::Full(item) | PushError::Closed(item) => item,
+        }
+    }
+}
+
+impl<T> fmt::Display for PushError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PushError::Full(_) => write!(f, "queue is full"),
+            PushError::Closed(_) => write!(f, "queue is closed"),
+        }
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for PushError<T> {}
+
+struct Inner<T> {
+    items: VecDeque<T>,
+    dropped: u64,
+    rejected: u64,
+    closed: bool,
+}
+
+pub(crate) struct BoundedQueue<T> {
+    inner: Mutex<Inner<T>>,
+    not_full: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(config: &QueueConfig) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                items: VecDeque::new(),
+                dropped: 0,
+                rejected: 0,
+                closed: false,
+            }),
+            not_full: Condvar::new(),
+            capacity: config.capacity.max(1),
+            policy: config.policy,
+        }
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    /// Queue `item`, waiting for room under the `Block` policy.
+    pub fn push(&self, item: T) -> Result<(), PushError<T>> {
+        let mut inner = self.inner.lock().unwrap();
+        if self.policy == OverflowPolicy::Block {
+            while inner.items.len() >= self.capacity && !inner.closed {
+                inner = self.not_full.wait(inner).unwrap();
+            }
+        }
+        self.push_locked(&mut inner, item)
+    }
+
+    /// Like `push`, but a full `Block` queue returns `Full` instead of waiting.
+    pub fn offer(&self, item: T) -> Result<(), PushError<T>> {
+        let mut inner = self.inner.lock().unwrap();
+        if self.policy == OverflowPolicy::Block && inner.items.len() >= self.capacity {
+            return Err(PushError::Full(item));
+        }
+        self.push_locked(&mut inner, item)
+    }
+
+    fn push_locked(&self, inner: &mut Inner<T>, item: T) -> Result<(), PushError<T>> {
+        if inner.closed {
+            return Err(PushError::Closed(item));
+        }
+        if inner.items.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropOldest => {
+                    inner.items.pop_front();
+                    inner.dropped += 1;
+                }
+                OverflowPolicy::Error => {
+                    inner.rejected += 1;
+                    return Err(PushError::Full(item));
+                }
+                OverflowPolicy::Block => unreachable!("checked by the caller"),
+            }
+        }
+        inner.items.push_back(item);
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.pop_if(|_| true)
+    }
+
+    /// Pop the front item only if `f` accepts it.
+    pub fn pop_if(&self, f: impl FnOnce(&T) -> bool) -> Option<T> {
+        let mut inner = self.inner.lock().unwrap();
+        let item = match inner.items.front() {
+            Some(front) if f(front) => inner.items.pop_front(),
+            _ => None,
+        };
+        if item.is_some() {
+            self.not_full.notify_one();
+        }
+        item
+    }
+
+    /// Refuse further pushes and wake up blocked senders. Queued items can
+    /// still be popped.
+    pub fn close(&self) {
+        self.inner.lock().unwrap().closed = true;
+        self.not_full.notify_all();
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let inner = self.inner.lock().unwrap();
+        QueueStats {
+            depth: inner.items.len(),
+            capacity: self.capacity,
+            dropped: inner.dropped,
+            rejected: inner.rejected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn queue(capacity: usize, policy: OverflowPolicy) -> BoundedQueue<u32> {
+        BoundedQueue::new(&QueueConfig { capacity, policy })
+    }
+
+    #[test]
+    fn drop_oldest_and_error_policies() {
+        let q = queue(2, OverflowPolicy::DropOldest);
+        for i in 0..4 {
+            q.push(i).unwrap();
+        }
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.pop(), Some(3));
+        assert_eq!(q.stats().dropped, 2);
+
+        let q = queue(1, OverflowPolicy::Error);
+        q.push(1).unwrap();
+        assert!(matches!(q.push(2), Err(PushError::Full(2))));
+        assert_eq!(
+            q.stats(),
+            QueueStats {
+                depth: 1,
+                capacity: 1,
+                dropped: 0,
+                rejected: 1
+            }
+        );
+    }
+
+    #[test]
+    fn block_waits_for_room_or_close() {
+        let q = Arc::new(queue(1, OverflowPolicy::Block));
+        q.push(1).unwrap();
+        assert!(matches!(q.offer(2), Err(PushError::Full(2))));
+
+        let pusher = {
+            let q = q.clone();
+            std::thread::spawn(move || q.push(2).is_ok())
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(q.pop(), Some(1));
+        assert!(pusher.join().unwrap());
+
+        let pusher = {
+            let q = q.clone();
+            std::thread::spawn(move || q.push(3))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        q.close();
+        assert!(matches!(pusher.join().unwrap(), Err(PushError::Closed(3))));
+        assert_eq!(q.pop(), Some(2));
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    io,
+    net::TcpStream,
+    str::FromStr,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use rand::Rng;
+
+use crate::queue::{OverflowPolicy, QueueConfig};
+use crate::streamthread::{Packet, Queues, StreamConfig, StreamStats, StreamThread};
+use crate::tls::TlsConnector;
+use crate::unixsock::UnixAddr;
+
+/// Where a client stream connects to.
+///
+/// Parsed from `unix:/path/to/sock` or `unix:@name` for Unix sockets,
+/// anything else is treated as a TCP `host:port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(String),
+    Unix(UnixAddr),
+}
+
+impl FromStr for Endpoint {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.strip_prefix("unix:") {
+            Some(addr) => Endpoint::Unix(addr.parse()?),
+            None => Endpoint::Tcp(s.to_string()),
+        })
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{}", addr),
+            Endpoint::Unix(addr) => write!(f, "unix:{}", addr),
+        }
+    }
+}
+
+/// Exponential backoff with random jitter.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    /// Fraction of the delay (0.0 ~ 1.0) that is randomized.
+    pub jitter: f64,
+    attempt: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            multiplier: 2.0,
+            jitter: 0.2,
+            attempt: 0,
+        }
+    }
+
+    /// Delay before the next attempt, without jitter.
+    fn base_delay(&self) -> Duration {
+        let factor = self.multiplier.powi(self.attempt.min(32) as i32);
+        self.initial.mul_f64(factor).min(self.max)
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let base = self.base_delay();
+        self.attempt = self.attempt.saturating_add(1);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return base;
+        }
+        let scale = rand::thread_rng().gen_range((1.0 - jitter)..=1.0);
+        base.mul_f64(scale)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    pub backoff: Backoff,
+    /// Messages kept while disconnected. By default new messages are refused when full.
+    pub outbox: QueueConfig,
+    pub inbox: QueueConfig,
+    /// Wrap TCP connections in TLS. Not supported for Unix endpoints.
+    pub tls: Option<TlsConnector>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::default(),
+            outbox: QueueConfig {
+                capacity: 256,
+                policy: OverflowPolicy::Error,
+            },
+            inbox: QueueConfig::default(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting { attempt: u32 },
+    Connected,
+    Disconnected { retry_in: Duration },
+    Stopped,
+}
+
+/// Client stream that keeps reconnecting to `endpoint` until stopped.
+///
+/// Messages sent while disconnected wait in a bounded outbox and are written
+/// once the connection is re-established.
+pub struct ReconnectingStream {
+    queues: Arc<Queues>,
+    state_rx: Receiver<ConnectionState>,
+    exit_flag: Arc<Mutex<bool>>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl ReconnectingStream {
+    /// Fails with `InvalidInput` if TLS is asked for on a Unix endpoint.
+    pub fn new(endpoint: Endpoint, config: ReconnectConfig) -> io::Result<Self> {
+        if let (Endpoint::Unix(addr), Some(_)) = (&endpoint, &config.tls) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("TLS is not supported over unix socket {}", addr),
+            ));
+        }
+        let queues = Queues::new(&StreamConfig {
+            outbox: config.outbox.clone(),
+            inbox: config.inbox.clone(),
+        });
+        let queues_clone = queues.clone();
+        let (state_tx, state_rx) = std::sync::mpsc::channel();
+
+        let exit_flag = Arc::new(Mutex::new(false));
+        let exit_flag_clone = exit_flag.clone();
+
+        let handle = std::thread::spawn(move || {
+            log::debug!("reconnect loop start : {}", endpoint);
+            Self::reconnect_loop(
+                &endpoint,
+                config.backoff,
+                config.tls.as_ref(),
+                &queues_clone,
+                &state_tx,
+                &exit_flag_clone,
+            );
+            queues_clone.outbox.close();
+            let _ = state_tx.send(ConnectionState::Stopped);
+            log::debug!("reconnect loop end : {}", endpoint);
+        });
+
+        Ok(Self {
+            queues,
+            state_rx,
+            exit_flag,
+            handle: Some(handle),
+        })
+    }
+
+    fn reconnect_loop(
+        endpoint: &Endpoint,
+        mut backoff: Backoff,
+        tls: Option<&TlsConnector>,
+        queues: &Queues,
+        state_tx: &Sender<ConnectionState>,
+        exit_flag: &Arc<Mutex<bool>>,
+    ) {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let _ = state_tx.send(ConnectionState::Connecting { attempt });
+
+            let exit = match (endpoint, tls) {
+                (Endpoint::Tcp(addr), Some(tls)) => tls.connect(addr).map(|stream| {
+                    Self::on_connected(state_tx, &mut backoff, &mut attempt);
+                    StreamThread::stream_loop(stream, queues, exit_flag)
+                }),
+                (Endpoint::Tcp(addr), None) => TcpStream::connect(addr).map(|stream| {
+                    Self::on_connected(state_tx, &mut backoff, &mut attempt);
+                    StreamThread::stream_loop(stream, queues, exit_flag)
+                }),
+                (Endpoint::Unix(addr), None) => addr.connect().map(|stream| {
+                    Self::on_connected(state_tx, &mut backoff, &mut attempt);
+                    StreamThread::stream_loop(stream, queues, exit_flag)
+                }),
+                (Endpoint::Unix(_), Some(_)) => unreachable!("refused in new"),
+            };
+
+            match exit {
+                Ok(true) => return,
+                Ok(false) => log::info!("disconnected from {}", endpoint),
+                Err(e) => log::debug!("failed to connect to {} : {}", endpoint, e),
+            }
+
+            let retry_in = backoff.next_delay();
+            let _ = state_tx.send(ConnectionState::Disconnected { retry_in });
+            if Self::sleep_unless_stopped(retry_in, exit_flag) {
+                return;
+            }
+        }
+    }
+
+    fn on_connected(state_tx: &Sender<ConnectionState>, backoff: &mut Backoff, attempt: &mut u32) {
+        log::info!("connected");
+        backoff.reset();
+        *attempt = 0;
+        let _ = state_tx.send(ConnectionState::Connected);
+    }
+
+    /// Returns true if the exit flag was raised while sleeping.
+    fn sleep_unless_stopped(duration: Duration, exit_flag: &Arc<Mutex<bool>>) -> bool {
+        let step = Duration::from_millis(100);
+        let mut remaining = duration;
+        while !remaining.is_zero() {
+            if *exit_flag.lock().unwrap() {
+                return true;
+            }
+            let d = remaining.min(step);
+            std::thread::sleep(d);
+            remaining -= d;
+        }
+        *exit_flag.lock().unwrap()
+    }
+
+    /// Queue a message. Returns false if the outbox refused it.
+    pub fn send(&self, msg: String) -> bool {
+        log::debug!("send: {}", msg);
+        self.queues.send(Packet::from(msg))
+    }
+
+    pub fn recv(&self) -> Option<String> {
+        self.queues.inbox.pop().map(|packet| packet.msg)
+    }
+
+    /// Queue depths and drop counters.
+    pub fn stats(&self) -> StreamStats {
+        self.queues.stats()
+    }
+
+    /// Next connection state change, if any.
+    pub fn state_changed(&self) -> Option<ConnectionState> {
+        self.state_rx.try_recv().ok()
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            log::debug!("stop");
+            *self.exit_flag.lock().unwrap() = true;
+            if let Err(e) = handle.join() {
+                log::error!("Failed to join thread: {:?}", e);
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match &self.handle {
+            Some(handle) => handle.is_finished(),
+            None => true,
+        }
+    }
+}
+
+impl Drop for ReconnectingStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(1000));
+        backoff.jitter = 0.0;
+
+        let delays: Vec<_> = (0..6).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_in_range() {
+        let mut backoff = Backoff::new(Duration::from_millis(1000), Duration::from_secs(10));
+        backoff.jitter = 0.5;
+        for _ in 0..20 {
+            backoff.reset();
+            let d = backoff.next_delay();
+            assert!(d >= Duration::from_millis(500) && d <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn endpoint_parse() {
+        assert_eq!(
+            "unix:/tmp/echo.sock".parse::<Endpoint>().unwrap(),
+            Endpoint::Unix(UnixAddr::Path("/tmp/echo.sock".into()))
+        );
+        assert_eq!(
+            "127.0.0.1:1".parse::<Endpoint>().unwrap(),
+            Endpoint::Tcp("127.0.0.1:1".into())
+        );
+    }
+
+    #[test]
+    fn tls_over_unix_is_refused() {
+        let config = ReconnectConfig {
+            tls: Some(
+                TlsConnector::new(
+                    Arc::new(
+                        rustls::ClientConfig::builder()
+                            .with_safe_defaults()
+                            .with_root_certificates(rustls::RootCertStore::empty())
+                            .with_no_client_auth(),
+                    ),
+                    "localhost",
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let endpoint = "unix:@reconnect-tls".parse().unwrap();
+        let err = ReconnectingStream::new(endpoint, config).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        for _ in 0..100 {
+            if let Some(v) = f() {
+                return v;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("timed out");
+    }
+
+    #[test]
+    fn outbox_is_flushed_after_server_restart() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let config = ReconnectConfig {
+            backoff: Backoff::new(Duration::from_millis(50), Duration::from_millis(200)),
+            outbox: QueueConfig {
+                capacity: 2,
+                policy: OverflowPolicy::Error,
+            },
+            ..Default::default()
+        };
+        let mut client = ReconnectingStream::new(Endpoint::Tcp(addr.to_string()), config).unwrap();
+
+        // first connection is accepted and closed right away
+        let (first, _) = listener.accept().unwrap();
+        wait_for(|| match client.state_changed() {
+            Some(ConnectionState::Connected) => Some(()),
+            _ => None,
+        });
+        drop(first);
+        drop(listener);
+        wait_for(|| match client.state_changed() {
+            Some(ConnectionState::Disconnected { .. }) => Some(()),
+            _ => None,
+        });
+
+        assert!(client.send("one".into()));
+        assert!(client.send("two".into()));
+        assert!(!client.send("three".into()));
+        assert_eq!(client.stats().outbox.rejected, 1);
+
+        let listener = TcpListener::bind(addr).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "one\n");
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "two\n");
+
+        (&stream).write_all(b"hello\n").unwrap();
+        assert_eq!(wait_for(|| client.recv()), "hello");
+
+        client.stop();
+        assert!(client.is_finished());
+    }
+}
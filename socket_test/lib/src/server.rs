@@ -1,10 +1,16 @@
-use std::{net::TcpListener, os::unix::net::UnixListener};
+use std::{
+    net::TcpListener,
+    os::{fd::OwnedFd, unix::net::UnixListener},
+};
 
-use crate::streamthread::{NonblockingStream, StreamThread};
+use crate::peer::{PeerAddr, PeerCred, PeerInfo};
+use crate::streamthread::{NonblockingStream, Packet, StreamConfig, StreamStats, StreamThread};
+use crate::systemd;
+use crate::unixsock::{UnixAddr, UnixSocketListener};
 
 use std::io;
 
-pub type SingleUnixServer = SingleServer<UnixListener>;
+pub type SingleUnixServer = SingleServer<UnixSocketListener>;
 pub type SingleTcpServer = SingleServer<TcpListener>;
 
 pub trait StreamListener {
@@ -13,7 +19,15 @@ pub trait StreamListener {
     fn bind(addr: &str) -> io::Result<Self>
     where
         Self: Sized;
-    fn accept(&self) -> io::Result<(Self::Stream, std::net::SocketAddr)>;
+
+    /// Socket for `addr` passed by systemd socket activation, if any.
+    fn activated(_addr: &str) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+    fn accept(&self) -> io::Result<(Self::Stream, PeerAddr)>;
     fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
 }
 
@@ -21,19 +35,16 @@ impl StreamListener for UnixListener {
     type Stream = std::os::unix::net::UnixStream;
 
     fn bind(addr: &str) -> io::Result<Self> {
-        UnixListener::bind(addr)
+        addr.parse::<UnixAddr>().unwrap().bind()
     }
 
-    fn accept(&self) -> io::Result<(Self::Stream, std::net::SocketAddr)> {
-        self.accept().map(|(stream, _)| {
-            (
-                stream,
-                std::net::SocketAddr::new(
-                    std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
-                    0,
-                ),
-            )
-        })
+    fn activated(addr: &str) -> Option<Self> {
+        systemd::take_unix_listener(&addr.parse::<UnixAddr>().unwrap())
+    }
+
+    fn accept(&self) -> io::Result<(Self::Stream, PeerAddr)> {
+        self.accept()
+            .map(|(stream, addr)| (stream, PeerAddr::from(addr)))
     }
 
     fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
@@ -48,8 +59,13 @@ impl StreamListener for TcpListener {
         TcpListener::bind(addr)
     }
 
-    fn accept(&self) -> io::Result<(Self::Stream, std::net::SocketAddr)> {
+    fn activated(addr: &str) -> Option<Self> {
+        systemd::take_tcp_listener(addr)
+    }
+
+    fn accept(&self) -> io::Result<(Self::Stream, PeerAddr)> {
         self.accept()
+            .map(|(stream, addr)| (stream, PeerAddr::from(addr)))
     }
 
     fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
@@ -60,38 +76,81 @@ impl StreamListener for TcpListener {
 pub struct SingleServer<L: StreamListener + 'static> {
     listener: L,
     stream_thread: Option<StreamThread>,
+    peer: Option<PeerInfo>,
+    stream_config: StreamConfig,
 }
 
 impl<L: StreamListener> SingleServer<L> {
+    /// Serve on the socket systemd passed for `addr`, or bind `addr`.
     pub fn new(addr: &str) -> Self {
-        let listener = L::bind(addr).unwrap();
+        let listener = match L::activated(addr) {
+            Some(listener) => {
+                log::info!("use activated socket {}", addr);
+                listener
+            }
+            None => L::bind(addr).unwrap(),
+        };
+        Self::with_listener(listener)
+    }
+
+    /// Serve on a listener that was set up by the caller.
+    pub fn with_listener(listener: L) -> Self {
         listener.set_nonblocking(true).unwrap();
         Self {
             listener,
             stream_thread: None,
+            peer: None,
+            stream_config: StreamConfig::default(),
         }
     }
 
+    /// Queue settings for the streams accepted from now on.
+    pub fn set_stream_config(&mut self, config: StreamConfig) {
+        self.stream_config = config;
+    }
+
     fn check_incoming(&mut self) {
         if self.stream_thread.is_none() {
-            if let Ok((stream, _)) = self.listener.accept() {
-                log::info!("accept new stream");
-                self.stream_thread = Some(StreamThread::new(stream));
+            if let Ok((stream, addr)) = self.listener.accept() {
+                let peer = PeerInfo {
+                    addr,
+                    cred: stream.peer_cred(),
+                };
+                log::info!("accept new stream from {}", peer);
+                self.stream_thread = Some(StreamThread::with_config(stream, &self.stream_config));
+                self.peer = Some(peer);
             }
         }
     }
 
-    pub fn recv(&mut self) -> Option<String> {
+    fn close_stream(&mut self) {
+        self.stream_thread = None;
+        self.peer = None;
+        log::info!("stream thread is finished");
+    }
+
+    /// Peer of the current connection, if any.
+    pub fn peer(&self) -> Option<&PeerInfo> {
+        self.peer.as_ref()
+    }
+
+    /// Credentials of the current peer (Unix sockets only).
+    pub fn peer_cred(&self) -> Option<PeerCred> {
+        self.peer.as_ref().and_then(|peer| peer.cred)
+    }
+
+    /// Queue stats of the current connection.
+    pub fn stats(&self) -> Option<StreamStats> {
+        self.stream_thread.as_ref().map(|worker| worker.stats())
+    }
+
+    fn active_worker(&mut self) -> Option<&StreamThread> {
         match self.stream_thread {
-            Some(ref worker) => {
-                if worker.is_finished() {
-                    self.stream_thread = None;
-                    log::info!("stream thread is finished");
-                    None
-                } else {
-                    worker.recv()
-                }
+            Some(ref worker) if worker.is_finished() => {
+                self.close_stream();
+                None
             }
+            Some(ref worker) => Some(worker),
             None => {
                 self.check_incoming();
                 None
@@ -99,19 +158,32 @@ impl<L: StreamListener> SingleServer<L> {
         }
     }
 
+    pub fn recv(&mut self) -> Option<String> {
+        self.active_worker().and_then(|worker| worker.recv())
+    }
+
+    /// Receive a message together with any fds the peer passed (Unix sockets only).
+    pub fn recv_with_fds(&mut self) -> Option<Packet> {
+        self.active_worker()
+            .and_then(|worker| worker.recv_with_fds())
+    }
+
     pub fn send(&mut self, msg: String) {
-        match self.stream_thread {
-            Some(ref worker) => {
-                if worker.is_finished() {
-                    self.stream_thread = None;
-                    log::info!("stream thread is finished");
-                } else {
-                    worker.send(msg);
-                }
-            }
-            None => {
-                self.check_incoming();
+        if let Some(worker) = self.active_worker() {
+            worker.send(msg);
+        }
+    }
+
+    /// Send `msg` with `fds` to the current peer (Unix sockets only).
+    ///
+    /// Returns the fds back if there is no connected peer.
+    pub fn send_with_fds(&mut self, msg: String, fds: Vec<OwnedFd>) -> Result<(), Vec<OwnedFd>> {
+        match self.active_worker() {
+            Some(worker) => {
+                worker.send_with_fds(msg, fds);
+                Ok(())
             }
+            None => Err(fds),
         }
     }
 }
@@ -1,20 +1,90 @@
 use std::{
+    collections::VecDeque,
     io::{Read, Write},
-    os::unix::net::UnixListener,
-    sync::{
-        mpsc::{Receiver, Sender},
-        Arc, Mutex,
+    os::{
+        fd::{AsFd, BorrowedFd, OwnedFd},
+        unix::net::UnixListener,
     },
+    sync::{Arc, Mutex},
 };
 
+use crate::fdpass;
+use crate::peer::PeerCred;
+use crate::queue::{BoundedQueue, OverflowPolicy, PushError, QueueConfig, QueueStats};
+use crate::systemd;
+use crate::unixsock::UnixAddr;
+
+/// Max number of fds accepted with a single message. A peer sending more is disconnected.
+pub const MAX_FDS_PER_MSG: usize = 16;
+
+/// One line of text and the file descriptors that came with it.
+///
+/// Fds can only travel over Unix sockets. Dropping a packet closes its fds.
+#[derive(Debug, Default)]
+pub struct Packet {
+    pub msg: String,
+    pub fds: Vec<OwnedFd>,
+}
+
+impl From<String> for Packet {
+    fn from(msg: String) -> Self {
+        Self {
+            msg,
+            fds: Vec::new(),
+        }
+    }
+}
+
 pub trait NonblockingStream: Read + Write + Send {
     fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()>;
+
+    /// Credentials of the peer process, only available for Unix sockets.
+    fn peer_cred(&self) -> Option<PeerCred> {
+        None
+    }
+
+    /// Write `buf` with `fds` attached to its first byte.
+    fn write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd]) -> std::io::Result<usize> {
+        if !fds.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "fd passing needs a Unix socket",
+            ));
+        }
+        self.write(buf)
+    }
+
+    /// Read into `buf`, appending any received fds to `fds`.
+    fn read_with_fds(&mut self, buf: &mut [u8], _fds: &mut Vec<OwnedFd>) -> std::io::Result<usize> {
+        self.read(buf)
+    }
 }
 
 impl NonblockingStream for std::os::unix::net::UnixStream {
     fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
         self.set_nonblocking(nonblocking)
     }
+
+    fn peer_cred(&self) -> Option<PeerCred> {
+        match PeerCred::from_socket(self) {
+            Ok(cred) => Some(cred),
+            Err(e) => {
+                log::error!("Failed to get peer credentials: {}", e);
+                None
+            }
+        }
+    }
+
+    fn write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd]) -> std::io::Result<usize> {
+        if fds.is_empty() {
+            return self.write(buf);
+        }
+        fdpass::send_with_fds(self, buf, fds)
+    }
+
+    fn read_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> std::io::Result<usize> {
+        fdpass::recv_with_fds(self, buf, MAX_FDS_PER_MSG, fds)
+    }
 }
 
 impl NonblockingStream for std::net::TcpStream {
@@ -23,29 +93,153 @@ impl NonblockingStream for std::net::TcpStream {
     }
 }
 
+/// Upper bound for the bytes gathered into one write.
+const MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// Reads done per loop iteration before giving the writer a turn.
+const MAX_READS_PER_ITERATION: usize = 16;
+
+/// Sleep when an iteration neither read nor wrote anything.
+const IDLE_SLEEP: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Queue settings for both directions of a stream.
+#[derive(Debug, Clone, Default)]
+pub struct StreamConfig {
+    /// Messages waiting to be written to the peer.
+    pub outbox: QueueConfig,
+    /// Messages read from the peer, waiting for the app.
+    pub inbox: QueueConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamStats {
+    pub outbox: QueueStats,
+    pub inbox: QueueStats,
+}
+
+/// Queues shared between the app and the stream loop.
+pub(crate) struct Queues {
+    pub outbox: BoundedQueue<Packet>,
+    pub inbox: BoundedQueue<Packet>,
+}
+
+impl Queues {
+    pub fn new(config: &StreamConfig) -> Arc<Self> {
+        Arc::new(Self {
+            outbox: BoundedQueue::new(&config.outbox),
+            inbox: BoundedQueue::new(&config.inbox),
+        })
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        StreamStats {
+            outbox: self.outbox.stats(),
+            inbox: self.inbox.stats(),
+        }
+    }
+
+    /// Queue a packet for the peer, logging why it was not queued.
+    pub fn send(&self, packet: Packet) -> bool {
+        match self.outbox.push(packet) {
+            Ok(()) => true,
+            Err(PushError::Full(packet)) => {
+                log::warn!("outbox is full, drop message: {}", packet.msg);
+                false
+            }
+            Err(PushError::Closed(_)) => {
+                log::error!("Failed to send message");
+                false
+            }
+        }
+    }
+}
+
+/// Packets being written, kept across loop iterations while the stream
+/// would block.
+struct Outgoing {
+    data: Vec<u8>,
+    written: usize,
+    fds: Vec<OwnedFd>,
+}
+
+impl Outgoing {
+    /// Take the next packet and as many following ones as fit in one write.
+    ///
+    /// A packet with fds is always sent on its own: the fds travel with the
+    /// first byte written, and the receiver can only tell which message they
+    /// belong to if that write holds no other.
+    fn next_batch(outbox: &BoundedQueue<Packet>) -> Option<Self> {
+        let first = outbox.pop()?;
+        let alone = !first.fds.is_empty();
+        let mut batch = Self {
+            data: Vec::new(),
+            written: 0,
+            fds: first.fds,
+        };
+        batch.push_line(first.msg);
+
+        while !alone && batch.data.len() < MAX_BATCH_BYTES {
+            match outbox.pop_if(|packet| packet.fds.is_empty()) {
+                Some(packet) => batch.push_line(packet.msg),
+                None => break,
+            }
+        }
+        Some(batch)
+    }
+
+    fn push_line(&mut self, msg: String) {
+        self.data.extend_from_slice(msg.as_bytes());
+        if !msg.ends_with('\n') {
+            self.data.push(b'\n');
+        }
+    }
+
+    /// Returns true once the whole batch is written.
+    fn write_to<T: NonblockingStream>(&mut self, stream: &mut T) -> std::io::Result<bool> {
+        while self.written < self.data.len() {
+            let fds: Vec<BorrowedFd> = self.fds.iter().map(|fd| fd.as_fd()).collect();
+            match stream.write_with_fds(&self.data[self.written..], &fds) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.written += n;
+                    // the fds went out with the first byte, close our copies
+                    self.fds.clear();
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
 pub struct StreamThread {
-    tx: Sender<String>,
-    rx: Receiver<String>,
+    queues: Arc<Queues>,
     exit_flag: Arc<Mutex<bool>>,
     handle: Option<std::thread::JoinHandle<()>>,
 }
 
 impl StreamThread {
     pub fn new(stream: impl NonblockingStream + 'static) -> Self {
-        let (tx, send_rx) = std::sync::mpsc::channel();
-        let (recv_tx, rx) = std::sync::mpsc::channel();
+        Self::with_config(stream, &StreamConfig::default())
+    }
+
+    pub fn with_config(stream: impl NonblockingStream + 'static, config: &StreamConfig) -> Self {
+        let queues = Queues::new(config);
+        let queues_clone = queues.clone();
 
         let exit_flag = Arc::new(Mutex::new(false));
 
         let exit_flag_clone = exit_flag.clone();
         let handle = std::thread::spawn(move || {
             log::debug!("stream_loop start");
-            Self::stream_loop(stream, &send_rx, &recv_tx, &exit_flag_clone);
+            Self::stream_loop(stream, &queues_clone, &exit_flag_clone);
+            queues_clone.outbox.close();
             log::debug!("stream_loop end");
         });
         Self {
-            tx,
-            rx,
+            queues,
             exit_flag,
             handle: Some(handle),
         }
@@ -54,22 +248,30 @@ impl StreamThread {
     pub fn create_unix_domain_server(sock_path: &str) -> Result<StreamThread, std::io::Error> {
         let sock_path = sock_path.to_string();
 
-        let (tx, send_rx) = std::sync::mpsc::channel();
-        let (recv_tx, rx) = std::sync::mpsc::channel();
+        let queues = Queues::new(&StreamConfig::default());
+        let queues_clone = queues.clone();
 
         let exit_flag = Arc::new(Mutex::new(false));
         let exit_flag_clone = exit_flag.clone();
 
+        let activated = systemd::take_unix_listener(&UnixAddr::Path(sock_path.clone().into()));
+
         let handle = std::thread::spawn(move || {
             log::debug!("stream thread start : {}", sock_path);
-            let listener = UnixListener::bind(sock_path.as_str()).unwrap();
+            let listener = match activated {
+                Some(listener) => {
+                    log::info!("use activated socket {}", sock_path);
+                    listener
+                }
+                None => UnixListener::bind(sock_path.as_str()).unwrap(),
+            };
             // listener.set_nonblocking(true).unwrap();
 
             for stream in listener.incoming() {
                 match stream {
                     Ok(stream) => {
                         let exit_flag =
-                            StreamThread::stream_loop(stream, &send_rx, &recv_tx, &exit_flag_clone);
+                            StreamThread::stream_loop(stream, &queues_clone, &exit_flag_clone);
                         if exit_flag {
                             break;
                         }
@@ -79,21 +281,22 @@ impl StreamThread {
                     }
                 }
             }
+            queues_clone.outbox.close();
             log::debug!("stream thread end: {}", sock_path);
         });
 
         Ok(StreamThread {
-            tx,
-            rx,
+            queues,
             exit_flag,
             handle: Some(handle),
         })
     }
 
-    fn stream_loop<T>(
+    /// Runs until the peer disconnects (returns false) or the exit flag is
+    /// raised (returns true).
+    pub(crate) fn stream_loop<T>(
         mut stream: T,
-        rx: &Receiver<String>,
-        tx: &Sender<String>,
+        queues: &Queues,
         exit_flag: &Arc<Mutex<bool>>,
     ) -> bool
     where
@@ -101,56 +304,161 @@ impl StreamThread {
     {
         let mut buf = [0; 2048];
         let mut incomplete_msg = String::new();
+        let mut pending_fds = Vec::new();
+        // fds of the message in `incomplete_msg`
+        let mut incomplete_fds = Vec::new();
+        let mut outgoing: Option<Outgoing> = None;
+        // lines read but not yet taken by a full `Block` inbox
+        let mut received: VecDeque<Packet> = VecDeque::new();
 
         stream.set_nonblocking(true).unwrap();
-        loop {
+        'outer: loop {
             if *exit_flag.lock().unwrap() {
                 log::debug!("exit flag is true");
                 return true;
             }
-            if let Ok(mut msg) = rx.try_recv() {
-                if !msg.ends_with('\n') {
-                    msg.push('\n');
+            let mut progress = false;
+
+            loop {
+                if outgoing.is_none() {
+                    outgoing = Outgoing::next_batch(&queues.outbox);
+                }
+                let Some(out) = &mut outgoing else {
+                    break;
+                };
+                match out.write_to(&mut stream) {
+                    Ok(true) => {
+                        outgoing = None;
+                        progress = true;
+                    }
+                    Ok(false) => {
+                        // socket buffer full or TLS handshake in progress, retry later
+                        break;
+                    }
+                    Err(e) => {
+                        log::error!("Failed to send data: {}", e);
+                        break 'outer;
+                    }
                 }
-                stream.write_all(msg.as_bytes()).unwrap();
             }
-            match stream.read(&mut buf) {
-                Ok(0) => {
-                    log::info!("Connection closed");
+
+            progress |= Self::deliver(&mut received, &queues.inbox);
+            for _ in 0..MAX_READS_PER_ITERATION {
+                if !received.is_empty() {
+                    // the app is behind, stop reading until it catches up
                     break;
                 }
-                Ok(n) => {
-                    let data = String::from_utf8_lossy(&buf[..n]);
-                    incomplete_msg.push_str(&data);
+                match stream.read_with_fds(&mut buf, &mut pending_fds) {
+                    Ok(0) => {
+                        log::info!("Connection closed");
+                        break 'outer;
+                    }
+                    Ok(n) => {
+                        progress = true;
+                        let data = String::from_utf8_lossy(&buf[..n]);
+                        incomplete_msg.push_str(&data);
+
+                        let mut lines = Vec::new();
+                        while let Some(newline_idx) = incomplete_msg.find('\n') {
+                            let mut line: String = incomplete_msg.drain(..=newline_idx).collect();
+                            line.pop();
+                            lines.push(line);
+                        }
 
-                    while let Some(newline_idx) = incomplete_msg.find('\n') {
-                        let msg = incomplete_msg[..newline_idx].to_string();
-                        tx.send(msg).unwrap();
-                        incomplete_msg = incomplete_msg[newline_idx + 1..].to_string();
+                        // A read that picks up fds stops inside the write they came
+                        // with, and that write holds only their message (see
+                        // `Outgoing::next_batch`), so they belong to the message
+                        // this read ends in.
+                        let mut fds = std::mem::take(&mut pending_fds);
+                        let last = lines.len();
+                        for (i, msg) in lines.into_iter().enumerate() {
+                            let mut packet = Packet::from(msg);
+                            if i == 0 {
+                                packet.fds = std::mem::take(&mut incomplete_fds);
+                            }
+                            if i + 1 == last && incomplete_msg.is_empty() {
+                                packet.fds.append(&mut fds);
+                            }
+                            received.push_back(packet);
+                        }
+                        incomplete_fds.append(&mut fds);
+                        Self::deliver(&mut received, &queues.inbox);
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        // No data available right now
+                        break;
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        log::error!("Failed to receive data: {}", e);
+                        break 'outer;
                     }
                 }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    // No data available right now, just continue
+            }
+
+            if !progress {
+                std::thread::sleep(IDLE_SLEEP);
+            }
+        }
+        false
+    }
+
+    /// Hand received packets to the app. Returns true if any was taken.
+    fn deliver(received: &mut VecDeque<Packet>, inbox: &BoundedQueue<Packet>) -> bool {
+        let mut delivered = false;
+        while let Some(packet) = received.pop_front() {
+            match inbox.offer(packet) {
+                Ok(()) => delivered = true,
+                Err(PushError::Full(packet)) if inbox.policy() == OverflowPolicy::Block => {
+                    received.push_front(packet);
+                    break;
                 }
                 Err(e) => {
-                    log::error!("Failed to receive data: {}", e);
-                    break;
+                    let reason = e.to_string();
+                    log::warn!("inbox: {}, drop message: {}", reason, e.into_inner().msg);
                 }
             }
-            std::thread::sleep(std::time::Duration::from_millis(100));
         }
-        false
+        delivered
     }
 
+    /// Queue a message for the peer. Failures are logged; use `try_send` to handle them.
     pub fn send(&self, msg: String) {
-        log::debug!("send: {}", msg);
-        if let Err(_) = self.tx.send(msg) {
-            log::error!("Failed to send message");
-        }
+        self.send_packet(Packet::from(msg));
+    }
+
+    /// Send `msg` with `fds` attached. The fds are closed on this side once sent.
+    pub fn send_with_fds(&self, msg: String, fds: Vec<OwnedFd>) {
+        self.send_packet(Packet { msg, fds });
+    }
+
+    fn send_packet(&self, packet: Packet) {
+        log::debug!("send: {} (fds: {})", packet.msg, packet.fds.len());
+        self.queues.send(packet);
+    }
+
+    /// Queue a message, handing it back if the outbox refused it.
+    pub fn try_send(&self, msg: String) -> Result<(), PushError<Packet>> {
+        self.queues.outbox.push(Packet::from(msg))
     }
 
+    /// Receive a message. Any fds that came with it are closed.
     pub fn recv(&self) -> Option<String> {
-        self.rx.try_recv().ok()
+        self.recv_with_fds().map(|packet| {
+            if !packet.fds.is_empty() {
+                log::warn!("closing {} unexpected fds", packet.fds.len());
+            }
+            packet.msg
+        })
+    }
+
+    pub fn recv_with_fds(&self) -> Option<Packet> {
+        self.queues.inbox.pop()
+    }
+
+    /// Queue depths and drop counters.
+    pub fn stats(&self) -> StreamStats {
+        self.queues.stats()
     }
 
     pub fn stop(&mut self) {
@@ -182,3 +490,124 @@ impl Drop for StreamThread {
         self.stop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::OverflowPolicy;
+    use std::os::unix::net::UnixStream;
+
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        for _ in 0..50 {
+            if let Some(v) = f() {
+                return v;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        panic!("timed out");
+    }
+
+    #[test]
+    fn fds_travel_with_their_message() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let sender = StreamThread::new(a);
+        let receiver = StreamThread::new(b);
+
+        let (x, mut y) = UnixStream::pair().unwrap();
+        sender.send("plain".to_string());
+        sender.send_with_fds("with fd".to_string(), vec![OwnedFd::from(x)]);
+
+        let first = wait_for(|| receiver.recv_with_fds());
+        assert_eq!(first.msg, "plain");
+        assert!(first.fds.is_empty());
+
+        let second = wait_for(|| receiver.recv_with_fds());
+        assert_eq!(second.msg, "with fd");
+        assert_eq!(second.fds.len(), 1);
+
+        let mut x = UnixStream::from(second.fds.into_iter().next().unwrap());
+        x.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        y.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    /// Read `n` messages, returning each with its number of fds.
+    fn recv_all(receiver: &StreamThread, n: usize) -> Vec<(String, usize)> {
+        (0..n)
+            .map(|_| {
+                let packet = wait_for(|| receiver.recv_with_fds());
+                (packet.msg, packet.fds.len())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fds_stay_with_a_message_followed_by_others() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (x, _y) = UnixStream::pair().unwrap();
+        let receiver = StreamThread::new(b);
+        let sender = StreamThread::new(a);
+
+        sender.send_with_fds("with fd".to_string(), vec![OwnedFd::from(x)]);
+        sender.send("after".to_string());
+        sender.send("and more".to_string());
+
+        assert_eq!(
+            recv_all(&receiver, 3),
+            vec![
+                ("with fd".to_string(), 1),
+                ("after".to_string(), 0),
+                ("and more".to_string(), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn fds_stay_with_a_message_longer_than_one_read() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (x, _y) = UnixStream::pair().unwrap();
+        let receiver = StreamThread::new(b);
+        let sender = StreamThread::new(a);
+
+        let long = "x".repeat(5000);
+        sender.send("before".to_string());
+        sender.send_with_fds(long.clone(), vec![OwnedFd::from(x)]);
+        sender.send("after".to_string());
+
+        assert_eq!(
+            recv_all(&receiver, 3),
+            vec![
+                ("before".to_string(), 0),
+                (long, 1),
+                ("after".to_string(), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn burst_is_written_in_batches() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let sender = StreamThread::new(a);
+        let config = StreamConfig {
+            inbox: QueueConfig {
+                capacity: 100,
+                policy: OverflowPolicy::DropOldest,
+            },
+            ..Default::default()
+        };
+        let receiver = StreamThread::with_config(b, &config);
+
+        for i in 0..1000 {
+            sender.send(format!("msg {}", i));
+        }
+        // with one message per iteration this would take minutes
+        wait_for(|| {
+            let stats = receiver.stats();
+            (stats.inbox.dropped == 900).then_some(())
+        });
+        assert_eq!(receiver.stats().inbox.depth, 100);
+        assert_eq!(receiver.recv().unwrap(), "msg 900");
+        assert_eq!(sender.stats().outbox.depth, 0);
+    }
+}
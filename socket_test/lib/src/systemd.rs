@@ -0,0 +1,16 @@
+//! systemd socket activation and `sd_notify`, from the shared `systemd`
+//! crate. A [`UnixAddr`] converts into a [`UnixName`] for the Unix socket
+//! lookups.
+
+pub use ::systemd::*;
+
+use crate::unixsock::UnixAddr;
+
+impl<'a> From<&'a UnixAddr> for UnixName<'a> {
+    fn from(addr: &'a UnixAddr) -> Self {
+        match addr {
+            UnixAddr::Path(path) => UnixName::Path(path),
+            UnixAddr::Abstract(name) => UnixName::Abstract(name),
+        }
+    }
+}
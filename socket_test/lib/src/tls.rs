@@ -0,0 +1,318 @@
+//! rustls-based TLS for `StreamListener` / `StreamThread`.
+//!
+//! The handshake is driven lazily by the first reads and writes of the
+//! stream loop, so accepting never blocks on a slow client.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    server::AllowAnyAuthenticatedClient, Certificate, ClientConfig, ClientConnection, PrivateKey,
+    RootCertStore, ServerConfig, ServerConnection, ServerName, StreamOwned,
+};
+
+use crate::peer::PeerAddr;
+use crate::server::StreamListener;
+use crate::streamthread::NonblockingStream;
+
+pub type TlsServerStream = StreamOwned<ServerConnection, TcpStream>;
+pub type TlsClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+fn invalid_data(e: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+pub fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(invalid_data(format!(
+            "no certificate in {}",
+            path.display()
+        )));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Load the first PKCS#8, RSA or SEC1 private key in `path`.
+pub fn load_private_key(path: &Path) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => {
+                return Err(invalid_data(format!(
+                    "no private key in {}",
+                    path.display()
+                )))
+            }
+        }
+    }
+}
+
+pub fn load_root_store(path: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(&cert).map_err(invalid_data)?;
+    }
+    Ok(roots)
+}
+
+/// Server certificate files. With `client_ca` set, clients must present a
+/// certificate signed by it.
+#[derive(Debug, Clone)]
+pub struct TlsServerFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsServerFiles {
+    pub fn load(&self) -> io::Result<Arc<ServerConfig>> {
+        let certs = load_certs(&self.cert)?;
+        let key = load_private_key(&self.key)?;
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let config = match &self.client_ca {
+            Some(ca) => builder.with_client_cert_verifier(Arc::new(
+                AllowAnyAuthenticatedClient::new(load_root_store(ca)?),
+            )),
+            None => builder.with_no_client_auth(),
+        }
+        .with_single_cert(certs, key)
+        .map_err(invalid_data)?;
+
+        Ok(Arc::new(config))
+    }
+}
+
+/// Client side trust and optional client certificate.
+#[derive(Debug, Clone)]
+pub struct TlsClientFiles {
+    pub ca: PathBuf,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl TlsClientFiles {
+    pub fn load(&self) -> io::Result<Arc<ClientConfig>> {
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(load_root_store(&self.ca)?);
+
+        let config = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_private_key(key)?)
+                .map_err(invalid_data)?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "client cert and key must be given together",
+                ))
+            }
+        };
+        Ok(Arc::new(config))
+    }
+}
+
+/// Everything needed to open a client TLS session.
+#[derive(Clone)]
+pub struct TlsConnector {
+    pub config: Arc<ClientConfig>,
+    pub server_name: ServerName,
+}
+
+impl fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConnector")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+impl TlsConnector {
+    pub fn new(config: Arc<ClientConfig>, server_name: &str) -> io::Result<Self> {
+        let server_name = ServerName::try_from(server_name)
+            .map_err(|_| invalid_data(format!("invalid server name: {}", server_name)))?;
+        Ok(Self {
+            config,
+            server_name,
+        })
+    }
+
+    /// Wrap a connected TCP stream. The handshake runs on first use.
+    pub fn wrap(&self, sock: TcpStream) -> io::Result<TlsClientStream> {
+        let conn = ClientConnection::new(self.config.clone(), self.server_name.clone())
+            .map_err(invalid_data)?;
+        Ok(StreamOwned::new(conn, sock))
+    }
+
+    pub fn connect(&self, addr: &str) -> io::Result<TlsClientStream> {
+        self.wrap(TcpStream::connect(addr)?)
+    }
+}
+
+impl NonblockingStream for TlsServerStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.sock.set_nonblocking(nonblocking)
+    }
+}
+
+impl NonblockingStream for TlsClientStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.sock.set_nonblocking(nonblocking)
+    }
+}
+
+/// TCP listener that hands out TLS server sessions.
+pub struct TlsListener {
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+}
+
+impl TlsListener {
+    pub fn bind_with(addr: &str, config: Arc<ServerConfig>) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            config,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+impl StreamListener for TlsListener {
+    type Stream = TlsServerStream;
+
+    /// Certificates can't be given through an address; use `TlsListener::bind_with`.
+    fn bind(_addr: &str) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TlsListener needs certificates, use TlsListener::bind_with",
+        ))
+    }
+
+    fn accept(&self) -> io::Result<(Self::Stream, PeerAddr)> {
+        let (sock, addr) = self.listener.accept()?;
+        let conn = ServerConnection::new(self.config.clone()).map_err(invalid_data)?;
+        Ok((StreamOwned::new(conn, sock), PeerAddr::from(addr)))
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.listener.set_nonblocking(nonblocking)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::SingleServer;
+    use crate::streamthread::StreamThread;
+
+    struct TestPki {
+        dir: PathBuf,
+    }
+
+    impl TestPki {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("tls-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let mut ca_params = rcgen::CertificateParams::new(vec![]);
+            ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            ca_params
+                .distinguished_name
+                .push(rcgen::DnType::CommonName, "test ca");
+            let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+
+            let server = rcgen::Certificate::from_params(rcgen::CertificateParams::new(vec![
+                "localhost".to_string(),
+            ]))
+            .unwrap();
+
+            std::fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+            std::fs::write(
+                dir.join("server.pem"),
+                server.serialize_pem_with_signer(&ca).unwrap(),
+            )
+            .unwrap();
+            std::fs::write(
+                dir.join("server-key.pem"),
+                server.serialize_private_key_pem(),
+            )
+            .unwrap();
+            Self { dir }
+        }
+    }
+
+    impl Drop for TestPki {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        for _ in 0..100 {
+            if let Some(v) = f() {
+                return v;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        panic!("timed out");
+    }
+
+    #[test]
+    fn echo_over_tls() {
+        let pki = TestPki::new("echo");
+        let server_config = TlsServerFiles {
+            cert: pki.dir.join("server.pem"),
+            key: pki.dir.join("server-key.pem"),
+            client_ca: None,
+        }
+        .load()
+        .unwrap();
+        let listener = TlsListener::bind_with("127.0.0.1:0", server_config).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut server = SingleServer::with_listener(listener);
+
+        let client_config = TlsClientFiles {
+            ca: pki.dir.join("ca.pem"),
+            cert: None,
+            key: None,
+        }
+        .load()
+        .unwrap();
+        let connector = TlsConnector::new(client_config, "localhost").unwrap();
+        let client = StreamThread::new(connector.connect(&addr.to_string()).unwrap());
+
+        client.send("hello".to_string());
+        let msg = wait_for(|| server.recv());
+        assert_eq!(msg, "hello");
+        server.send(msg);
+        assert_eq!(wait_for(|| client.recv()), "hello");
+    }
+
+    #[test]
+    fn missing_key_is_an_error() {
+        let pki = TestPki::new("nokey");
+        let err = TlsServerFiles {
+            cert: pki.dir.join("server.pem"),
+            key: pki.dir.join("ca.pem"),
+            client_ca: None,
+        }
+        .load()
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,356 @@
+//! Unix socket addresses and listener setup.
+//!
+//! A socket path is only unlinked after probing shows no server is still
+//! listening on it, and a `<path>.lock` file held with `flock` keeps two
+//! servers from racing on the same path.
+
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io,
+    os::{
+        fd::AsRawFd,
+        linux::net::SocketAddrExt,
+        unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            net::{SocketAddr, UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use crate::peer::PeerAddr;
+use crate::server::StreamListener;
+use crate::systemd;
+
+/// Unix socket address: a filesystem path, or `@name` for the Linux abstract namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnixAddr {
+    Path(PathBuf),
+    Abstract(Vec<u8>),
+}
+
+impl FromStr for UnixAddr {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.strip_prefix('@') {
+            Some(name) => UnixAddr::Abstract(name.as_bytes().to_vec()),
+            None => UnixAddr::Path(PathBuf::from(s)),
+        })
+    }
+}
+
+impl fmt::Display for UnixAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnixAddr::Path(path) => write!(f, "{}", path.display()),
+            UnixAddr::Abstract(name) => write!(f, "@{}", String::from_utf8_lossy(name)),
+        }
+    }
+}
+
+impl UnixAddr {
+    fn to_socket_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            UnixAddr::Path(path) => SocketAddr::from_pathname(path),
+            UnixAddr::Abstract(name) => SocketAddr::from_abstract_name(name),
+        }
+    }
+
+    pub fn connect(&self) -> io::Result<UnixStream> {
+        UnixStream::connect_addr(&self.to_socket_addr()?)
+    }
+
+    /// Plain bind, without any stale socket handling.
+    pub fn bind(&self) -> io::Result<UnixListener> {
+        UnixListener::bind_addr(&self.to_socket_addr()?)
+    }
+}
+
+/// Permissions of a socket file. It is bound accessible to the owner only
+/// and opened up once the mode and owner are set. Ignored for abstract
+/// addresses.
+#[derive(Debug, Clone, Default)]
+pub struct UnixSocketOptions {
+    /// File mode, e.g. `0o660`.
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// Unix listener that owns its socket file: the file is removed again on drop.
+pub struct UnixSocketListener {
+    listener: UnixListener,
+    path: Option<PathBuf>,
+    _lock: Option<File>,
+}
+
+impl UnixSocketListener {
+    pub fn bind_with(addr: &UnixAddr, options: &UnixSocketOptions) -> io::Result<Self> {
+        let path = match addr {
+            UnixAddr::Abstract(_) => {
+                if options.mode.is_some() || options.uid.is_some() || options.gid.is_some() {
+                    log::warn!("mode/owner are ignored for abstract socket {}", addr);
+                }
+                return Ok(Self {
+                    listener: addr.bind()?,
+                    path: None,
+                    _lock: None,
+                });
+            }
+            UnixAddr::Path(path) => path,
+        };
+
+        let lock = lock_socket_path(path)?;
+        remove_stale_socket(path)?;
+
+        let (listener, umask) = bind_private(addr)?;
+        // dropped on error, which removes the socket file again
+        let this = Self {
+            listener,
+            path: Some(path.clone()),
+            _lock: Some(lock),
+        };
+
+        if options.uid.is_some() || options.gid.is_some() {
+            std::os::unix::fs::chown(path, options.uid, options.gid)?;
+        }
+        let mode = options.mode.unwrap_or(0o777 & !umask);
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        Ok(this)
+    }
+
+    /// Wrap a listener passed by systemd. Its socket file is left alone on drop.
+    pub fn from_activated(listener: UnixListener) -> Self {
+        Self {
+            listener,
+            path: None,
+            _lock: None,
+        }
+    }
+
+    pub fn addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            if let Err(e) = std::fs::remove_file(path) {
+                log::error!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+impl StreamListener for UnixSocketListener {
+    type Stream = UnixStream;
+
+    fn bind(addr: &str) -> io::Result<Self> {
+        Self::bind_with(&addr.parse().unwrap(), &UnixSocketOptions::default())
+    }
+
+    fn activated(addr: &str) -> Option<Self> {
+        systemd::take_unix_listener(&addr.parse::<UnixAddr>().unwrap()).map(Self::from_activated)
+    }
+
+    fn accept(&self) -> io::Result<(Self::Stream, PeerAddr)> {
+        self.listener
+            .accept()
+            .map(|(stream, addr)| (stream, PeerAddr::from(addr)))
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.listener.set_nonblocking(nonblocking)
+    }
+}
+
+/// The umask is per process, so binds through here take turns with it.
+static UMASK: Mutex<()> = Mutex::new(());
+
+/// Bind with a umask that leaves the socket file to its owner, so nobody
+/// can connect before the requested mode is set. Returns the umask that
+/// was in effect.
+fn bind_private(addr: &UnixAddr) -> io::Result<(UnixListener, libc::mode_t)> {
+    let _guard = UMASK.lock().unwrap_or_else(|e| e.into_inner());
+    // SAFETY: umask only swaps the process file mode mask.
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = addr.bind();
+    // SAFETY: as above, putting the old mask back.
+    unsafe { libc::umask(umask) };
+    Ok((listener?, umask))
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+/// Take an exclusive `flock` on `<path>.lock`, held as long as the file stays open.
+fn lock_socket_path(path: &Path) -> io::Result<File> {
+    let lock_path = lock_path(path);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)?;
+
+    // SAFETY: flock on a valid, open fd.
+    let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if ret != 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::WouldBlock {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is locked by another server", lock_path.display()),
+            ));
+        }
+        return Err(err);
+    }
+    Ok(file)
+}
+
+/// Remove `path` only if it is a socket nobody is listening on.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("a server is still listening on {}", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            log::info!("remove stale socket {}", path.display());
+            std::fs::remove_file(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    fn temp_sock(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("unixsock-{}-{}.sock", name, std::process::id()))
+    }
+
+    #[test]
+    fn parse_addr() {
+        assert_eq!(
+            "@echo".parse::<UnixAddr>().unwrap(),
+            UnixAddr::Abstract(b"echo".to_vec())
+        );
+        assert_eq!(
+            "/tmp/echo.sock".parse::<UnixAddr>().unwrap(),
+            UnixAddr::Path("/tmp/echo.sock".into())
+        );
+    }
+
+    #[test]
+    fn stale_socket_is_replaced_and_mode_applied() {
+        let path = temp_sock("stale");
+        // a bound socket whose listener is gone leaves a stale file behind
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let options = UnixSocketOptions {
+            mode: Some(0o600),
+            ..Default::default()
+        };
+        let listener =
+            UnixSocketListener::bind_with(&UnixAddr::Path(path.clone()), &options).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+
+        drop(listener);
+        assert!(!path.exists());
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn default_mode_follows_the_umask() {
+        let plain = temp_sock("plain");
+        let path = temp_sock("default");
+        let _ = std::fs::remove_file(&plain);
+        let expected = {
+            let _guard = UMASK.lock().unwrap();
+            let _listener = UnixListener::bind(&plain).unwrap();
+            std::fs::metadata(&plain).unwrap().mode() & 0o777
+        };
+        let listener =
+            UnixSocketListener::bind_with(&UnixAddr::Path(path.clone()), &Default::default())
+                .unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o777, expected);
+
+        drop(listener);
+        let _ = std::fs::remove_file(&plain);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn live_socket_is_not_clobbered() {
+        let path = temp_sock("live");
+        let _ = std::fs::remove_file(&path);
+        let _live = UnixListener::bind(&path).unwrap();
+
+        let err = UnixSocketListener::bind_with(&UnixAddr::Path(path.clone()), &Default::default())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(path.exists());
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn second_server_fails_on_lock() {
+        let path = temp_sock("lock");
+        let addr = UnixAddr::Path(path.clone());
+        let _first = UnixSocketListener::bind_with(&addr, &Default::default()).unwrap();
+
+        let err = UnixSocketListener::bind_with(&addr, &Default::default())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn abstract_socket_round_trip() {
+        let addr = UnixAddr::Abstract(format!("unixsock-test-{}", std::process::id()).into_bytes());
+        let listener = UnixSocketListener::bind_with(&addr, &Default::default()).unwrap();
+
+        let _client = addr.connect().unwrap();
+        let (_stream, peer) = StreamListener::accept(&listener).unwrap();
+        assert_eq!(peer, PeerAddr::Unnamed);
+        assert_eq!(
+            listener.addr().unwrap().as_abstract_name(),
+            Some(&addr_name(&addr)[..])
+        );
+    }
+
+    fn addr_name(addr: &UnixAddr) -> Vec<u8> {
+        match addr {
+            UnixAddr::Abstract(name) => name.clone(),
+            UnixAddr::Path(_) => unreachable!(),
+        }
+    }
+}
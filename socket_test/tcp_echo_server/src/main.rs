@@ -1,25 +1,40 @@
+use lib::command::{Command, CommandRegistry, Dispatch, Flow};
 use lib::logger;
 use lib::server::SingleTcpServer;
 use lib::stdinthread::StdinThread;
+use lib::systemd;
 
 fn main() {
+    // 스레드가 생기기 전에 systemd 가 넘겨준 소켓을 가져오고 환경 변수를 지움
+    let activation = systemd::init();
     let _logger = logger::start("debug", "", true);
-    let stdin = StdinThread::new();
+    if let Err(e) = activation {
+        log::error!("Failed to read activated sockets: {}", e);
+    }
+    let mut commands = commands();
+    let stdin = StdinThread::with_completions(commands.completions());
     let mut server = SingleTcpServer::new("127.0.0.1:12345");
 
     log::info!("Start main loop - tcp echo server :12345");
-    help();
+    help(&commands);
+    if let Err(e) = systemd::notify_ready() {
+        log::error!("Failed to notify systemd: {}", e);
+    }
+    let mut watchdog = systemd::Watchdog::from_env();
     loop {
+        if let Some(watchdog) = &mut watchdog {
+            watchdog.tick();
+        }
+
         if let Some(cmd) = stdin.read_line() {
             println!("cmd from stdin: {}", cmd);
 
-            match cmd.as_str() {
-                "/q" => {
-                    break;
-                }
-                _ => {
+            match commands.dispatch(&mut (), &cmd) {
+                Dispatch::Quit => break,
+                Dispatch::Continue => {}
+                Dispatch::Text(_) => {
                     log::info!("unknown command: {}", cmd);
-                    help();
+                    help(&commands);
                 }
             }
         }
@@ -31,9 +46,18 @@ fn main() {
 
         std::thread::sleep(std::time::Duration::from_millis(100));
     }
+    let _ = systemd::notify_stopping();
+}
+
+fn commands() -> CommandRegistry<()> {
+    let mut commands = CommandRegistry::new();
+    commands.register(Command::new("q", "quit").alias("quit"), |_, _| Flow::Quit);
+    commands
 }
 
-fn help() {
+fn help(commands: &CommandRegistry<()>) {
     log::info!("help");
-    log::info!("/q : quit");
+    for line in commands.help_text().lines() {
+        log::info!("{}", line);
+    }
 }
@@ -1,6 +1,15 @@
+use lib::command::{Command, CommandRegistry, Dispatch, Flow};
 use lib::{stdinthread::StdinThread, streamthread::StreamThread};
 fn main() {
-    let stdin = StdinThread::new();
+    let mut commands = CommandRegistry::new();
+    commands.register(
+        Command::new("q", "quit").alias("quit"),
+        |stream_thread: &mut StreamThread, _| {
+            stream_thread.stop();
+            Flow::Quit
+        },
+    );
+    let stdin = StdinThread::with_completions(commands.completions());
     let sock_path = "/tmp/echo.sock";
     println!("echo client start");
     println!("sock: {}", sock_path);
@@ -8,18 +17,15 @@ fn main() {
     let stream = std::os::unix::net::UnixStream::connect(sock_path).unwrap();
     let mut stream_thread = StreamThread::new(stream);
 
-    println!("/q : quit\n");
+    print!("{}", commands.help_text());
     loop {
         if let Some(cmd) = stdin.read_line() {
             println!("cmd from stdin: {}", cmd);
-            match cmd.as_str() {
-                "/q" => {
-                    stream_thread.stop();
-                    break;
-                }
-                _ => {
-                    let msg = cmd + "\n";
-                    stream_thread.send(msg);
+            match commands.dispatch(&mut stream_thread, &cmd) {
+                Dispatch::Quit => break,
+                Dispatch::Continue => {}
+                Dispatch::Text(msg) => {
+                    stream_thread.send(msg + "\n");
                 }
             }
         }
@@ -1,5 +1,6 @@
 use std::os::unix::net::UnixListener;
 
+use lib::command::{Command, CommandRegistry, Dispatch, Flow};
 use lib::logger;
 use lib::stdinthread::StdinThread;
 use lib::streamthread::StreamThread;
@@ -9,29 +10,29 @@ fn main() {
 
     let sock_path = "/tmp/echo.sock";
 
-    if let Ok(_) = std::fs::remove_file(sock_path) {
+    if std::fs::remove_file(sock_path).is_ok() {
         log::info!("remove old sock file");
     }
 
-    let stdin = StdinThread::new();
+    let mut commands = commands();
+    let stdin = StdinThread::with_completions(commands.completions());
 
     let listener = UnixListener::bind(sock_path).unwrap();
     listener.set_nonblocking(true).unwrap();
     let mut stream_thread: Option<StreamThread> = None;
 
     log::info!("Start main loop");
-    help();
+    help(&commands);
     loop {
         if let Some(cmd) = stdin.read_line() {
             println!("cmd from stdin: {}", cmd);
 
-            match cmd.as_str() {
-                "/q" => {
-                    break;
-                }
-                _ => {
+            match commands.dispatch(&mut (), &cmd) {
+                Dispatch::Quit => break,
+                Dispatch::Continue => {}
+                Dispatch::Text(_) => {
                     log::info!("unknown command: {}", cmd);
-                    help();
+                    help(&commands);
                 }
             }
         }
@@ -56,7 +57,15 @@ fn main() {
     }
 }
 
-fn help() {
+fn commands() -> CommandRegistry<()> {
+    let mut commands = CommandRegistry::new();
+    commands.register(Command::new("q", "quit").alias("quit"), |_, _| Flow::Quit);
+    commands
+}
+
+fn help(commands: &CommandRegistry<()>) {
     log::info!("help");
-    log::info!("/q : quit");
+    for line in commands.help_text().lines() {
+        log::info!("{}", line);
+    }
 }
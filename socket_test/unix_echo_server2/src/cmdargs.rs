@@ -0,0 +1,25 @@
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// socket path, or @name for the abstract namespace
+    #[arg(short = 's', long, default_value = "/tmp/echo.sock")]
+    pub sock: String,
+
+    /// socket file mode (octal)
+    #[arg(short = 'm', long, value_parser = parse_mode)]
+    pub mode: Option<u32>,
+
+    /// socket file owner uid
+    #[arg(long)]
+    pub uid: Option<u32>,
+
+    /// socket file owner gid
+    #[arg(long)]
+    pub gid: Option<u32>,
+}
+
+fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim_start_matches("0o"), 8).map_err(|e| e.to_string())
+}
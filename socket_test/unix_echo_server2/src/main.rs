@@ -1,33 +1,68 @@
+mod cmdargs;
+
+use clap::Parser;
+
+use cmdargs::Args;
+use lib::command::{Command, CommandRegistry, Dispatch, Flow};
 use lib::logger;
-use lib::server::SingleUnixServer;
+use lib::server::{SingleUnixServer, StreamListener};
 use lib::stdinthread::StdinThread;
+use lib::systemd;
+use lib::unixsock::{UnixSocketListener, UnixSocketOptions};
 
 fn main() {
+    // 스레드가 생기기 전에 systemd 가 넘겨준 소켓을 가져오고 환경 변수를 지움
+    let activation = systemd::init();
     let _logger = logger::start("debug", "", true);
-
-    let sock_path = "/tmp/echo.sock";
-
-    if let Ok(_) = std::fs::remove_file(sock_path) {
-        log::info!("remove old sock file");
+    if let Err(e) = activation {
+        log::error!("Failed to read activated sockets: {}", e);
     }
+    let args = Args::parse();
+
+    let options = UnixSocketOptions {
+        mode: args.mode,
+        uid: args.uid,
+        gid: args.gid,
+    };
+    let listener = match UnixSocketListener::activated(&args.sock) {
+        Some(listener) => {
+            log::info!("use activated socket {}", args.sock);
+            listener
+        }
+        None => match UnixSocketListener::bind_with(&args.sock.parse().unwrap(), &options) {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::error!("failed to bind {} : {}", args.sock, e);
+                return;
+            }
+        },
+    };
 
-    let stdin = StdinThread::new();
+    let mut commands = commands();
+    let stdin = StdinThread::with_completions(commands.completions());
 
-    let mut server = SingleUnixServer::new(sock_path);
+    let mut server = SingleUnixServer::with_listener(listener);
 
-    log::info!("Start main loop");
-    help();
+    log::info!("Start main loop : {}", args.sock);
+    help(&commands);
+    if let Err(e) = systemd::notify_ready() {
+        log::error!("Failed to notify systemd: {}", e);
+    }
+    let mut watchdog = systemd::Watchdog::from_env();
     loop {
+        if let Some(watchdog) = &mut watchdog {
+            watchdog.tick();
+        }
+
         if let Some(cmd) = stdin.read_line() {
             println!("cmd from stdin: {}", cmd);
 
-            match cmd.as_str() {
-                "/q" => {
-                    break;
-                }
-                _ => {
+            match commands.dispatch(&mut (), &cmd) {
+                Dispatch::Quit => break,
+                Dispatch::Continue => {}
+                Dispatch::Text(_) => {
                     log::info!("unknown command: {}", cmd);
-                    help();
+                    help(&commands);
                 }
             }
         }
@@ -39,9 +74,18 @@ fn main() {
 
         std::thread::sleep(std::time::Duration::from_millis(100));
     }
+    let _ = systemd::notify_stopping();
 }
 
-fn help() {
+fn commands() -> CommandRegistry<()> {
+    let mut commands = CommandRegistry::new();
+    commands.register(Command::new("q", "quit").alias("quit"), |_, _| Flow::Quit);
+    commands
+}
+
+fn help(commands: &CommandRegistry<()>) {
     log::info!("help");
-    log::info!("/q : quit");
+    for line in commands.help_text().lines() {
+        log::info!("{}", line);
+    }
 }
@@ -7,17 +7,8 @@ pub struct Args {
     #[arg(short = 'a', long, default_value = "10.82.79.2:8274")]
     pub addr: String,
 
-    /// log file path
-    #[arg(short = 'd', long, default_value = "/tmp/client.log")]
-    pub log_file: std::path::PathBuf,
-
-    /// log level
-    #[arg(short = 'l', long, default_value = "debug")]
-    pub log_level: String,
-
-    /// log to stderr as well
-    #[arg(short = 'e', long, default_value = "true")]
-    pub log_stderr: bool,
+    #[command(flatten)]
+    pub log: logger::LogArgs,
 
     /// fs test file path
     #[arg(short = 'f', long, default_value = "/tmp/DMC_BD.bin")]
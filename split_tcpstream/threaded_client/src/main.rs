@@ -1,5 +1,4 @@
 mod cmdargs;
-mod logger;
 mod stdinthread;
 mod tcpthread;
 
@@ -7,17 +6,21 @@ use std::{fs::File, io::Read};
 
 use clap::Parser;
 use cmdargs::Args;
-use log::{debug, info};
+use log::{debug, error, info};
 use stdinthread::StdinThread;
 
 fn main() {
     let args = Args::parse();
     let ver = env!("CARGO_PKG_VERSION");
-    logger::start(
-        args.log_level.as_str(),
-        args.log_file.to_str().unwrap(),
-        args.log_stderr,
-    );
+    let log_config = args
+        .log
+        .config(logger::LogConfig {
+            level: "debug".into(),
+            file: Some("/tmp/client.log".into()),
+            ..Default::default()
+        })
+        .unwrap();
+    let logger = log_config.start().unwrap();
 
     info!("client test {}", ver);
     info!("args: {:?}", args);
@@ -26,7 +29,7 @@ fn main() {
     let tcp = tcpthread::connect_to_server(&args.addr).unwrap();
     let test_bin_path = args.test_file.as_str();
 
-    info!("/q to exit");
+    info!("/q to exit, /log <spec> to change the log level");
 
     debug!("start main loop");
     loop {
@@ -35,6 +38,11 @@ fn main() {
             if input == "/q" {
                 debug!("exit");
                 break;
+            } else if let Some(spec) = input.strip_prefix("/log ") {
+                match logger.set_spec(spec) {
+                    Ok(()) => info!("log spec: {}", spec),
+                    Err(e) => error!("invalid log spec {}: {}", spec, e),
+                }
             } else if input == "/fs" {
                 let cmd = format!(
                     "nt f;nt fs {} {}\n",
@@ -70,7 +78,7 @@ fn load_file(path: &str) -> Result<Vec<u8>, std::io::Error> {
     Ok(buffer)
 }
 
-fn calculate_checksum(data: &Vec<u8>) -> u8 {
+fn calculate_checksum(data: &[u8]) -> u8 {
     data.iter().fold(0, |acc, &byte| acc ^ byte)
 }
 
@@ -78,7 +86,7 @@ fn get_file_size(path: &str) -> u64 {
     if let Ok(metadata) = std::fs::metadata(path) {
         metadata.len()
     } else {
-        return 0;
+        0
     }
 }
 
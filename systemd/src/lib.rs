@@ -0,0 +1,376 @@
+//! systemd socket activation and `sd_notify` without libsystemd.
+//!
+//! Call [`init`] first thing in `main`, before any thread is started. It
+//! takes the sockets passed in `LISTEN_PID`/`LISTEN_FDS`/`LISTEN_FDNAMES` and
+//! removes the variables so child processes don't pick up the same fds;
+//! changing the environment is only sound while no other thread runs.
+//! Without `init` the variables are read on first use and left in place.
+
+use std::{
+    ffi::OsStr,
+    io,
+    net::{TcpListener, ToSocketAddrs},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram, UnixListener},
+        },
+    },
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// First fd passed by systemd.
+pub const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A socket passed by the service manager.
+#[derive(Debug)]
+pub struct ListenFd {
+    pub fd: OwnedFd,
+    /// `FileDescriptorName=` of the socket unit, `unknown` if not set.
+    pub name: String,
+}
+
+/// Not yet claimed activated sockets. `None` until the environment is read.
+static LISTEN_FDS: Mutex<Option<Vec<ListenFd>>> = Mutex::new(None);
+
+/// Parse the socket activation variables. Returns the fds meant for `pid`
+/// with their names; empty if the variables are missing or for another process.
+fn parse_listen_env(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    listen_fdnames: Option<&str>,
+    pid: u32,
+) -> io::Result<Vec<(RawFd, String)>> {
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return Ok(Vec::new());
+    };
+    let invalid = |what: &str, value: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid {}: {}", what, value),
+        )
+    };
+
+    let listen_pid: u32 = listen_pid
+        .trim()
+        .parse()
+        .map_err(|_| invalid("LISTEN_PID", listen_pid))?;
+    if listen_pid != pid {
+        log::debug!("LISTEN_PID {} is not for us ({})", listen_pid, pid);
+        return Ok(Vec::new());
+    }
+    let count: RawFd = listen_fds
+        .trim()
+        .parse()
+        .map_err(|_| invalid("LISTEN_FDS", listen_fds))?;
+
+    let names: Vec<&str> = listen_fdnames
+        .map(|n| n.split(':').collect())
+        .unwrap_or_default();
+    if !names.is_empty() && names.len() != count as usize {
+        log::warn!(
+            "LISTEN_FDNAMES has {} names for {} fds, ignore names",
+            names.len(),
+            count
+        );
+    }
+    Ok((0..count)
+        .map(|i| {
+            let name = match names.get(i as usize) {
+                Some(name) if names.len() == count as usize => name.to_string(),
+                _ => "unknown".to_string(),
+            };
+            (SD_LISTEN_FDS_START + i, name)
+        })
+        .collect())
+}
+
+fn listen_fds_from_env() -> io::Result<Vec<ListenFd>> {
+    let var = |name| std::env::var(name).ok();
+    let fds = parse_listen_env(
+        var("LISTEN_PID").as_deref(),
+        var("LISTEN_FDS").as_deref(),
+        var("LISTEN_FDNAMES").as_deref(),
+        std::process::id(),
+    )?;
+
+    fds.into_iter()
+        .map(|(raw, name)| {
+            // SAFETY: fcntl on an fd number; fails with EBADF if it isn't open.
+            if unsafe { libc::fcntl(raw, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: the fd is open and was handed to this process; the
+            // environment is read only once, so nobody else owns it.
+            let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+            Ok(ListenFd { fd, name })
+        })
+        .collect()
+}
+
+/// Take the activated sockets and clear the activation variables.
+///
+/// Must run before the process starts any thread, as it changes the
+/// environment. Returns the number of sockets passed; later calls return 0.
+/// The logger usually isn't up yet, so nothing is logged here.
+pub fn init() -> io::Result<usize> {
+    let mut guard = LISTEN_FDS.lock().unwrap_or_else(|e| e.into_inner());
+    if guard.is_some() {
+        return Ok(0);
+    }
+    let fds = listen_fds_from_env();
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+    let fds = guard.insert(fds?);
+    Ok(fds.len())
+}
+
+fn with_listen_fds<R>(f: impl FnOnce(&mut Vec<ListenFd>) -> R) -> R {
+    let mut guard = LISTEN_FDS.lock().unwrap_or_else(|e| e.into_inner());
+    let fds = guard.get_or_insert_with(|| {
+        listen_fds_from_env().unwrap_or_else(|e| {
+            log::error!("Failed to read activated sockets: {}", e);
+            Vec::new()
+        })
+    });
+    f(fds)
+}
+
+/// Take all activated sockets that were not claimed yet.
+pub fn take_listen_fds() -> Vec<ListenFd> {
+    with_listen_fds(std::mem::take)
+}
+
+/// Take the first activated socket accepted by `f`.
+pub fn take_listen_fd(f: impl Fn(&ListenFd) -> bool) -> Option<ListenFd> {
+    with_listen_fds(|fds| take_matching(fds, f))
+}
+
+fn take_matching(fds: &mut Vec<ListenFd>, f: impl Fn(&ListenFd) -> bool) -> Option<ListenFd> {
+    let idx = fds.iter().position(f)?;
+    Some(fds.remove(idx))
+}
+
+/// Address of a Unix socket to look for among the activated ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnixName<'a> {
+    Path(&'a Path),
+    Abstract(&'a [u8]),
+}
+
+impl<'a> From<&'a Path> for UnixName<'a> {
+    fn from(path: &'a Path) -> Self {
+        UnixName::Path(path)
+    }
+}
+
+fn is_unix_listener_at(listen_fd: &ListenFd, name: UnixName) -> bool {
+    is_stream_listener(listen_fd.fd.as_fd())
+        && unix_local_addr(listen_fd.fd.as_fd()).is_some_and(|local| match name {
+            UnixName::Path(path) => local.as_pathname() == Some(path),
+            UnixName::Abstract(name) => local.as_abstract_name() == Some(name),
+        })
+}
+
+/// Take the activated Unix stream listener bound to `name`, if any.
+pub fn take_unix_listener<'a>(name: impl Into<UnixName<'a>>) -> Option<UnixListener> {
+    let name = name.into();
+    take_listen_fd(|listen_fd| is_unix_listener_at(listen_fd, name))
+        .map(|listen_fd| UnixListener::from(listen_fd.fd))
+}
+
+/// Whether an unclaimed activated socket is bound to `name`. Such a socket
+/// file belongs to the service manager and must not be removed.
+pub fn is_activated_unix<'a>(name: impl Into<UnixName<'a>>) -> bool {
+    let name = name.into();
+    with_listen_fds(|fds| {
+        fds.iter()
+            .any(|listen_fd| is_unix_listener_at(listen_fd, name))
+    })
+}
+
+/// Take the activated TCP listener bound to `addr` (`ip:port`), if any.
+pub fn take_tcp_listener(addr: &str) -> Option<TcpListener> {
+    let addrs: Vec<std::net::SocketAddr> = addr.to_socket_addrs().ok()?.collect();
+    take_listen_fd(|listen_fd| {
+        is_stream_listener(listen_fd.fd.as_fd())
+            && tcp_local_addr(listen_fd.fd.as_fd()).is_some_and(|local| addrs.contains(&local))
+    })
+    .map(|listen_fd| TcpListener::from(listen_fd.fd))
+}
+
+fn sockopt(fd: BorrowedFd, opt: libc::c_int) -> Option<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: valid fd, value/len point to an int sized buffer.
+    let ret = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            opt,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (ret == 0).then_some(value)
+}
+
+/// A listening SOCK_STREAM socket.
+pub fn is_stream_listener(fd: BorrowedFd) -> bool {
+    sockopt(fd, libc::SO_TYPE) == Some(libc::SOCK_STREAM)
+        && sockopt(fd, libc::SO_ACCEPTCONN) == Some(1)
+}
+
+/// Local address of a Unix socket; `None` for other families.
+pub fn unix_local_addr(fd: BorrowedFd) -> Option<SocketAddr> {
+    let dup = fd.try_clone_to_owned().ok()?;
+    UnixListener::from(dup).local_addr().ok()
+}
+
+/// Local address of an inet socket; `None` for other families.
+pub fn tcp_local_addr(fd: BorrowedFd) -> Option<std::net::SocketAddr> {
+    let dup = fd.try_clone_to_owned().ok()?;
+    TcpListener::from(dup).local_addr().ok()
+}
+
+/// Send a state string such as `READY=1` to the service manager.
+///
+/// Returns false if `NOTIFY_SOCKET` is not set, i.e. we don't run under systemd.
+pub fn notify(state: &str) -> io::Result<bool> {
+    match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => notify_socket(&socket, state).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+/// Send `state` to the notify socket at `socket` (a path, or `@name` for an
+/// abstract socket).
+pub fn notify_socket(socket: &OsStr, state: &str) -> io::Result<()> {
+    let addr = match socket.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    let sock = UnixDatagram::unbound()?;
+    sock.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+pub fn notify_ready() -> io::Result<bool> {
+    notify("READY=1")
+}
+
+pub fn notify_stopping() -> io::Result<bool> {
+    notify("STOPPING=1")
+}
+
+pub fn notify_watchdog() -> io::Result<bool> {
+    notify("WATCHDOG=1")
+}
+
+pub fn notify_status(status: &str) -> io::Result<bool> {
+    notify(&format!("STATUS={}", status))
+}
+
+/// Watchdog timeout set by the service manager. Send `WATCHDOG=1` at least
+/// every half of it.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.trim().parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.trim().parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Sends `WATCHDOG=1` at half the interval the service manager asked for.
+pub struct Watchdog {
+    interval: Duration,
+    last: Instant,
+}
+
+impl Watchdog {
+    /// `None` if the watchdog is not enabled for this process.
+    pub fn from_env() -> Option<Self> {
+        watchdog_interval().map(|interval| Self {
+            interval: interval / 2,
+            last: Instant::now(),
+        })
+    }
+
+    /// Call regularly from the main loop.
+    pub fn tick(&mut self) {
+        if self.last.elapsed() >= self.interval {
+            if let Err(e) = notify_watchdog() {
+                log::error!("Failed to notify watchdog: {}", e);
+            }
+            self.last = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_env() {
+        assert!(parse_listen_env(None, None, None, 1).unwrap().is_empty());
+        assert!(parse_listen_env(Some("2"), Some("1"), None, 1)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            parse_listen_env(Some("7"), Some("2"), Some("ctl:data"), 7).unwrap(),
+            vec![(3, "ctl".to_string()), (4, "data".to_string())]
+        );
+        assert_eq!(
+            parse_listen_env(Some("7"), Some("2"), Some("ctl"), 7).unwrap(),
+            vec![(3, "unknown".to_string()), (4, "unknown".to_string())]
+        );
+        assert!(parse_listen_env(Some("7"), Some("x"), None, 7).is_err());
+    }
+
+    #[test]
+    fn match_listener_by_path() {
+        let dir = std::env::temp_dir();
+        let a = dir.join(format!("systemd-a-{}.sock", std::process::id()));
+        let b = dir.join(format!("systemd-b-{}.sock", std::process::id()));
+        let mut fds: Vec<ListenFd> = [&a, &b]
+            .iter()
+            .map(|path| {
+                let _ = std::fs::remove_file(path);
+                ListenFd {
+                    fd: OwnedFd::from(UnixListener::bind(path).unwrap()),
+                    name: "unknown".into(),
+                }
+            })
+            .collect();
+
+        let found =
+            take_matching(&mut fds, |l| is_unix_listener_at(l, b.as_path().into())).unwrap();
+        assert!(is_stream_listener(found.fd.as_fd()));
+        assert_eq!(fds.len(), 1);
+
+        let _ = std::fs::remove_file(&a);
+        let _ = std::fs::remove_file(&b);
+    }
+
+    #[test]
+    fn notify_stand_in_socket() {
+        let path = std::env::temp_dir().join(format!("systemd-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let manager = UnixDatagram::bind(&path).unwrap();
+
+        notify_socket(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0u8; 64];
+        let n = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,295 @@
+//! Echo benchmark for the servers in this directory and their siblings.
+//!
+//! Starts N clients at once; each connects, does the TLS handshake and
+//! echoes M messages one at a time. Reports connect, handshake and
+//! round-trip percentiles and the overall throughput.
+//!
+//! ```text
+//! cargo run --release -- --mode plain -a 127.0.0.1:8279            # plain_svr
+//! cargo run --release --                                           # svr, tls13 svr
+//! cargo run --release -- --client-cert ../certs/echo-client.pem \
+//!     --client-key ../certs/echo-client-key.pem                    # svr_rustls
+//! cargo run --release -- --ca ca.cert.pem --server-name localhost \
+//!     --client-cert client.cert.pem --client-key client.key.pem   # rustls2 (its ca_test certs)
+//! cargo run --release -- --insecure                                # tls_echo/server (self-signed)
+//! cargo run --release -- -c 64 -n 1000 --format json --label svr_rustls > svr_rustls.json
+//! ```
+//!
+//! The servers' connection limits apply to the benchmark too; start them
+//! with e.g. `--max-per-ip 1000 --rate-bytes 0 --rate-messages 0` to
+//! measure the TLS stack rather than the limits.
+
+mod report;
+mod stats;
+mod tls;
+
+use clap::{Parser, ValueEnum};
+use rustls::{ClientConnection, ServerName, StreamOwned};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use report::Report;
+use stats::Summary;
+use tls::Error;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// 평문 TCP (plain_svr)
+    Plain,
+    /// TLS (svr, svr_rustls, tls13, rustls2, tls_echo)
+    Tls,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "에코 서버 벤치마크", long_about = None)]
+struct Cli {
+    /// 서버 주소
+    #[arg(short, long, default_value = "127.0.0.1:8443")]
+    address: String,
+
+    #[arg(long, value_enum, default_value_t = Mode::Tls)]
+    mode: Mode,
+
+    /// 동시에 연결하는 클라이언트 수
+    #[arg(short, long, default_value_t = 16)]
+    clients: usize,
+
+    /// 클라이언트마다 에코할 메시지 수
+    #[arg(short = 'n', long, default_value_t = 100)]
+    messages: usize,
+
+    /// 메시지 크기 (바이트)
+    #[arg(short, long, default_value_t = 64)]
+    size: usize,
+
+    /// SNI 와 인증서 검증에 쓰는 서버 이름
+    #[arg(long, default_value = "test.edger.dev")]
+    server_name: String,
+
+    #[arg(long, default_value = "../certs/rootCA.pem")]
+    ca: PathBuf,
+
+    /// 서버 인증서를 검증하지 않음 (자체 서명 인증서를 쓰는 tls_echo/server 용)
+    #[arg(long)]
+    insecure: bool,
+
+    /// 클라이언트 인증서 (svr_rustls, rustls2 는 필수)
+    #[arg(long, requires = "client_key")]
+    client_cert: Option<PathBuf>,
+
+    #[arg(long, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
+    /// 연결, 읽기, 쓰기 제한 시간 (초)
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+
+    /// 보고서에 붙일 이름 (예: svr_rustls)
+    #[arg(long)]
+    label: Option<String>,
+}
+
+/// What one client measured, up to where it failed.
+#[derive(Default)]
+struct ClientResult {
+    connect: Option<Duration>,
+    handshake: Option<Duration>,
+    rtts: Vec<Duration>,
+    protocol: Option<String>,
+    error: Option<String>,
+}
+
+enum Conn {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Conn::Plain(stream) => stream.read(buf),
+            Conn::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Conn::Plain(stream) => stream.write(buf),
+            Conn::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Conn::Plain(stream) => stream.flush(),
+            Conn::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Settings every client shares.
+struct Plan {
+    address: SocketAddr,
+    tls: Option<(Arc<rustls::ClientConfig>, ServerName)>,
+    messages: usize,
+    size: usize,
+    timeout: Duration,
+}
+
+/// 연결, 핸드셰이크, 메시지 에코를 차례로 재면서 `result` 에 기록
+fn run_client(plan: &Plan, id: usize, result: &mut ClientResult) -> Result<(), Error> {
+    let start = Instant::now();
+    let mut stream = TcpStream::connect_timeout(&plan.address, plan.timeout)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(plan.timeout))?;
+    stream.set_write_timeout(Some(plan.timeout))?;
+    result.connect = Some(start.elapsed());
+
+    let mut conn = match &plan.tls {
+        None => Conn::Plain(stream),
+        Some((config, server_name)) => {
+            let start = Instant::now();
+            let mut tls = ClientConnection::new(Arc::clone(config), server_name.clone())?;
+            while tls.is_handshaking() {
+                tls.complete_io(&mut stream)?;
+            }
+            result.handshake = Some(start.elapsed());
+            result.protocol = Some(format!(
+                "{:?} {:?}",
+                tls.protocol_version().ok_or("no protocol version")?,
+                tls.negotiated_cipher_suite()
+                    .ok_or("no cipher suite")?
+                    .suite()
+            ));
+            Conn::Tls(Box::new(StreamOwned::new(tls, stream)))
+        }
+    };
+
+    let mut echoed = vec![0; plan.size];
+    for i in 0..plan.messages {
+        // 메시지마다 내용을 바꿔서 다른 메시지의 에코와 섞이지 않았는지 확인
+        let message: Vec<u8> = (0..plan.size)
+            .map(|j| b'a' + ((id + i + j) % 26) as u8)
+            .collect();
+        let start = Instant::now();
+        conn.write_all(&message)?;
+        conn.read_exact(&mut echoed)?;
+        result.rtts.push(start.elapsed());
+        if echoed != message {
+            return Err(format!("message {}: echo does not match", i).into());
+        }
+    }
+
+    if let Conn::Tls(tls) = &mut conn {
+        tls.conn.send_close_notify();
+        tls.flush()?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    let cli = Cli::parse();
+    if cli.clients == 0 || cli.size == 0 {
+        return Err("--clients and --size must be at least 1".into());
+    }
+
+    let tls = match cli.mode {
+        Mode::Plain => None,
+        Mode::Tls => {
+            let client = cli.client_cert.as_deref().zip(cli.client_key.as_deref());
+            let config = tls::client_config(&cli.ca, cli.insecure, client)?;
+            let server_name = ServerName::try_from(cli.server_name.as_str())
+                .map_err(|e| format!("invalid --server-name {}: {}", cli.server_name, e))?;
+            Some((config, server_name))
+        }
+    };
+    // 이름 풀이는 한 번만, 연결 시간에 들어가지 않도록
+    let address = cli
+        .address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("{}: no address", cli.address))?;
+    let plan = Arc::new(Plan {
+        address,
+        tls,
+        messages: cli.messages,
+        size: cli.size,
+        timeout: Duration::from_secs(cli.timeout),
+    });
+
+    // 모든 클라이언트가 준비되면 한꺼번에 시작
+    let barrier = Arc::new(Barrier::new(cli.clients + 1));
+    let handles: Vec<_> = (0..cli.clients)
+        .map(|id| {
+            let (plan, barrier) = (Arc::clone(&plan), Arc::clone(&barrier));
+            thread::spawn(move || {
+                let mut result = ClientResult::default();
+                barrier.wait();
+                if let Err(e) = run_client(&plan, id, &mut result) {
+                    result.error = Some(format!("client {}: {}", id, e));
+                }
+                result
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    let results: Vec<ClientResult> = handles
+        .into_iter()
+        .map(|handle| handle.join().expect("client thread panicked"))
+        .collect();
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let echoed: usize = results.iter().map(|result| result.rtts.len()).sum();
+    let report = Report {
+        label: cli.label,
+        address: cli.address,
+        mode: match cli.mode {
+            Mode::Plain => "plain",
+            Mode::Tls => "tls",
+        },
+        protocol: results.iter().find_map(|result| result.protocol.clone()),
+        clients: cli.clients,
+        messages_per_client: cli.messages,
+        message_size: cli.size,
+        completed: results
+            .iter()
+            .filter(|result| result.error.is_none())
+            .count(),
+        errors: results
+            .iter()
+            .filter_map(|result| result.error.clone())
+            .collect(),
+        elapsed_ms: elapsed * 1000.0,
+        messages_per_sec: echoed as f64 / elapsed,
+        mib_per_sec: (echoed * cli.size) as f64 / elapsed / (1024.0 * 1024.0),
+        connect: Summary::of(results.iter().filter_map(|result| result.connect).collect()),
+        handshake: Summary::of(
+            results
+                .iter()
+                .filter_map(|result| result.handshake)
+                .collect(),
+        ),
+        rtt: Summary::of(results.into_iter().flat_map(|result| result.rtts).collect()),
+    };
+    match cli.format {
+        Format::Table => print!("{}", report),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(())
+}
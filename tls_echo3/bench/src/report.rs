@@ -0,0 +1,92 @@
+//! Benchmark results, as a table for people or JSON for scripts.
+
+use serde::Serialize;
+use std::fmt;
+
+use crate::stats::Summary;
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// `--label`, e.g. the server name, to tell runs apart.
+    pub label: Option<String>,
+    pub address: String,
+    pub mode: &'static str,
+    /// Version and cipher suite the first client negotiated.
+    pub protocol: Option<String>,
+    pub clients: usize,
+    pub messages_per_client: usize,
+    pub message_size: usize,
+    /// Clients that sent and got back all their messages.
+    pub completed: usize,
+    /// One line per failed client.
+    pub errors: Vec<String>,
+    pub elapsed_ms: f64,
+    /// Echoed messages per second over the whole run.
+    pub messages_per_sec: f64,
+    /// Echoed payload, one direction, per second over the whole run.
+    pub mib_per_sec: f64,
+    pub connect: Option<Summary>,
+    pub handshake: Option<Summary>,
+    pub rtt: Option<Summary>,
+}
+
+/// How many failed clients the table lists.
+const SHOWN_ERRORS: usize = 5;
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}", label)?;
+        }
+        write!(f, "server      {} ({}", self.address, self.mode)?;
+        if let Some(protocol) = &self.protocol {
+            write!(f, ", {}", protocol)?;
+        }
+        writeln!(f, ")")?;
+        writeln!(
+            f,
+            "clients     {} ({} completed, {} failed), {} x {} byte messages each",
+            self.clients,
+            self.completed,
+            self.errors.len(),
+            self.messages_per_client,
+            self.message_size
+        )?;
+        writeln!(
+            f,
+            "elapsed     {:.3} s, {:.1} msg/s, {:.2} MiB/s",
+            self.elapsed_ms / 1000.0,
+            self.messages_per_sec,
+            self.mib_per_sec
+        )?;
+        writeln!(
+            f,
+            "{:<11} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "(ms)", "count", "min", "mean", "p50", "p90", "p99", "max"
+        )?;
+        for (name, summary) in [
+            ("connect", &self.connect),
+            ("handshake", &self.handshake),
+            ("rtt", &self.rtt),
+        ] {
+            if let Some(s) = summary {
+                writeln!(
+                    f,
+                    "{:<11} {:>7} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+                    name, s.count, s.min, s.mean, s.p50, s.p90, s.p99, s.max
+                )?;
+            }
+        }
+        for error in self.errors.iter().take(SHOWN_ERRORS) {
+            writeln!(f, "error       {}", error)?;
+        }
+        if self.errors.len() > SHOWN_ERRORS {
+            writeln!(
+                f,
+                "error       ... {} more",
+                self.errors.len() - SHOWN_ERRORS
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+//! Percentile summaries of measured durations.
+
+use serde::Serialize;
+use std::time::Duration;
+
+/// Milliseconds, so the JSON report is easy to plot.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub count: usize,
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+impl Summary {
+    /// `None` without samples.
+    pub fn of(mut samples: Vec<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        // nearest rank: the smallest sample with at least p% at or below it
+        let percentile = |p: usize| {
+            let rank = (samples.len() * p).div_ceil(100).max(1);
+            ms(samples[rank - 1])
+        };
+        let total: Duration = samples.iter().sum();
+        Some(Self {
+            count: samples.len(),
+            min: ms(samples[0]),
+            mean: ms(total) / samples.len() as f64,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: ms(samples[samples.len() - 1]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let samples = (1..=200).rev().map(Duration::from_millis).collect();
+        let summary = Summary::of(samples).unwrap();
+        assert_eq!(summary.count, 200);
+        assert_eq!((summary.min, summary.max), (1.0, 200.0));
+        assert_eq!(summary.mean, 100.5);
+        assert_eq!(
+            (summary.p50, summary.p90, summary.p99),
+            (100.0, 180.0, 198.0)
+        );
+
+        let one = Summary::of(vec![Duration::from_micros(1500)]).unwrap();
+        assert_eq!((one.p50, one.p99), (1.5, 1.5));
+        assert_eq!(Summary::of(Vec::new()), None);
+    }
+}
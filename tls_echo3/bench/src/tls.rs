@@ -0,0 +1,96 @@
+//! rustls client configuration for the servers under test.
+
+use rustls::client::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier, WebPkiVerifier,
+};
+use rustls::{
+    Certificate, ClientConfig, DigitallySignedStruct, PrivateKey, RootCertStore, ServerName,
+};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Accepts any server certificate and handshake signature. Only for
+/// servers with a certificate webpki cannot check, like the self-signed
+/// one of `tls_echo/server`.
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &Certificate,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &Certificate,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", path.display()).into());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey, Error> {
+    rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?))?
+        .into_iter()
+        .next()
+        .map(PrivateKey)
+        .ok_or_else(|| format!("no PKCS#8 private key in {}", path.display()).into())
+}
+
+/// `ca` is ignored with `insecure`; `client` is the certificate and key for
+/// servers that require client authentication.
+pub fn client_config(
+    ca: &Path,
+    insecure: bool,
+    client: Option<(&Path, &Path)>,
+) -> Result<Arc<ClientConfig>, Error> {
+    let verifier: Arc<dyn ServerCertVerifier> = if insecure {
+        Arc::new(NoVerification)
+    } else {
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(ca)? {
+            roots.add(&cert)?;
+        }
+        Arc::new(WebPkiVerifier::new(roots, None))
+    };
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier);
+    let mut config = match client {
+        Some((cert, key)) => builder.with_client_auth_cert(read_certs(cert)?, read_key(key)?)?,
+        None => builder.with_no_client_auth(),
+    };
+    // 세션 재개 없이 매번 전체 핸드셰이크를 재도록
+    config.resumption = rustls::client::Resumption::disabled();
+    Ok(Arc::new(config))
+}
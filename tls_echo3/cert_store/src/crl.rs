@@ -0,0 +1,71 @@
+//! Certificate revocation lists, PEM (one or more `X509 CRL` blocks) or DER.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::pem;
+
+/// A DER file starts with a SEQUENCE; PEM starts with text.
+pub fn is_der(data: &[u8]) -> bool {
+    data.first() == Some(&0x30)
+}
+
+/// DER of each CRL in `data`.
+pub fn crl_ders(data: &[u8]) -> Vec<Vec<u8>> {
+    if is_der(data) {
+        vec![data.to_vec()]
+    } else {
+        pem::blocks(data, "X509 CRL")
+    }
+}
+
+/// DER of each CRL in the file at `path`; a file with none is an error.
+pub fn read_crls(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let crls = crl_ders(&fs::read(path)?);
+    if crls.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no CRL in {}", path.display()),
+        ));
+    }
+    Ok(crls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_pem_and_der() {
+        let mut ca_params = rcgen::CertificateParams::new(vec![]);
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        ca_params.key_usages = vec![rcgen::KeyUsagePurpose::CrlSign];
+        // Ed25519 signatures are deterministic, so the PEM and DER below match
+        ca_params.alg = &rcgen::PKCS_ED25519;
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+        let crl =
+            rcgen::CertificateRevocationList::from_params(rcgen::CertificateRevocationListParams {
+                this_update: rcgen::date_time_ymd(2026, 1, 1),
+                next_update: rcgen::date_time_ymd(2027, 1, 1),
+                crl_number: rcgen::SerialNumber::from(1u64),
+                issuing_distribution_point: None,
+                revoked_certs: vec![rcgen::RevokedCertParams {
+                    serial_number: rcgen::SerialNumber::from(42u64),
+                    revocation_time: rcgen::date_time_ymd(2026, 6, 1),
+                    reason_code: Some(rcgen::RevocationReason::KeyCompromise),
+                    invalidity_date: None,
+                }],
+                key_identifier_method: rcgen::KeyIdMethod::Sha256,
+                alg: &rcgen::PKCS_ED25519,
+            })
+            .unwrap();
+        let der = crl.serialize_der_with_signer(&ca).unwrap();
+        let pem = crl.serialize_pem_with_signer(&ca).unwrap();
+
+        assert_eq!(crl_ders(&der), vec![der.clone()]);
+        let two = format!("{}{}", pem, pem);
+        assert_eq!(crl_ders(two.as_bytes()), vec![der.clone(), der]);
+        assert!(crl_ders(b"-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n").is_empty());
+    }
+}
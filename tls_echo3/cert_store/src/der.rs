@@ -0,0 +1,145 @@
+//! Just enough DER to read certificates and CRLs without a parser crate.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_SEQUENCE: u8 = 0x30;
+pub const TAG_SET: u8 = 0x31;
+pub const TAG_UTC_TIME: u8 = 0x17;
+pub const TAG_GENERALIZED_TIME: u8 = 0x18;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// `(tag, contents, rest)` of the DER element at the start of `data`.
+pub fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&first, mut data) = data.split_first()?;
+    let len = if first & 0x80 == 0 {
+        usize::from(first)
+    } else {
+        let n = usize::from(first & 0x7f);
+        if n == 0 || n > 4 || data.len() < n {
+            return None;
+        }
+        let len = data[..n]
+            .iter()
+            .fold(0, |len, &b| len << 8 | usize::from(b));
+        data = &data[n..];
+        len
+    };
+    if data.len() < len {
+        return None;
+    }
+    let (contents, rest) = data.split_at(len);
+    Some((tag, contents, rest))
+}
+
+/// Every element in `data`, e.g. the contents of a SEQUENCE.
+pub fn elements(mut data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut items = Vec::new();
+    while !data.is_empty() {
+        let (tag, contents, rest) = read_tlv(data)?;
+        items.push((tag, contents));
+        data = rest;
+    }
+    Some(items)
+}
+
+/// UTCTime or GeneralizedTime contents as a point in time.
+pub fn read_time(tag: u8, contents: &[u8]) -> Option<SystemTime> {
+    let time = std::str::from_utf8(contents).ok()?;
+    let secs = match tag {
+        TAG_UTC_TIME => {
+            // YYMMDDHHMMSSZ, 50..99 are 19xx
+            let yy: i64 = time.get(..2)?.parse().ok()?;
+            let year = if yy >= 50 { 1900 + yy } else { 2000 + yy };
+            epoch_secs(year, time.get(2..)?)?
+        }
+        TAG_GENERALIZED_TIME => epoch_secs(time.get(..4)?.parse().ok()?, time.get(4..)?)?,
+        _ => return None,
+    };
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// `2027-10-19 12:00:00 UTC`
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let (year, month, day) = civil_from_days((secs / SECS_PER_DAY) as i64);
+    let time = secs % SECS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// `1.2.840.10045.4.3.2`
+pub fn format_oid(contents: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut value = 0u64;
+    for &b in contents {
+        value = value << 7 | u64::from(b & 0x7f);
+        if b & 0x80 != 0 {
+            continue;
+        }
+        if arcs.is_empty() {
+            let first = (value / 40).min(2);
+            arcs.push(first);
+            arcs.push(value - first * 40);
+        } else {
+            arcs.push(value);
+        }
+        value = 0;
+    }
+    arcs.iter()
+        .map(|arc| arc.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// `MMDDHHMMSSZ` of `year` as seconds since the epoch.
+fn epoch_secs(year: i64, rest: &str) -> Option<u64> {
+    if rest.len() != 11 || !rest.ends_with('Z') {
+        return None;
+    }
+    let field = |i: usize| rest.get(i..i + 2)?.parse::<u64>().ok();
+    let (month, day) = (field(0)?, field(2)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(days * SECS_PER_DAY + field(4)? * 3600 + field(6)? * 60 + field(8)?)
+}
+
+// Howard Hinnant's days_from_civil / civil_from_days
+
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u64;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u64;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
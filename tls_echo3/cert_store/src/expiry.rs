@@ -0,0 +1,90 @@
+//! `notAfter` of a PEM certificate, read straight from the DER so it works
+//! the same for the openssl and rustls servers.
+
+use std::fmt;
+use std::time::SystemTime;
+
+use crate::der::{TAG_SEQUENCE, format_time, read_time, read_tlv};
+use crate::pem;
+
+const TAG_VERSION: u8 = 0xa0;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expiry {
+    pub not_after: SystemTime,
+}
+
+impl Expiry {
+    pub fn is_expired(&self) -> bool {
+        self.not_after <= SystemTime::now()
+    }
+}
+
+/// `2027-10-19 12:00:00 UTC (in 365 days)`
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_time(self.not_after))?;
+        match self.not_after.duration_since(SystemTime::now()) {
+            Ok(left) => write!(f, " (in {} days)", left.as_secs() / SECS_PER_DAY),
+            Err(e) => write!(
+                f,
+                " (expired {} days ago)",
+                e.duration().as_secs() / SECS_PER_DAY
+            ),
+        }
+    }
+}
+
+/// Expiry of the first certificate in `cert_pem`, the leaf of a chain file.
+pub fn not_after(cert_pem: &[u8]) -> Option<Expiry> {
+    let der = pem::blocks(cert_pem, "CERTIFICATE").into_iter().next()?;
+    let (tag, cert, _) = read_tlv(&der)?;
+    if tag != TAG_SEQUENCE {
+        return None;
+    }
+    let (tag, mut tbs, _) = read_tlv(cert)?;
+    if tag != TAG_SEQUENCE {
+        return None;
+    }
+    // version (optional), serialNumber, signature, issuer, validity
+    if tbs.first() == Some(&TAG_VERSION) {
+        tbs = read_tlv(tbs)?.2;
+    }
+    for _ in 0..3 {
+        tbs = read_tlv(tbs)?.2;
+    }
+    let (tag, validity, _) = read_tlv(tbs)?;
+    if tag != TAG_SEQUENCE {
+        return None;
+    }
+    let (_, _, rest) = read_tlv(validity)?;
+    let (tag, time, _) = read_tlv(rest)?;
+    Some(Expiry {
+        not_after: read_time(tag, time)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_not_after_of_generated_cert() {
+        let mut params = rcgen::CertificateParams::new(vec!["echo.edger.dev".to_string()]);
+        params.not_after = rcgen::date_time_ymd(2031, 5, 6);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let pem = cert.serialize_pem().unwrap();
+
+        let expiry = not_after(pem.as_bytes()).unwrap();
+        assert!(!expiry.is_expired());
+        assert!(
+            expiry
+                .to_string()
+                .starts_with("2031-05-06 00:00:00 UTC (in ")
+        );
+
+        assert_eq!(not_after(b"not a certificate"), None);
+    }
+}
@@ -0,0 +1,251 @@
+//! Server certificate and key that can be replaced while the server runs.
+//!
+//! A [`CertStore`] holds whatever the server builds from the pair (an
+//! openssl `SslAcceptor`, a rustls `CertifiedKey`, ...) behind an `Arc`.
+//! Every handshake takes [`CertStore::current`], so a reload only affects
+//! new handshakes and open sessions keep the value they started with.
+//!
+//! [`CertStore::watch`] reloads when either file, or one added with
+//! [`CertStore::watch_also`] such as a CRL, changes or the process gets
+//! SIGHUP. A pair that does not load, e.g. the certificate was replaced
+//! but the key not yet, is logged and the previous one stays in use.
+//!
+//! ```no_run
+//! use cert_store::CertStore;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let store = Arc::new(
+//!     CertStore::new("../certs/server.pem", "../certs/server-key.pem", |cert, key| {
+//!         // build and validate the TLS context here
+//!         Ok((std::fs::read(cert)?, std::fs::read(key)?))
+//!     })
+//!     .unwrap(),
+//! );
+//! CertStore::watch(&store, Duration::from_secs(2)).unwrap();
+//! let (_cert, _key) = &*store.current();
+//! ```
+
+pub mod crl;
+pub mod der;
+mod expiry;
+pub mod pem;
+#[cfg(feature = "test-pki")]
+pub mod test_pki;
+
+pub use expiry::{Expiry, not_after};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Builds the server value from the certificate and key paths. It should
+/// fail if the pair does not match, so a bad pair is never put in use.
+pub type Loader<T> = dyn Fn(&Path, &Path) -> Result<T, Error> + Send + Sync;
+
+/// How often [`CertStore::watch`] looks at the files by default.
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct CertStore<T> {
+    cert: PathBuf,
+    key: PathBuf,
+    /// Other files the loader reads.
+    also: Vec<PathBuf>,
+    loader: Box<Loader<T>>,
+    current: RwLock<Arc<T>>,
+}
+
+impl<T> CertStore<T> {
+    /// Load the pair once; a failure here is returned, unlike in reloads.
+    pub fn new<F>(
+        cert: impl Into<PathBuf>,
+        key: impl Into<PathBuf>,
+        loader: F,
+    ) -> Result<Self, Error>
+    where
+        F: Fn(&Path, &Path) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        let cert = cert.into();
+        let key = key.into();
+        let value = loader(&cert, &key)?;
+        let store = Self {
+            cert,
+            key,
+            also: Vec::new(),
+            loader: Box::new(loader),
+            current: RwLock::new(Arc::new(value)),
+        };
+        store.log_loaded("loaded");
+        Ok(store)
+    }
+
+    /// Also reload when `path` changes, e.g. the CA file or CRL the loader
+    /// reads. A missing file counts as a state too, so creating it reloads.
+    pub fn watch_also(mut self, path: impl Into<PathBuf>) -> Self {
+        self.also.push(path.into());
+        self
+    }
+
+    pub fn cert_path(&self) -> &Path {
+        &self.cert
+    }
+
+    /// Value for a new handshake.
+    pub fn current(&self) -> Arc<T> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    /// Load the pair again and swap it in if it loads. On error the
+    /// previous value stays in use.
+    pub fn reload(&self) -> Result<(), Error> {
+        let value = (self.loader)(&self.cert, &self.key)?;
+        *self.current.write().unwrap() = Arc::new(value);
+        self.log_loaded("reloaded");
+        Ok(())
+    }
+
+    /// Expiry of the certificate file as it is now on disk.
+    pub fn expiry(&self) -> io::Result<Option<Expiry>> {
+        Ok(not_after(&fs::read(&self.cert)?))
+    }
+
+    fn log_loaded(&self, what: &str) {
+        match self.expiry() {
+            Ok(Some(expiry)) => {
+                println!(
+                    "certificate {} {}, expires {}",
+                    self.cert.display(),
+                    what,
+                    expiry
+                );
+                if expiry.is_expired() {
+                    eprintln!("certificate {} has expired", self.cert.display());
+                }
+            }
+            Ok(None) => println!(
+                "certificate {} {}, expiry unknown",
+                self.cert.display(),
+                what
+            ),
+            Err(e) => eprintln!("certificate {}: {}", self.cert.display(), e),
+        }
+    }
+
+    fn file_stamps(&self) -> Vec<Option<(SystemTime, u64)>> {
+        [&self.cert, &self.key]
+            .into_iter()
+            .chain(&self.also)
+            .map(|path| file_stamp(path))
+            .collect()
+    }
+}
+
+impl<T: Send + Sync + 'static> CertStore<T> {
+    /// Reload in a thread when the certificate or key file changes, or on
+    /// SIGHUP. The thread ends once the store is dropped.
+    pub fn watch(store: &Arc<Self>, interval: Duration) -> io::Result<thread::JoinHandle<()>> {
+        let hangup = Arc::new(AtomicBool::new(false));
+        let signal = signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&hangup))?;
+        let mut seen = store.file_stamps();
+        let store: Weak<Self> = Arc::downgrade(store);
+        Ok(thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+                let stamps = store.file_stamps();
+                let signalled = hangup.swap(false, Ordering::Relaxed);
+                if !signalled && stamps == seen {
+                    continue;
+                }
+                // remember the stamps even if the load fails: retry on the
+                // next change, not on every tick
+                seen = stamps;
+                if signalled {
+                    println!("SIGHUP: reloading {}", store.cert.display());
+                }
+                if let Err(e) = store.reload() {
+                    eprintln!(
+                        "certificate {} not reloaded, keeping the current one: {}",
+                        store.cert.display(),
+                        e
+                    );
+                }
+            }
+            signal_hook::low_level::unregister(signal);
+        }))
+    }
+}
+
+/// Modification time and size; `None` while the file is missing.
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Instant;
+
+    fn wait_for(mut cond: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if cond() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[test]
+    fn reloads_on_change_and_keeps_old_pair_on_error() {
+        let dir = std::env::temp_dir().join(format!("cert_store_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        fs::write(&cert, "one").unwrap();
+        fs::write(&key, "one").unwrap();
+
+        let loads = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&loads);
+        let store = Arc::new(
+            CertStore::new(&cert, &key, move |cert, key| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let (cert, key) = (fs::read_to_string(cert)?, fs::read_to_string(key)?);
+                if cert != key {
+                    return Err(format!("{} does not match {}", cert, key).into());
+                }
+                Ok(cert)
+            })
+            .unwrap(),
+        );
+        let watcher = CertStore::watch(&store, Duration::from_millis(20)).unwrap();
+
+        // only the cert replaced: the pair does not match, keep the old one
+        fs::write(&cert, "two").unwrap();
+        assert!(wait_for(|| loads.load(Ordering::Relaxed) >= 2));
+        assert_eq!(*store.current(), "one");
+
+        fs::write(&key, "two").unwrap();
+        assert!(wait_for(|| *store.current() == "two"));
+
+        // SIGHUP reloads even if nothing changed
+        let before = loads.load(Ordering::Relaxed);
+        signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+        assert!(wait_for(|| loads.load(Ordering::Relaxed) > before));
+
+        drop(store);
+        watcher.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
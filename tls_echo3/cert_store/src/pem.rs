@@ -0,0 +1,49 @@
+//! Just enough PEM to get the DER out.
+
+/// DER of every `-----BEGIN <label>-----` block, in file order. Blocks that
+/// are not valid base64 are skipped.
+pub fn blocks(data: &[u8], label: &str) -> Vec<Vec<u8>> {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return Vec::new();
+    };
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let mut ders = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(&begin) {
+        rest = &rest[start + begin.len()..];
+        let Some(stop) = rest.find(&end) else {
+            break;
+        };
+        if let Some(der) = base64_decode(&rest[..stop]) {
+            ders.push(der);
+        }
+        rest = &rest[stop + end.len()..];
+    }
+    ders
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            c if c.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        acc = acc << 6 | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
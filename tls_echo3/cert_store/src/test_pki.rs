@@ -0,0 +1,126 @@
+//! Throwaway CA, certificates and CRLs for handshake tests
+//! (feature `test-pki`).
+//!
+//! The CA signs with Ed25519, whose signatures are deterministic, so the
+//! PEM and DER of a certificate or CRL describe the same bytes.
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertificateRevocationList,
+    CertificateRevocationListParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyIdMethod,
+    KeyUsagePurpose, RevocationReason, RevokedCertParams, SerialNumber, date_time_ymd,
+};
+
+/// A certificate with its private key.
+pub struct Issued {
+    pub cert_pem: String,
+    pub cert_der: Vec<u8>,
+    /// PKCS#8
+    pub key_pem: String,
+    /// PKCS#8
+    pub key_der: Vec<u8>,
+}
+
+pub struct TestCa {
+    ca: Certificate,
+}
+
+impl Default for TestCa {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestCa {
+    pub fn new() -> Self {
+        let mut params = CertificateParams::new(vec![]);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Test CA");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        params.alg = &rcgen::PKCS_ED25519;
+        Self {
+            ca: Certificate::from_params(params).expect("CA certificate"),
+        }
+    }
+
+    pub fn cert_pem(&self) -> String {
+        self.ca.serialize_pem().expect("CA PEM")
+    }
+
+    pub fn cert_der(&self) -> Vec<u8> {
+        self.ca.serialize_der().expect("CA DER")
+    }
+
+    /// Server certificate for the DNS name `name`.
+    pub fn server(&self, name: &str, serial: u64) -> Issued {
+        self.issue(
+            name,
+            vec![name.to_string()],
+            ExtendedKeyUsagePurpose::ServerAuth,
+            serial,
+        )
+    }
+
+    /// Client certificate with common name `cn`.
+    pub fn client(&self, cn: &str, serial: u64) -> Issued {
+        self.issue(cn, vec![], ExtendedKeyUsagePurpose::ClientAuth, serial)
+    }
+
+    fn issue(
+        &self,
+        cn: &str,
+        dns_names: Vec<String>,
+        usage: ExtendedKeyUsagePurpose,
+        serial: u64,
+    ) -> Issued {
+        let mut params = CertificateParams::new(dns_names);
+        params.distinguished_name.push(DnType::CommonName, cn);
+        params.serial_number = Some(SerialNumber::from(serial));
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![usage];
+        let cert = Certificate::from_params(params).expect("certificate");
+        Issued {
+            cert_pem: cert.serialize_pem_with_signer(&self.ca).expect("PEM"),
+            cert_der: cert.serialize_der_with_signer(&self.ca).expect("DER"),
+            key_pem: cert.serialize_private_key_pem(),
+            key_der: cert.serialize_private_key_der(),
+        }
+    }
+
+    /// PEM of a CRL listing `serials`.
+    pub fn crl_pem(&self, serials: &[u64]) -> String {
+        self.crl(serials)
+            .serialize_pem_with_signer(&self.ca)
+            .expect("CRL PEM")
+    }
+
+    /// DER of a CRL listing `serials`.
+    pub fn crl_der(&self, serials: &[u64]) -> Vec<u8> {
+        self.crl(serials)
+            .serialize_der_with_signer(&self.ca)
+            .expect("CRL DER")
+    }
+
+    fn crl(&self, serials: &[u64]) -> CertificateRevocationList {
+        let revoked_certs = serials
+            .iter()
+            .map(|&serial| RevokedCertParams {
+                serial_number: SerialNumber::from(serial),
+                revocation_time: date_time_ymd(2025, 1, 1),
+                reason_code: Some(RevocationReason::KeyCompromise),
+                invalidity_date: None,
+            })
+            .collect();
+        CertificateRevocationList::from_params(CertificateRevocationListParams {
+            this_update: date_time_ymd(2025, 1, 1),
+            next_update: date_time_ymd(2099, 1, 1),
+            crl_number: SerialNumber::from(1u64),
+            issuing_distribution_point: None,
+            revoked_certs,
+            key_identifier_method: KeyIdMethod::Sha256,
+            alg: &rcgen::PKCS_ED25519,
+        })
+        .expect("CRL")
+    }
+}
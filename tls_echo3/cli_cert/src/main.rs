@@ -1,32 +1,81 @@
-use openssl::ssl::{SslConnector, SslFiletype, SslMethod};
+use openssl::ssl::{HandshakeError, SslConnector, SslFiletype, SslMethod, SslRef};
+use openssl::x509::store::X509Lookup;
+use openssl::x509::verify::X509VerifyFlags;
 use std::io::{self, Read, Write};
 use std::net::TcpStream;
+use std::path::Path;
 
 const ROOT_CA_PATH: &str = "../certs/rootCA.pem";
+/// 없으면 폐기 확인 없이 동작
+const CRL_PATH: &str = "../certs/rootCA.crl";
 const CLIENT_CERT_PATH: &str = "../certs/echo-client.pem";
 const CLIENT_KEY_PATH: &str = "../certs/echo-client-key.pem";
 
 const SERVER_ADDRESS: &str = "127.0.0.1:8443";
 const SERVER_HOSTNAME: &str = "test.edger.dev";
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// `X509_V_ERR_CERT_REVOKED` in openssl/x509_vfy.h
+const X509_V_ERR_CERT_REVOKED: i32 = 23;
+
+/// Connector that trusts `ca`, checks the server against `crl` if that file
+/// exists and presents `cert`/`key`.
+fn build_connector(
+    ca: &Path,
+    crl: &Path,
+    cert: &Path,
+    key: &Path,
+) -> Result<SslConnector, Box<dyn std::error::Error>> {
     // TLS connector 설정
     let mut connector = SslConnector::builder(SslMethod::tls())?;
-    connector.set_ca_file(ROOT_CA_PATH)?;
+    connector.set_ca_file(ca)?;
+
+    // 폐기된 서버 인증서 목록 (PEM/DER)
+    if crl.exists() {
+        let filetype = if cert_store::crl::is_der(&std::fs::read(crl)?) {
+            SslFiletype::ASN1
+        } else {
+            SslFiletype::PEM
+        };
+        let store = connector.cert_store_mut();
+        store
+            .add_lookup(X509Lookup::file())?
+            .load_crl_file(crl, filetype)?;
+        store.set_flags(X509VerifyFlags::CRL_CHECK)?;
+    }
 
     // 클라이언트 인증서 설정
-    connector.set_certificate_file(CLIENT_CERT_PATH, SslFiletype::PEM)?;
-    connector.set_private_key_file(CLIENT_KEY_PATH, SslFiletype::PEM)?;
+    connector.set_certificate_file(cert, SslFiletype::PEM)?;
+    connector.set_private_key_file(key, SslFiletype::PEM)?;
 
     // wrong client cert
     // connector.set_certificate_file("test-client.pem", SslFiletype::PEM)?;
     // connector.set_private_key_file("test-client-key.pem", SslFiletype::PEM)?;
 
-    let connector = connector.build();
+    Ok(connector.build())
+}
+
+/// 서버 인증서가 CRL 에 있어서 핸드셰이크가 실패했는지
+fn is_revoked(ssl: &SslRef) -> bool {
+    ssl.verify_result().as_raw() == X509_V_ERR_CERT_REVOKED
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let connector = build_connector(
+        Path::new(ROOT_CA_PATH),
+        Path::new(CRL_PATH),
+        Path::new(CLIENT_CERT_PATH),
+        Path::new(CLIENT_KEY_PATH),
+    )?;
 
     // 서버에 연결
     let stream = TcpStream::connect(SERVER_ADDRESS)?;
-    let mut ssl_stream = connector.connect(SERVER_HOSTNAME, stream)?;
+    let mut ssl_stream = match connector.connect(SERVER_HOSTNAME, stream) {
+        Ok(stream) => stream,
+        Err(HandshakeError::Failure(mid)) if is_revoked(mid.ssl()) => {
+            return Err(format!("서버 인증서가 폐기됨 ({})", CRL_PATH).into());
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     println!("서버에 연결되었습니다. 메시지를 입력하세요 (종료하려면 'quit' 입력):");
 
@@ -55,3 +104,76 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cert_store::test_pki::TestCa;
+    use openssl::pkey::PKey;
+    use openssl::ssl::SslAcceptor;
+    use openssl::x509::X509;
+    use std::fs;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const REVOKED: u64 = 2;
+
+    #[derive(Debug, PartialEq)]
+    enum Outcome {
+        Connected,
+        Revoked,
+        Failed,
+    }
+
+    /// Handshake over loopback with a server whose certificate has `serial`.
+    fn connect(ca: &TestCa, serial: u64, crl_der: Option<Vec<u8>>) -> Outcome {
+        let server_cert = ca.server("localhost", serial);
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor
+            .set_certificate(&X509::from_der(&server_cert.cert_der).unwrap())
+            .unwrap();
+        acceptor
+            .set_private_key(&PKey::private_key_from_der(&server_cert.key_der).unwrap())
+            .unwrap();
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = acceptor.accept(stream);
+        });
+
+        let dir = std::env::temp_dir().join(format!("cli_cert_crl_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let client_cert = ca.client("echo-client", 10);
+        let [ca_file, crl, cert, key] =
+            ["ca.pem", "ca.crl", "client.pem", "client-key.pem"].map(|name| dir.join(name));
+        fs::write(&ca_file, ca.cert_pem()).unwrap();
+        if let Some(der) = &crl_der {
+            fs::write(&crl, der).unwrap();
+        }
+        fs::write(&cert, &client_cert.cert_pem).unwrap();
+        fs::write(&key, &client_cert.key_pem).unwrap();
+        let connector = build_connector(&ca_file, &crl, &cert, &key).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let outcome = match connector.connect("localhost", stream) {
+            Ok(_) => Outcome::Connected,
+            Err(HandshakeError::Failure(mid)) if is_revoked(mid.ssl()) => Outcome::Revoked,
+            Err(_) => Outcome::Failed,
+        };
+        server.join().unwrap();
+        outcome
+    }
+
+    #[test]
+    fn refuses_revoked_server_certificate() {
+        let ca = TestCa::new();
+        let crl = Some(ca.crl_der(&[REVOKED]));
+
+        assert_eq!(connect(&ca, 3, crl.clone()), Outcome::Connected);
+        assert_eq!(connect(&ca, REVOKED, crl), Outcome::Revoked);
+        assert_eq!(connect(&ca, REVOKED, None), Outcome::Connected);
+    }
+}
@@ -0,0 +1,5 @@
+//! Session resumption and CRL checks of the rustls echo client, in a
+//! library so other crates' tests can run the client side of a handshake.
+
+pub mod resumption;
+pub mod revocation;
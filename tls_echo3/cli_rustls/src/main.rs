@@ -1,12 +1,19 @@
 use anyhow::Result;
 use clap::Parser;
+use rustls::client::{ServerCertVerifier, WebPkiVerifier};
 use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore};
 use std::io::{BufReader, Read, Write};
 use std::net::TcpStream;
+use std::path::Path;
 use std::sync::Arc;
 
+use cli_rustls::resumption::{self, CountingVerifier};
+use cli_rustls::revocation::{is_revoked, CrlVerifier};
+
 const SERVER_HOSTNAME: &str = "127.0.0.1";
 const ROOT_CA_PATH: &str = "../certs/rootCA.pem";
+/// 없으면 폐기 확인 없이 동작
+const CRL_PATH: &str = "../certs/rootCA.crl";
 const CLIENT_CERT_PATH: &str = "../certs/echo-client.pem";
 const CLIENT_KEY_PATH: &str = "../certs/echo-client-key.pem";
 
@@ -16,6 +23,22 @@ struct Cli {
     /// 서버 주소 (예: 127.0.0.1:8443)
     #[arg(short, long, default_value = "127.0.0.1:8443")]
     server_address: String,
+
+    /// 클라이언트 인증서 제출 (svr_rustls 는 필수)
+    #[arg(long)]
+    client_cert: bool,
+
+    /// N 번 다시 연결해서 전체/재개 핸드셰이크 시간 비교
+    #[arg(long, value_name = "N")]
+    reconnect: Option<usize>,
+
+    /// 세션 재개 시 메시지를 TLS 1.3 0-RTT 로 전송 (재전송 공격 가능)
+    #[arg(long)]
+    early_data: bool,
+
+    /// --reconnect 에서 연결마다 에코할 메시지
+    #[arg(long, default_value = "ping")]
+    message: String,
 }
 
 fn load_client_cert_and_key() -> Result<(Vec<Certificate>, PrivateKey)> {
@@ -36,22 +59,50 @@ fn main() -> Result<()> {
     // Root CA 인증서 로드
     let mut root_store = RootCertStore::empty();
     let certs = rustls_pemfile::certs(&mut BufReader::new(std::fs::File::open(ROOT_CA_PATH)?))?;
-    for cert in certs {
-        root_store.add(&rustls::Certificate(cert))?;
+    for cert in &certs {
+        root_store.add(&rustls::Certificate(cert.clone()))?;
     }
 
     // 클라이언트 인증서와 키 로드
     let (client_certs, client_key) = load_client_cert_and_key()?;
 
     // TLS 설정
-    let config = ClientConfig::builder()
+    // 폐기된 서버 인증서 목록 (PEM/DER)
+    let inner: Arc<dyn ServerCertVerifier> = if Path::new(CRL_PATH).exists() {
+        let crls = cert_store::crl::read_crls(Path::new(CRL_PATH))?;
+        Arc::new(CrlVerifier::new(certs, crls)?)
+    } else {
+        Arc::new(WebPkiVerifier::new(root_store, None))
+    };
+    // 재개된 핸드셰이크는 서버 인증서를 다시 검증하지 않으므로 검증 횟수로 구분
+    let verifier = Arc::new(CountingVerifier::new(inner));
+    let builder = ClientConfig::builder()
         .with_safe_defaults()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
-    // .with_client_auth_cert(client_certs, client_key)?;
+        .with_custom_certificate_verifier(verifier.clone());
+    let mut config = if cli.client_cert {
+        builder.with_client_auth_cert(client_certs, client_key)?
+    } else {
+        builder.with_no_client_auth()
+    };
+    // 세션 캐시는 ClientConfig 의 기본값 (메모리, 256개)
+    config.enable_early_data = cli.early_data;
+    let config = Arc::new(config);
+    let server_name: rustls::ServerName = SERVER_HOSTNAME.try_into()?;
+
+    if let Some(count) = cli.reconnect {
+        let samples = resumption::reconnect(
+            &config,
+            &verifier,
+            &cli.server_address,
+            &server_name,
+            cli.message.as_bytes(),
+            count,
+        )?;
+        handshake_stats::report(&samples);
+        return Ok(());
+    }
 
-    let mut connector =
-        rustls::ClientConnection::new(Arc::new(config), SERVER_HOSTNAME.try_into()?)?;
+    let mut connector = rustls::ClientConnection::new(config, server_name)?;
 
     // 서버에 연결
     let mut stream = TcpStream::connect(&cli.server_address)?;
@@ -70,7 +121,11 @@ fn main() -> Result<()> {
 
         // 서버에 데이터 전송
         if let Err(e) = tls_stream.write_all(input.as_bytes()) {
-            println!("write error: {}", e);
+            if is_revoked(&e) {
+                println!("server certificate revoked ({})", CRL_PATH);
+            } else {
+                println!("write error: {}", e);
+            }
             break;
         }
 
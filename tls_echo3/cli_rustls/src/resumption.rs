@@ -0,0 +1,153 @@
+//! `--reconnect N`: connect repeatedly with one `ClientConfig`, so every
+//! connection after the first can resume the session, and compare the
+//! handshake latency of full and resumed handshakes.
+//!
+//! With `--early-data` the message goes out as TLS 1.3 0-RTT data when the
+//! session allows it. Early data is not protected against replay: anyone
+//! who recorded it can send it to the server again, and the server can
+//! only refuse that within its own session cache. Send nothing in 0-RTT
+//! that is unsafe to repeat.
+
+use anyhow::{bail, Result};
+use handshake_stats::Sample;
+use rustls::client::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier, ServerName};
+use rustls::{
+    Certificate, ClientConfig, ClientConnection, DigitallySignedStruct, Error, SignatureScheme,
+    Stream,
+};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+/// Counts server certificate verifications. rustls skips them when it
+/// resumes a session, so no new count during a handshake means resumed.
+pub struct CountingVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    verified: AtomicUsize,
+}
+
+impl CountingVerifier {
+    pub fn new(inner: Arc<dyn ServerCertVerifier>) -> Self {
+        Self {
+            inner,
+            verified: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn full_handshakes(&self) -> usize {
+        self.verified.load(Ordering::Relaxed)
+    }
+}
+
+impl ServerCertVerifier for CountingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.verified.fetch_add(1, Ordering::Relaxed);
+        self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.inner.request_scts()
+    }
+}
+
+/// Connect, handshake, echo `message` once and close.
+pub fn connect_once(
+    config: &Arc<ClientConfig>,
+    verifier: &CountingVerifier,
+    address: &str,
+    server_name: &ServerName,
+    message: &[u8],
+) -> Result<Sample> {
+    let start = Instant::now();
+    let before = verifier.full_handshakes();
+    let mut tcp = TcpStream::connect(address)?;
+    tcp.set_nodelay(true)?;
+    let mut conn = ClientConnection::new(Arc::clone(config), server_name.clone())?;
+
+    // 재개할 세션이 0-RTT 를 허용하면 ClientHello 와 같이 전송
+    let mut early = 0;
+    if let Some(mut early_data) = conn.early_data() {
+        early = early_data.write(message)?;
+    }
+    while conn.is_handshaking() {
+        conn.complete_io(&mut tcp)?;
+    }
+    let handshake = start.elapsed();
+
+    // 서버가 0-RTT 를 거절했으면 다시 보내야 함
+    let early_data_accepted = early > 0 && conn.is_early_data_accepted();
+    let sent = if early_data_accepted { early } else { 0 };
+    let mut tls = Stream::new(&mut conn, &mut tcp);
+    tls.write_all(&message[sent..])?;
+    let mut reply = vec![0; message.len()];
+    tls.read_exact(&mut reply)?;
+    let echo = start.elapsed();
+    if reply != message {
+        bail!("echo does not match what was sent");
+    }
+    tls.conn.send_close_notify();
+    tls.flush()?;
+
+    Ok(Sample {
+        resumed: verifier.full_handshakes() == before,
+        early_data_accepted,
+        handshake,
+        echo,
+    })
+}
+
+pub fn reconnect(
+    config: &Arc<ClientConfig>,
+    verifier: &CountingVerifier,
+    address: &str,
+    server_name: &ServerName,
+    message: &[u8],
+    count: usize,
+) -> Result<Vec<Sample>> {
+    let mut samples = Vec::with_capacity(count);
+    for i in 1..=count {
+        let sample = connect_once(config, verifier, address, server_name, message)?;
+        println!("#{:<4} {}", i, sample);
+        samples.push(sample);
+    }
+    Ok(samples)
+}
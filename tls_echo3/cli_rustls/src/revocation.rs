@@ -0,0 +1,188 @@
+//! Server certificate revocation check against local CRLs.
+//!
+//! rustls 0.21 checks CRLs only for client certificates, so the server
+//! certificate goes through the normal `WebPkiVerifier` first and then once
+//! more through webpki with the CRLs.
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::server::UnparsedCertRevocationList;
+use rustls::{Certificate, CertificateError, Error, RootCertStore, ServerName};
+use std::io;
+use std::time::SystemTime;
+
+/// rustls 기본 검증과 같은 서명 알고리즘
+static SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+pub struct CrlVerifier {
+    inner: WebPkiVerifier,
+    /// DER of the root CA certificates.
+    roots: Vec<Vec<u8>>,
+    crls: Vec<webpki::OwnedCertRevocationList>,
+}
+
+impl CrlVerifier {
+    pub fn new(roots: Vec<Vec<u8>>, crls: Vec<Vec<u8>>) -> Result<Self, Error> {
+        let mut root_store = RootCertStore::empty();
+        for root in &roots {
+            root_store.add(&Certificate(root.clone()))?;
+        }
+        let crls = crls
+            .into_iter()
+            .map(|der| UnparsedCertRevocationList(der).parse())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::InvalidCertRevocationList)?;
+        Ok(Self {
+            inner: WebPkiVerifier::new(root_store, None),
+            roots,
+            crls,
+        })
+    }
+}
+
+impl ServerCertVerifier for CrlVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        // 체인, 유효기간, 호스트 이름은 기본 검증으로
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        let bad_encoding = |_| Error::InvalidCertificate(CertificateError::BadEncoding);
+        let anchors = self
+            .roots
+            .iter()
+            .map(|der| webpki::TrustAnchor::try_from_cert_der(der))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(bad_encoding)?;
+        let cert =
+            webpki::EndEntityCert::try_from(end_entity.0.as_slice()).map_err(bad_encoding)?;
+        let chain: Vec<&[u8]> = intermediates.iter().map(|cert| cert.0.as_slice()).collect();
+        let crls: Vec<&dyn webpki::CertRevocationList> = self
+            .crls
+            .iter()
+            .map(|crl| crl as &dyn webpki::CertRevocationList)
+            .collect();
+        let time = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
+
+        match cert.verify_for_usage(
+            SIG_ALGS,
+            &anchors,
+            &chain,
+            time,
+            webpki::KeyUsage::server_auth(),
+            &crls,
+        ) {
+            Ok(()) => Ok(verified),
+            Err(webpki::Error::CertRevoked) => {
+                Err(Error::InvalidCertificate(CertificateError::Revoked))
+            }
+            Err(e) => Err(Error::General(format!("revocation check failed: {:?}", e))),
+        }
+    }
+}
+
+/// 서버 인증서가 CRL 에 있어서 핸드셰이크가 실패했는지
+pub fn is_revoked(e: &io::Error) -> bool {
+    matches!(
+        e.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+        Some(Error::InvalidCertificate(CertificateError::Revoked))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cert_store::test_pki::TestCa;
+    use rustls::{
+        ClientConfig, ClientConnection, ConnectionCommon, PrivateKey, ServerConfig,
+        ServerConnection,
+    };
+    use std::sync::Arc;
+
+    const REVOKED: u64 = 2;
+
+    /// Pass pending TLS records from one side to the other. False if there
+    /// were none.
+    fn transfer<A, B>(
+        from: &mut ConnectionCommon<A>,
+        to: &mut ConnectionCommon<B>,
+    ) -> Result<bool, Error> {
+        let mut records = Vec::new();
+        while from.wants_write() {
+            from.write_tls(&mut records).unwrap();
+        }
+        let mut rest = records.as_slice();
+        while !rest.is_empty() {
+            to.read_tls(&mut rest).unwrap();
+            to.process_new_packets()?;
+        }
+        Ok(!records.is_empty())
+    }
+
+    /// Handshake in memory with a server whose certificate has `serial`.
+    fn connect(ca: &TestCa, serial: u64, crls: Vec<Vec<u8>>) -> Result<(), Error> {
+        let server_cert = ca.server("localhost", serial);
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(server_cert.cert_der)],
+                PrivateKey(server_cert.key_der),
+            )
+            .unwrap();
+        let mut server = ServerConnection::new(Arc::new(config)).unwrap();
+
+        let verifier = CrlVerifier::new(vec![ca.cert_der()], crls).unwrap();
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        let mut client =
+            ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+
+        while client.is_handshaking() || server.is_handshaking() {
+            let sent = transfer(&mut client, &mut server)?;
+            let received = transfer(&mut server, &mut client)?;
+            assert!(sent || received, "handshake stalled");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_revoked_server_certificate() {
+        let ca = TestCa::new();
+        let crls = vec![ca.crl_der(&[REVOKED])];
+
+        assert_eq!(connect(&ca, 3, crls.clone()), Ok(()));
+        assert_eq!(connect(&ca, REVOKED, vec![ca.crl_der(&[])]), Ok(()));
+        let err = connect(&ca, REVOKED, crls).unwrap_err();
+        assert_eq!(err, Error::InvalidCertificate(CertificateError::Revoked));
+        // what the client sees from the stream
+        assert!(is_revoked(&io::Error::new(io::ErrorKind::InvalidData, err)));
+    }
+}
@@ -0,0 +1,60 @@
+//! Token bucket: `rate` tokens per second, at most `burst` saved up.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Starts full.
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take `n` tokens now and return how long to wait until they would
+    /// have been there. Going into debt keeps the long-run rate exact even
+    /// for takes bigger than the burst.
+    pub fn take(&mut self, n: f64) -> Duration {
+        self.take_at(n, Instant::now())
+    }
+
+    fn take_at(&mut self, n: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - n;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_tokens_beyond_the_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(100.0, 50.0);
+        bucket.last = start;
+        assert_eq!(bucket.take_at(50.0, start), Duration::ZERO);
+        // 10 more right away: 0.1 s worth at 100/s
+        assert_eq!(bucket.take_at(10.0, start), Duration::from_millis(100));
+        // 0.6 s later the debt is paid and 50 are back, no more
+        let later = start + Duration::from_millis(600);
+        assert_eq!(bucket.take_at(50.0, later), Duration::ZERO);
+        assert_eq!(bucket.tokens, 0.0);
+    }
+}
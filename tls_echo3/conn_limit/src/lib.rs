@@ -0,0 +1,556 @@
+//! Connection limits for the echo servers: a bounded worker pool instead of
+//! a thread per connection, a cap on connections per source address,
+//! handshake and idle timeouts, and token-bucket rate limits per client.
+//!
+//! ```no_run
+//! use conn_limit::{Limiter, Limits};
+//! use std::io::{Read, Write};
+//! use std::net::TcpListener;
+//!
+//! let limiter = Limiter::new(Limits::default());
+//! for stream in TcpListener::bind("0.0.0.0:8443").unwrap().incoming() {
+//!     let Ok(stream) = stream else { continue };
+//!     limiter.serve(stream, |stream, client| {
+//!         // the handshake timeout is set here; switch to the idle one
+//!         // once the handshake is done
+//!         client.set_idle_timeout(&stream).unwrap();
+//!         let mut stream = client.rate_limit(stream);
+//!         let mut buf = [0; 1024];
+//!         while let Ok(n @ 1..) = stream.read(&mut buf) {
+//!             if stream.write_all(&buf[..n]).is_err() {
+//!                 break;
+//!             }
+//!         }
+//!     });
+//! }
+//! ```
+//!
+//! Refused connections are closed right away and logged with the reason.
+//!
+//! The handshake timeout is a deadline for the whole handshake, counted from
+//! when a worker picks the connection up: a watchdog shrinks the socket
+//! timeouts as it runs out, so a client that trickles one byte at a time
+//! still times out.
+//!
+//! Behind a proxy that sends a PROXY protocol header, every connection comes
+//! from the proxy's address; [`Limiter::serve_proxied`] leaves the
+//! per-address count to [`Client::set_source`] with the header's address.
+
+mod bucket;
+
+pub use bucket::TokenBucket;
+
+use clap::Args;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the watchdog shrinks the timeouts of running handshakes, so
+/// how far past its deadline a handshake can get.
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Args, Debug, Clone)]
+pub struct Limits {
+    /// 연결을 처리하는 워커 스레드 수 (최대 동시 연결 수)
+    #[arg(long, default_value_t = 64)]
+    pub workers: usize,
+
+    /// 워커를 기다릴 수 있는 연결 수, 넘으면 거부
+    #[arg(long, default_value_t = 64)]
+    pub queue: usize,
+
+    /// 같은 IP 에서의 최대 동시 연결 수 (대기 중 포함),
+    /// PROXY 헤더를 읽는 서버는 헤더의 클라이언트 주소 기준
+    #[arg(long, default_value_t = 8)]
+    pub max_per_ip: usize,
+
+    /// 핸드셰이크 전체 제한 시간 (초), 워커가 연결을 맡은 때부터
+    #[arg(long, default_value_t = 10)]
+    pub handshake_timeout: u64,
+
+    /// 주고받는 데이터 없이 연결을 유지하는 최대 시간 (초)
+    #[arg(long, default_value_t = 60)]
+    pub idle_timeout: u64,
+
+    /// 클라이언트별 초당 수신 바이트, 0 이면 제한 없음
+    #[arg(long, default_value_t = 1024 * 1024)]
+    pub rate_bytes: u64,
+
+    /// 클라이언트별 초당 메시지 (read) 수, 0 이면 제한 없음
+    #[arg(long, default_value_t = 100)]
+    pub rate_messages: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            workers: 64,
+            queue: 64,
+            max_per_ip: 8,
+            handshake_timeout: 10,
+            idle_timeout: 60,
+            rate_bytes: 1024 * 1024,
+            rate_messages: 100,
+        }
+    }
+}
+
+impl Limits {
+    fn handshake_timeout(&self) -> Option<Duration> {
+        (self.handshake_timeout > 0).then(|| Duration::from_secs(self.handshake_timeout))
+    }
+
+    fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout > 0).then(|| Duration::from_secs(self.idle_timeout))
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Open connections per source address.
+type PerIp = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// Connections still in their handshake: the deadline and a handle to the
+/// socket to shrink its timeouts.
+type Pending = Mutex<HashMap<u64, (Instant, TcpStream)>>;
+type Handshakes = Arc<Pending>;
+
+/// Admits connections and runs them on the worker pool.
+pub struct Limiter {
+    limits: Arc<Limits>,
+    per_ip: PerIp,
+    handshakes: Handshakes,
+    next_id: AtomicU64,
+    jobs: SyncSender<Job>,
+}
+
+/// An admitted client. Holds its slot in the per-address count until it is
+/// dropped, which the handler does when it returns.
+pub struct Client {
+    /// TCP peer, or the address from the PROXY header after
+    /// [`Client::set_source`].
+    pub peer: SocketAddr,
+    /// Address this client is counted under in `per_ip`.
+    counted: Option<IpAddr>,
+    id: u64,
+    limits: Arc<Limits>,
+    per_ip: PerIp,
+    handshakes: Handshakes,
+}
+
+/// Take a slot for `ip`, or the number of connections it already has.
+fn admit(per_ip: &PerIp, ip: IpAddr, max: usize) -> Result<(), usize> {
+    let mut per_ip = per_ip.lock().unwrap_or_else(|e| e.into_inner());
+    let count = per_ip.entry(ip).or_insert(0);
+    if *count >= max {
+        let count = *count;
+        if count == 0 {
+            per_ip.remove(&ip);
+        }
+        return Err(count);
+    }
+    *count += 1;
+    Ok(())
+}
+
+fn release(per_ip: &PerIp, ip: IpAddr) {
+    let mut per_ip = per_ip.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(count) = per_ip.get_mut(&ip) {
+        *count -= 1;
+        if *count == 0 {
+            per_ip.remove(&ip);
+        }
+    }
+}
+
+/// Every [`WATCH_INTERVAL`], set the timeouts of the running handshakes to
+/// what is left until their deadline. A read that started earlier ends by
+/// its old timeout, so a handshake gets at most one interval extra. Stops
+/// with the limiter.
+fn watch(handshakes: Weak<Pending>) {
+    while let Some(handshakes) = handshakes.upgrade() {
+        let now = Instant::now();
+        for (deadline, stream) in handshakes.lock().unwrap().values() {
+            // a zero timeout would mean none
+            let left = deadline
+                .saturating_duration_since(now)
+                .max(Duration::from_millis(1));
+            let _ = stream
+                .set_read_timeout(Some(left))
+                .and_then(|()| stream.set_write_timeout(Some(left)));
+        }
+        drop(handshakes);
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+impl Limiter {
+    /// Start `limits.workers` worker threads.
+    pub fn new(limits: Limits) -> Self {
+        let (jobs, queue) = mpsc::sync_channel::<Job>(limits.queue);
+        let queue = Arc::new(Mutex::new(queue));
+        for id in 0..limits.workers.max(1) {
+            let queue = Arc::clone(&queue);
+            thread::Builder::new()
+                .name(format!("worker-{}", id))
+                .spawn(move || {
+                    loop {
+                        let job = match queue.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        };
+                        // a panicking handler must not take the worker with it
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            eprintln!("worker-{}: connection handler panicked", id);
+                        }
+                    }
+                })
+                .expect("failed to spawn worker thread");
+        }
+        let handshakes: Handshakes = Arc::new(Mutex::new(HashMap::new()));
+        if limits.handshake_timeout().is_some() {
+            let handshakes = Arc::downgrade(&handshakes);
+            thread::Builder::new()
+                .name("handshake-watchdog".to_string())
+                .spawn(move || watch(handshakes))
+                .expect("failed to spawn watchdog thread");
+        }
+        Self {
+            limits: Arc::new(limits),
+            per_ip: Arc::new(Mutex::new(HashMap::new())),
+            handshakes,
+            next_id: AtomicU64::new(0),
+            jobs,
+        }
+    }
+
+    /// Run `handler` for `stream` on a worker, or close the connection if
+    /// its address has too many connections or every worker is busy and
+    /// the queue is full. The stream has the handshake timeout set until
+    /// [`Client::handshake_done`].
+    pub fn serve<F>(&self, stream: TcpStream, handler: F)
+    where
+        F: FnOnce(TcpStream, Client) + Send + 'static,
+    {
+        self.dispatch(stream, true, handler)
+    }
+
+    /// [`Limiter::serve`] for connections that start with a PROXY protocol
+    /// header. The TCP peer is the proxy and is not counted; the handler
+    /// counts the client with [`Client::set_source`] once it has read the
+    /// header. Anyone who can connect can claim any address in the header,
+    /// so only the proxy should be able to reach the listener.
+    pub fn serve_proxied<F>(&self, stream: TcpStream, handler: F)
+    where
+        F: FnOnce(TcpStream, Client) + Send + 'static,
+    {
+        self.dispatch(stream, false, handler)
+    }
+
+    fn dispatch<F>(&self, stream: TcpStream, count_peer: bool, handler: F)
+    where
+        F: FnOnce(TcpStream, Client) + Send + 'static,
+    {
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
+            Err(e) => {
+                println!("rejected connection: no peer address: {}", e);
+                return;
+            }
+        };
+        if count_peer && let Err(count) = admit(&self.per_ip, peer.ip(), self.limits.max_per_ip) {
+            println!(
+                "rejected {}: too many connections from {} ({})",
+                peer,
+                peer.ip(),
+                count
+            );
+            return;
+        }
+        let client = Client {
+            peer,
+            counted: count_peer.then_some(peer.ip()),
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            limits: Arc::clone(&self.limits),
+            per_ip: Arc::clone(&self.per_ip),
+            handshakes: Arc::clone(&self.handshakes),
+        };
+
+        // 각 read/write 의 제한 시간, 핸드셰이크 전체 기한은 워커가 맡은 뒤
+        // watchdog 이 줄여 나감 (대기열에서 기다린 시간은 포함하지 않음)
+        let timeout = self.limits.handshake_timeout();
+        if let Err(e) = stream
+            .set_read_timeout(timeout)
+            .and_then(|()| stream.set_write_timeout(timeout))
+        {
+            println!("rejected {}: {}", peer, e);
+            return;
+        }
+
+        let job: Job = Box::new(move || {
+            if let Some(timeout) = client.limits.handshake_timeout() {
+                match stream.try_clone() {
+                    Ok(watched) => {
+                        let deadline = Instant::now() + timeout;
+                        let mut handshakes = client.handshakes.lock().unwrap();
+                        handshakes.insert(client.id, (deadline, watched));
+                    }
+                    Err(e) => {
+                        println!("rejected {}: {}", client.peer, e);
+                        return;
+                    }
+                }
+            }
+            handler(stream, client)
+        });
+        match self.jobs.try_send(job) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => println!(
+                "rejected {}: server busy ({} workers, {} waiting)",
+                peer, self.limits.workers, self.limits.queue
+            ),
+            Err(TrySendError::Disconnected(_)) => {
+                println!("rejected {}: no workers left", peer)
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Stop the handshake deadline. Done by [`Client::set_idle_timeout`];
+    /// handlers that switch the stream to non-blocking call it themselves.
+    pub fn handshake_done(&self) {
+        let mut handshakes = self.handshakes.lock().unwrap_or_else(|e| e.into_inner());
+        handshakes.remove(&self.id);
+    }
+
+    /// Count this client under `source`, the address from its PROXY header,
+    /// instead of where it is counted now. Fails if `source` already has
+    /// `max_per_ip` connections; the handler should then close it.
+    pub fn set_source(&mut self, source: SocketAddr) -> io::Result<()> {
+        if let Some(ip) = self.counted.take() {
+            release(&self.per_ip, ip);
+        }
+        admit(&self.per_ip, source.ip(), self.limits.max_per_ip).map_err(|count| {
+            io::Error::other(format!(
+                "too many connections from {} ({})",
+                source.ip(),
+                count
+            ))
+        })?;
+        self.counted = Some(source.ip());
+        self.peer = source;
+        Ok(())
+    }
+
+    /// Replace the handshake timeout with the idle timeout: a read or write
+    /// that waits longer fails with [`is_timeout`].
+    pub fn set_idle_timeout(&self, stream: &TcpStream) -> io::Result<()> {
+        // 먼저 빼야 watchdog 이 idle timeout 을 덮어쓰지 않음
+        self.handshake_done();
+        let timeout = self.limits.idle_timeout();
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)
+    }
+
+    /// For streams that are non-blocking and time out on their own.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.limits.idle_timeout()
+    }
+
+    /// Throttle reads from `stream` to the client's byte and message rates.
+    pub fn rate_limit<S>(&self, stream: S) -> RateLimited<S> {
+        let bucket = |rate: u64| (rate > 0).then(|| TokenBucket::new(rate as f64, rate as f64));
+        RateLimited {
+            inner: stream,
+            peer: self.peer,
+            bytes: bucket(self.limits.rate_bytes),
+            messages: bucket(self.limits.rate_messages),
+            throttled: false,
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.handshake_done();
+        if let Some(ip) = self.counted {
+            release(&self.per_ip, ip);
+        }
+    }
+}
+
+/// A read or write gave up because of the handshake or idle timeout.
+pub fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Stream whose reads are slowed down to the client's rate limits. The
+/// client is not disconnected, it just gets its data echoed more slowly,
+/// and TCP flow control pushes back on the sender.
+pub struct RateLimited<S> {
+    inner: S,
+    peer: SocketAddr,
+    bytes: Option<TokenBucket>,
+    messages: Option<TokenBucket>,
+    /// Logged once per connection.
+    throttled: bool,
+}
+
+impl<S> RateLimited<S> {
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S: Read> Read for RateLimited<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 {
+            return Ok(0);
+        }
+        let wait = [
+            self.bytes.as_mut().map(|bucket| bucket.take(n as f64)),
+            self.messages.as_mut().map(|bucket| bucket.take(1.0)),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or_default();
+        if !wait.is_zero() {
+            if !self.throttled {
+                println!("rate limited {}: slowing down reads", self.peer);
+                self.throttled = true;
+            }
+            thread::sleep(wait);
+        }
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for RateLimited<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc::{Receiver, channel};
+
+    /// Connect `n` clients and hand each to `limiter`; the handlers report
+    /// that they started and then block until `release` gets a message.
+    fn occupy(limiter: &Limiter, n: usize) -> (Vec<TcpStream>, Receiver<()>, mpsc::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (release, released) = channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        let (started, starts) = channel::<()>();
+        let mut clients = Vec::new();
+        for i in 0..n {
+            clients.push(TcpStream::connect(addr).unwrap());
+            let (stream, _) = listener.accept().unwrap();
+            let (released, started) = (Arc::clone(&released), started.clone());
+            limiter.serve(stream, move |_stream, _client| {
+                started.send(()).unwrap();
+                let _ = released.lock().unwrap().recv();
+            });
+            if i == 0 {
+                // the worker has picked it up, so the next one queues
+                starts.recv().unwrap();
+            }
+        }
+        (clients, starts, release)
+    }
+
+    fn closed(stream: &mut TcpStream) -> bool {
+        stream.read(&mut [0; 1]).unwrap() == 0
+    }
+
+    #[test]
+    fn refuses_when_workers_and_queue_are_full() {
+        let limiter = Limiter::new(Limits {
+            workers: 1,
+            queue: 1,
+            ..Limits::default()
+        });
+        let (mut clients, starts, release) = occupy(&limiter, 3);
+        // one running, one queued, the third refused
+        assert!(closed(&mut clients[2]));
+        assert_eq!(limiter.per_ip.lock().unwrap().values().sum::<usize>(), 2);
+
+        release.send(()).unwrap();
+        starts.recv().unwrap();
+        release.send(()).unwrap();
+        // slots are given back when the handlers return
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !limiter.per_ip.lock().unwrap().is_empty() {
+            assert!(std::time::Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn refuses_over_max_per_ip() {
+        let limiter = Limiter::new(Limits {
+            max_per_ip: 2,
+            ..Limits::default()
+        });
+        let (mut clients, _starts, release) = occupy(&limiter, 3);
+        assert!(closed(&mut clients[2]));
+        drop(release);
+    }
+
+    #[test]
+    fn handshake_deadline_covers_a_trickling_client() {
+        let limiter = Limiter::new(Limits {
+            handshake_timeout: 1,
+            ..Limits::default()
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (done, result) = channel();
+        limiter.serve(stream, move |mut stream, _client| {
+            // byte by byte like a request head, each read well within 1s
+            let start = std::time::Instant::now();
+            let mut byte = [0; 1];
+            let err = loop {
+                if let Err(e) = stream.read(&mut byte) {
+                    break e;
+                }
+            };
+            done.send((is_timeout(&err), start.elapsed())).unwrap();
+        });
+
+        let trickle = thread::spawn(move || {
+            while client.write_all(b"x").is_ok() {
+                thread::sleep(Duration::from_millis(200));
+            }
+        });
+        let (timed_out, elapsed) = result.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(timed_out);
+        assert!(elapsed < Duration::from_millis(1500), "{:?}", elapsed);
+        // the handler closed the stream, so the writes start failing
+        trickle.join().unwrap();
+    }
+}
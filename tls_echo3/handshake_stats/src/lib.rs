@@ -0,0 +1,72 @@
+//! Handshake timings of `--reconnect` runs, shared by the rustls client
+//! (`cli_rustls`) and the openssl one (`tls13`'s `cli`): full and resumed
+//! handshakes side by side, and how often 0-RTT data was accepted.
+
+use std::fmt;
+use std::time::Duration;
+
+/// One connection of a `--reconnect` run.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub resumed: bool,
+    pub early_data_accepted: bool,
+    /// TCP connect until the handshake is done.
+    pub handshake: Duration,
+    /// TCP connect until the echo is back.
+    pub echo: Duration,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<8} handshake {:>8.3} ms  echo {:>8.3} ms{}",
+            if self.resumed { "resumed" } else { "full" },
+            millis(self.handshake),
+            millis(self.echo),
+            if self.early_data_accepted {
+                "  0-RTT"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+/// Average, min and max handshake and echo time of full and resumed
+/// handshakes.
+pub fn report(samples: &[Sample]) {
+    for (label, resumed) in [("full", false), ("resumed", true)] {
+        let group: Vec<&Sample> = samples.iter().filter(|s| s.resumed == resumed).collect();
+        if group.is_empty() {
+            println!("{:<8} 0", label);
+            continue;
+        }
+        let stats = |f: fn(&Sample) -> Duration| {
+            let values: Vec<f64> = group.iter().map(|s| millis(f(s))).collect();
+            let avg = values.iter().sum::<f64>() / values.len() as f64;
+            let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = values.iter().cloned().fold(0.0, f64::max);
+            format!("avg {:.3} ms (min {:.3}, max {:.3})", avg, min, max)
+        };
+        println!(
+            "{:<8} {:>4}  handshake {}  echo {}",
+            label,
+            group.len(),
+            stats(|s| s.handshake),
+            stats(|s| s.echo)
+        );
+    }
+    let early = samples.iter().filter(|s| s.early_data_accepted).count();
+    if early > 0 {
+        println!(
+            "0-RTT accepted on {} of {} connections",
+            early,
+            samples.len()
+        );
+    }
+}
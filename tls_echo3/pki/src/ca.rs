@@ -0,0 +1,304 @@
+//! The CA key and everything it signs.
+//!
+//! rcgen cannot load an existing certificate to sign with, so the signer
+//! is rebuilt from the CA key and the CN kept in the index. The issuer name
+//! and authority key identifier come out the same as in the CA certificate
+//! written by `init-ca`.
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertificateRevocationList,
+    CertificateRevocationListParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyIdMethod, KeyPair, KeyUsagePurpose, RevocationReason, RevokedCertParams, SanType,
+    SerialNumber,
+};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use time::{Duration, OffsetDateTime};
+
+use crate::Error;
+use crate::index::{Entry, Index, Kind, epoch_secs, from_epoch_secs};
+
+/// What to put in a new leaf certificate.
+pub struct Request {
+    pub kind: Kind,
+    pub cn: String,
+    pub ou: Vec<String>,
+    pub dns: Vec<String>,
+    pub ip: Vec<IpAddr>,
+    pub uri: Vec<String>,
+    pub days: u32,
+}
+
+/// A signed certificate and its new key, PEM.
+pub struct Issued {
+    pub cert_pem: String,
+    pub key_pem: String,
+    pub der: Vec<u8>,
+}
+
+fn distinguished_name(cn: &str, ou: &[String]) -> DistinguishedName {
+    let mut dn = DistinguishedName::new();
+    dn.push(DnType::CommonName, cn);
+    for ou in ou {
+        dn.push(DnType::OrganizationalUnitName, ou.as_str());
+    }
+    dn
+}
+
+fn ca_params(cn: &str) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = distinguished_name(cn, &[]);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params
+}
+
+/// New self-signed CA, ECDSA P-256.
+pub fn create_ca(cn: &str, days: u32) -> Result<Issued, Error> {
+    let mut params = ca_params(cn);
+    params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + Duration::days(days.into());
+    let ca = Certificate::from_params(params)?;
+    let cert_pem = ca.serialize_pem()?;
+    Ok(Issued {
+        der: cert_der(&cert_pem)?,
+        cert_pem,
+        key_pem: ca.serialize_private_key_pem(),
+    })
+}
+
+/// Signer for the CA in `dir`, checked against the CA certificate on disk.
+pub fn load_ca(index: &Index, dir: &Path) -> Result<Certificate, Error> {
+    let key_path = dir.join(&index.ca_key);
+    let key_pem = fs::read_to_string(&key_path)
+        .map_err(|e| format!("failed to read {}: {}", key_path.display(), e))?;
+    let key_pair = KeyPair::from_pem(&key_pem)
+        .map_err(|e| format!("invalid CA key {}: {}", key_path.display(), e))?;
+
+    let cert_path = dir.join(&index.ca_cert);
+    let ca_der = cert_der(
+        &fs::read_to_string(&cert_path)
+            .map_err(|e| format!("failed to read {}: {}", cert_path.display(), e))?,
+    )?;
+    let public_key = key_pair.public_key_raw();
+    if !ca_der
+        .windows(public_key.len())
+        .any(|window| window == public_key)
+    {
+        return Err(format!(
+            "{} is not the key of {}",
+            key_path.display(),
+            cert_path.display()
+        )
+        .into());
+    }
+
+    let mut params = ca_params(&index.ca_cn);
+    params.alg = key_pair.algorithm();
+    params.key_pair = Some(key_pair);
+    Ok(Certificate::from_params(params)?)
+}
+
+/// Sign a new leaf certificate with a new ECDSA P-256 key.
+pub fn issue(ca: &Certificate, serial: u64, request: &Request) -> Result<Issued, Error> {
+    let mut params = CertificateParams::default();
+    params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+    params.serial_number = Some(SerialNumber::from(serial));
+    params.distinguished_name = distinguished_name(&request.cn, &request.ou);
+    params.is_ca = IsCa::ExplicitNoCa;
+    params.use_authority_key_identifier_extension = true;
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + Duration::days(request.days.into());
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    params.extended_key_usages = match request.kind {
+        Kind::Server => vec![ExtendedKeyUsagePurpose::ServerAuth],
+        Kind::Client => vec![ExtendedKeyUsagePurpose::ClientAuth],
+        Kind::Both => vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ],
+    };
+    params.subject_alt_names = request
+        .dns
+        .iter()
+        .map(|name| SanType::DnsName(name.clone()))
+        .chain(request.ip.iter().map(|ip| SanType::IpAddress(*ip)))
+        .chain(request.uri.iter().map(|uri| SanType::URI(uri.clone())))
+        .collect();
+
+    let cert = Certificate::from_params(params)?;
+    // sign once: every signature differs, and the PEM, DER and fingerprint
+    // in the index have to be the same certificate
+    let cert_pem = cert.serialize_pem_with_signer(ca)?;
+    Ok(Issued {
+        der: cert_der(&cert_pem)?,
+        cert_pem,
+        key_pem: cert.serialize_private_key_pem(),
+    })
+}
+
+/// CRL with every revoked certificate in the index, valid for `days`.
+pub fn crl(ca: &Certificate, index: &Index, days: u32) -> Result<String, Error> {
+    let now = OffsetDateTime::now_utc();
+    let revoked_certs = index
+        .certs
+        .iter()
+        .filter_map(|entry| {
+            let revoked_at = entry.revoked_at?;
+            Some(RevokedCertParams {
+                serial_number: SerialNumber::from(entry.serial),
+                revocation_time: OffsetDateTime::from(from_epoch_secs(revoked_at)),
+                reason_code: entry.reason.as_deref().and_then(reason_code),
+                invalidity_date: None,
+            })
+        })
+        .collect();
+    let crl = CertificateRevocationList::from_params(CertificateRevocationListParams {
+        this_update: now,
+        next_update: now + Duration::days(days.into()),
+        crl_number: SerialNumber::from(index.crl_number),
+        issuing_distribution_point: None,
+        revoked_certs,
+        key_identifier_method: KeyIdMethod::Sha256,
+        alg: ca.get_params().alg,
+    })?;
+    Ok(crl.serialize_pem_with_signer(ca)?)
+}
+
+/// Revocation reasons as `revoke --reason` and the index spell them.
+pub const REASONS: &[(&str, RevocationReason)] = &[
+    ("unspecified", RevocationReason::Unspecified),
+    ("key-compromise", RevocationReason::KeyCompromise),
+    ("ca-compromise", RevocationReason::CaCompromise),
+    ("affiliation-changed", RevocationReason::AffiliationChanged),
+    ("superseded", RevocationReason::Superseded),
+    (
+        "cessation-of-operation",
+        RevocationReason::CessationOfOperation,
+    ),
+    ("certificate-hold", RevocationReason::CertificateHold),
+    ("privilege-withdrawn", RevocationReason::PrivilegeWithdrawn),
+];
+
+fn reason_code(reason: &str) -> Option<RevocationReason> {
+    REASONS
+        .iter()
+        .find(|(name, _)| *name == reason)
+        .map(|(_, code)| *code)
+}
+
+fn cert_der(cert_pem: &str) -> Result<Vec<u8>, Error> {
+    cert_store::pem::blocks(cert_pem.as_bytes(), "CERTIFICATE")
+        .into_iter()
+        .next()
+        .ok_or_else(|| "no certificate in PEM".into())
+}
+
+/// `AB:CD:...`, upper case, like `svr_cli_auth` prints it.
+pub fn fingerprint(der: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, der)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Index entry for a certificate just issued.
+pub fn entry(serial: u64, request: &Request, issued: &Issued, cert: &Path, key: &Path) -> Entry {
+    let not_after = cert_store::not_after(issued.cert_pem.as_bytes())
+        .map(|expiry| epoch_secs(expiry.not_after))
+        .unwrap_or_default();
+    Entry {
+        serial,
+        kind: request.kind,
+        cn: request.cn.clone(),
+        ou: request.ou.clone(),
+        dns: request.dns.clone(),
+        ip: request.ip.iter().map(IpAddr::to_string).collect(),
+        uri: request.uri.clone(),
+        cert: cert.to_path_buf(),
+        key: key.to_path_buf(),
+        not_after,
+        sha256: fingerprint(&issued.der),
+        revoked_at: None,
+        reason: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inspect;
+
+    #[test]
+    fn issues_and_revokes_under_reloaded_ca() {
+        let dir = std::env::temp_dir().join(format!("pki_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut index = Index::new(
+            "Test Root CA",
+            Path::new("ca.pem"),
+            Path::new("ca.key"),
+            Path::new("ca.crl"),
+        );
+        let root = create_ca(&index.ca_cn, 30).unwrap();
+        fs::write(dir.join("ca.pem"), &root.cert_pem).unwrap();
+        fs::write(dir.join("ca.key"), &root.key_pem).unwrap();
+        let signer = load_ca(&index, &dir).unwrap();
+
+        let request = Request {
+            kind: Kind::Both,
+            cn: "echo".to_string(),
+            ou: vec!["backend".to_string()],
+            dns: vec!["echo.edger.dev".to_string()],
+            ip: vec![IpAddr::from([127, 0, 0, 1])],
+            uri: Vec::new(),
+            days: 10,
+        };
+        let serial = index.take_serial();
+        let issued = issue(&signer, serial, &request).unwrap();
+        let info = inspect::certificate(&issued.der).unwrap();
+        assert_eq!(info.subject, "CN=echo, OU=backend");
+        assert_eq!(info.issuer, "CN=Test Root CA");
+        assert_eq!(info.serial.as_u64(), Some(serial));
+        assert!(!info.is_ca);
+        assert_eq!(info.usages, ["serverAuth", "clientAuth"]);
+        assert_eq!(info.dns, ["echo.edger.dev"]);
+        assert_eq!(info.ip, [IpAddr::from([127, 0, 0, 1])]);
+        assert!(inspect::certificate(&root.der).unwrap().is_ca);
+
+        let mut entry = entry(
+            serial,
+            &request,
+            &issued,
+            Path::new("echo.pem"),
+            Path::new("k"),
+        );
+        entry.revoked_at = Some(epoch_secs(std::time::SystemTime::now()));
+        entry.reason = Some("superseded".to_string());
+        index.certs.push(entry);
+        let crl_pem = crl(&signer, &index, 7).unwrap();
+        let der = cert_store::crl::crl_ders(crl_pem.as_bytes()).remove(0);
+        let info = inspect::crl(&der).unwrap();
+        assert_eq!(info.issuer, "CN=Test Root CA");
+        assert_eq!(info.number.unwrap().as_u64(), Some(1));
+        assert_eq!(info.revoked.len(), 1);
+        assert_eq!(info.revoked[0].0.as_u64(), Some(serial));
+
+        // a key that is not the CA's is refused
+        fs::write(dir.join("ca.key"), &issued.key_pem).unwrap();
+        assert!(load_ca(&index, &dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+//! `index.toml`: the CA and every certificate it issued.
+//!
+//! ```toml
+//! ca_cn = "Edger Root CA"
+//! ca_cert = "rootCA.pem"
+//! ca_key = "rootCA.key"
+//! crl = "rootCA.crl"
+//! next_serial = 4098
+//! crl_number = 3
+//!
+//! [[cert]]
+//! serial = 4096
+//! kind = "server"
+//! cn = "*.edger.dev"
+//! dns = ["*.edger.dev", "localhost"]
+//! ip = ["127.0.0.1"]
+//! cert = "server.pem"
+//! key = "server-key.pem"
+//! not_after = 1792396800
+//! sha256 = "3F:2A:...:9C"
+//!
+//! [[cert]]
+//! serial = 4097
+//! kind = "client"
+//! cn = "echo-client"
+//! cert = "echo-client.pem"
+//! key = "echo-client-key.pem"
+//! not_after = 1792396800
+//! sha256 = "8B:01:...:4E"
+//! revoked_at = 1776556800
+//! reason = "key-compromise"
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::Error;
+
+pub const INDEX_FILE: &str = "index.toml";
+
+/// Serials start high enough to always take the same number of bytes.
+const FIRST_SERIAL: u64 = 0x1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Index {
+    pub ca_cn: String,
+    /// Files relative to the index directory.
+    pub ca_cert: PathBuf,
+    pub ca_key: PathBuf,
+    pub crl: PathBuf,
+    pub next_serial: u64,
+    pub crl_number: u64,
+    #[serde(rename = "cert", default)]
+    pub certs: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Server,
+    Client,
+    /// serverAuth and clientAuth
+    Both,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub serial: u64,
+    pub kind: Kind,
+    pub cn: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ou: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ip: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uri: Vec<String>,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Seconds since the epoch.
+    pub not_after: u64,
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Server => "server",
+            Kind::Client => "client",
+            Kind::Both => "both",
+        })
+    }
+}
+
+pub fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+pub fn from_epoch_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+impl Entry {
+    /// `valid`, `expired` or `revoked (key-compromise)`
+    pub fn status(&self) -> String {
+        match (&self.revoked_at, &self.reason) {
+            (Some(_), Some(reason)) => format!("revoked ({})", reason),
+            (Some(_), None) => "revoked".to_string(),
+            _ if self.not_after <= epoch_secs(SystemTime::now()) => "expired".to_string(),
+            _ => "valid".to_string(),
+        }
+    }
+}
+
+impl Index {
+    pub fn new(ca_cn: &str, ca_cert: &Path, ca_key: &Path, crl: &Path) -> Self {
+        Self {
+            ca_cn: ca_cn.to_string(),
+            ca_cert: ca_cert.to_path_buf(),
+            ca_key: ca_key.to_path_buf(),
+            crl: crl.to_path_buf(),
+            next_serial: FIRST_SERIAL,
+            crl_number: 1,
+            certs: Vec::new(),
+        }
+    }
+
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(INDEX_FILE)
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = Self::path(dir);
+        let s = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "failed to read {}: {} (run `pki init-ca` first)",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(toml::from_str(&s).map_err(|e| format!("invalid index {}: {}", path.display(), e))?)
+    }
+
+    /// Written to a temporary file and renamed, so a crash never leaves a
+    /// half written index.
+    pub fn save(&self, dir: &Path) -> Result<(), Error> {
+        let path = Self::path(dir);
+        let tmp = path.with_extension("toml.tmp");
+        fs::write(&tmp, toml::to_string_pretty(self)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    pub fn take_serial(&mut self) -> u64 {
+        let serial = self.next_serial;
+        self.next_serial += 1;
+        serial
+    }
+
+    /// By serial (decimal or `0x` hex), certificate file or CN. A name given
+    /// to more than one certificate picks the newest one.
+    pub fn find_mut(&mut self, target: &str) -> Option<&mut Entry> {
+        let serial = match target.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => target.parse().ok(),
+        };
+        self.certs.iter_mut().rev().find(|entry| {
+            Some(entry.serial) == serial
+                || entry.cn == target
+                || entry.cert.as_os_str() == target
+                || entry.cert.file_stem().is_some_and(|stem| stem == target)
+        })
+    }
+
+    pub fn find_serial(&self, serial: u64) -> Option<&Entry> {
+        self.certs.iter().find(|entry| entry.serial == serial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(index: &mut Index, kind: Kind, cn: &str) -> Entry {
+        Entry {
+            serial: index.take_serial(),
+            kind,
+            cn: cn.to_string(),
+            ou: Vec::new(),
+            dns: Vec::new(),
+            ip: Vec::new(),
+            uri: Vec::new(),
+            cert: PathBuf::from(format!("{}.pem", cn)),
+            key: PathBuf::from(format!("{}-key.pem", cn)),
+            not_after: epoch_secs(SystemTime::now()) + 3600,
+            sha256: "AB:CD".to_string(),
+            revoked_at: None,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn saved_index_loads_back() {
+        let dir = std::env::temp_dir().join(format!("pki_index_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut index = Index::new(
+            "Test Root CA",
+            Path::new("ca.pem"),
+            Path::new("ca.key"),
+            Path::new("ca.crl"),
+        );
+        index.crl_number = 3;
+        let mut server = entry(&mut index, Kind::Both, "echo");
+        server.dns = vec!["*.edger.dev".to_string()];
+        server.ip = vec!["127.0.0.1".to_string()];
+        let mut client = entry(&mut index, Kind::Client, "echo-client");
+        client.ou = vec!["backend".to_string()];
+        client.revoked_at = Some(1776556800);
+        client.reason = Some("key-compromise".to_string());
+        let mut old = entry(&mut index, Kind::Client, "echo-client");
+        old.cert = PathBuf::from("old.pem");
+        old.not_after = 1;
+        index.certs = vec![server, client, old];
+        index.save(&dir).unwrap();
+        assert!(!Index::path(&dir).with_extension("toml.tmp").exists());
+
+        let mut loaded = Index::load(&dir).unwrap();
+        assert_eq!(loaded.ca_cn, "Test Root CA");
+        assert_eq!(loaded.ca_cert, Path::new("ca.pem"));
+        assert_eq!(loaded.ca_key, Path::new("ca.key"));
+        assert_eq!(loaded.crl, Path::new("ca.crl"));
+        assert_eq!(loaded.next_serial, FIRST_SERIAL + 3);
+        assert_eq!(loaded.crl_number, 3);
+        assert_eq!(loaded.certs.len(), 3);
+        assert_eq!(loaded.take_serial(), FIRST_SERIAL + 3);
+
+        let server = loaded.find_serial(FIRST_SERIAL).unwrap();
+        assert_eq!(server.kind, Kind::Both);
+        assert_eq!(server.dns, ["*.edger.dev"]);
+        assert_eq!(server.ip, ["127.0.0.1"]);
+        assert_eq!(server.status(), "valid");
+        let client = loaded.find_serial(FIRST_SERIAL + 1).unwrap();
+        assert_eq!(client.ou, ["backend"]);
+        assert_eq!(client.revoked_at, Some(1776556800));
+        assert_eq!(client.status(), "revoked (key-compromise)");
+        assert_eq!(
+            loaded.find_serial(FIRST_SERIAL + 2).unwrap().status(),
+            "expired"
+        );
+
+        // a CN issued twice finds the newest, a file name or serial the exact one
+        assert_eq!(
+            loaded.find_mut("echo-client").unwrap().serial,
+            FIRST_SERIAL + 2
+        );
+        assert_eq!(
+            loaded.find_mut("echo-client.pem").unwrap().serial,
+            FIRST_SERIAL + 1
+        );
+        assert_eq!(loaded.find_mut("0x1000").unwrap().cn, "echo");
+        assert_eq!(loaded.find_mut("4097").unwrap().cn, "echo-client");
+        assert!(loaded.find_mut("nobody").is_none());
+
+        fs::write(Index::path(&dir), "ca_cn = \"x\"\nunknown = 1\n").unwrap();
+        assert!(Index::load(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+//! What is in a certificate or CRL file, for `pki inspect`.
+
+use cert_store::der::{
+    TAG_INTEGER, TAG_OID, TAG_SEQUENCE, TAG_SET, elements, format_oid, format_time, read_time,
+    read_tlv,
+};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+const TAG_CRL_EXTENSIONS: u8 = 0xa0;
+const TAG_SAN_DNS: u8 = 0x82;
+const TAG_SAN_URI: u8 = 0x86;
+const TAG_SAN_IP: u8 = 0x87;
+
+const OID_CN: &str = "2.5.4.3";
+const OID_OU: &str = "2.5.4.11";
+const OID_O: &str = "2.5.4.10";
+const OID_C: &str = "2.5.4.6";
+const OID_SAN: &str = "2.5.29.17";
+const OID_BASIC_CONSTRAINTS: &str = "2.5.29.19";
+const OID_EKU: &str = "2.5.29.37";
+const OID_CRL_NUMBER: &str = "2.5.29.20";
+
+#[derive(Debug, Default)]
+pub struct CertInfo {
+    pub serial: Serial,
+    pub signature: String,
+    pub issuer: String,
+    pub subject: String,
+    pub not_before: Option<SystemTime>,
+    pub not_after: Option<SystemTime>,
+    pub key: String,
+    pub is_ca: bool,
+    /// `serverAuth`, `clientAuth`, or the OID.
+    pub usages: Vec<String>,
+    pub dns: Vec<String>,
+    pub ip: Vec<IpAddr>,
+    pub uri: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct CrlInfo {
+    pub issuer: String,
+    pub number: Option<Serial>,
+    pub this_update: Option<SystemTime>,
+    pub next_update: Option<SystemTime>,
+    pub revoked: Vec<(Serial, Option<SystemTime>)>,
+}
+
+/// Certificate serial, the raw integer bytes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Serial(pub Vec<u8>);
+
+impl Serial {
+    /// The serial if it fits, as `pki` issues them.
+    pub fn as_u64(&self) -> Option<u64> {
+        let bytes = match self.0.split_first() {
+            Some((0, rest)) => rest,
+            _ => &self.0,
+        };
+        (bytes.len() <= 8).then(|| bytes.iter().fold(0, |n, &b| n << 8 | u64::from(b)))
+    }
+}
+
+/// `4096 (0x1000)`, or just the hex bytes for long random serials.
+impl fmt::Display for Serial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.as_u64() {
+            Some(n) => write!(f, "{} (0x{:x})", n, n),
+            None => {
+                let hex: Vec<String> = self.0.iter().map(|b| format!("{:02x}", b)).collect();
+                f.write_str(&hex.join(":"))
+            }
+        }
+    }
+}
+
+fn expect(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (found, contents, rest) = read_tlv(data)?;
+    (found == tag).then_some((contents, rest))
+}
+
+fn oid_name(oid: &str) -> &str {
+    match oid {
+        "1.2.840.10045.4.3.2" => "ecdsa-with-SHA256",
+        "1.2.840.10045.4.3.3" => "ecdsa-with-SHA384",
+        "1.2.840.113549.1.1.11" => "sha256WithRSAEncryption",
+        "1.2.840.113549.1.1.12" => "sha384WithRSAEncryption",
+        "1.2.840.113549.1.1.13" => "sha512WithRSAEncryption",
+        "1.2.840.113549.1.1.10" => "RSASSA-PSS",
+        "1.3.101.112" => "Ed25519",
+        "1.2.840.113549.1.1.1" => "RSA",
+        "1.2.840.10045.2.1" => "EC",
+        "1.2.840.10045.3.1.7" => "P-256",
+        "1.3.132.0.34" => "P-384",
+        "1.3.6.1.5.5.7.3.1" => "serverAuth",
+        "1.3.6.1.5.5.7.3.2" => "clientAuth",
+        other => other,
+    }
+}
+
+/// `CN=echo-client, OU=backend`
+fn name(contents: &[u8]) -> Option<String> {
+    let mut parts = Vec::new();
+    for (tag, rdn) in elements(contents)? {
+        if tag != TAG_SET {
+            return None;
+        }
+        for (_, attribute) in elements(rdn)? {
+            let (oid, rest) = expect(attribute, TAG_OID)?;
+            let (_, value, _) = read_tlv(rest)?;
+            let label = match format_oid(oid).as_str() {
+                OID_CN => "CN".to_string(),
+                OID_OU => "OU".to_string(),
+                OID_O => "O".to_string(),
+                OID_C => "C".to_string(),
+                other => other.to_string(),
+            };
+            parts.push(format!("{}={}", label, String::from_utf8_lossy(value)));
+        }
+    }
+    Some(parts.join(", "))
+}
+
+/// `SEQUENCE { OID, params }` as `EC P-256`, `RSA`, ...
+fn algorithm(contents: &[u8]) -> Option<String> {
+    let (oid, rest) = expect(contents, TAG_OID)?;
+    let mut text = oid_name(&format_oid(oid)).to_string();
+    if let Some((curve, _)) = expect(rest, TAG_OID) {
+        text = format!("{} {}", text, oid_name(&format_oid(curve)));
+    }
+    Some(text)
+}
+
+fn validity(contents: &[u8]) -> Option<(Option<SystemTime>, Option<SystemTime>)> {
+    let (tag, not_before, rest) = read_tlv(contents)?;
+    let not_before = read_time(tag, not_before);
+    let (tag, not_after, _) = read_tlv(rest)?;
+    Some((not_before, read_time(tag, not_after)))
+}
+
+fn extension(info: &mut CertInfo, contents: &[u8]) -> Option<()> {
+    let (oid, mut rest) = expect(contents, TAG_OID)?;
+    if let Some((_, after)) = expect(rest, TAG_BOOLEAN) {
+        rest = after;
+    }
+    let (value, _) = expect(rest, TAG_OCTET_STRING)?;
+    let oid = format_oid(oid);
+    if ![OID_SAN, OID_BASIC_CONSTRAINTS, OID_EKU].contains(&oid.as_str()) {
+        return Some(());
+    }
+    let (value, _) = expect(value, TAG_SEQUENCE)?;
+    match oid.as_str() {
+        OID_SAN => {
+            for (tag, name) in elements(value)? {
+                match tag {
+                    TAG_SAN_DNS => info.dns.push(String::from_utf8_lossy(name).into_owned()),
+                    TAG_SAN_URI => info.uri.push(String::from_utf8_lossy(name).into_owned()),
+                    TAG_SAN_IP => match name.len() {
+                        4 => info.ip.push(IpAddr::from(<[u8; 4]>::try_from(name).ok()?)),
+                        16 => info.ip.push(IpAddr::from(<[u8; 16]>::try_from(name).ok()?)),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+        }
+        OID_BASIC_CONSTRAINTS => {
+            info.is_ca = expect(value, TAG_BOOLEAN).is_some_and(|(flag, _)| flag != [0]);
+        }
+        OID_EKU => {
+            for (_, oid) in elements(value)? {
+                info.usages.push(oid_name(&format_oid(oid)).to_string());
+            }
+        }
+        _ => {}
+    }
+    Some(())
+}
+
+pub fn certificate(der: &[u8]) -> Option<CertInfo> {
+    let (cert, _) = expect(der, TAG_SEQUENCE)?;
+    let (mut tbs, _) = expect(cert, TAG_SEQUENCE)?;
+    if let Some((_, rest)) = expect(tbs, TAG_VERSION) {
+        tbs = rest;
+    }
+    let mut info = CertInfo::default();
+    let (serial, rest) = expect(tbs, TAG_INTEGER)?;
+    info.serial = Serial(serial.to_vec());
+    let (signature, rest) = expect(rest, TAG_SEQUENCE)?;
+    info.signature = algorithm(signature)?;
+    let (issuer, rest) = expect(rest, TAG_SEQUENCE)?;
+    info.issuer = name(issuer)?;
+    let (times, rest) = expect(rest, TAG_SEQUENCE)?;
+    (info.not_before, info.not_after) = validity(times)?;
+    let (subject, rest) = expect(rest, TAG_SEQUENCE)?;
+    info.subject = name(subject)?;
+    let (spki, rest) = expect(rest, TAG_SEQUENCE)?;
+    let (key, _) = expect(spki, TAG_SEQUENCE)?;
+    info.key = algorithm(key)?;
+    for (tag, contents) in elements(rest)? {
+        if tag == TAG_EXTENSIONS {
+            let (list, _) = expect(contents, TAG_SEQUENCE)?;
+            for (_, ext) in elements(list)? {
+                extension(&mut info, ext)?;
+            }
+        }
+    }
+    Some(info)
+}
+
+pub fn crl(der: &[u8]) -> Option<CrlInfo> {
+    let (list, _) = expect(der, TAG_SEQUENCE)?;
+    let (mut tbs, _) = expect(list, TAG_SEQUENCE)?;
+    if let Some((_, rest)) = expect(tbs, TAG_INTEGER) {
+        tbs = rest;
+    }
+    let mut info = CrlInfo::default();
+    let (_, rest) = expect(tbs, TAG_SEQUENCE)?;
+    let (issuer, rest) = expect(rest, TAG_SEQUENCE)?;
+    info.issuer = name(issuer)?;
+    let (tag, this_update, rest) = read_tlv(rest)?;
+    info.this_update = read_time(tag, this_update);
+    for (tag, contents) in elements(rest)? {
+        match tag {
+            TAG_SEQUENCE => {
+                for (_, revoked) in elements(contents)? {
+                    let (serial, rest) = expect(revoked, TAG_INTEGER)?;
+                    let date = read_tlv(rest).and_then(|(tag, time, _)| read_time(tag, time));
+                    info.revoked.push((Serial(serial.to_vec()), date));
+                }
+            }
+            TAG_CRL_EXTENSIONS => {
+                let (list, _) = expect(contents, TAG_SEQUENCE)?;
+                for (_, ext) in elements(list)? {
+                    let (oid, rest) = expect(ext, TAG_OID)?;
+                    if format_oid(oid) == OID_CRL_NUMBER {
+                        let (value, _) = expect(rest, TAG_OCTET_STRING)?;
+                        let (number, _) = expect(value, TAG_INTEGER)?;
+                        info.number = Some(Serial(number.to_vec()));
+                    }
+                }
+            }
+            _ => info.next_update = read_time(tag, contents),
+        }
+    }
+    Some(info)
+}
+
+fn time(time: Option<SystemTime>) -> String {
+    time.map(format_time).unwrap_or_else(|| "-".to_string())
+}
+
+fn list<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for CertInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  subject     {}", self.subject)?;
+        writeln!(f, "  issuer      {}", self.issuer)?;
+        writeln!(f, "  serial      {}", self.serial)?;
+        writeln!(f, "  not before  {}", time(self.not_before))?;
+        match self.not_after {
+            Some(not_after) => writeln!(f, "  not after   {}", cert_store::Expiry { not_after })?,
+            None => writeln!(f, "  not after   -")?,
+        }
+        writeln!(f, "  key         {}", self.key)?;
+        writeln!(f, "  signature   {}", self.signature)?;
+        writeln!(f, "  ca          {}", if self.is_ca { "yes" } else { "no" })?;
+        for (label, values) in [
+            ("usage", list(&self.usages)),
+            ("dns", list(&self.dns)),
+            ("ip", list(&self.ip)),
+            ("uri", list(&self.uri)),
+        ] {
+            if !values.is_empty() {
+                writeln!(f, "  {:<11} {}", label, values)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CrlInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  issuer      {}", self.issuer)?;
+        if let Some(number) = &self.number {
+            writeln!(f, "  number      {}", number)?;
+        }
+        writeln!(f, "  this update {}", time(self.this_update))?;
+        writeln!(f, "  next update {}", time(self.next_update))?;
+        writeln!(f, "  revoked     {}", self.revoked.len())?;
+        for (serial, date) in &self.revoked {
+            writeln!(f, "    {}  {}", serial, time(*date))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,488 @@
+//! Test PKI for the echo servers: one CA, server and client certificates,
+//! revocation and the CRL, with an index of everything issued.
+//!
+//! Run from this directory; files go to `../certs`, where the servers and
+//! clients look for them.
+//!
+//! ```text
+//! cargo run -- init-ca                          # rootCA.pem, rootCA.key, rootCA.crl
+//! cargo run -- issue-server                     # server.pem, *.edger.dev + 127.0.0.1
+//! cargo run -- issue-client --cn echo-client    # echo-client.pem
+//! cargo run -- revoke echo-client --reason key-compromise
+//! cargo run -- inspect server.pem
+//! cargo run -- inspect                          # everything issued
+//! ```
+//!
+//! `rustls2/ca_test/setup.sh` uses the same tool with the file names the
+//! rustls2 binaries expect.
+
+mod ca;
+mod index;
+mod inspect;
+
+use clap::{Args, Parser, Subcommand};
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use ca::Request;
+use index::{Index, Kind, epoch_secs, from_epoch_secs};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// 인증서와 index.toml 이 있는 디렉터리
+    #[arg(short, long, default_value = "../certs")]
+    dir: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Root CA 생성, 빈 CRL 과 index.toml 도 새로 만듦
+    InitCa {
+        #[arg(long, default_value = "Edger Root CA")]
+        cn: String,
+        #[arg(long, default_value_t = 7300)]
+        days: u32,
+        #[arg(long, default_value = "rootCA.pem")]
+        cert: PathBuf,
+        #[arg(long, default_value = "rootCA.key")]
+        key: PathBuf,
+        #[arg(long, default_value = "rootCA.crl")]
+        crl: PathBuf,
+        /// 기존 CA 와 index 를 덮어씀
+        #[arg(long)]
+        force: bool,
+    },
+    /// 서버 인증서 발급 (serverAuth), --dns/--ip 가 없으면 *.edger.dev 와 127.0.0.1
+    IssueServer {
+        #[arg(long, default_value = "server")]
+        name: String,
+        /// 기본값은 첫 번째 --dns
+        #[arg(long)]
+        cn: Option<String>,
+        #[arg(long)]
+        dns: Vec<String>,
+        #[arg(long)]
+        ip: Vec<IpAddr>,
+        /// clientAuth 도 추가 (서버 간 mTLS 용)
+        #[arg(long)]
+        client_auth: bool,
+        #[command(flatten)]
+        out: Output,
+    },
+    /// 클라이언트 인증서 발급 (clientAuth)
+    IssueClient {
+        #[arg(long)]
+        cn: String,
+        /// 파일 이름, 기본값은 CN
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        ou: Vec<String>,
+        #[arg(long)]
+        dns: Vec<String>,
+        #[arg(long)]
+        uri: Vec<String>,
+        #[command(flatten)]
+        out: Output,
+    },
+    /// 인증서 폐기 후 CRL 재생성, 대상은 serial, CN 또는 파일 이름
+    Revoke {
+        target: String,
+        #[arg(long, default_value = "unspecified", value_parser = reason_names())]
+        reason: String,
+        /// CRL 유효 기간
+        #[arg(long, default_value_t = 30)]
+        crl_days: u32,
+    },
+    /// 폐기 목록으로 CRL 만 다시 생성 (기한 연장)
+    GenCrl {
+        #[arg(long, default_value_t = 30)]
+        days: u32,
+    },
+    /// 인증서나 CRL 내용 출력, 파일이 없으면 발급 목록
+    Inspect { file: Option<PathBuf> },
+}
+
+#[derive(Args, Debug)]
+struct Output {
+    #[arg(long, default_value_t = 365)]
+    days: u32,
+    /// 인증서 파일, 기본값은 <name>.pem
+    #[arg(long)]
+    cert: Option<PathBuf>,
+    /// 개인키 파일, 기본값은 <name>-key.pem
+    #[arg(long)]
+    key: Option<PathBuf>,
+    /// 같은 이름의 파일이 있어도 덮어씀
+    #[arg(long)]
+    force: bool,
+}
+
+fn reason_names() -> Vec<&'static str> {
+    ca::REASONS.iter().map(|(name, _)| *name).collect()
+}
+
+/// Write a new file; keys are readable by the owner only.
+fn write_file(path: &Path, contents: &str, force: bool, private: bool) -> Result<(), Error> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    if private {
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::AlreadyExists {
+            format!(
+                "{} already exists, use --force to replace it",
+                path.display()
+            )
+        } else {
+            format!("failed to write {}: {}", path.display(), e)
+        }
+    })?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+fn write_crl(index: &mut Index, dir: &Path, days: u32) -> Result<(), Error> {
+    let signer = ca::load_ca(index, dir)?;
+    let crl = ca::crl(&signer, index, days)?;
+    write_file(&dir.join(&index.crl), &crl, true, false)?;
+    println!(
+        "{} written: crl number {}, {} revoked, next update in {} days",
+        dir.join(&index.crl).display(),
+        index.crl_number,
+        index
+            .certs
+            .iter()
+            .filter(|e| e.revoked_at.is_some())
+            .count(),
+        days
+    );
+    index.crl_number += 1;
+    Ok(())
+}
+
+fn init_ca(dir: &Path, cn: &str, days: u32, files: [&Path; 3], force: bool) -> Result<(), Error> {
+    let [cert, key, crl] = files;
+    if !force && Index::path(dir).exists() {
+        return Err(format!(
+            "{} already exists, use --force to start a new CA",
+            Index::path(dir).display()
+        )
+        .into());
+    }
+    fs::create_dir_all(dir)?;
+    let issued = ca::create_ca(cn, days)?;
+    write_file(&dir.join(key), &issued.key_pem, force, true)?;
+    write_file(&dir.join(cert), &issued.cert_pem, force, false)?;
+    println!(
+        "CA \"{}\" created: {}, {}",
+        cn,
+        dir.join(cert).display(),
+        dir.join(key).display()
+    );
+
+    let mut index = Index::new(cn, cert, key, crl);
+    write_crl(&mut index, dir, 30)?;
+    index.save(dir)?;
+    Ok(())
+}
+
+fn issue(dir: &Path, name: &str, request: Request, out: Output) -> Result<(), Error> {
+    let mut index = Index::load(dir)?;
+    let signer = ca::load_ca(&index, dir)?;
+    let cert = out
+        .cert
+        .unwrap_or_else(|| PathBuf::from(format!("{}.pem", name)));
+    let key = out
+        .key
+        .unwrap_or_else(|| PathBuf::from(format!("{}-key.pem", name)));
+
+    let serial = index.take_serial();
+    let issued = ca::issue(&signer, serial, &request)?;
+    write_file(&dir.join(&key), &issued.key_pem, out.force, true)?;
+    write_file(&dir.join(&cert), &issued.cert_pem, out.force, false)?;
+    let entry = ca::entry(serial, &request, &issued, &cert, &key);
+    println!(
+        "{} certificate for \"{}\" issued: serial {}, {}, {}",
+        entry.kind,
+        entry.cn,
+        serial,
+        dir.join(&cert).display(),
+        dir.join(&key).display()
+    );
+    println!("  sha256 {}", entry.sha256);
+    index.certs.push(entry);
+    index.save(dir)
+}
+
+fn revoke(dir: &Path, target: &str, reason: &str, crl_days: u32) -> Result<(), Error> {
+    let mut index = Index::load(dir)?;
+    let entry = index
+        .find_mut(target)
+        .ok_or_else(|| format!("no certificate {} in the index", target))?;
+    if entry.revoked_at.is_some() {
+        return Err(format!("serial {} ({}) is already revoked", entry.serial, entry.cn).into());
+    }
+    entry.revoked_at = Some(epoch_secs(SystemTime::now()));
+    entry.reason = Some(reason.to_string());
+    println!(
+        "serial {} ({}, {}) revoked: {}",
+        entry.serial,
+        entry.cn,
+        entry.cert.display(),
+        reason
+    );
+    write_crl(&mut index, dir, crl_days)?;
+    index.save(dir)
+}
+
+fn gen_crl(dir: &Path, days: u32) -> Result<(), Error> {
+    let mut index = Index::load(dir)?;
+    write_crl(&mut index, dir, days)?;
+    index.save(dir)
+}
+
+fn inspect_file(dir: &Path, file: &Path) -> Result<(), Error> {
+    // 현재 디렉터리에 없으면 --dir 에서 찾음
+    let path = if file.exists() {
+        file.to_path_buf()
+    } else {
+        dir.join(file)
+    };
+    let data = fs::read(&path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let index = Index::load(dir).ok();
+
+    let (certs, crls) = if !cert_store::crl::is_der(&data) {
+        (
+            cert_store::pem::blocks(&data, "CERTIFICATE"),
+            cert_store::crl::crl_ders(&data),
+        )
+    } else if inspect::certificate(&data).is_some() {
+        (vec![data], Vec::new())
+    } else {
+        (Vec::new(), vec![data])
+    };
+    if certs.is_empty() && crls.is_empty() {
+        return Err(format!("no certificate or CRL in {}", path.display()).into());
+    }
+    for der in &certs {
+        let info = inspect::certificate(der)
+            .ok_or_else(|| format!("cannot parse certificate in {}", path.display()))?;
+        println!("certificate {}", path.display());
+        print!("{}", info);
+        println!("  sha256      {}", ca::fingerprint(der));
+        let entry = index.as_ref().and_then(|index| {
+            let serial = info.serial.as_u64()?;
+            index
+                .find_serial(serial)
+                .filter(|entry| entry.sha256 == ca::fingerprint(der))
+        });
+        match entry {
+            Some(entry) => println!("  index       {} ({})", entry.status(), entry.kind),
+            None if info.is_ca => {}
+            None => println!("  index       not issued by this CA"),
+        }
+    }
+    for der in &crls {
+        let info =
+            inspect::crl(der).ok_or_else(|| format!("cannot parse CRL in {}", path.display()))?;
+        println!("CRL {}", path.display());
+        print!("{}", info);
+    }
+    Ok(())
+}
+
+fn list(dir: &Path) -> Result<(), Error> {
+    let index = Index::load(dir)?;
+    println!(
+        "CA \"{}\" {}, crl {}, {} certificates",
+        index.ca_cn,
+        dir.join(&index.ca_cert).display(),
+        dir.join(&index.crl).display(),
+        index.certs.len()
+    );
+    for entry in &index.certs {
+        let names: Vec<&str> = entry
+            .dns
+            .iter()
+            .chain(&entry.ip)
+            .chain(&entry.uri)
+            .map(String::as_str)
+            .collect();
+        println!(
+            "{:>6}  {:<6}  {:<20}  {:<24}  {}  {}",
+            entry.serial,
+            entry.kind,
+            entry.cn,
+            entry.cert.display(),
+            cert_store::der::format_time(from_epoch_secs(entry.not_after)),
+            entry.status()
+        );
+        if !names.is_empty() {
+            println!("        {}", names.join(", "));
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    let cli = Cli::parse();
+    let dir = cli.dir.as_path();
+
+    match cli.command {
+        Command::InitCa {
+            cn,
+            days,
+            cert,
+            key,
+            crl,
+            force,
+        } => init_ca(dir, &cn, days, [&cert, &key, &crl], force),
+        Command::IssueServer {
+            name,
+            cn,
+            mut dns,
+            mut ip,
+            client_auth,
+            out,
+        } => {
+            // 클라이언트들이 접속하는 test.edger.dev 와 127.0.0.1
+            if dns.is_empty() && ip.is_empty() {
+                dns.push("*.edger.dev".to_string());
+                ip.push(IpAddr::from([127, 0, 0, 1]));
+            }
+            let cn = cn
+                .or_else(|| dns.first().cloned())
+                .unwrap_or_else(|| ip[0].to_string());
+            let request = Request {
+                kind: if client_auth {
+                    Kind::Both
+                } else {
+                    Kind::Server
+                },
+                cn,
+                ou: Vec::new(),
+                dns,
+                ip,
+                uri: Vec::new(),
+                days: out.days,
+            };
+            issue(dir, &name, request, out)
+        }
+        Command::IssueClient {
+            cn,
+            name,
+            ou,
+            dns,
+            uri,
+            out,
+        } => {
+            let name = name.unwrap_or_else(|| cn.clone());
+            let request = Request {
+                kind: Kind::Client,
+                cn,
+                ou,
+                dns,
+                ip: Vec::new(),
+                uri,
+                days: out.days,
+            };
+            issue(dir, &name, request, out)
+        }
+        Command::Revoke {
+            target,
+            reason,
+            crl_days,
+        } => revoke(dir, &target, &reason, crl_days),
+        Command::GenCrl { days } => gen_crl(dir, days),
+        Command::Inspect { file: Some(file) } => inspect_file(dir, &file),
+        Command::Inspect { file: None } => list(dir),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(kind: Kind, cn: &str) -> Request {
+        Request {
+            kind,
+            cn: cn.to_string(),
+            ou: Vec::new(),
+            dns: Vec::new(),
+            ip: Vec::new(),
+            uri: Vec::new(),
+            days: 10,
+        }
+    }
+
+    fn output() -> Output {
+        Output {
+            days: 10,
+            cert: None,
+            key: None,
+            force: false,
+        }
+    }
+
+    fn read_crl(dir: &Path) -> inspect::CrlInfo {
+        let pem = fs::read(dir.join("rootCA.crl")).unwrap();
+        let der = cert_store::crl::crl_ders(&pem).remove(0);
+        inspect::crl(&der).unwrap()
+    }
+
+    #[test]
+    fn gen_crl_lists_revoked_serials_with_a_new_number() {
+        let dir = std::env::temp_dir().join(format!("pki_gen_crl_{}", std::process::id()));
+        let files = [
+            Path::new("rootCA.pem"),
+            Path::new("rootCA.key"),
+            Path::new("rootCA.crl"),
+        ];
+        init_ca(&dir, "Test Root CA", 30, files, false).unwrap();
+        assert!(init_ca(&dir, "Test Root CA", 30, files, false).is_err());
+        let info = read_crl(&dir);
+        assert_eq!(info.number.unwrap().as_u64(), Some(1));
+        assert!(info.revoked.is_empty());
+
+        issue(&dir, "server", request(Kind::Server, "echo"), output()).unwrap();
+        issue(&dir, "a", request(Kind::Client, "a"), output()).unwrap();
+        issue(&dir, "b", request(Kind::Client, "b"), output()).unwrap();
+        revoke(&dir, "a", "key-compromise", 7).unwrap();
+        assert!(revoke(&dir, "a", "key-compromise", 7).is_err());
+        revoke(&dir, "0x1002", "superseded", 7).unwrap();
+
+        gen_crl(&dir, 7).unwrap();
+        let info = read_crl(&dir);
+        assert_eq!(info.issuer, "CN=Test Root CA");
+        assert_eq!(info.number.unwrap().as_u64(), Some(4));
+        let revoked: Vec<_> = info.revoked.iter().map(|(s, _)| s.as_u64()).collect();
+        assert_eq!(revoked, [Some(0x1001), Some(0x1002)]);
+        let week = info
+            .next_update
+            .unwrap()
+            .duration_since(info.this_update.unwrap());
+        assert_eq!(week.unwrap().as_secs(), 7 * 24 * 60 * 60);
+
+        let index = Index::load(&dir).unwrap();
+        assert_eq!(index.crl_number, 5);
+        assert_eq!(index.next_serial, 0x1003);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
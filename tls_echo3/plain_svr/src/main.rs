@@ -1,9 +1,50 @@
+use clap::Parser;
+use conn_limit::{Client, Limiter, Limits};
 use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
 
 const ADDR: &str = "0.0.0.0:8279";
 
-fn handle_client(mut stream: TcpStream) {
+/// plain_svr [addr] [--proxy-protocol], e.g. several backends on different ports
+#[derive(Parser)]
+struct Args {
+    /// 바인딩할 주소
+    #[arg(default_value = ADDR)]
+    addr: String,
+
+    /// 연결마다 PROXY protocol 헤더를 먼저 읽음. --max-per-ip 는 프록시가 아니라
+    /// 헤더의 클라이언트 주소로 셈, 헤더는 누구나 쓸 수 있으니 프록시만 접속하게 할 것
+    #[arg(long)]
+    proxy_protocol: bool,
+
+    #[command(flatten)]
+    limits: Limits,
+}
+
+fn handle_client(mut stream: TcpStream, mut client: Client, proxy_protocol: bool) {
+    if proxy_protocol {
+        // the proxy sends the real client address first
+        let header = match proxy_protocol::read_header(&mut stream) {
+            Ok(header) => header,
+            Err(e) => {
+                println!("PROXY header error: {}", e);
+                return;
+            }
+        };
+        println!("client {}", header);
+        // 연결 수는 실제 클라이언트 기준, LOCAL (프록시 자체의 health check) 은 프록시 주소로
+        let source = header.source.unwrap_or(client.peer);
+        if let Err(e) = client.set_source(source) {
+            println!("rejected {}: {}", source, e);
+            return;
+        }
+    }
+    if let Err(e) = client.set_idle_timeout(&stream) {
+        println!("Socket error: {}", e);
+        return;
+    }
+
+    let mut stream = client.rate_limit(stream);
     let mut buffer = [0; 1024];
     loop {
         match stream.read(&mut buffer) {
@@ -22,6 +63,10 @@ fn handle_client(mut stream: TcpStream) {
                     break;
                 }
             }
+            Err(e) if conn_limit::is_timeout(&e) => {
+                println!("Idle timeout, closing {}", client.peer);
+                break;
+            }
             Err(e) => {
                 println!("Read error: {}", e);
                 break;
@@ -31,17 +76,82 @@ fn handle_client(mut stream: TcpStream) {
 }
 
 fn main() {
-    let listener = TcpListener::bind(ADDR).unwrap();
-    println!("server listening on {ADDR}");
+    let args = Args::parse();
+    let listener = TcpListener::bind(&args.addr).unwrap();
+    println!("server listening on {}", args.addr);
 
+    let proxy_protocol = args.proxy_protocol;
+    let limiter = Limiter::new(args.limits);
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                std::thread::spawn(|| {
-                    handle_client(stream);
-                });
+                let handler = move |stream, client| handle_client(stream, client, proxy_protocol);
+                if proxy_protocol {
+                    limiter.serve_proxied(stream, handler);
+                } else {
+                    limiter.serve(stream, handler);
+                }
             }
             Err(e) => println!("error: {}", e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proxy_protocol::{Header, Version};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+
+    /// Connection through a proxy at 127.0.0.1 for the client `source`.
+    fn connect(addr: SocketAddr, source: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        Header::tcp(source.parse().unwrap(), addr)
+            .write_to(&mut stream, Version::V1)
+            .unwrap();
+        stream
+    }
+
+    fn echoes(stream: &mut TcpStream) -> bool {
+        let mut reply = [0; 4];
+        stream.write_all(b"ping").is_ok() && stream.read_exact(&mut reply).is_ok()
+    }
+
+    #[test]
+    fn max_per_ip_counts_the_proxied_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let limiter = Limiter::new(Limits {
+                max_per_ip: 1,
+                ..Limits::default()
+            });
+            for stream in listener.incoming() {
+                limiter.serve_proxied(stream.unwrap(), |stream, client| {
+                    handle_client(stream, client, true)
+                });
+            }
+        });
+
+        // all from the proxy's address, but two different clients
+        let mut first = connect(addr, "192.0.2.1:5000");
+        assert!(echoes(&mut first));
+        let mut second = connect(addr, "192.0.2.2:5000");
+        assert!(echoes(&mut second));
+        let mut again = connect(addr, "192.0.2.1:5001");
+        assert!(!echoes(&mut again));
+
+        // the slot is given back when the connection ends
+        drop(first);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let mut retry = connect(addr, "192.0.2.1:5002");
+            if echoes(&mut retry) {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline);
+            thread::sleep(std::time::Duration::from_millis(20));
+        }
+    }
+}
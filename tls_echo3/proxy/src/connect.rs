@@ -0,0 +1,352 @@
+//! HTTP CONNECT forward proxy: request parsing, the destination allow-list
+//! and Basic proxy authentication.
+//!
+//! The credentials file has one user per line, the password in plain text
+//! or as its SHA-256 (`printf %s 'secret' | sha256sum`):
+//!
+//! ```text
+//! # user:password or user:sha256:HEX
+//! alice:secret
+//! bob:sha256:2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b
+//! ```
+
+use openssl::base64;
+use openssl::memcmp;
+use openssl::sha::sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Longest request head accepted, request line and headers.
+pub const MAX_HEAD: usize = 8 * 1024;
+
+pub const REALM: &str = "tls_echo3 proxy";
+
+/// Response status sent back to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    pub code: u16,
+    pub reason: &'static str,
+}
+
+impl Status {
+    pub const ESTABLISHED: Status = Status {
+        code: 200,
+        reason: "Connection Established",
+    };
+    pub const BAD_REQUEST: Status = Status {
+        code: 400,
+        reason: "Bad Request",
+    };
+    pub const FORBIDDEN: Status = Status {
+        code: 403,
+        reason: "Forbidden",
+    };
+    pub const METHOD_NOT_ALLOWED: Status = Status {
+        code: 405,
+        reason: "Method Not Allowed",
+    };
+    pub const REQUEST_TIMEOUT: Status = Status {
+        code: 408,
+        reason: "Request Timeout",
+    };
+    pub const PROXY_AUTH_REQUIRED: Status = Status {
+        code: 407,
+        reason: "Proxy Authentication Required",
+    };
+    pub const BAD_GATEWAY: Status = Status {
+        code: 502,
+        reason: "Bad Gateway",
+    };
+    pub const GATEWAY_TIMEOUT: Status = Status {
+        code: 504,
+        reason: "Gateway Timeout",
+    };
+
+    /// Write the status line and headers; the tunnel starts right after a 200.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut response = format!("HTTP/1.1 {} {}\r\n", self.code, self.reason);
+        match *self {
+            Status::ESTABLISHED => {}
+            Status::METHOD_NOT_ALLOWED => response.push_str("Allow: CONNECT\r\n"),
+            Status::PROXY_AUTH_REQUIRED => response.push_str(&format!(
+                "Proxy-Authenticate: Basic realm=\"{}\"\r\n",
+                REALM
+            )),
+            _ => {}
+        }
+        if *self != Status::ESTABLISHED {
+            response.push_str("Content-Length: 0\r\nConnection: close\r\n");
+        }
+        response.push_str("\r\n");
+        w.write_all(response.as_bytes())?;
+        w.flush()
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.reason)
+    }
+}
+
+/// A parsed `CONNECT host:port HTTP/1.1` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// Lower case, without the brackets of an IPv6 address.
+    pub host: String,
+    pub port: u16,
+    /// `Proxy-Authorization` header value.
+    pub authorization: Option<String>,
+}
+
+impl Request {
+    /// `host:port`, with brackets around an IPv6 address.
+    pub fn target(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Read up to the blank line ending the request head. One byte at a time,
+/// so nothing the client sends after the head is consumed here.
+pub fn read_head<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut byte = [0; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too long",
+            ));
+        }
+        match r.read(&mut byte) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => head.push(byte[0]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(head)
+}
+
+pub fn parse_request(head: &[u8]) -> Result<Request, Status> {
+    let head = std::str::from_utf8(head).map_err(|_| Status::BAD_REQUEST)?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target), Some(version), None) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Err(Status::BAD_REQUEST);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Status::BAD_REQUEST);
+    }
+    if method != "CONNECT" {
+        return Err(Status::METHOD_NOT_ALLOWED);
+    }
+
+    let (host, port) = target.rsplit_once(':').ok_or(Status::BAD_REQUEST)?;
+    let host = match host.strip_prefix('[') {
+        Some(v6) => v6.strip_suffix(']').ok_or(Status::BAD_REQUEST)?,
+        None => host,
+    };
+    let port: u16 = port.parse().map_err(|_| Status::BAD_REQUEST)?;
+    if host.is_empty() || port == 0 {
+        return Err(Status::BAD_REQUEST);
+    }
+
+    let mut authorization = None;
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or(Status::BAD_REQUEST)?;
+        if name.trim().eq_ignore_ascii_case("proxy-authorization") {
+            authorization = Some(value.trim().to_string());
+        }
+    }
+    Ok(Request {
+        host: host.to_ascii_lowercase(),
+        port,
+        authorization,
+    })
+}
+
+/// One `--allow` entry: `echo.edger.dev:8443`, `*.edger.dev:443`,
+/// `127.0.0.1:*`, `*:8443`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowRule {
+    /// `*` is any host, `*.edger.dev` one more label in front.
+    host: String,
+    /// `None` is any port.
+    port: Option<u16>,
+}
+
+impl FromStr for AllowRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid destination {:?}, expected HOST:PORT", s);
+        let (host, port) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = match port {
+            "*" => None,
+            port => Some(port.parse().map_err(|_| invalid())?),
+        };
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl AllowRule {
+    fn allows(&self, host: &str, port: u16) -> bool {
+        let host_ok = match self.host.as_str() {
+            "*" => true,
+            pattern => match pattern.strip_prefix("*.") {
+                Some(suffix) => host
+                    .split_once('.')
+                    .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+                None => pattern == host,
+            },
+        };
+        host_ok && self.port.is_none_or(|allowed| allowed == port)
+    }
+}
+
+/// Destinations clients may CONNECT to; nothing else is allowed.
+#[derive(Debug, Clone, Default)]
+pub struct AllowList(pub Vec<AllowRule>);
+
+impl AllowList {
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        self.0.iter().any(|rule| rule.allows(host, port))
+    }
+}
+
+/// Proxy users and the SHA-256 of their passwords.
+pub struct Credentials {
+    users: HashMap<String, [u8; 32]>,
+}
+
+fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    let mut digest = [0; 32];
+    if hex.len() != 64 {
+        return None;
+    }
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+impl Credentials {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut users = HashMap::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, password) = line
+                .split_once(':')
+                .ok_or_else(|| format!("line {}: expected user:password", idx + 1))?;
+            let digest = match password.strip_prefix("sha256:") {
+                Some(hex) => from_hex(hex)
+                    .ok_or_else(|| format!("line {}: invalid sha256 for {}", idx + 1, user))?,
+                None => sha256(password.as_bytes()),
+            };
+            users.insert(user.to_string(), digest);
+        }
+        if users.is_empty() {
+            return Err("no users".to_string());
+        }
+        Ok(Self { users })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// User name if `authorization` is `Basic` with a known user and its
+    /// password.
+    pub fn check(&self, authorization: Option<&str>) -> Option<String> {
+        let (scheme, encoded) = authorization?.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = base64::decode_block(encoded.trim()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        let expected = self.users.get(user)?;
+        memcmp::eq(expected, &sha256(password.as_bytes())).then(|| user.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_request_allow_list_and_credentials() {
+        let head = b"CONNECT Echo.Edger.Dev:8443 HTTP/1.1\r\nHost: echo.edger.dev:8443\r\n\
+                     Proxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n";
+        let request = parse_request(head).unwrap();
+        assert_eq!(request.target(), "echo.edger.dev:8443");
+        assert_eq!(
+            request.authorization.as_deref(),
+            Some("Basic YWxpY2U6c2VjcmV0")
+        );
+        let v6 = parse_request(b"CONNECT [::1]:443 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(
+            (v6.host.as_str(), v6.target()),
+            ("::1", "[::1]:443".to_string())
+        );
+        assert_eq!(
+            parse_request(b"GET / HTTP/1.1\r\n\r\n"),
+            Err(Status::METHOD_NOT_ALLOWED)
+        );
+        assert_eq!(
+            parse_request(b"CONNECT echo.edger.dev HTTP/1.1\r\n\r\n"),
+            Err(Status::BAD_REQUEST)
+        );
+
+        let allow = AllowList(vec![
+            "*.edger.dev:8443".parse().unwrap(),
+            "127.0.0.1:*".parse().unwrap(),
+        ]);
+        assert!(allow.allows("echo.edger.dev", 8443));
+        assert!(!allow.allows("echo.edger.dev", 22));
+        assert!(!allow.allows("edger.dev", 8443));
+        assert!(allow.allows("127.0.0.1", 22));
+        assert!("edger.dev".parse::<AllowRule>().is_err());
+
+        // alice:secret, bob:hunter2 stored hashed
+        let credentials = Credentials::parse(
+            "# users\nalice:secret\nbob:sha256:f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7\n",
+        )
+        .unwrap();
+        assert_eq!(
+            credentials.check(request.authorization.as_deref()),
+            Some("alice".to_string())
+        );
+        assert_eq!(
+            credentials.check(Some("Basic Ym9iOmh1bnRlcjI=")),
+            Some("bob".to_string())
+        );
+        assert_eq!(credentials.check(Some("Basic YWxpY2U6d3Jvbmc=")), None);
+        assert_eq!(credentials.check(None), None);
+    }
+}
@@ -1,14 +1,93 @@
+mod connect;
+
+use clap::{Parser, ValueEnum};
+use conn_limit::{Client, Limiter, Limits};
 use openssl::ssl::{HandshakeError, SslConnector, SslMethod};
-use std::io::{self, Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::thread;
+use relay::relay;
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use connect::{AllowList, AllowRule, Credentials, Status};
 
 const PROXY_ADDR: &str = "0.0.0.0:8279";
 const TLS_SERVER_ADDR: &str = "127.0.0.1:8443";
 const TLS_SERVER_NAME: &str = "test.edger.dev";
 const ROOT_CA_PATH: &str = "../certs/rootCA.pem";
 
-fn handle_client(mut client_stream: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// 모든 연결을 TLS_SERVER_ADDR 로 TLS 중계
+    Fixed,
+    /// HTTP CONNECT 포워드 프록시
+    Connect,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[arg(long, value_enum, default_value_t = Mode::Fixed)]
+    mode: Mode,
+
+    /// CONNECT 를 허용할 목적지 (echo.edger.dev:8443, *.edger.dev:443, 127.0.0.1:*),
+    /// 여러 번 지정 가능, 목록에 없는 목적지는 거부
+    #[arg(long = "allow", value_name = "HOST:PORT")]
+    allow: Vec<AllowRule>,
+
+    /// 목적지와 TLS 로 연결 (클라이언트 쪽은 평문), 없으면 바이트 그대로 터널링
+    #[arg(long)]
+    upstream_tls: bool,
+
+    /// 프록시 인증 계정 파일 (user:password 또는 user:sha256:HEX), 없으면 인증 안 함
+    #[arg(long)]
+    auth: Option<PathBuf>,
+
+    /// 목적지 연결 제한 시간 (초), 주소마다 적용, --upstream-tls 면 TLS 핸드셰이크에도 적용
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    upstream_timeout: u64,
+
+    /// 핸드셰이크 제한 시간은 CONNECT 요청을 읽는 데 적용
+    #[command(flatten)]
+    limits: Limits,
+}
+
+/// Settings for `--mode connect`.
+struct ConnectProxy {
+    allow: AllowList,
+    credentials: Option<Credentials>,
+    /// Set with `--upstream-tls`.
+    upstream_tls: Option<SslConnector>,
+    /// For each connect attempt and for the upstream TLS handshake.
+    upstream_timeout: Duration,
+}
+
+/// Connect to the first address of `host` that answers within `timeout`.
+/// The name lookup itself is not bounded.
+fn dial(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", host))
+    }))
+}
+
+/// 504 if the destination did not answer in time, 502 otherwise.
+fn upstream_status(timed_out: bool) -> Status {
+    if timed_out {
+        Status::GATEWAY_TIMEOUT
+    } else {
+        Status::BAD_GATEWAY
+    }
+}
+
+fn handle_fixed(client_stream: TcpStream, conn: &Client) -> Result<(), Box<dyn std::error::Error>> {
     // TLS connection setup
     let mut connector = SslConnector::builder(SslMethod::tls())?;
     connector.set_ca_file(ROOT_CA_PATH)?;
@@ -20,10 +99,7 @@ fn handle_client(mut client_stream: TcpStream) -> Result<(), Box<dyn std::error:
         Ok(stream) => stream,
         Err(HandshakeError::Failure(e)) => {
             println!("TLS handshake failed: {:?}", e);
-            return Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
-                "TLS handshake failed",
-            )));
+            return Err(Box::new(io::Error::other("TLS handshake failed")));
         }
         Err(HandshakeError::WouldBlock(_)) => {
             println!("TLS handshake would block");
@@ -34,69 +110,202 @@ fn handle_client(mut client_stream: TcpStream) -> Result<(), Box<dyn std::error:
         }
         Err(HandshakeError::SetupFailure(e)) => {
             println!("TLS setup failed: {:?}", e);
-            return Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
-                "TLS setup failed",
-            )));
+            return Err(Box::new(io::Error::other("TLS setup failed")));
         }
     };
 
-    // Message relay between client and TLS server
-    let mut client_buf = [0; 1024];
-    let mut server_buf = [0; 1024];
-
-    loop {
-        // Receive message from client
-        match client_stream.read(&mut client_buf) {
-            Ok(0) => break, // Connection closed
-            Ok(n) => {
-                // Send to TLS server
-                if let Err(e) = tls_stream.write_all(&client_buf[..n]) {
-                    println!("Failed to send to TLS server: {}", e);
-                    break;
-                }
-                tls_stream.flush()?;
-                println!("Client -> Server: {} bytes", n);
-
-                // Receive response from TLS server
-                match tls_stream.read(&mut server_buf) {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        // Send response to client
-                        if let Err(e) = client_stream.write_all(&server_buf[..n]) {
-                            println!("Failed to send to client: {}", e);
-                            break;
+    // Message relay between client and TLS server, both directions at once
+    conn.handshake_done();
+    client_stream.set_nonblocking(true)?;
+    tls_stream.get_ref().set_nonblocking(true)?;
+    let mut client_stream = conn.rate_limit(client_stream);
+    relay(&mut client_stream, &mut tls_stream, conn.idle_timeout())?;
+
+    Ok(())
+}
+
+/// Answer `status` and end the connection, logging why.
+fn reject(client: &mut TcpStream, peer: &str, target: &str, status: Status, why: &str) {
+    println!("{}: CONNECT {} -> {} ({})", peer, target, status, why);
+    let _ = status.write_to(client);
+}
+
+fn handle_connect(
+    mut client: TcpStream,
+    conn: &Client,
+    proxy: &ConnectProxy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let peer = conn.peer.to_string();
+
+    // CONNECT host:port 요청 읽기
+    let head = match connect::read_head(&mut client) {
+        Ok(head) => head,
+        Err(e) if conn_limit::is_timeout(&e) => {
+            let why = "request head not received in time";
+            reject(&mut client, &peer, "-", Status::REQUEST_TIMEOUT, why);
+            return Ok(());
+        }
+        Err(e) => {
+            reject(&mut client, &peer, "-", Status::BAD_REQUEST, &e.to_string());
+            return Ok(());
+        }
+    };
+    conn.handshake_done();
+    let request = match connect::parse_request(&head) {
+        Ok(request) => request,
+        Err(status) => {
+            reject(&mut client, &peer, "-", status, "not a CONNECT request");
+            return Ok(());
+        }
+    };
+    let target = request.target();
+
+    // 프록시 인증, 목적지 허용 목록 순서로 확인
+    let user = match &proxy.credentials {
+        None => "-".to_string(),
+        Some(credentials) => match credentials.check(request.authorization.as_deref()) {
+            Some(user) => user,
+            None => {
+                let why = if request.authorization.is_some() {
+                    "bad credentials"
+                } else {
+                    "no credentials"
+                };
+                reject(
+                    &mut client,
+                    &peer,
+                    &target,
+                    Status::PROXY_AUTH_REQUIRED,
+                    why,
+                );
+                return Ok(());
+            }
+        },
+    };
+    if !proxy.allow.allows(&request.host, request.port) {
+        let why = format!("user {}, destination not allowed", user);
+        reject(&mut client, &peer, &target, Status::FORBIDDEN, &why);
+        return Ok(());
+    }
+
+    let upstream = match dial(&request.host, request.port, proxy.upstream_timeout) {
+        Ok(stream) => stream,
+        Err(e) => {
+            let status = upstream_status(conn_limit::is_timeout(&e));
+            reject(&mut client, &peer, &target, status, &e.to_string());
+            return Ok(());
+        }
+    };
+
+    match &proxy.upstream_tls {
+        None => {
+            Status::ESTABLISHED.write_to(&mut client)?;
+            println!("{}: CONNECT {} -> tunnel (user {})", peer, target, user);
+            client.set_nonblocking(true)?;
+            let mut upstream = upstream;
+            upstream.set_nonblocking(true)?;
+            let mut client = conn.rate_limit(client);
+            relay(&mut client, &mut upstream, conn.idle_timeout())?;
+        }
+        Some(connector) => {
+            // 목적지 이름으로 SNI 와 인증서 검증, 응답 없는 목적지에 묶이지 않도록 제한 시간
+            upstream.set_read_timeout(Some(proxy.upstream_timeout))?;
+            upstream.set_write_timeout(Some(proxy.upstream_timeout))?;
+            let mut tls_stream = match connector.connect(&request.host, upstream) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    // 소켓 시간 초과는 WouldBlock 으로 끝남
+                    let timed_out = match &e {
+                        HandshakeError::WouldBlock(_) => true,
+                        HandshakeError::Failure(mid) => {
+                            mid.error().io_error().is_some_and(conn_limit::is_timeout)
                         }
-                        client_stream.flush()?;
-                        println!("Server -> Client: {} bytes", n);
-                    }
-                    Err(e) => {
-                        println!("Failed to read from TLS server: {}", e);
-                        break;
-                    }
+                        HandshakeError::SetupFailure(_) => false,
+                    };
+                    let why = format!("upstream TLS failed: {}", e);
+                    reject(
+                        &mut client,
+                        &peer,
+                        &target,
+                        upstream_status(timed_out),
+                        &why,
+                    );
+                    return Ok(());
                 }
-            }
-            Err(e) => {
-                println!("Failed to read from client: {}", e);
-                break;
-            }
+            };
+            tls_stream.get_ref().set_read_timeout(None)?;
+            tls_stream.get_ref().set_write_timeout(None)?;
+            Status::ESTABLISHED.write_to(&mut client)?;
+            println!(
+                "{}: CONNECT {} -> TLS {} (user {})",
+                peer,
+                target,
+                tls_stream.ssl().version_str(),
+                user
+            );
+            client.set_nonblocking(true)?;
+            tls_stream.get_ref().set_nonblocking(true)?;
+            let mut client = conn.rate_limit(client);
+            relay(&mut client, &mut tls_stream, conn.idle_timeout())?;
         }
     }
-
+    println!("{}: CONNECT {} closed", peer, target);
     Ok(())
 }
 
+fn connect_proxy(cli: &Cli) -> Result<ConnectProxy, Box<dyn std::error::Error>> {
+    if cli.allow.is_empty() {
+        return Err("--mode connect needs at least one --allow HOST:PORT".into());
+    }
+    let credentials = match &cli.auth {
+        Some(path) => Some(Credentials::load(path)?),
+        None => {
+            println!("No --auth file, proxy authentication is off");
+            None
+        }
+    };
+    let upstream_tls = if cli.upstream_tls {
+        let mut connector = SslConnector::builder(SslMethod::tls())?;
+        connector.set_ca_file(ROOT_CA_PATH)?;
+        Some(connector.build())
+    } else {
+        None
+    };
+    Ok(ConnectProxy {
+        allow: AllowList(cli.allow.clone()),
+        credentials,
+        upstream_tls,
+        upstream_timeout: Duration::from_secs(cli.upstream_timeout),
+    })
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let proxy = match cli.mode {
+        Mode::Fixed => None,
+        Mode::Connect => Some(Arc::new(connect_proxy(&cli)?)),
+    };
+
     let listener = TcpListener::bind(PROXY_ADDR)?;
-    println!("Proxy server is running on {}...", PROXY_ADDR);
+    println!(
+        "Proxy server is running on {} ({:?} mode)...",
+        PROXY_ADDR, cli.mode
+    );
 
+    // 연결은 정해진 수의 워커 스레드가 처리, 넘치면 거부
+    let limiter = Limiter::new(cli.limits);
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                println!("New client connection: {}", stream.peer_addr()?);
-                thread::spawn(move || {
-                    if let Err(e) = handle_client(stream) {
-                        println!("Error handling client: {}", e);
+                let proxy = proxy.clone();
+                limiter.serve(stream, move |stream, conn| {
+                    println!("New client connection: {}", conn.peer);
+                    let result = match &proxy {
+                        Some(proxy) => handle_connect(stream, &conn, proxy),
+                        None => handle_fixed(stream, &conn),
+                    };
+                    if let Err(e) = result {
+                        println!("Error handling client {}: {}", conn.peer, e);
                     }
                 });
             }
@@ -106,3 +315,49 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::time::Instant;
+
+    #[test]
+    fn silent_upstream_tls_gets_a_gateway_timeout() {
+        // accepts the connection but never answers the ClientHello
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+
+        let proxy = Arc::new(ConnectProxy {
+            allow: AllowList(vec!["127.0.0.1:*".parse().unwrap()]),
+            credentials: None,
+            upstream_tls: Some(SslConnector::builder(SslMethod::tls()).unwrap().build()),
+            upstream_timeout: Duration::from_millis(200),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let limiter = Limiter::new(Limits::default());
+            for stream in listener.incoming() {
+                let proxy = Arc::clone(&proxy);
+                limiter.serve(stream.unwrap(), move |stream, conn| {
+                    handle_connect(stream, &conn, &proxy).unwrap()
+                });
+            }
+        });
+
+        let start = Instant::now();
+        let mut client = TcpStream::connect(proxy_addr).unwrap();
+        write!(
+            client,
+            "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n",
+            upstream_addr
+        )
+        .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 504 "), "{}", response);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        drop(upstream);
+    }
+}
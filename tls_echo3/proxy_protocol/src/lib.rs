@@ -0,0 +1,469 @@
+//! HAProxy PROXY protocol v1 (text) and v2 (binary) headers.
+//!
+//! A proxy sends the header to the backend before any payload, so the
+//! backend learns the real client address. v2 can also carry the SNI name
+//! and TLS details of the client connection as TLVs.
+//!
+//! ```
+//! use proxy_protocol::{Header, Version};
+//!
+//! let header = Header::tcp("192.0.2.1:50000".parse().unwrap(), "198.51.100.1:443".parse().unwrap());
+//! let mut wire = Vec::new();
+//! header.write_to(&mut wire, Version::V1).unwrap();
+//! assert_eq!(wire, b"PROXY TCP4 192.0.2.1 198.51.100.1 50000 443\r\n");
+//! assert_eq!(proxy_protocol::read_header(&mut wire.as_slice()).unwrap(), header);
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::str::FromStr;
+
+/// First 12 bytes of every v2 header.
+pub const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest v1 line, CRLF included.
+const V1_MAX_LEN: usize = 107;
+
+const V2_VERSION: u8 = 0x20;
+const V2_CMD_LOCAL: u8 = 0x00;
+const V2_CMD_PROXY: u8 = 0x01;
+
+const V2_AF_UNSPEC: u8 = 0x00;
+const V2_TCP4: u8 = 0x11;
+const V2_TCP6: u8 = 0x21;
+const V2_UNIX_STREAM: u8 = 0x31;
+
+pub const PP2_TYPE_ALPN: u8 = 0x01;
+pub const PP2_TYPE_AUTHORITY: u8 = 0x02;
+pub const PP2_TYPE_SSL: u8 = 0x20;
+pub const PP2_SUBTYPE_SSL_VERSION: u8 = 0x21;
+pub const PP2_SUBTYPE_SSL_CN: u8 = 0x22;
+pub const PP2_SUBTYPE_SSL_CIPHER: u8 = 0x23;
+
+const PP2_CLIENT_SSL: u8 = 0x01;
+const PP2_CLIENT_CERT_CONN: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" | "1" => Ok(Version::V1),
+            "v2" | "2" => Ok(Version::V2),
+            _ => Err(format!("invalid PROXY protocol version '{}' (v1, v2)", s)),
+        }
+    }
+}
+
+/// TLS details of the client connection (v2 `PP2_TYPE_SSL`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The client presented a certificate on this connection.
+    pub client_cert: bool,
+    /// The client certificate was verified.
+    pub verified: bool,
+    /// e.g. `TLSv1.3`
+    pub version: Option<String>,
+    /// Common name of the client certificate.
+    pub cn: Option<String>,
+    pub cipher: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    /// Client address; `None` for v1 `UNKNOWN` and v2 `LOCAL` headers.
+    pub source: Option<SocketAddr>,
+    /// Address the client connected to.
+    pub destination: Option<SocketAddr>,
+    /// SNI host name (v2 only).
+    pub authority: Option<String>,
+    /// Negotiated ALPN protocol (v2 only).
+    pub alpn: Option<Vec<u8>>,
+    /// v2 only.
+    pub tls: Option<TlsInfo>,
+}
+
+impl Header {
+    pub fn tcp(source: SocketAddr, destination: SocketAddr) -> Self {
+        Self {
+            source: Some(source),
+            destination: Some(destination),
+            ..Default::default()
+        }
+    }
+
+    /// Both addresses in the same family; v4 is mapped to v6 if they differ.
+    fn addresses(&self) -> Option<(SocketAddr, SocketAddr)> {
+        let (source, destination) = (self.source?, self.destination?);
+        match (source, destination) {
+            (SocketAddr::V4(_), SocketAddr::V4(_)) | (SocketAddr::V6(_), SocketAddr::V6(_)) => {
+                Some((source, destination))
+            }
+            _ => Some((to_v6(source), to_v6(destination))),
+        }
+    }
+
+    pub fn to_bytes(&self, version: Version) -> Vec<u8> {
+        match version {
+            Version::V1 => self.v1().into_bytes(),
+            Version::V2 => self.v2(),
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W, version: Version) -> io::Result<()> {
+        w.write_all(&self.to_bytes(version))
+    }
+
+    fn v1(&self) -> String {
+        match self.addresses() {
+            Some((source, destination)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if source.is_ipv4() { "TCP4" } else { "TCP6" },
+                source.ip(),
+                destination.ip(),
+                source.port(),
+                destination.port()
+            ),
+            None => "PROXY UNKNOWN\r\n".to_string(),
+        }
+    }
+
+    fn v2(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        let (command, family) = match self.addresses() {
+            Some((SocketAddr::V4(source), SocketAddr::V4(destination))) => {
+                body.extend_from_slice(&source.ip().octets());
+                body.extend_from_slice(&destination.ip().octets());
+                body.extend_from_slice(&source.port().to_be_bytes());
+                body.extend_from_slice(&destination.port().to_be_bytes());
+                (V2_CMD_PROXY, V2_TCP4)
+            }
+            Some((source, destination)) => {
+                body.extend_from_slice(&to_v6_ip(source.ip()).octets());
+                body.extend_from_slice(&to_v6_ip(destination.ip()).octets());
+                body.extend_from_slice(&source.port().to_be_bytes());
+                body.extend_from_slice(&destination.port().to_be_bytes());
+                (V2_CMD_PROXY, V2_TCP6)
+            }
+            None => (V2_CMD_LOCAL, V2_AF_UNSPEC),
+        };
+
+        if let Some(alpn) = &self.alpn {
+            put_tlv(&mut body, PP2_TYPE_ALPN, alpn);
+        }
+        if let Some(authority) = &self.authority {
+            put_tlv(&mut body, PP2_TYPE_AUTHORITY, authority.as_bytes());
+        }
+        if let Some(tls) = &self.tls {
+            let mut value = Vec::new();
+            let mut client = PP2_CLIENT_SSL;
+            if tls.client_cert {
+                client |= PP2_CLIENT_CERT_CONN;
+            }
+            value.push(client);
+            // 0 means the certificate was verified
+            let verify: u32 = if tls.client_cert && tls.verified {
+                0
+            } else {
+                1
+            };
+            value.extend_from_slice(&verify.to_be_bytes());
+            if let Some(version) = &tls.version {
+                put_tlv(&mut value, PP2_SUBTYPE_SSL_VERSION, version.as_bytes());
+            }
+            if let Some(cn) = &tls.cn {
+                put_tlv(&mut value, PP2_SUBTYPE_SSL_CN, cn.as_bytes());
+            }
+            if let Some(cipher) = &tls.cipher {
+                put_tlv(&mut value, PP2_SUBTYPE_SSL_CIPHER, cipher.as_bytes());
+            }
+            put_tlv(&mut body, PP2_TYPE_SSL, &value);
+        }
+
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(V2_VERSION | command);
+        header.push(family);
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend_from_slice(&body);
+        header
+    }
+}
+
+impl fmt::Display for Header {
+    /// `192.0.2.1:50000 -> 198.51.100.1:443 sni=echo.edger.dev TLSv1.3 cn=client`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.source, self.destination) {
+            (Some(source), Some(destination)) => write!(f, "{} -> {}", source, destination)?,
+            _ => write!(f, "unknown")?,
+        }
+        if let Some(authority) = &self.authority {
+            write!(f, " sni={}", authority)?;
+        }
+        if let Some(tls) = &self.tls {
+            if let Some(version) = &tls.version {
+                write!(f, " {}", version)?;
+            }
+            if let Some(cn) = &tls.cn {
+                write!(f, " cn={}", cn)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_v6_ip(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn to_v6(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(IpAddr::V6(to_v6_ip(addr.ip())), addr.port())
+}
+
+fn put_tlv(out: &mut Vec<u8>, kind: u8, value: &[u8]) {
+    out.push(kind);
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Read a v1 or v2 header from the start of a connection. Reads nothing
+/// past the header, so the payload that follows is left in the stream.
+pub fn read_header<R: Read>(r: &mut R) -> io::Result<Header> {
+    let mut prefix = [0u8; 5];
+    r.read_exact(&mut prefix)?;
+    if &prefix == b"PROXY" {
+        read_v1(r)
+    } else if prefix == V2_SIGNATURE[..5] {
+        let mut rest = [0u8; 11];
+        r.read_exact(&mut rest)?;
+        if rest[..7] != V2_SIGNATURE[5..] {
+            return Err(invalid("bad PROXY v2 signature"));
+        }
+        let len = u16::from_be_bytes([rest[9], rest[10]]) as usize;
+        let mut body = vec![0u8; len];
+        r.read_exact(&mut body)?;
+        parse_v2(rest[7], rest[8], &body)
+    } else {
+        Err(invalid("no PROXY protocol header"))
+    }
+}
+
+fn read_v1<R: Read>(r: &mut R) -> io::Result<Header> {
+    // byte by byte, the line has no length prefix
+    let mut line = b"PROXY".to_vec();
+    let mut byte = [0u8; 1];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY v1 line too long"));
+        }
+        r.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY v1 line is not ASCII"))?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> io::Result<Header> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(Header::default()),
+        ["PROXY", proto @ ("TCP4" | "TCP6"), src, dst, sport, dport] => {
+            let ip = |s: &str| -> io::Result<IpAddr> {
+                let ip: IpAddr = s
+                    .parse()
+                    .map_err(|_| invalid(format!("bad address {}", s)))?;
+                if ip.is_ipv4() != (*proto == "TCP4") {
+                    return Err(invalid(format!("{} is not {}", s, proto)));
+                }
+                Ok(ip)
+            };
+            let port = |s: &str| -> io::Result<u16> {
+                s.parse().map_err(|_| invalid(format!("bad port {}", s)))
+            };
+            Ok(Header::tcp(
+                SocketAddr::new(ip(src)?, port(sport)?),
+                SocketAddr::new(ip(dst)?, port(dport)?),
+            ))
+        }
+        _ => Err(invalid(format!("bad PROXY v1 line {:?}", line))),
+    }
+}
+
+fn parse_v2(ver_cmd: u8, family: u8, body: &[u8]) -> io::Result<Header> {
+    if ver_cmd & 0xf0 != V2_VERSION {
+        return Err(invalid(format!("unsupported PROXY version {:#x}", ver_cmd)));
+    }
+    let command = ver_cmd & 0x0f;
+    if command != V2_CMD_LOCAL && command != V2_CMD_PROXY {
+        return Err(invalid(format!("unsupported PROXY command {:#x}", command)));
+    }
+
+    let addr_len = match family {
+        V2_TCP4 => 12,
+        V2_TCP6 => 36,
+        V2_UNIX_STREAM => 216,
+        _ => 0,
+    };
+    if body.len() < addr_len {
+        return Err(invalid("PROXY v2 header too short"));
+    }
+    let (addrs, tlvs) = body.split_at(addr_len);
+
+    let mut header = Header::default();
+    // LOCAL connections (e.g. health checks) carry no client address
+    if command == V2_CMD_PROXY {
+        match family {
+            V2_TCP4 => {
+                let ip = |b: &[u8]| Ipv4Addr::new(b[0], b[1], b[2], b[3]);
+                let port = |b: &[u8]| u16::from_be_bytes([b[0], b[1]]);
+                header.source = Some(SocketAddrV4::new(ip(&addrs[0..]), port(&addrs[8..])).into());
+                header.destination =
+                    Some(SocketAddrV4::new(ip(&addrs[4..]), port(&addrs[10..])).into());
+            }
+            V2_TCP6 => {
+                let ip = |b: &[u8]| Ipv6Addr::from(<[u8; 16]>::try_from(&b[..16]).unwrap());
+                let port = |b: &[u8]| u16::from_be_bytes([b[0], b[1]]);
+                header.source =
+                    Some(SocketAddrV6::new(ip(&addrs[0..]), port(&addrs[32..]), 0, 0).into());
+                header.destination =
+                    Some(SocketAddrV6::new(ip(&addrs[16..]), port(&addrs[34..]), 0, 0).into());
+            }
+            _ => {}
+        }
+    }
+
+    for (kind, value) in tlv_iter(tlvs)? {
+        match kind {
+            PP2_TYPE_ALPN => header.alpn = Some(value.to_vec()),
+            PP2_TYPE_AUTHORITY => header.authority = Some(utf8(value)?),
+            PP2_TYPE_SSL => header.tls = Some(parse_ssl(value)?),
+            _ => {}
+        }
+    }
+    Ok(header)
+}
+
+fn parse_ssl(value: &[u8]) -> io::Result<TlsInfo> {
+    if value.len() < 5 {
+        return Err(invalid("PROXY v2 SSL TLV too short"));
+    }
+    let client = value[0];
+    let verify = u32::from_be_bytes([value[1], value[2], value[3], value[4]]);
+    let mut tls = TlsInfo {
+        client_cert: client & PP2_CLIENT_CERT_CONN != 0,
+        verified: client & PP2_CLIENT_CERT_CONN != 0 && verify == 0,
+        ..Default::default()
+    };
+    for (kind, value) in tlv_iter(&value[5..])? {
+        match kind {
+            PP2_SUBTYPE_SSL_VERSION => tls.version = Some(utf8(value)?),
+            PP2_SUBTYPE_SSL_CN => tls.cn = Some(utf8(value)?),
+            PP2_SUBTYPE_SSL_CIPHER => tls.cipher = Some(utf8(value)?),
+            _ => {}
+        }
+    }
+    Ok(tls)
+}
+
+fn tlv_iter(mut data: &[u8]) -> io::Result<Vec<(u8, &[u8])>> {
+    let mut tlvs = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 3 {
+            return Err(invalid("truncated PROXY v2 TLV"));
+        }
+        let len = u16::from_be_bytes([data[1], data[2]]) as usize;
+        if data.len() < 3 + len {
+            return Err(invalid("truncated PROXY v2 TLV"));
+        }
+        tlvs.push((data[0], &data[3..3 + len]));
+        data = &data[3 + len..];
+    }
+    Ok(tlvs)
+}
+
+fn utf8(value: &[u8]) -> io::Result<String> {
+    String::from_utf8(value.to_vec()).map_err(|_| invalid("PROXY v2 TLV is not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_round_trip() {
+        let v6 = Header::tcp(
+            "[2001:db8::1]:4000".parse().unwrap(),
+            "[2001:db8::2]:443".parse().unwrap(),
+        );
+        assert_eq!(
+            v6.to_bytes(Version::V1),
+            b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n"
+        );
+
+        let mut wire = Header::default().to_bytes(Version::V1);
+        wire.extend_from_slice(b"payload");
+        let mut r = wire.as_slice();
+        assert_eq!(read_header(&mut r).unwrap(), Header::default());
+        assert_eq!(r, b"payload");
+
+        assert!(read_header(&mut &b"PROXY TCP4 ::1 127.0.0.1 1 2\r\n"[..]).is_err());
+        assert!(read_header(&mut &b"GET / HTTP/1.1\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn v2_round_trip_with_tlvs() {
+        let header = Header {
+            authority: Some("echo.edger.dev".into()),
+            tls: Some(TlsInfo {
+                client_cert: true,
+                verified: true,
+                version: Some("TLSv1.3".into()),
+                cn: Some("echo-client".into()),
+                cipher: Some("TLS_AES_256_GCM_SHA384".into()),
+            }),
+            ..Header::tcp(
+                "192.0.2.1:50000".parse().unwrap(),
+                "198.51.100.1:8443".parse().unwrap(),
+            )
+        };
+        let mut wire = header.to_bytes(Version::V2);
+        assert_eq!(&wire[..12], &V2_SIGNATURE);
+        assert_eq!(&wire[12..14], &[0x21, 0x11]);
+        assert_eq!(&wire[16..20], &[192, 0, 2, 1]);
+
+        wire.extend_from_slice(b"hello");
+        let mut r = wire.as_slice();
+        assert_eq!(read_header(&mut r).unwrap(), header);
+        assert_eq!(r, b"hello");
+
+        // mixed families go out as v4-mapped v6
+        let mixed = Header::tcp(
+            "192.0.2.1:1".parse().unwrap(),
+            "[2001:db8::2]:2".parse().unwrap(),
+        );
+        let parsed = read_header(&mut mixed.to_bytes(Version::V2).as_slice()).unwrap();
+        assert_eq!(parsed.source, Some("[::ffff:192.0.2.1]:1".parse().unwrap()));
+
+        let local = Header::default().to_bytes(Version::V2);
+        assert_eq!(local.len(), 16);
+        assert_eq!(
+            read_header(&mut local.as_slice()).unwrap(),
+            Header::default()
+        );
+    }
+}
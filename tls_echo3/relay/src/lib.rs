@@ -0,0 +1,365 @@
+//! Bidirectional copy between a client and the stream it is proxied to,
+//! shared by `proxy` and `reverse_proxy`.
+//!
+//! Both streams are switched to non-blocking mode by the caller and pumped
+//! in turn, waiting in `poll(2)` while neither can move, so one thread
+//! serves both directions and either side can be half-closed while the
+//! other keeps sending.
+
+use conn_limit::RateLimited;
+use openssl::ssl::{ErrorCode, SslStream};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::time::{Duration, Instant};
+
+const BUF_SIZE: usize = 16 * 1024;
+const MAX_READS_PER_PUMP: usize = 16;
+
+/// Streams whose sending side can be closed while still reading.
+pub trait HalfClose {
+    fn shutdown_write(&mut self) -> io::Result<()>;
+}
+
+impl HalfClose for TcpStream {
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+}
+
+impl HalfClose for SslStream<TcpStream> {
+    /// Send close_notify, then close the TCP sending side. Reading goes on
+    /// until the peer's close_notify. On a non-blocking socket this fails
+    /// with `WouldBlock` until the close_notify is out; call it again then.
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        match self.shutdown() {
+            Ok(_) => {}
+            Err(e) if e.code() == ErrorCode::WANT_WRITE || e.code() == ErrorCode::WANT_READ => {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            Err(e) => return Err(e.into_io_error().unwrap_or_else(io::Error::other)),
+        }
+        self.get_ref().shutdown(Shutdown::Write)
+    }
+}
+
+impl<S: HalfClose> HalfClose for RateLimited<S> {
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.get_mut().shutdown_write()
+    }
+}
+
+/// Streams on top of a socket the relay can wait on.
+pub trait Socket {
+    fn socket(&self) -> BorrowedFd<'_>;
+}
+
+impl Socket for TcpStream {
+    fn socket(&self) -> BorrowedFd<'_> {
+        self.as_fd()
+    }
+}
+
+impl Socket for SslStream<TcpStream> {
+    fn socket(&self) -> BorrowedFd<'_> {
+        self.get_ref().as_fd()
+    }
+}
+
+impl<S: Socket> Socket for RateLimited<S> {
+    fn socket(&self) -> BorrowedFd<'_> {
+        self.get_ref().socket()
+    }
+}
+
+/// One direction of the relay.
+struct Pipe {
+    name: &'static str,
+    buf: Box<[u8]>,
+    start: usize,
+    end: usize,
+    /// EOF read from the source
+    eof: bool,
+    /// EOF passed on to the destination
+    closed: bool,
+}
+
+impl Pipe {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            buf: vec![0; BUF_SIZE].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            eof: false,
+            closed: false,
+        }
+    }
+
+    /// Waiting for data from the source.
+    fn wants_read(&self) -> bool {
+        !self.eof && self.start == self.end
+    }
+
+    /// Waiting for room at the destination, for data or the EOF.
+    fn wants_write(&self) -> bool {
+        !self.closed && (self.start < self.end || self.eof)
+    }
+
+    /// Move whatever is ready from `from` to `to` without blocking. Both
+    /// streams must be non-blocking. Returns whether anything happened.
+    fn pump<R, W>(&mut self, from: &mut R, to: &mut W) -> io::Result<bool>
+    where
+        R: Read,
+        W: Write + HalfClose,
+    {
+        let mut progress = false;
+        let mut reads = 0;
+        while !self.closed {
+            if self.start < self.end {
+                match to.write(&self.buf[self.start..self.end]) {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(n) => {
+                        self.start += n;
+                        progress = true;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            } else if self.eof {
+                to.flush()?;
+                match to.shutdown_write() {
+                    Ok(()) => {}
+                    // close_notify still queued, retried on the next pump
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        return Err(io::Error::new(
+                            e.kind(),
+                            format!("{}: failed to forward EOF: {}", self.name, e),
+                        ));
+                    }
+                }
+                println!("{}: EOF", self.name);
+                self.closed = true;
+                progress = true;
+            } else if reads < MAX_READS_PER_PUMP {
+                reads += 1;
+                match from.read(&mut self.buf) {
+                    Ok(0) => self.eof = true,
+                    Ok(n) => {
+                        self.start = 0;
+                        self.end = n;
+                        println!("{}: {} bytes", self.name, n);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+                progress = true;
+            } else {
+                break;
+            }
+        }
+        Ok(progress)
+    }
+}
+
+/// Copy both directions independently until each side has sent EOF.
+/// A half-closed side keeps receiving until the other one is done too.
+/// Gives up with `TimedOut` after `idle_timeout` without traffic either way;
+/// the streams are non-blocking, so socket timeouts do not apply.
+pub fn relay<C, S>(client: &mut C, server: &mut S, idle_timeout: Option<Duration>) -> io::Result<()>
+where
+    C: Read + Write + HalfClose + Socket,
+    S: Read + Write + HalfClose + Socket,
+{
+    let mut upstream = Pipe::new("Client -> Server");
+    let mut downstream = Pipe::new("Server -> Client");
+    let mut last_active = Instant::now();
+
+    while !(upstream.closed && downstream.closed) {
+        let sent = upstream.pump(client, server)?;
+        let received = downstream.pump(server, client)?;
+        if sent || received {
+            last_active = Instant::now();
+            continue;
+        }
+
+        let remaining = match idle_timeout {
+            Some(timeout) => match timeout.checked_sub(last_active.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout")),
+            },
+            None => None,
+        };
+        wait(
+            [
+                (
+                    client.socket(),
+                    upstream.wants_read(),
+                    downstream.wants_write(),
+                ),
+                (
+                    server.socket(),
+                    downstream.wants_read(),
+                    upstream.wants_write(),
+                ),
+            ],
+            remaining,
+        )?;
+    }
+    Ok(())
+}
+
+/// Block until one of `(socket, read, write)` is ready for what is asked
+/// of it, or `timeout` passes.
+fn wait(sockets: [(BorrowedFd<'_>, bool, bool); 2], timeout: Option<Duration>) -> io::Result<()> {
+    let mut fds = sockets.map(|(fd, read, write)| {
+        let mut events = 0;
+        if read {
+            events |= libc::POLLIN;
+        }
+        if write {
+            events |= libc::POLLOUT;
+        }
+        libc::pollfd {
+            // a socket nobody waits on is skipped, or its hangup would
+            // wake the loop up for nothing
+            fd: if events == 0 { -1 } else { fd.as_raw_fd() },
+            events,
+            revents: 0,
+        }
+    });
+    // rounded up, so the timeout has passed when poll returns
+    let timeout = timeout.map_or(-1, |timeout| {
+        i32::try_from(timeout.as_micros().div_ceil(1000)).unwrap_or(i32::MAX)
+    });
+    match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } {
+        -1 => {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                Ok(())
+            } else {
+                Err(e)
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cert_store::test_pki::TestCa;
+    use openssl::pkey::PKey;
+    use openssl::ssl::{SslAcceptor, SslConnector, SslMethod};
+    use openssl::x509::X509;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Connected pair: (accepted side, connecting side).
+    fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connected = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener.accept().unwrap().0, connected)
+    }
+
+    #[test]
+    fn copies_both_ways_after_a_half_close() {
+        let (mut client, mut client_peer) = pair();
+        let (mut server, mut server_peer) = pair();
+        let proxy = thread::spawn(move || {
+            client.set_nonblocking(true).unwrap();
+            server.set_nonblocking(true).unwrap();
+            relay(&mut client, &mut server, Some(Duration::from_secs(5)))
+        });
+
+        client_peer.write_all(b"request").unwrap();
+        client_peer.shutdown(Shutdown::Write).unwrap();
+        let mut request = Vec::new();
+        server_peer.read_to_end(&mut request).unwrap();
+        assert_eq!(request, b"request");
+
+        // the client stopped sending but still gets the whole reply
+        let reply = vec![7; 3 * BUF_SIZE];
+        server_peer.write_all(&reply).unwrap();
+        server_peer.shutdown(Shutdown::Write).unwrap();
+        let mut received = Vec::new();
+        client_peer.read_to_end(&mut received).unwrap();
+        assert_eq!(received, reply);
+        proxy.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn gives_up_when_idle() {
+        let (mut client, _client_peer) = pair();
+        let (mut server, _server_peer) = pair();
+        client.set_nonblocking(true).unwrap();
+        server.set_nonblocking(true).unwrap();
+        let err = relay(&mut client, &mut server, Some(Duration::from_millis(50))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn close_notify_waits_for_room_in_the_socket() {
+        let ca = TestCa::new();
+        let issued = ca.server("localhost", 2);
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor
+            .set_certificate(&X509::from_der(&issued.cert_der).unwrap())
+            .unwrap();
+        acceptor
+            .set_private_key(&PKey::private_key_from_der(&issued.key_der).unwrap())
+            .unwrap();
+        let acceptor = acceptor.build();
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector
+            .cert_store_mut()
+            .add_cert(X509::from_der(&ca.cert_der()).unwrap())
+            .unwrap();
+        let connector = connector.build();
+
+        let (accepted, connected) = pair();
+        let peer = thread::spawn(move || acceptor.accept(accepted).unwrap());
+        let mut tls = connector.connect("localhost", connected).unwrap();
+        let mut peer = peer.join().unwrap();
+        tls.get_ref().set_nonblocking(true).unwrap();
+
+        // fill the socket while the peer is not reading, so the
+        // close_notify has to wait behind the data
+        let data = vec![7; 4 << 20];
+        let mut pipe = Pipe::new("test");
+        let mut source = &data[..];
+        while !pipe.closed {
+            if !pipe.pump(&mut source, &mut tls).unwrap() {
+                break;
+            }
+        }
+        assert!(!pipe.closed);
+
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            peer.read_to_end(&mut received).unwrap();
+            // after the close_notify the TCP stream ends as well
+            assert_eq!(peer.get_mut().read(&mut [0; 1]).unwrap(), 0);
+            received
+        });
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !pipe.closed {
+            assert!(Instant::now() < deadline, "close_notify never went out");
+            if !pipe.pump(&mut source, &mut tls).unwrap() {
+                wait(
+                    [
+                        (tls.socket(), false, pipe.wants_write()),
+                        (tls.socket(), false, false),
+                    ],
+                    Some(Duration::from_millis(100)),
+                )
+                .unwrap();
+            }
+        }
+        assert_eq!(reader.join().unwrap().len(), data.len());
+    }
+}
@@ -0,0 +1,420 @@
+//! Backend pools: round-robin or least-connections balancing, connection
+//! counters and active health checks.
+
+use proxy_protocol::{Header, Version};
+use serde::Deserialize;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// Sent by the echo probe; a healthy echo backend sends it back.
+const ECHO_PROBE: &[u8] = b"health-check\n";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    /// The backend accepts a TCP connection.
+    #[default]
+    Tcp,
+    /// The backend echoes a probe line back.
+    Echo,
+}
+
+fn default_interval_ms() -> u64 {
+    5000
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_threshold() -> u32 {
+    2
+}
+
+/// `[route.health_check]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheckConfig {
+    #[serde(default)]
+    pub probe: Probe,
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Failed checks in a row before a backend is taken out.
+    #[serde(default = "default_threshold")]
+    pub fall: u32,
+    /// Passed checks in a row before it is put back.
+    #[serde(default = "default_threshold")]
+    pub rise: u32,
+}
+
+pub struct Backend {
+    pub addr: String,
+    healthy: AtomicBool,
+    /// open connections
+    active: AtomicUsize,
+    /// connections made so far
+    total: AtomicU64,
+    /// failed connection attempts
+    errors: AtomicU64,
+    // check results in a row, only updated by the health check thread
+    failed_checks: AtomicU32,
+    passed_checks: AtomicU32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendStats {
+    pub addr: String,
+    pub healthy: bool,
+    pub active: usize,
+    pub total: u64,
+    pub errors: u64,
+}
+
+impl Backend {
+    fn new(addr: &str) -> Self {
+        Self {
+            addr: addr.to_string(),
+            healthy: AtomicBool::new(true),
+            active: AtomicUsize::new(0),
+            total: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            failed_checks: AtomicU32::new(0),
+            passed_checks: AtomicU32::new(0),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    fn set_healthy(&self, healthy: bool) {
+        if self.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                println!("backend {} is up", self.addr);
+            } else {
+                eprintln!("backend {} is down", self.addr);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> BackendStats {
+        BackendStats {
+            addr: self.addr.clone(),
+            healthy: self.is_healthy(),
+            active: self.active.load(Ordering::Relaxed),
+            total: self.total.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A connection to a backend; counted as active until dropped.
+pub struct Connection {
+    pub stream: TcpStream,
+    backend: Arc<Backend>,
+}
+
+impl Connection {
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.backend.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct Pool {
+    backends: Vec<Arc<Backend>>,
+    strategy: Strategy,
+    next: AtomicUsize,
+    /// Failed connects take a backend out only while health checks can
+    /// bring it back.
+    checked: AtomicBool,
+}
+
+impl Pool {
+    pub fn new(addrs: &[String], strategy: Strategy) -> Self {
+        Self {
+            backends: addrs
+                .iter()
+                .map(|addr| Arc::new(Backend::new(addr)))
+                .collect(),
+            strategy,
+            next: AtomicUsize::new(0),
+            checked: AtomicBool::new(false),
+        }
+    }
+
+    /// Backends in the order to try them. Unhealthy ones come last, so a
+    /// pool with every backend down still gets a chance.
+    fn candidates(&self) -> Vec<Arc<Backend>> {
+        let count = self.backends.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % count.max(1);
+        let mut backends: Vec<Arc<Backend>> = (0..count)
+            .map(|i| Arc::clone(&self.backends[(start + i) % count]))
+            .collect();
+        // stable sort keeps the rotation among equals
+        match self.strategy {
+            Strategy::RoundRobin => backends.sort_by_key(|backend| !backend.is_healthy()),
+            Strategy::LeastConnections => backends.sort_by_key(|backend| {
+                (
+                    !backend.is_healthy(),
+                    backend.active.load(Ordering::Relaxed),
+                )
+            }),
+        }
+        backends
+    }
+
+    pub fn connect(&self) -> io::Result<Connection> {
+        let mut last_error = None;
+        for backend in self.candidates() {
+            // count before connecting so concurrent least-connections picks spread out
+            backend.active.fetch_add(1, Ordering::Relaxed);
+            match TcpStream::connect(&backend.addr) {
+                Ok(stream) => {
+                    backend.total.fetch_add(1, Ordering::Relaxed);
+                    return Ok(Connection { stream, backend });
+                }
+                Err(e) => {
+                    backend.active.fetch_sub(1, Ordering::Relaxed);
+                    backend.errors.fetch_add(1, Ordering::Relaxed);
+                    eprintln!("backend {} failed: {}", backend.addr, e);
+                    if self.checked.load(Ordering::Relaxed) {
+                        // down at once, but back up only after `rise` checks
+                        backend.passed_checks.store(0, Ordering::Relaxed);
+                        backend.set_healthy(false);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no backends")))
+    }
+
+    pub fn stats(&self) -> Vec<BackendStats> {
+        self.backends
+            .iter()
+            .map(|backend| backend.stats())
+            .collect()
+    }
+
+    /// Check every backend each `interval_ms` until the pool is dropped.
+    /// Backends expecting a PROXY header get a LOCAL one before the probe.
+    pub fn spawn_health_check(
+        pool: &Arc<Pool>,
+        config: HealthCheckConfig,
+        proxy_protocol: Option<Version>,
+    ) -> thread::JoinHandle<()> {
+        pool.checked.store(true, Ordering::Relaxed);
+        let pool: Weak<Pool> = Arc::downgrade(pool);
+        thread::spawn(move || {
+            let interval = Duration::from_millis(config.interval_ms);
+            let timeout = Duration::from_millis(config.timeout_ms);
+            while let Some(pool) = pool.upgrade() {
+                for backend in &pool.backends {
+                    match probe(&backend.addr, config.probe, proxy_protocol, timeout) {
+                        Ok(()) => {
+                            backend.failed_checks.store(0, Ordering::Relaxed);
+                            let passed = backend.passed_checks.fetch_add(1, Ordering::Relaxed) + 1;
+                            if passed >= config.rise {
+                                backend.set_healthy(true);
+                            }
+                        }
+                        Err(e) => {
+                            backend.passed_checks.store(0, Ordering::Relaxed);
+                            let failed = backend.failed_checks.fetch_add(1, Ordering::Relaxed) + 1;
+                            if failed >= config.fall && backend.is_healthy() {
+                                eprintln!("backend {} check failed: {}", backend.addr, e);
+                                backend.set_healthy(false);
+                            }
+                        }
+                    }
+                }
+                drop(pool);
+                thread::sleep(interval);
+            }
+        })
+    }
+}
+
+fn resolve(addr: &str) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", addr)))
+}
+
+fn probe(
+    addr: &str,
+    probe: Probe,
+    proxy_protocol: Option<Version>,
+    timeout: Duration,
+) -> io::Result<()> {
+    let mut stream = TcpStream::connect_timeout(&resolve(addr)?, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    if let Some(version) = proxy_protocol {
+        Header::default().write_to(&mut stream, version)?;
+    }
+    if probe == Probe::Echo {
+        stream.write_all(ECHO_PROBE)?;
+        let mut reply = [0u8; ECHO_PROBE.len()];
+        stream.read_exact(&mut reply)?;
+        if reply != ECHO_PROBE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected echo reply",
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::process::{Child, Command, Stdio};
+    use std::time::Instant;
+
+    pub(crate) fn wait_for(mut f: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if f() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    /// Build `../plain_svr` once and return its binary.
+    pub(crate) fn plain_svr_bin() -> PathBuf {
+        let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let target_dir = std::env::temp_dir().join("reverse_proxy-plain_svr");
+        let status = Command::new(env!("CARGO"))
+            .arg("build")
+            .arg("--quiet")
+            .arg("--manifest-path")
+            .arg(manifest_dir.join("../plain_svr/Cargo.toml"))
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        target_dir.join("debug/plain_svr")
+    }
+
+    pub(crate) fn free_addr() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn start(bin: &PathBuf, addr: &str) -> Child {
+        let child = Command::new(bin)
+            .arg(addr)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        assert!(wait_for(|| TcpStream::connect(addr).is_ok()));
+        child
+    }
+
+    #[test]
+    fn balance_and_health_check_plain_svr() {
+        let bin = plain_svr_bin();
+        let addrs: Vec<String> = (0..3).map(|_| free_addr()).collect();
+        let mut servers: Vec<Child> = addrs.iter().map(|addr| start(&bin, addr)).collect();
+
+        let pool = Arc::new(Pool::new(&addrs, Strategy::RoundRobin));
+        let _checker = Pool::spawn_health_check(
+            &pool,
+            HealthCheckConfig {
+                probe: Probe::Echo,
+                interval_ms: 50,
+                timeout_ms: 200,
+                fall: 1,
+                rise: 1,
+            },
+            None,
+        );
+
+        // round-robin: one connection per backend
+        let conns: Vec<Connection> = (0..3).map(|_| pool.connect().unwrap()).collect();
+        let mut used: Vec<&str> = conns.iter().map(|c| c.backend().addr.as_str()).collect();
+        used.sort();
+        let mut expected: Vec<&str> = addrs.iter().map(String::as_str).collect();
+        expected.sort();
+        assert_eq!(used, expected);
+        assert!(pool.stats().iter().all(|s| s.active == 1 && s.total == 1));
+        drop(conns);
+        assert!(pool.stats().iter().all(|s| s.active == 0));
+
+        // kill one; the checker takes it out
+        servers[1].kill().unwrap();
+        servers[1].wait().unwrap();
+        assert!(wait_for(|| !pool.stats()[1].healthy));
+        for _ in 0..4 {
+            let conn = pool.connect().unwrap();
+            assert_ne!(conn.backend().addr, addrs[1]);
+        }
+
+        // and puts it back once it answers again
+        servers[1] = start(&bin, &addrs[1]);
+        assert!(wait_for(|| pool.stats()[1].healthy));
+
+        for server in &mut servers {
+            let _ = server.kill();
+            let _ = server.wait();
+        }
+    }
+
+    #[test]
+    fn least_connections_prefers_idle_backend() {
+        let listeners: Vec<TcpListener> = (0..2)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let addrs: Vec<String> = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().to_string())
+            .collect();
+        let pool = Pool::new(&addrs, Strategy::LeastConnections);
+
+        let first = pool.connect().unwrap();
+        for _ in 0..3 {
+            let next = pool.connect().unwrap();
+            assert_ne!(next.backend().addr, first.backend().addr);
+        }
+    }
+
+    #[test]
+    fn failed_dial_restarts_the_rise_count() {
+        let addr = free_addr();
+        let pool = Pool::new(std::slice::from_ref(&addr), Strategy::RoundRobin);
+        pool.checked.store(true, Ordering::Relaxed);
+        let backend = &pool.backends[0];
+        backend.passed_checks.store(5, Ordering::Relaxed);
+
+        assert!(pool.connect().is_err());
+        assert!(!backend.is_healthy());
+        assert_eq!(backend.passed_checks.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,134 @@
+//! Proxy configuration file.
+//!
+//! ```toml
+//! listen = "0.0.0.0:8443"
+//!
+//! [[route]]
+//! server_names = ["echo.edger.dev", "*.echo.edger.dev"]
+//! cert = "../certs/server.pem"
+//! key = "../certs/server-key.pem"
+//! backends = ["127.0.0.1:8279"]
+//!
+//! [[route]]
+//! default = true          # clients without SNI or with an unknown name
+//! cert = "../certs/server.pem"
+//! key = "../certs/server-key.pem"
+//! backends = ["127.0.0.1:8280", "127.0.0.1:8281"]
+//! balance = "least_connections"     # or round_robin (default)
+//! proxy_protocol = "v2"   # send the client address to the backends (v1 or v2)
+//! client_ca = "../certs/ca.pem"   # ask clients for a certificate signed by this CA
+//! require_client_cert = true      # and refuse those without one
+//!
+//! [route.health_check]
+//! probe = "echo"          # tcp (connect only) or echo
+//! interval_ms = 5000
+//! timeout_ms = 1000
+//! fall = 2                # failed checks before a backend is taken out
+//! rise = 2                # passed checks before it is put back
+//! ```
+//!
+//! Relative `cert`/`key`/`client_ca` paths are relative to the config file.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::balance::{HealthCheckConfig, Strategy};
+
+fn default_listen() -> String {
+    "0.0.0.0:8443".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    #[serde(rename = "route")]
+    pub routes: Vec<RouteConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// SNI names, exact (`echo.edger.dev`) or one wildcard label (`*.edger.dev`).
+    #[serde(default)]
+    pub server_names: Vec<String>,
+    /// Take connections no other route matches.
+    #[serde(default)]
+    pub default: bool,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// `host:port` of the plain TCP backends.
+    pub backends: Vec<String>,
+    #[serde(default)]
+    pub balance: Strategy,
+    /// No health checks if not set.
+    pub health_check: Option<HealthCheckConfig>,
+    /// PROXY protocol header sent to the backend before the client data.
+    pub proxy_protocol: Option<proxy_protocol::Version>,
+    /// CA (PEM) for client certificates. Clients are only asked for one if
+    /// set; the CN goes to the backend in the PROXY header.
+    pub client_ca: Option<PathBuf>,
+    /// Refuse clients without a certificate. Needs `client_ca`.
+    #[serde(default)]
+    pub require_client_cert: bool,
+}
+
+impl RouteConfig {
+    /// Name for log messages.
+    pub fn label(&self) -> String {
+        match self.server_names.first() {
+            Some(name) => name.clone(),
+            None => "default".to_string(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut config: Config =
+            toml::from_str(&s).with_context(|| format!("invalid config {}", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for route in &mut config.routes {
+            route.cert = base.join(&route.cert);
+            route.key = base.join(&route.key);
+            route.client_ca = route.client_ca.as_ref().map(|ca| base.join(ca));
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.routes.is_empty() {
+            bail!("no [[route]] configured");
+        }
+        for route in &self.routes {
+            if route.server_names.is_empty() && !route.default {
+                bail!("route for {} has no server_names", route.cert.display());
+            }
+            if route.require_client_cert && route.client_ca.is_none() {
+                bail!(
+                    "route {} requires client certificates but has no client_ca",
+                    route.label()
+                );
+            }
+            if route.backends.is_empty() {
+                bail!("route {} has no backends", route.label());
+            }
+            for name in &route.server_names {
+                let host = name.strip_prefix("*.").unwrap_or(name);
+                if host.is_empty() || host.contains('*') {
+                    bail!("invalid server name {:?}", name);
+                }
+            }
+        }
+        if self.routes.iter().filter(|route| route.default).count() > 1 {
+            bail!("more than one default route");
+        }
+        Ok(())
+    }
+}
@@ -1,79 +1,138 @@
-use anyhow::Result;
-use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod, SslStream};
-use std::io::{Read, Write};
+mod balance;
+mod config;
+mod route;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use conn_limit::{Client, Limiter, Limits};
+use openssl::nid::Nid;
+use openssl::ssl::{HandshakeError, NameType, Ssl, SslAcceptor, SslRef, SslStream};
+use openssl::x509::X509VerifyResult;
+use proxy_protocol::{Header, TlsInfo};
+use relay::relay;
 use std::net::{TcpListener, TcpStream};
-use std::thread;
-
-const PROXY_ADDR: &str = "0.0.0.0:8443";
-const ECHO_SERVER_ADDR: &str = "127.0.0.1:8279";
-
-const SERVER_CERT_PATH: &str = "../certs/server.pem";
-const SERVER_KEY_PATH: &str = "../certs/server-key.pem";
-
-fn handle_client(mut tls_stream: SslStream<TcpStream>) -> Result<()> {
-    // 에코 서버에 연결
-    let mut echo_stream = TcpStream::connect(ECHO_SERVER_ADDR)?;
-
-    // 클라이언트와 에코 서버 간의 양방향 데이터 전송
-    let mut buf = [0u8; 1024];
-    loop {
-        // 클라이언트로부터 데이터 읽기
-        match tls_stream.read(&mut buf) {
-            Ok(0) => break, // 연결 종료
-            Ok(n) => {
-                // 에코 서버로 데이터 전송
-                echo_stream.write_all(&buf[..n])?;
-
-                // 에코 서버로부터 응답 받기
-                let mut echo_buf = [0u8; 1024];
-                match echo_stream.read(&mut echo_buf) {
-                    Ok(0) => break,
-                    Ok(m) => {
-                        // 클라이언트에게 응답 전송
-                        tls_stream.write_all(&echo_buf[..m])?;
-                        tls_stream.flush()?;
-                    }
-                    Err(e) => {
-                        eprintln!("Error reading from echo server: {}", e);
-                        break;
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("Error reading from client: {}", e);
-                break;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use config::Config;
+use route::Router;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// 설정 파일 (TOML)
+    #[arg(short, long, default_value = "reverse_proxy.toml")]
+    config: PathBuf,
+
+    #[command(flatten)]
+    limits: Limits,
+}
+
+/// PROXY protocol header with the client address, SNI and TLS details.
+fn proxy_header(tls_stream: &SslStream<TcpStream>) -> Result<Header> {
+    let ssl: &SslRef = tls_stream.ssl();
+    let peer_cert = ssl.peer_certificate();
+    let cn = peer_cert.as_ref().and_then(|cert| {
+        cert.subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| std::str::from_utf8(entry.data().as_slice()).ok())
+            .map(str::to_string)
+    });
+    Ok(Header {
+        authority: ssl.servername(NameType::HOST_NAME).map(str::to_string),
+        alpn: ssl.selected_alpn_protocol().map(<[u8]>::to_vec),
+        tls: Some(TlsInfo {
+            client_cert: peer_cert.is_some(),
+            verified: peer_cert.is_some() && ssl.verify_result() == X509VerifyResult::OK,
+            version: Some(ssl.version_str().to_string()),
+            cn,
+            cipher: ssl.current_cipher().map(|cipher| cipher.name().to_string()),
+        }),
+        ..Header::tcp(
+            tls_stream.get_ref().peer_addr()?,
+            tls_stream.get_ref().local_addr()?,
+        )
+    })
+}
+
+fn handle_client(tls_stream: SslStream<TcpStream>, client: &Client, router: &Router) -> Result<()> {
+    client.handshake_done();
+    // SNI 에 맞는 route 의 백엔드에 연결
+    let route = router
+        .route_of(tls_stream.ssl())
+        .context("no route for connection")?;
+    let mut backend = route.connect()?;
+    println!(
+        "{} -> route {} ({})",
+        tls_stream.get_ref().peer_addr()?,
+        route.label,
+        backend.backend().addr
+    );
+
+    // 백엔드가 실제 클라이언트 주소를 알 수 있도록 PROXY 헤더 전송
+    if let Some(version) = route.proxy_protocol {
+        proxy_header(&tls_stream)?.write_to(&mut backend.stream, version)?;
+    }
+
+    // 클라이언트와 백엔드 간의 양방향 데이터 전송, 방향별로 독립적으로 복사
+    tls_stream.get_ref().set_nonblocking(true)?;
+    backend.stream.set_nonblocking(true)?;
+    let mut tls_stream = client.rate_limit(tls_stream);
+    let result = relay(&mut tls_stream, &mut backend.stream, client.idle_timeout());
+    drop(backend);
+    println!("route {}: {}", route.label, route.backend_summary());
+    result?;
+    Ok(())
+}
+
+/// TLS 핸드셰이크 후 백엔드로 중계, 워커 스레드에서 실행
+fn serve(acceptor: &SslAcceptor, router: &Router, stream: TcpStream, client: Client) {
+    let ssl = match Ssl::new(acceptor.context()) {
+        Ok(ssl) => ssl,
+        Err(e) => {
+            eprintln!("Failed to create SSL context: {}", e);
+            return;
+        }
+    };
+    match ssl.accept(stream) {
+        Ok(tls_stream) => {
+            if let Err(e) = handle_client(tls_stream, &client, router) {
+                eprintln!("Error handling client {}: {}", client.peer, e);
             }
         }
+        Err(HandshakeError::WouldBlock(_)) => {
+            eprintln!("TLS handshake timed out: {}", client.peer)
+        }
+        Err(e) => eprintln!("TLS handshake failed: {}", e),
     }
-    Ok(())
 }
 
 fn main() -> Result<()> {
-    // TLS 설정
-    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
-    acceptor.set_private_key_file(SERVER_KEY_PATH, SslFiletype::PEM)?;
-    acceptor.set_certificate_chain_file(SERVER_CERT_PATH)?;
-    acceptor.check_private_key()?;
-    let acceptor = acceptor.build();
+    let cli = Cli::parse();
+
+    // 설정 및 route 별 인증서 로드
+    let config = Config::load(&cli.config)?;
+    let router = Arc::new(Router::new(&config)?);
+    let acceptor = Router::acceptor(&router)?;
 
     // 리스너 시작
-    let listener = TcpListener::bind(PROXY_ADDR)?;
-    println!("TLS reverse proxy listening on {}", PROXY_ADDR);
+    let listener = TcpListener::bind(&config.listen)?;
+    println!(
+        "TLS reverse proxy listening on {} ({} routes)",
+        config.listen,
+        config.routes.len()
+    );
 
+    // 연결은 정해진 수의 워커 스레드가 처리, 넘치면 거부
+    let limiter = Limiter::new(cli.limits);
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
                 let acceptor = acceptor.clone();
-                thread::spawn(move || match Ssl::new(acceptor.context()) {
-                    Ok(ssl) => match ssl.accept(stream) {
-                        Ok(tls_stream) => {
-                            if let Err(e) = handle_client(tls_stream) {
-                                eprintln!("Error handling client: {}", e);
-                            }
-                        }
-                        Err(e) => eprintln!("TLS handshake failed: {}", e),
-                    },
-                    Err(e) => eprintln!("Failed to create SSL context: {}", e),
+                let router = Arc::clone(&router);
+                limiter.serve(stream, move |stream, client| {
+                    serve(&acceptor, &router, stream, client)
                 });
             }
             Err(e) => eprintln!("Failed to accept connection: {}", e),
@@ -81,3 +140,120 @@ fn main() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::tests::{free_addr, plain_svr_bin, wait_for};
+    use cert_store::test_pki::TestCa;
+    use conn_limit::Limits;
+    use openssl::pkey::PKey;
+    use openssl::ssl::{SslConnector, SslMethod};
+    use openssl::x509::X509;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    #[test]
+    fn client_cn_reaches_the_backend() {
+        let dir = std::env::temp_dir().join(format!("reverse_proxy-mtls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca = TestCa::new();
+        let server = ca.server("localhost", 2);
+        let client = ca.client("alice", 3);
+        std::fs::write(dir.join("server.pem"), &server.cert_pem).unwrap();
+        std::fs::write(dir.join("server-key.pem"), &server.key_pem).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.cert_pem()).unwrap();
+
+        let backend_addr = free_addr();
+        let mut backend = Command::new(plain_svr_bin())
+            .arg(&backend_addr)
+            .arg("--proxy-protocol")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        assert!(wait_for(|| TcpStream::connect(&backend_addr).is_ok()));
+        let mut backend_log = BufReader::new(backend.stdout.take().unwrap());
+
+        std::fs::write(
+            dir.join("reverse_proxy.toml"),
+            format!(
+                r#"
+                [[route]]
+                default = true
+                cert = "server.pem"
+                key = "server-key.pem"
+                backends = ["{}"]
+                proxy_protocol = "v2"
+                client_ca = "ca.pem"
+                require_client_cert = true
+                "#,
+                backend_addr
+            ),
+        )
+        .unwrap();
+        let config = Config::load(&dir.join("reverse_proxy.toml")).unwrap();
+        let router = Arc::new(Router::new(&config).unwrap());
+        let acceptor = Router::acceptor(&router).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let limiter = Limiter::new(Limits::default());
+            for stream in listener.incoming() {
+                let (acceptor, router) = (acceptor.clone(), Arc::clone(&router));
+                limiter.serve(stream.unwrap(), move |stream, client| {
+                    serve(&acceptor, &router, stream, client)
+                });
+            }
+        });
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector
+            .cert_store_mut()
+            .add_cert(X509::from_der(&ca.cert_der()).unwrap())
+            .unwrap();
+        connector
+            .set_certificate(&X509::from_der(&client.cert_der).unwrap())
+            .unwrap();
+        connector
+            .set_private_key(&PKey::private_key_from_der(&client.key_der).unwrap())
+            .unwrap();
+        let connector = connector.build();
+        let mut tls = connector
+            .connect("localhost", TcpStream::connect(proxy_addr).unwrap())
+            .unwrap();
+        tls.write_all(b"ping").unwrap();
+        let mut reply = [0; 4];
+        tls.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"ping");
+
+        // plain_svr logs the header it parsed before echoing
+        let mut line = String::new();
+        loop {
+            line.clear();
+            assert!(backend_log.read_line(&mut line).unwrap() > 0);
+            if line.starts_with("client ") {
+                break;
+            }
+        }
+        assert!(line.contains(" sni=localhost "), "{}", line);
+        assert!(line.trim_end().ends_with(" cn=alice"), "{}", line);
+
+        // without a certificate the proxy ends the handshake; with TLS 1.3
+        // the client only sees it on its first read
+        let mut anonymous = SslConnector::builder(SslMethod::tls()).unwrap();
+        anonymous
+            .cert_store_mut()
+            .add_cert(X509::from_der(&ca.cert_der()).unwrap())
+            .unwrap();
+        let stream = TcpStream::connect(proxy_addr).unwrap();
+        if let Ok(mut tls) = anonymous.build().connect("localhost", stream) {
+            let _ = tls.write_all(b"ping");
+            assert!(tls.read(&mut reply).is_err());
+        }
+
+        let _ = backend.kill();
+        let _ = backend.wait();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,207 @@
+//! SNI routing: each route has its own certificate and backends.
+//!
+//! Route certificates are reloaded when their files change or on SIGHUP;
+//! the new one is used from the next handshake.
+
+use anyhow::{Context, Result, anyhow};
+use cert_store::{CertStore, WATCH_INTERVAL};
+use openssl::sha::sha256;
+use openssl::ssl::{
+    NameType, SniError, SslAcceptor, SslAlert, SslContext, SslFiletype, SslMethod, SslRef,
+    SslVerifyMode,
+};
+use openssl::x509::X509Name;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::balance::{Connection, Pool};
+use crate::config::{Config, RouteConfig};
+
+pub struct Route {
+    pub label: String,
+    server_names: Vec<String>,
+    pub pool: Arc<Pool>,
+    pub proxy_protocol: Option<proxy_protocol::Version>,
+    context: Arc<CertStore<SslContext>>,
+    /// Client certificate check, set per connection in the SNI callback:
+    /// switching the context does not change it.
+    verify: SslVerifyMode,
+}
+
+impl Route {
+    fn new(config: &RouteConfig) -> Result<Self> {
+        let label = config.label();
+        let verify = match (&config.client_ca, config.require_client_cert) {
+            (None, _) => SslVerifyMode::NONE,
+            (Some(_), false) => SslVerifyMode::PEER,
+            (Some(_), true) => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        };
+        let client_ca = config.client_ca.clone();
+        let session_id_context = sha256(label.as_bytes());
+        let context = CertStore::new(&config.cert, &config.key, move |cert, key| {
+            load_context(cert, key, client_ca.as_deref(), verify, &session_id_context)
+        })
+        .map_err(|e| anyhow!(e))
+        .with_context(|| {
+            format!(
+                "route {}: failed to load {} / {}",
+                label,
+                config.cert.display(),
+                config.key.display()
+            )
+        })?;
+        let context = match &config.client_ca {
+            Some(ca) => context.watch_also(ca),
+            None => context,
+        };
+        let context = Arc::new(context);
+        CertStore::watch(&context, WATCH_INTERVAL)?;
+        let pool = Arc::new(Pool::new(&config.backends, config.balance));
+        if let Some(health_check) = &config.health_check {
+            Pool::spawn_health_check(&pool, health_check.clone(), config.proxy_protocol);
+        }
+        Ok(Self {
+            label,
+            server_names: config
+                .server_names
+                .iter()
+                .map(|name| name.to_ascii_lowercase())
+                .collect(),
+            pool,
+            proxy_protocol: config.proxy_protocol,
+            context,
+            verify,
+        })
+    }
+
+    /// Connect to a backend picked by the route's balancing strategy,
+    /// trying the others if it fails.
+    pub fn connect(&self) -> Result<Connection> {
+        self.pool
+            .connect()
+            .with_context(|| format!("route {}: no backend available", self.label))
+    }
+
+    /// `addr up 1/20, ...`: health, open and total connections per backend.
+    pub fn backend_summary(&self) -> String {
+        self.pool
+            .stats()
+            .iter()
+            .map(|s| {
+                format!(
+                    "{} {} {}/{} ({} errors)",
+                    s.addr,
+                    if s.healthy { "up" } else { "down" },
+                    s.active,
+                    s.total,
+                    s.errors
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Route context; with `client_ca` it also verifies client certificates.
+/// Sessions only resume on the route that made them.
+fn load_context(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+    verify: SslVerifyMode,
+    session_id_context: &[u8],
+) -> Result<SslContext, cert_store::Error> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    acceptor.set_private_key_file(key, SslFiletype::PEM)?;
+    acceptor.set_certificate_chain_file(cert)?;
+    acceptor.check_private_key()?;
+    if let Some(ca) = client_ca {
+        acceptor.set_ca_file(ca)?;
+        acceptor.set_client_ca_list(X509Name::load_client_ca_file(ca)?);
+    }
+    acceptor.set_verify(verify);
+    acceptor.set_session_id_context(session_id_context)?;
+    Ok(acceptor.build().into_context())
+}
+
+/// `*.edger.dev` matches `echo.edger.dev` but not `edger.dev` or `a.echo.edger.dev`.
+fn name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => name
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => pattern == name,
+    }
+}
+
+pub struct Router {
+    routes: Vec<Route>,
+    default: Option<usize>,
+}
+
+impl Router {
+    pub fn new(config: &Config) -> Result<Self> {
+        let routes = config
+            .routes
+            .iter()
+            .map(Route::new)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            routes,
+            default: config.routes.iter().position(|route| route.default),
+        })
+    }
+
+    /// Route for the SNI `server_name`: an exact name first, then a
+    /// wildcard, then the default route.
+    pub fn find(&self, server_name: Option<&str>) -> Option<&Route> {
+        let by_name = server_name.map(str::to_ascii_lowercase).and_then(|name| {
+            let exact = self
+                .routes
+                .iter()
+                .find(|route| route.server_names.contains(&name));
+            exact.or_else(|| {
+                self.routes.iter().find(|route| {
+                    route
+                        .server_names
+                        .iter()
+                        .any(|pattern| pattern.starts_with("*.") && name_matches(pattern, &name))
+                })
+            })
+        });
+        by_name.or_else(|| self.default.map(|idx| &self.routes[idx]))
+    }
+
+    /// Route of an established connection.
+    pub fn route_of(&self, ssl: &SslRef) -> Option<&Route> {
+        self.find(ssl.servername(NameType::HOST_NAME))
+    }
+
+    /// TLS acceptor that switches to the route's certificate and client
+    /// certificate check in the SNI callback. Unknown names without a
+    /// default route get an `unrecognized_name` alert.
+    pub fn acceptor(router: &Arc<Router>) -> Result<SslAcceptor> {
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+        let router = Arc::clone(router);
+        acceptor.set_servername_callback(move |ssl, alert| {
+            let server_name = ssl.servername(NameType::HOST_NAME).map(str::to_string);
+            match router.find(server_name.as_deref()) {
+                Some(route) => {
+                    let context = route.context.current();
+                    if let Err(e) = ssl.set_ssl_context(&context) {
+                        eprintln!("route {}: failed to set context: {}", route.label, e);
+                        return Err(SniError::ALERT_FATAL);
+                    }
+                    ssl.set_verify(route.verify);
+                    Ok(())
+                }
+                None => {
+                    eprintln!("no route for server name {:?}", server_name);
+                    *alert = SslAlert::UNRECOGNIZED_NAME;
+                    Err(SniError::ALERT_FATAL)
+                }
+            }
+        });
+        Ok(acceptor.build())
+    }
+}
@@ -1,6 +1,11 @@
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use cert_store::{CertStore, WATCH_INTERVAL};
+use clap::Parser;
+use conn_limit::{Client, Limiter, Limits};
+use openssl::ssl::{HandshakeError, SslAcceptor, SslFiletype, SslMethod};
 use std::io::{Read, Write};
-use std::net::TcpListener;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
 
 // const ROOT_CA_PATH: &str = "../certs/rootCA.pem";
 const SERVER_CERT_PATH: &str = "../certs/server.pem";
@@ -8,18 +13,26 @@ const SERVER_KEY_PATH: &str = "../certs/server-key.pem";
 
 const BIND_ADDRESS: &str = "0.0.0.0:8443";
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+#[derive(Parser)]
+#[command(about = "TLS echo server")]
+struct Cli {
+    #[command(flatten)]
+    limits: Limits,
+}
+
+/// Acceptor for `cert`/`key`; also called on every certificate reload.
+fn load_acceptor(cert: &Path, key: &Path) -> Result<SslAcceptor, cert_store::Error> {
     // TLS acceptor 설정
     let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
 
-    acceptor.set_private_key_file(SERVER_KEY_PATH, SslFiletype::PEM)?;
+    acceptor.set_private_key_file(key, SslFiletype::PEM)?;
 
     // load private key from file
     // let key_data = std::fs::read("../localhost-key.pem")?;
     // let key = openssl::pkey::PKey::private_key_from_pem(&key_data)?;
     // acceptor.set_private_key(&key)?;
 
-    acceptor.set_certificate_chain_file(SERVER_CERT_PATH)?;
+    acceptor.set_certificate_chain_file(cert)?;
     // load certificate from file
     // let cert_data = std::fs::read("../localhost.pem")?;
     // let cert = openssl::x509::X509::from_pem(&cert_data)?;
@@ -30,54 +43,95 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // acceptor.set_ca_file(ROOT_CA_PATH)?;
 
     acceptor.check_private_key()?;
-    let acceptor = acceptor.build();
+    Ok(acceptor.build())
+}
+
+/// 핸드셰이크 후 에코, 워커 스레드에서 실행
+fn handle_client(acceptor: Arc<SslAcceptor>, stream: TcpStream, client: Client) {
+    // TLS 스트림 생성
+    let ssl_stream = match acceptor.accept(stream) {
+        Ok(stream) => stream,
+        Err(HandshakeError::WouldBlock(_)) => {
+            println!("TLS handshake timed out: {}", client.peer);
+            return;
+        }
+        Err(e) => {
+            println!("TLS handshake failed: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = client.set_idle_timeout(ssl_stream.get_ref()) {
+        println!("Socket error: {}", e);
+        return;
+    }
+
+    // 클라이언트 인증서 검증
+    if let Some(cert) = ssl_stream.ssl().peer_certificate() {
+        println!("Client certificate verification successful");
+        if let Some(subject) = cert.subject_name().entries().next() {
+            match std::str::from_utf8(subject.data().as_slice()) {
+                Ok(subject) => println!("Client subject: {}", subject),
+                Err(e) => println!("Client subject: {}", e),
+            }
+        }
+    }
+
+    // 클라이언트로부터 데이터 수신
+    let mut ssl_stream = client.rate_limit(ssl_stream);
+    let mut buf = [0; 1024];
+    loop {
+        match ssl_stream.read(&mut buf) {
+            Ok(0) => {
+                println!("Client connection closed");
+                break;
+            }
+            Ok(size) => {
+                if let Err(e) = ssl_stream.write_all(&buf[..size]) {
+                    println!("Write error: {}", e);
+                    break;
+                }
+                println!(
+                    "recv&echo {} bytes : {}",
+                    size,
+                    String::from_utf8_lossy(&buf[..size])
+                );
+            }
+            Err(e) if conn_limit::is_timeout(&e) => {
+                println!("Idle timeout, closing {}", client.peer);
+                break;
+            }
+            Err(e) => {
+                println!("Read error: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cli = Cli::parse();
+
+    // 인증서가 바뀌면 (또는 SIGHUP) 새 핸드셰이크부터 새 인증서 사용
+    let store = Arc::new(CertStore::new(
+        SERVER_CERT_PATH,
+        SERVER_KEY_PATH,
+        load_acceptor,
+    )?);
+    CertStore::watch(&store, WATCH_INTERVAL)?;
 
     // TCP 리스너 생성
     let listener = TcpListener::bind(BIND_ADDRESS)?;
     println!("Server is running on {}...", BIND_ADDRESS);
 
+    // 연결은 정해진 수의 워커 스레드가 처리, 넘치면 거부
+    let limiter = Limiter::new(cli.limits);
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                // TLS 스트림 생성
-                let mut ssl_stream = match acceptor.accept(stream) {
-                    Ok(stream) => stream,
-                    Err(e) => {
-                        println!("TLS handshake failed: {}", e);
-                        continue;
-                    }
-                };
-
-                // 클라이언트 인증서 검증
-                if let Some(cert) = ssl_stream.ssl().peer_certificate() {
-                    println!("Client certificate verification successful");
-                    if let Some(subject) = cert.subject_name().entries().next() {
-                        println!("Client subject: {}", subject.data().as_utf8()?);
-                    }
-                }
-
-                // 클라이언트로부터 데이터 수신
-                let mut buf = [0; 1024];
-                loop {
-                    match ssl_stream.read(&mut buf) {
-                        Ok(0) => {
-                            println!("Client connection closed");
-                            break;
-                        }
-                        Ok(size) => {
-                            ssl_stream.write_all(&buf[..size])?;
-                            println!(
-                                "recv&echo {} bytes : {}",
-                                size,
-                                String::from_utf8_lossy(&buf[..size])
-                            );
-                        }
-                        Err(e) => {
-                            println!("Read error: {}", e);
-                            break;
-                        }
-                    }
-                }
+                let store = Arc::clone(&store);
+                limiter.serve(stream, move |stream, client| {
+                    handle_client(store.current(), stream, client)
+                });
             }
             Err(e) => println!("Connection error: {}", e),
         }
@@ -1,25 +1,69 @@
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
-use std::io::{Read, Write};
-use std::net::TcpListener;
+mod policy;
+
+use cert_store::{CertStore, WATCH_INTERVAL};
+use clap::Parser;
+use conn_limit::{Limiter, Limits};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::ssl::{HandshakeError, SslAcceptor, SslFiletype, SslMethod, SslRef, SslVerifyMode};
+use openssl::x509::store::X509Lookup;
+use openssl::x509::verify::X509VerifyFlags;
+use openssl::x509::{GeneralNameRef, X509Ref};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use policy::{Authorized, Identity, Policy};
 
 const ROOT_CA_PATH: &str = "../certs/rootCA.pem";
+/// 없으면 폐기 확인 없이 동작
+const CRL_PATH: &str = "../certs/rootCA.crl";
 const SERVER_CERT_PATH: &str = "../certs/server.pem";
 const SERVER_KEY_PATH: &str = "../certs/server-key.pem";
 
 const BIND_ADDRESS: &str = "0.0.0.0:8443";
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// `X509_V_ERR_CERT_REVOKED` in openssl/x509_vfy.h
+const X509_V_ERR_CERT_REVOKED: i32 = 23;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// 클라이언트 인증서 권한 정책 파일 (TOML)
+    #[arg(short, long, default_value = "policy.toml")]
+    policy: PathBuf,
+
+    #[command(flatten)]
+    limits: Limits,
+}
+
+/// Acceptor for `cert`/`key` with the root CA and CRL; also called on every
+/// reload of any of them.
+fn load_acceptor(cert: &Path, key: &Path) -> Result<SslAcceptor, cert_store::Error> {
+    build_acceptor(cert, key, Path::new(ROOT_CA_PATH), Path::new(CRL_PATH))
+}
+
+/// Acceptor that asks for a client certificate issued by `ca`, checked
+/// against `crl` if that file exists.
+fn build_acceptor(
+    cert: &Path,
+    key: &Path,
+    ca: &Path,
+    crl: &Path,
+) -> Result<SslAcceptor, cert_store::Error> {
     // TLS acceptor 설정
     let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
 
-    acceptor.set_private_key_file(SERVER_KEY_PATH, SslFiletype::PEM)?;
+    acceptor.set_private_key_file(key, SslFiletype::PEM)?;
 
     // load private key from file
     // let key_data = std::fs::read("../localhost-key.pem")?;
     // let key = openssl::pkey::PKey::private_key_from_pem(&key_data)?;
     // acceptor.set_private_key(&key)?;
 
-    acceptor.set_certificate_chain_file(SERVER_CERT_PATH)?;
+    acceptor.set_certificate_chain_file(cert)?;
     // load certificate from file
     // let cert_data = std::fs::read("../localhost.pem")?;
     // let cert = openssl::x509::X509::from_pem(&cert_data)?;
@@ -27,53 +71,187 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // 클라이언트 인증서 검증 설정
     acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
-    acceptor.set_ca_file(ROOT_CA_PATH)?;
+    acceptor.set_ca_file(ca)?;
+
+    // 폐기된 클라이언트 인증서 목록 (PEM/DER)
+    if crl.exists() {
+        let filetype = if cert_store::crl::is_der(&std::fs::read(crl)?) {
+            SslFiletype::ASN1
+        } else {
+            SslFiletype::PEM
+        };
+        let store = acceptor.cert_store_mut();
+        let crls = store
+            .add_lookup(X509Lookup::file())?
+            .load_crl_file(crl, filetype)?;
+        store.set_flags(X509VerifyFlags::CRL_CHECK)?;
+        println!("CRL {}: {}개 로드", crl.display(), crls);
+    } else {
+        println!("CRL {} 없음, 폐기 확인 안 함", crl.display());
+    }
 
     acceptor.check_private_key()?;
-    let acceptor = acceptor.build();
+    Ok(acceptor.build())
+}
+
+/// 클라이언트 인증서에서 정책 검사에 쓰는 항목 추출
+fn peer_identity(cert: &X509Ref) -> Result<Identity, ErrorStack> {
+    let subject = |nid: Nid| {
+        cert.subject_name()
+            .entries_by_nid(nid)
+            .filter_map(|entry| std::str::from_utf8(entry.data().as_slice()).ok())
+            .map(str::to_string)
+    };
+    let alt_names = cert.subject_alt_names();
+    let san = |field: fn(&GeneralNameRef) -> Option<&str>| -> Vec<String> {
+        alt_names
+            .iter()
+            .flatten()
+            .filter_map(field)
+            .map(str::to_string)
+            .collect()
+    };
+    let sha256 = cert
+        .digest(MessageDigest::sha256())?
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":");
+    Ok(Identity {
+        cn: subject(Nid::COMMONNAME).next(),
+        ou: subject(Nid::ORGANIZATIONALUNITNAME).collect(),
+        dns: san(GeneralNameRef::dnsname),
+        uri: san(GeneralNameRef::uri),
+        sha256,
+    })
+}
+
+/// 클라이언트 인증서가 CRL 에 있어서 핸드셰이크가 실패했는지
+fn is_revoked(ssl: &SslRef) -> bool {
+    ssl.verify_result().as_raw() == X509_V_ERR_CERT_REVOKED
+}
+
+/// 정책을 통과한 클라이언트와 에코
+fn handle_client(ssl_stream: &mut (impl Read + Write), client: &Authorized) -> io::Result<()> {
+    println!(
+        "클라이언트 {} 역할 {}",
+        client.identity.cn.as_deref().unwrap_or("-"),
+        client.role
+    );
+
+    // 클라이언트로부터 데이터 수신
+    let mut buf = [0; 1024];
+    loop {
+        match ssl_stream.read(&mut buf) {
+            Ok(0) => {
+                println!("클라이언트 연결 종료");
+                return Ok(());
+            }
+            Ok(size) => {
+                ssl_stream.write_all(&buf[..size])?;
+                println!("에코 완료: {} 바이트", size);
+            }
+            Err(e) if conn_limit::is_timeout(&e) => {
+                println!("유휴 시간 초과, 연결 종료");
+                return Ok(());
+            }
+            Err(e) => {
+                println!("읽기 오류: {}", e);
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// 핸드셰이크, 정책 확인, 에코; 워커 스레드에서 실행
+fn serve(acceptor: Arc<SslAcceptor>, policy: &Policy, stream: TcpStream, conn: conn_limit::Client) {
+    let peer = conn.peer;
+
+    // TLS 스트림 생성
+    let mut ssl_stream = match acceptor.accept(stream) {
+        Ok(stream) => stream,
+        Err(HandshakeError::Failure(mid)) if is_revoked(mid.ssl()) => {
+            println!("audit deny peer={} reason=certificate revoked", peer);
+            return;
+        }
+        Err(HandshakeError::WouldBlock(_)) => {
+            println!("audit deny peer={} reason=handshake timeout", peer);
+            return;
+        }
+        Err(e) => {
+            println!("TLS 핸드셰이크 실패: {}", e);
+            return;
+        }
+    };
+
+    // 체인과 폐기 검증은 핸드셰이크에서 끝났고, 여기서는 정책으로 권한 확인
+    let Some(cert) = ssl_stream.ssl().peer_certificate() else {
+        println!("audit deny peer={} reason=no client certificate", peer);
+        let _ = ssl_stream.shutdown();
+        return;
+    };
+    let identity = match peer_identity(&cert) {
+        Ok(identity) => identity,
+        Err(e) => {
+            println!(
+                "audit deny peer={} reason=unreadable certificate: {}",
+                peer, e
+            );
+            let _ = ssl_stream.shutdown();
+            return;
+        }
+    };
+    let client = match policy.authorize(identity) {
+        Ok(client) => client,
+        Err(identity) => {
+            println!(
+                "audit deny peer={} {} reason=no matching rule",
+                peer, identity
+            );
+            let _ = ssl_stream.shutdown();
+            return;
+        }
+    };
+    println!(
+        "audit allow peer={} {} role={}",
+        peer, client.identity, client.role
+    );
+
+    if let Err(e) = conn.set_idle_timeout(ssl_stream.get_ref()) {
+        println!("소켓 오류: {}", e);
+        return;
+    }
+    if let Err(e) = handle_client(&mut conn.rate_limit(ssl_stream), &client) {
+        println!("쓰기 오류: {}", e);
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cli = Cli::parse();
+    let policy = Policy::load(&cli.policy)?;
+    println!("{}: {} rule(s)", cli.policy.display(), policy.rules.len());
+
+    // 인증서, 루트 CA, CRL 이 바뀌면 (또는 SIGHUP) 새 핸드셰이크부터 새 설정 사용
+    let store = CertStore::new(SERVER_CERT_PATH, SERVER_KEY_PATH, load_acceptor)?
+        .watch_also(ROOT_CA_PATH)
+        .watch_also(CRL_PATH);
+    let store = Arc::new(store);
+    CertStore::watch(&store, WATCH_INTERVAL)?;
 
     // TCP 리스너 생성
     let listener = TcpListener::bind(BIND_ADDRESS)?;
     println!("서버가 {}에서 실행 중입니다...", BIND_ADDRESS);
 
+    // 연결은 정해진 수의 워커 스레드가 처리, 넘치면 거부
+    let policy = Arc::new(policy);
+    let limiter = Limiter::new(cli.limits);
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                // TLS 스트림 생성
-                let mut ssl_stream = match acceptor.accept(stream) {
-                    Ok(stream) => stream,
-                    Err(e) => {
-                        println!("TLS 핸드셰이크 실패: {}", e);
-                        continue;
-                    }
-                };
-
-                // 클라이언트 인증서 검증
-                if let Some(cert) = ssl_stream.ssl().peer_certificate() {
-                    println!("클라이언트 인증서 검증 성공");
-                    if let Some(subject) = cert.subject_name().entries().next() {
-                        println!("클라이언트 주체: {}", subject.data().as_utf8()?);
-                    }
-                }
-
-                // 클라이언트로부터 데이터 수신
-                let mut buf = [0; 1024];
-                loop {
-                    match ssl_stream.read(&mut buf) {
-                        Ok(0) => {
-                            println!("클라이언트 연결 종료");
-                            break;
-                        }
-                        Ok(size) => {
-                            ssl_stream.write_all(&buf[..size])?;
-                            println!("에코 완료: {} 바이트", size);
-                        }
-                        Err(e) => {
-                            println!("읽기 오류: {}", e);
-                            break;
-                        }
-                    }
-                }
+                let (store, policy) = (Arc::clone(&store), Arc::clone(&policy));
+                limiter.serve(stream, move |stream, conn| {
+                    serve(store.current(), &policy, stream, conn)
+                });
             }
             Err(e) => println!("연결 오류: {}", e),
         }
@@ -81,3 +259,86 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cert_store::test_pki::{Issued, TestCa};
+    use openssl::pkey::PKey;
+    use openssl::ssl::SslConnector;
+    use openssl::x509::X509;
+    use std::fs;
+    use std::thread;
+
+    const REVOKED: u64 = 2;
+
+    #[derive(Debug, PartialEq)]
+    enum Outcome {
+        Accepted,
+        Revoked,
+        Failed,
+    }
+
+    /// Handshake over loopback with `client_cert`; what the server saw.
+    fn connect(ca: &TestCa, client_cert: &Issued, crl_der: Option<Vec<u8>>) -> Outcome {
+        let dir = std::env::temp_dir().join(format!("svr_cli_auth_crl_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let server_cert = ca.server("localhost", 1);
+        let [cert, key, ca_file, crl] =
+            ["server.pem", "server-key.pem", "ca.pem", "ca.crl"].map(|name| dir.join(name));
+        fs::write(&cert, &server_cert.cert_pem).unwrap();
+        fs::write(&key, &server_cert.key_pem).unwrap();
+        fs::write(&ca_file, ca.cert_pem()).unwrap();
+        if let Some(der) = &crl_der {
+            fs::write(&crl, der).unwrap();
+        }
+        let acceptor = build_acceptor(&cert, &key, &ca_file, &crl).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            match acceptor.accept(stream) {
+                Ok(_) => Outcome::Accepted,
+                Err(HandshakeError::Failure(mid)) if is_revoked(mid.ssl()) => Outcome::Revoked,
+                Err(_) => Outcome::Failed,
+            }
+        });
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector
+            .cert_store_mut()
+            .add_cert(X509::from_der(&ca.cert_der()).unwrap())
+            .unwrap();
+        connector
+            .set_certificate(&X509::from_der(&client_cert.cert_der).unwrap())
+            .unwrap();
+        connector
+            .set_private_key(&PKey::private_key_from_der(&client_cert.key_der).unwrap())
+            .unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        // with TLS 1.3 the client is done before the server checks its certificate
+        let _ = connector.build().connect("localhost", stream);
+        server.join().unwrap()
+    }
+
+    #[test]
+    fn refuses_revoked_client_certificate() {
+        let ca = TestCa::new();
+        let crl = Some(ca.crl_der(&[REVOKED]));
+
+        assert_eq!(
+            connect(&ca, &ca.client("good", 3), crl.clone()),
+            Outcome::Accepted
+        );
+        assert_eq!(
+            connect(&ca, &ca.client("revoked", REVOKED), crl),
+            Outcome::Revoked
+        );
+        assert_eq!(
+            connect(&ca, &ca.client("revoked", REVOKED), None),
+            Outcome::Accepted
+        );
+    }
+}
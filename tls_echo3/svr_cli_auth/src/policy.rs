@@ -0,0 +1,226 @@
+//! Client certificate authorization policy.
+//!
+//! ```toml
+//! [[rule]]
+//! role = "admin"
+//! cn = ["echo-client"]
+//!
+//! [[rule]]
+//! role = "service"
+//! ou = ["backend"]                  # every listed field must match,
+//! dns = ["*.svc.edger.dev"]         # any one value of each
+//!
+//! [[rule]]
+//! role = "ops"
+//! uri = ["spiffe://edger.dev/ops"]
+//!
+//! [[rule]]
+//! role = "legacy"
+//! sha256 = ["3F:2A:...:9C"]         # certificate fingerprint, colons and case optional
+//! ```
+//!
+//! Rules are tried in order and the first match gives the role. Clients no
+//! rule matches are rejected.
+
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(rename = "rule", default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub role: String,
+    /// Subject common names, exact.
+    #[serde(default)]
+    pub cn: Vec<String>,
+    /// Subject organizational units, exact.
+    #[serde(default)]
+    pub ou: Vec<String>,
+    /// SAN DNS names, exact or one wildcard label (`*.edger.dev`).
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// SAN URIs, exact.
+    #[serde(default)]
+    pub uri: Vec<String>,
+    /// SHA-256 certificate fingerprints.
+    #[serde(default)]
+    pub sha256: Vec<String>,
+}
+
+/// What the client certificate says about the client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub cn: Option<String>,
+    pub ou: Vec<String>,
+    pub dns: Vec<String>,
+    pub uri: Vec<String>,
+    /// `AB:CD:...`, upper case.
+    pub sha256: String,
+}
+
+/// A client that passed the policy.
+#[derive(Debug, Clone)]
+pub struct Authorized {
+    pub identity: Identity,
+    pub role: String,
+}
+
+/// `cn=echo-client ou=backend dns=a.edger.dev,b.edger.dev sha256=AB:...`
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(cn) = &self.cn {
+            write!(f, "cn={} ", cn)?;
+        }
+        for (name, values) in [("ou", &self.ou), ("dns", &self.dns), ("uri", &self.uri)] {
+            if !values.is_empty() {
+                write!(f, "{}={} ", name, values.join(","))?;
+            }
+        }
+        write!(f, "sha256={}", self.sha256)
+    }
+}
+
+/// Hex digits only, upper case, so `ab:cd` and `ABCD` compare equal.
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| *c != ':')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// `*.edger.dev` matches `echo.edger.dev` but not `edger.dev` or `a.echo.edger.dev`.
+fn dns_matches(pattern: &str, name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => name
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => pattern == name,
+    }
+}
+
+impl Rule {
+    fn is_empty(&self) -> bool {
+        self.cn.is_empty()
+            && self.ou.is_empty()
+            && self.dns.is_empty()
+            && self.uri.is_empty()
+            && self.sha256.is_empty()
+    }
+
+    fn matches(&self, identity: &Identity) -> bool {
+        let cn = self.cn.is_empty() || identity.cn.as_ref().is_some_and(|cn| self.cn.contains(cn));
+        let ou = self.ou.is_empty() || identity.ou.iter().any(|ou| self.ou.contains(ou));
+        let dns = self.dns.is_empty()
+            || identity
+                .dns
+                .iter()
+                .any(|name| self.dns.iter().any(|pattern| dns_matches(pattern, name)));
+        let uri = self.uri.is_empty() || identity.uri.iter().any(|uri| self.uri.contains(uri));
+        let sha256 = self.sha256.is_empty()
+            || self
+                .sha256
+                .contains(&normalize_fingerprint(&identity.sha256));
+        cn && ou && dns && uri && sha256
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let mut policy: Policy =
+            toml::from_str(&s).map_err(|e| format!("invalid policy {}: {}", path.display(), e))?;
+        for (idx, rule) in policy.rules.iter_mut().enumerate() {
+            if rule.is_empty() {
+                return Err(format!("rule {} ({}) matches nothing", idx + 1, rule.role).into());
+            }
+            for pattern in &mut rule.dns {
+                *pattern = pattern.to_ascii_lowercase();
+            }
+            for fingerprint in &mut rule.sha256 {
+                *fingerprint = normalize_fingerprint(fingerprint);
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Role for `identity`; the identity is handed back if no rule allows it.
+    pub fn authorize(&self, identity: Identity) -> Result<Authorized, Identity> {
+        match self.rules.iter().find(|rule| rule.matches(&identity)) {
+            Some(rule) => Ok(Authorized {
+                identity,
+                role: rule.role.clone(),
+            }),
+            None => Err(identity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_gives_the_role() {
+        let dir = std::env::temp_dir().join(format!("policy_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("policy.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [[rule]]
+            role = "service"
+            ou = ["backend"]
+            dns = ["*.svc.edger.dev"]
+
+            [[rule]]
+            role = "admin"
+            cn = ["echo-client"]
+
+            [[rule]]
+            role = "legacy"
+            sha256 = ["ab:cd:ef"]
+            "#,
+        )
+        .unwrap();
+        let policy = Policy::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let client = Identity {
+            cn: Some("echo-client".to_string()),
+            sha256: "01:02".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(policy.authorize(client).unwrap().role, "admin");
+
+        // both ou and dns have to match
+        let service = Identity {
+            ou: vec!["backend".to_string()],
+            dns: vec!["API.svc.edger.dev".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(policy.authorize(service.clone()).unwrap().role, "service");
+        let other = Identity {
+            dns: vec!["a.b.svc.edger.dev".to_string()],
+            ..service
+        };
+        assert!(policy.authorize(other).is_err());
+
+        let legacy = Identity {
+            sha256: "AB:CD:EF".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(policy.authorize(legacy).unwrap().role, "legacy");
+    }
+}
@@ -1,80 +1,348 @@
 use std::fs;
-use std::io::{Read, Write};
-use std::net::TcpListener;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
 use std::sync::Arc;
 
+use cert_store::{CertStore, WATCH_INTERVAL};
+use clap::Parser;
+use conn_limit::{Client, Limiter, Limits};
 use rustls::{
-    Certificate, PrivateKey, RootCertStore, ServerConfig, ServerConnection, Stream,
-    server::AllowAnyAuthenticatedClient,
+    server::{
+        AllowAnyAuthenticatedClient, ProducesTickets, ServerSessionMemoryCache,
+        StoresServerSessions, UnparsedCertRevocationList,
+    },
+    sign::SigningKey,
+    Certificate, CertificateError, PrivateKey, RootCertStore, ServerConfig, ServerConnection,
+    SignatureScheme, Stream, Ticketer,
 };
 
 const ROOT_CA_PATH: &str = "../certs/rootCA.pem";
+/// 없으면 폐기 확인 없이 동작
+const CRL_PATH: &str = "../certs/rootCA.crl";
 const SERVER_CERT_PATH: &str = "../certs/server.pem";
 const SERVER_KEY_PATH: &str = "../certs/server-key.pem";
 const BIND_ADDRESS: &str = "0.0.0.0:8443";
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// 세션 캐시 크기 (서버에 저장하는 세션 재개), 0 이면 끔
+    #[arg(long, default_value_t = 256)]
+    session_cache: usize,
+
+    /// 세션 캐시 대신 서버에 상태가 없는 세션 티켓 사용
+    #[arg(long)]
+    tickets: bool,
+
+    /// TLS 1.3 0-RTT early data 최대 크기 (바이트), 0 이면 끔.
+    /// 재전송 공격에 주의, --tickets 와 같이 쓸 수 없음
+    #[arg(long, default_value_t = 0)]
+    early_data: u32,
+
+    #[command(flatten)]
+    limits: Limits,
+}
+
+/// How clients can resume sessions. Kept across certificate reloads, so a
+/// reload does not force every client through a full handshake.
+///
+/// 0-RTT early data is sent before the handshake authenticates anything
+/// and an attacker who recorded it can send it again. rustls only accepts
+/// it with the stateful cache, where each session can be resumed once, so
+/// a replay to this process is refused; a replay to another instance, or
+/// after a restart, is not. Only requests that are safe to repeat (like an
+/// echo) should ride in early data.
+struct Resumption {
+    session_storage: Arc<dyn StoresServerSessions + Send + Sync>,
+    /// Stateless tickets instead of the cache.
+    ticketer: Option<Arc<dyn ProducesTickets>>,
+    max_early_data_size: u32,
+}
+
+impl Resumption {
+    fn new(cli: &Cli) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if cli.tickets && cli.early_data > 0 {
+            return Err(
+                "--early-data needs the session cache, rustls refuses 0-RTT with stateless tickets"
+                    .into(),
+            );
+        }
+        let session_storage: Arc<dyn StoresServerSessions + Send + Sync> = if cli.session_cache > 0
+        {
+            ServerSessionMemoryCache::new(cli.session_cache)
+        } else {
+            Arc::new(rustls::server::NoServerSessionStorage {})
+        };
+        let ticketer = if cli.tickets {
+            Some(Ticketer::new()?)
+        } else {
+            None
+        };
+        Ok(Self {
+            session_storage,
+            ticketer,
+            max_early_data_size: cli.early_data,
+        })
+    }
+
+    fn apply(&self, config: &mut ServerConfig) {
+        config.session_storage = Arc::clone(&self.session_storage);
+        if let Some(ticketer) = &self.ticketer {
+            config.ticketer = Arc::clone(ticketer);
+        }
+        config.max_early_data_size = self.max_early_data_size;
+    }
+
+    fn describe(&self) -> String {
+        match (&self.ticketer, self.max_early_data_size) {
+            (Some(_), _) => "세션 재개: 티켓".to_string(),
+            (None, 0) => "세션 재개: 세션 캐시".to_string(),
+            (None, size) => format!("세션 재개: 세션 캐시, 0-RTT 최대 {} 바이트", size),
+        }
+    }
+}
+
+/// 키 검사에 쓰는 서명 방식과 대응하는 webpki 검증 알고리즘
+const KEY_CHECK_SCHEMES: [(SignatureScheme, &webpki::SignatureAlgorithm); 7] = [
+    (
+        SignatureScheme::RSA_PKCS1_SHA256,
+        &webpki::RSA_PKCS1_2048_8192_SHA256,
+    ),
+    (
+        SignatureScheme::RSA_PSS_SHA256,
+        &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    ),
+    (
+        SignatureScheme::RSA_PSS_SHA384,
+        &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    ),
+    (
+        SignatureScheme::RSA_PSS_SHA512,
+        &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    ),
+    (
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        &webpki::ECDSA_P256_SHA256,
+    ),
+    (
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        &webpki::ECDSA_P384_SHA384,
+    ),
+    (SignatureScheme::ED25519, &webpki::ED25519),
+];
+
+/// Certificate chain and key for `cert`/`key`, checked to belong together.
+fn load_cert_and_key(
+    cert: &Path,
+    key: &Path,
+) -> Result<(Vec<Certificate>, PrivateKey), cert_store::Error> {
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut fs::read(cert)?.as_slice())?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let leaf = certs.first().ok_or("no certificate found")?;
+    let key = rustls_pemfile::pkcs8_private_keys(&mut fs::read(key)?.as_slice())?
+        .into_iter()
+        .next()
+        .map(PrivateKey)
+        .ok_or("no PKCS#8 private key found")?;
+    let signing_key =
+        rustls::sign::any_supported_type(&key).map_err(|_| "unsupported private key type")?;
+    check_key_matches(leaf, signing_key.as_ref())?;
+    Ok((certs, key))
+}
+
+/// Server config for `cert`/`key` with the root CA and CRL; also called on
+/// every reload of any of them.
+fn load_server_config(
+    cert: &Path,
+    key: &Path,
+    resumption: &Resumption,
+) -> Result<ServerConfig, cert_store::Error> {
     // 루트 CA 인증서 로드
+    let root_ca = fs::read(ROOT_CA_PATH)?;
+
+    // 폐기된 클라이언트 인증서 목록 (PEM/DER)
+    let crls = if Path::new(CRL_PATH).exists() {
+        let crls = cert_store::crl::read_crls(Path::new(CRL_PATH))?;
+        println!("CRL {}: {}개 로드", CRL_PATH, crls.len());
+        crls
+    } else {
+        println!("CRL {} 없음, 폐기 확인 안 함", CRL_PATH);
+        Vec::new()
+    };
+
+    let (certs, key) = load_cert_and_key(cert, key)?;
+    let mut config = server_config(&root_ca, crls, certs, key)?;
+    resumption.apply(&mut config);
+    Ok(config)
+}
+
+/// Config that asks for a client certificate issued by a CA in `root_ca`
+/// (PEM) and not revoked by any of `crls` (DER).
+fn server_config(
+    root_ca: &[u8],
+    crls: Vec<Vec<u8>>,
+    certs: Vec<Certificate>,
+    key: PrivateKey,
+) -> Result<ServerConfig, cert_store::Error> {
     let mut root_store = RootCertStore::empty();
-    let root_ca_data = fs::read(ROOT_CA_PATH)?;
-    for cert in rustls_pemfile::certs(&mut root_ca_data.as_slice())? {
+    for cert in rustls_pemfile::certs(&mut &root_ca[..])? {
         root_store.add(&Certificate(cert))?;
     }
 
     // 클라이언트 인증 검증기 설정
-    let client_auth = AllowAnyAuthenticatedClient::new(root_store);
+    let client_auth = AllowAnyAuthenticatedClient::new(root_store)
+        .with_crls(crls.into_iter().map(UnparsedCertRevocationList))
+        .map_err(|e| format!("invalid CRL {}: {:?}", CRL_PATH, e))?;
 
-    // 서버 설정 구성
-    let config = ServerConfig::builder()
+    Ok(ServerConfig::builder()
         .with_safe_defaults()
-        .with_client_cert_verifier(Arc::new(client_auth))
-        .with_single_cert(
-            rustls_pemfile::certs(&mut fs::read(SERVER_CERT_PATH)?.as_slice())?
-                .into_iter()
-                .map(Certificate)
-                .collect(),
-            PrivateKey(
-                rustls_pemfile::pkcs8_private_keys(&mut fs::read(SERVER_KEY_PATH)?.as_slice())?
-                    .remove(0),
-            ),
-        )?;
-
-    let config = Arc::new(config);
+        .with_client_cert_verifier(client_auth.boxed())
+        .with_single_cert(certs, key)?)
+}
+
+/// 새 키로 서명하고 인증서의 공개키로 검증해서 짝이 맞는지 확인
+fn check_key_matches(cert: &Certificate, key: &dyn SigningKey) -> Result<(), cert_store::Error> {
+    let offered: Vec<SignatureScheme> = KEY_CHECK_SCHEMES.iter().map(|(s, _)| *s).collect();
+    let signer = key
+        .choose_scheme(&offered)
+        .ok_or("unsupported private key type")?;
+    let (_, alg) = KEY_CHECK_SCHEMES
+        .iter()
+        .find(|(scheme, _)| *scheme == signer.scheme())
+        .ok_or("unsupported signature scheme")?;
+
+    let message = b"svr_rustls key check";
+    let signature = signer.sign(message)?;
+    let end_entity = webpki::EndEntityCert::try_from(cert.0.as_slice())
+        .map_err(|e| format!("invalid certificate: {:?}", e))?;
+    end_entity
+        .verify_signature(alg, message, &signature)
+        .map_err(|_| "private key does not match the certificate")?;
+    Ok(())
+}
+
+/// 클라이언트 인증서가 CRL 에 있어서 핸드셰이크가 실패했는지
+fn is_revoked(e: &io::Error) -> bool {
+    matches!(
+        e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()),
+        Some(rustls::Error::InvalidCertificate(CertificateError::Revoked))
+    )
+}
+
+/// Stored in every session; getting it back means the client resumed.
+const RESUMPTION_TAG: &[u8] = b"svr_rustls";
+
+/// 핸드셰이크를 끝내고, 0-RTT 로 받은 데이터가 있으면 먼저 에코
+fn handshake(conn: &mut ServerConnection, stream: &mut TcpStream) -> io::Result<()> {
+    conn.set_resumption_data(RESUMPTION_TAG);
+    while conn.is_handshaking() {
+        conn.complete_io(stream)?;
+    }
+    let resumed = conn.received_resumption_data() == Some(RESUMPTION_TAG);
+    let mut early = Vec::new();
+    if let Some(mut data) = conn.early_data() {
+        data.read_to_end(&mut early)?;
+    }
+    if early.is_empty() {
+        println!("핸드셰이크 완료 (resumed: {})", resumed);
+    } else {
+        println!("핸드셰이크 완료, 0-RTT {} 바이트", early.len());
+        let mut tls = Stream::new(conn, stream);
+        tls.write_all(&early)?;
+    }
+    Ok(())
+}
+
+/// 핸드셰이크 후 에코, 워커 스레드에서 실행
+fn handle_client(config: Arc<ServerConfig>, mut stream: TcpStream, client: Client) {
+    // 핸드셰이크 후 보내는 세션 티켓의 ACK 를 기다리느라 에코가 늦어지지 않도록
+    let _ = stream.set_nodelay(true);
+    let mut conn = match ServerConnection::new(config) {
+        Ok(conn) => conn,
+        Err(e) => {
+            println!("TLS 연결 생성 실패: {}", e);
+            return;
+        }
+    };
+    match handshake(&mut conn, &mut stream) {
+        Ok(()) => {}
+        Err(e) if is_revoked(&e) => {
+            println!("폐기된 클라이언트 인증서, 연결 거부: {}", e);
+            return;
+        }
+        Err(e) if conn_limit::is_timeout(&e) => {
+            println!("TLS 핸드셰이크 시간 초과, 연결 거부: {}", client.peer);
+            return;
+        }
+        Err(e) => {
+            println!("TLS 핸드셰이크 실패: {}", e);
+            return;
+        }
+    }
+    if let Err(e) = client.set_idle_timeout(&stream) {
+        println!("소켓 오류: {}", e);
+        return;
+    }
+    let mut tls = client.rate_limit(Stream::new(&mut conn, &mut stream));
+
+    // 에코 서비스
+    let mut buf = [0; 1024];
+    loop {
+        match tls.read(&mut buf) {
+            Ok(0) => {
+                println!("클라이언트 연결 종료");
+                break;
+            }
+            Ok(n) => {
+                if let Err(e) = tls.write_all(&buf[..n]) {
+                    println!("쓰기 오류: {}", e);
+                    break;
+                }
+                println!("에코 완료: {} 바이트", n);
+            }
+            Err(e) if conn_limit::is_timeout(&e) => {
+                println!("유휴 시간 초과, 연결 종료: {}", client.peer);
+                break;
+            }
+            Err(e) => {
+                println!("읽기 오류: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cli = Cli::parse();
+    let resumption = Resumption::new(&cli)?;
+    println!("{}", resumption.describe());
+
+    // 인증서, 루트 CA, CRL 이 바뀌면 (또는 SIGHUP) 새 핸드셰이크부터 새 설정 사용
+    let store = CertStore::new(SERVER_CERT_PATH, SERVER_KEY_PATH, move |cert, key| {
+        load_server_config(cert, key, &resumption)
+    })?
+    .watch_also(ROOT_CA_PATH)
+    .watch_also(CRL_PATH);
+    let store = Arc::new(store);
+    CertStore::watch(&store, WATCH_INTERVAL)?;
 
     // TCP 리스너 생성
     let listener = TcpListener::bind(BIND_ADDRESS)?;
     println!("서버가 {}에서 실행 중입니다...", BIND_ADDRESS);
 
+    // 연결은 정해진 수의 워커 스레드가 처리, 넘치면 거부
+    let limiter = Limiter::new(cli.limits);
     for stream in listener.incoming() {
         match stream {
-            Ok(mut stream) => {
-                let server_config = Arc::clone(&config);
-
-                match ServerConnection::new(server_config) {
-                    Ok(mut conn) => {
-                        let mut tls = Stream::new(&mut conn, &mut stream);
-
-                        // 에코 서비스
-                        let mut buf = [0; 1024];
-                        loop {
-                            match tls.read(&mut buf) {
-                                Ok(0) => {
-                                    println!("클라이언트 연결 종료");
-                                    break;
-                                }
-                                Ok(n) => {
-                                    tls.write_all(&buf[..n])?;
-                                    println!("에코 완료: {} 바이트", n);
-                                }
-                                Err(e) => {
-                                    println!("읽기 오류: {}", e);
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => println!("TLS 연결 생성 실패: {}", e),
-                }
+            Ok(stream) => {
+                let store = Arc::clone(&store);
+                limiter.serve(stream, move |stream, client| {
+                    // 대기열에서 기다린 사이 다시 로드됐을 수 있으니 핸드셰이크 직전에
+                    handle_client(store.current(), stream, client)
+                });
             }
             Err(e) => println!("연결 오류: {}", e),
         }
@@ -82,3 +350,168 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cert_store::test_pki::{Issued, TestCa};
+    use cli_rustls::resumption::{connect_once, CountingVerifier};
+    use rustls::client::WebPkiVerifier;
+    use rustls::{ClientConfig, ClientConnection, ConnectionCommon};
+
+    const REVOKED: u64 = 2;
+
+    /// Pass pending TLS records from one side to the other. False if there
+    /// were none.
+    fn transfer<A, B>(
+        from: &mut ConnectionCommon<A>,
+        to: &mut ConnectionCommon<B>,
+    ) -> Result<bool, rustls::Error> {
+        let mut records = Vec::new();
+        while from.wants_write() {
+            from.write_tls(&mut records).unwrap();
+        }
+        let mut rest = records.as_slice();
+        while !rest.is_empty() {
+            to.read_tls(&mut rest).unwrap();
+            to.process_new_packets()?;
+        }
+        Ok(!records.is_empty())
+    }
+
+    /// Handshake in memory with `client_cert`, against a server that has `crls`.
+    fn connect(ca: &TestCa, client_cert: &Issued, crls: Vec<Vec<u8>>) -> Result<(), rustls::Error> {
+        let server_cert = ca.server("localhost", 1);
+        let config = server_config(
+            ca.cert_pem().as_bytes(),
+            crls,
+            vec![Certificate(server_cert.cert_der)],
+            PrivateKey(server_cert.key_der),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::new(config)).unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&Certificate(ca.cert_der())).unwrap();
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                vec![Certificate(client_cert.cert_der.clone())],
+                PrivateKey(client_cert.key_der.clone()),
+            )
+            .unwrap();
+        let mut client =
+            ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+
+        while client.is_handshaking() || server.is_handshaking() {
+            let sent = transfer(&mut client, &mut server)?;
+            let received = transfer(&mut server, &mut client)?;
+            assert!(sent || received, "handshake stalled");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_revoked_client_certificate() {
+        let ca = TestCa::new();
+        let crls = vec![ca.crl_der(&[REVOKED])];
+
+        assert_eq!(connect(&ca, &ca.client("good", 3), crls.clone()), Ok(()));
+        let err = connect(&ca, &ca.client("revoked", REVOKED), crls).unwrap_err();
+        assert_eq!(
+            err,
+            rustls::Error::InvalidCertificate(CertificateError::Revoked)
+        );
+        // what handle_client sees from complete_io
+        assert!(is_revoked(&io::Error::new(io::ErrorKind::InvalidData, err)));
+    }
+
+    #[test]
+    fn accepts_any_client_certificate_without_crl() {
+        let ca = TestCa::new();
+        assert_eq!(
+            connect(&ca, &ca.client("revoked", REVOKED), Vec::new()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn p384_key_matches_its_certificate() {
+        let generate = || {
+            let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+            params.alg = &rcgen::PKCS_ECDSA_P384_SHA384;
+            rcgen::Certificate::from_params(params).unwrap()
+        };
+        let (cert, other) = (generate(), generate());
+        let leaf = Certificate(cert.serialize_der().unwrap());
+        let key = |cert: &rcgen::Certificate| {
+            rustls::sign::any_supported_type(&PrivateKey(cert.serialize_private_key_der())).unwrap()
+        };
+
+        check_key_matches(&leaf, key(&cert).as_ref()).unwrap();
+        let err = check_key_matches(&leaf, key(&other).as_ref()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "private key does not match the certificate"
+        );
+    }
+
+    #[test]
+    fn resumed_connection_echoes_early_data() {
+        let ca = TestCa::new();
+        let server_cert = ca.server("localhost", 1);
+        let mut config = server_config(
+            ca.cert_pem().as_bytes(),
+            Vec::new(),
+            vec![Certificate(server_cert.cert_der)],
+            PrivateKey(server_cert.key_der),
+        )
+        .unwrap();
+        Resumption {
+            session_storage: ServerSessionMemoryCache::new(16),
+            ticketer: None,
+            max_early_data_size: 1024,
+        }
+        .apply(&mut config);
+        let config = Arc::new(config);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let limiter = Limiter::new(Limits::default());
+            for stream in listener.incoming() {
+                let config = Arc::clone(&config);
+                limiter.serve(stream.unwrap(), move |stream, client| {
+                    handle_client(config, stream, client)
+                });
+            }
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&Certificate(ca.cert_der())).unwrap();
+        let verifier = Arc::new(CountingVerifier::new(Arc::new(WebPkiVerifier::new(
+            roots, None,
+        ))));
+        let client_cert = ca.client("client", 2);
+        let mut config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(verifier.clone())
+            .with_client_auth_cert(
+                vec![Certificate(client_cert.cert_der)],
+                PrivateKey(client_cert.key_der),
+            )
+            .unwrap();
+        config.enable_early_data = true;
+        let config = Arc::new(config);
+        let server_name = "localhost".try_into().unwrap();
+
+        // connect_once fails unless the echo matches what was sent
+        let full = connect_once(&config, &verifier, &addr, &server_name, b"ping").unwrap();
+        assert!(!full.resumed && !full.early_data_accepted);
+        // the whole echo comes back from handshake(), which echoes the
+        // early data; nothing is sent after the handshake
+        let resumed = connect_once(&config, &verifier, &addr, &server_name, b"ping").unwrap();
+        assert!(resumed.resumed);
+        assert!(resumed.early_data_accepted);
+    }
+}
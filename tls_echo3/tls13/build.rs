@@ -0,0 +1,12 @@
+use std::env;
+
+fn main() {
+    // openssl-sys 가 넘겨주는 OpenSSL 버전, early data 상태는 1.1.1 부터
+    println!("cargo:rustc-check-cfg=cfg(ossl111)");
+    let version = env::var("DEP_OPENSSL_VERSION_NUMBER")
+        .ok()
+        .and_then(|version| u64::from_str_radix(&version, 16).ok());
+    if env::var("DEP_OPENSSL_LIBRESSL").is_err() && version.is_some_and(|v| v >= 0x1010_1000) {
+        println!("cargo:rustc-cfg=ossl111");
+    }
+}
@@ -1,39 +1,137 @@
-use openssl::ssl::{SslConnector, SslMethod};
+use clap::Parser;
+use handshake_stats::Sample;
+use openssl::nid::Nid;
+use openssl::ssl::{SslConnector, SslMethod, SslStream};
 use std::io::{self, Read, Write};
 use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Instant;
+use tls13::{Negotiated, SessionSlot, TlsOptions};
 
-const ROOT_CA_PATH: &str = "../certs/rootCA.pem";
+#[derive(Parser, Debug)]
+#[command(author, version, about = "TLS 1.3 전용 에코 클라이언트", long_about = None)]
+struct Cli {
+    #[arg(short, long, default_value = "127.0.0.1:8443")]
+    connect: String,
 
-const SERVER_ADDRESS: &str = "127.0.0.1:8443";
-const SERVER_HOSTNAME: &str = "test.edger.dev";
+    /// SNI 와 인증서 검증에 쓰는 서버 이름
+    #[arg(long, default_value = "test.edger.dev")]
+    server_name: String,
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // TLS connector 설정
-    let mut connector = SslConnector::builder(SslMethod::tls())?;
+    #[arg(long, default_value = "../certs/rootCA.pem")]
+    ca: PathBuf,
+
+    /// N 번 다시 연결해서 전체/재개 핸드셰이크 시간 비교
+    #[arg(long, value_name = "N")]
+    reconnect: Option<usize>,
+
+    /// 세션 재개 시 메시지를 0-RTT 로 전송 (재전송 공격 가능)
+    #[arg(long)]
+    early_data: bool,
+
+    /// --reconnect 에서 연결마다 에코할 메시지
+    #[arg(long, default_value = "ping")]
+    message: String,
+
+    #[command(flatten)]
+    tls: TlsOptions,
+}
+
+/// Connect, handshake, echo `message` once and close.
+fn connect_once(
+    connector: &SslConnector,
+    sessions: &SessionSlot,
+    cli: &Cli,
+) -> Result<Sample, tls13::Error> {
+    let message = cli.message.as_bytes();
+    let start = Instant::now();
+    let stream = TcpStream::connect(&cli.connect)?;
+    stream.set_nodelay(true)?;
+
+    // 지난 연결에서 받은 세션으로 재개 시도, 티켓은 한 번만 사용
+    let mut config = connector.configure()?;
+    let session = sessions.lock().unwrap().take();
+    if let Some(session) = &session {
+        // 같은 SslContext 로 받은 세션
+        unsafe { config.set_session(session)? };
+    }
+    // connect() 전에 early data 를 쓰려면 클라이언트 모드로 먼저 바꿔야 함
+    let mut ssl = config.into_ssl(&cli.server_name)?;
+    ssl.set_connect_state();
+    let mut ssl_stream = SslStream::new(ssl, stream)?;
+
+    let mut early = 0;
+    if cli.early_data && session.is_some_and(|session| session.max_early_data() > 0) {
+        early = ssl_stream.write_early_data(message)?;
+    }
+    ssl_stream.connect()?;
+    let handshake = start.elapsed();
 
-    // TLS 1.3만 허용하도록 설정
-    connector.set_min_proto_version(Some(openssl::ssl::SslVersion::TLS1_3))?;
-    connector.set_max_proto_version(Some(openssl::ssl::SslVersion::TLS1_3))?;
+    // 서버가 0-RTT 를 거절했으면 다시 보내야 함
+    let early_data_accepted = early > 0 && tls13::early_data_accepted(ssl_stream.ssl());
+    let sent = if early_data_accepted { early } else { 0 };
+    ssl_stream.write_all(&message[sent..])?;
+    let mut reply = vec![0; message.len()];
+    ssl_stream.read_exact(&mut reply)?;
+    let echo = start.elapsed();
+    if reply != message {
+        return Err("echo does not match what was sent".into());
+    }
+    let _ = ssl_stream.shutdown();
 
-    connector.set_ca_file(ROOT_CA_PATH)?;
+    Ok(Sample {
+        resumed: ssl_stream.ssl().session_reused(),
+        early_data_accepted,
+        handshake,
+        echo,
+    })
+}
 
+fn main() -> Result<(), tls13::Error> {
+    let cli = Cli::parse();
+
+    // TLS connector 설정, TLS 1.3만 허용
+    let mut connector = SslConnector::builder(SslMethod::tls())?;
+    cli.tls.apply_client(&mut connector)?;
+    connector.set_ca_file(&cli.ca)?;
+    let sessions = tls13::client_sessions(&mut connector);
     let connector = connector.build();
 
+    if let Some(count) = cli.reconnect {
+        let mut samples = Vec::with_capacity(count);
+        for i in 1..=count {
+            let sample = connect_once(&connector, &sessions, &cli)?;
+            println!("#{:<4} {}", i, sample);
+            samples.push(sample);
+        }
+        handshake_stats::report(&samples);
+        return Ok(());
+    }
+
     // 서버에 연결
-    let stream = TcpStream::connect(SERVER_ADDRESS)?;
-    let mut ssl_stream = connector.connect(SERVER_HOSTNAME, stream)?;
+    let stream = TcpStream::connect(&cli.connect)?;
+    let mut ssl_stream = connector.connect(&cli.server_name, stream)?;
 
-    // TLS 버전 정보 표시
-    let tls_version = ssl_stream.ssl().version_str();
-    println!("TLS connection established: Version {}", tls_version);
+    // 협상된 파라미터와 서버 인증서 표시
+    println!(
+        "TLS connection established: {}",
+        Negotiated::of(ssl_stream.ssl())
+    );
+    if let Some(cert) = ssl_stream.ssl().peer_certificate() {
+        let cn = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| std::str::from_utf8(entry.data().as_slice()).ok())
+            .map(str::to_string);
+        println!("Server certificate: CN={}", cn.as_deref().unwrap_or("-"));
+    }
     println!("Connected to server. Enter message (type 'quit' to exit):");
 
     loop {
         // 사용자 입력 받기
         let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-
-        if input.trim() == "quit" {
+        if io::stdin().read_line(&mut input)? == 0 || input.trim() == "quit" {
             break;
         }
 
@@ -51,5 +149,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    let _ = ssl_stream.shutdown();
     Ok(())
 }
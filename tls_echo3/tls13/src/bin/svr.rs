@@ -1,69 +1,173 @@
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use clap::Parser;
+use conn_limit::{Client, Limiter, Limits};
+use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod, SslOptions, SslStream};
 use std::io::{Read, Write};
-use std::net::TcpListener;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tls13::{Negotiated, TlsOptions};
 
-const SERVER_CERT_PATH: &str = "../certs/server.pem";
-const SERVER_KEY_PATH: &str = "../certs/server-key.pem";
+#[derive(Parser, Debug)]
+#[command(author, version, about = "TLS 1.3 전용 에코 서버", long_about = None)]
+struct Cli {
+    #[arg(short, long, default_value = "0.0.0.0:8443")]
+    bind: String,
 
-const BIND_ADDRESS: &str = "0.0.0.0:8443";
+    #[arg(long, default_value = "../certs/server.pem")]
+    cert: PathBuf,
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // TLS acceptor 설정
-    let mut acceptor = SslAcceptor::mozilla_modern_v5(SslMethod::tls())?;
+    #[arg(long, default_value = "../certs/server-key.pem")]
+    key: PathBuf,
+
+    /// 0-RTT early data 최대 크기 (바이트), 0 이면 끔. 재전송 공격에 주의
+    #[arg(long, default_value_t = 0)]
+    early_data: u32,
+
+    /// 세션 티켓 대신 서버 세션 캐시로 재개
+    #[arg(long)]
+    no_tickets: bool,
+
+    #[command(flatten)]
+    tls: TlsOptions,
+
+    #[command(flatten)]
+    limits: Limits,
+}
+
+/// 핸드셰이크, early data 를 허용하면 그 전에 0-RTT 데이터를 읽음
+fn accept(
+    acceptor: &SslAcceptor,
+    stream: TcpStream,
+    early_data: bool,
+) -> Result<(SslStream<TcpStream>, Vec<u8>), tls13::Error> {
+    let mut ssl_stream = SslStream::new(Ssl::new(acceptor.context())?, stream)?;
+    let mut early = Vec::new();
+    if early_data {
+        let mut buf = [0; 1024];
+        loop {
+            match ssl_stream.read_early_data(&mut buf)? {
+                0 => break,
+                size => early.extend_from_slice(&buf[..size]),
+            }
+        }
+    }
+    ssl_stream.accept()?;
+    Ok((ssl_stream, early))
+}
+
+/// 핸드셰이크가 제한 시간 안에 끝나지 않았는지
+fn timed_out(e: &tls13::Error) -> bool {
+    e.downcast_ref::<openssl::ssl::Error>()
+        .and_then(|e| e.io_error())
+        .is_some_and(conn_limit::is_timeout)
+}
+
+fn handle_client(mut ssl_stream: impl Read + Write, peer: &str) {
+    // 클라이언트로부터 데이터 수신
+    let mut buf = [0; 1024];
+    loop {
+        match ssl_stream.read(&mut buf) {
+            Ok(0) => {
+                println!("Client connection closed");
+                break;
+            }
+            Ok(size) => {
+                if let Err(e) = ssl_stream.write_all(&buf[..size]) {
+                    println!("Write error: {}", e);
+                    break;
+                }
+                println!(
+                    "recv&echo {} bytes : {}",
+                    size,
+                    String::from_utf8_lossy(&buf[..size])
+                );
+            }
+            Err(e) if conn_limit::is_timeout(&e) => {
+                println!("{}: idle timeout, closing", peer);
+                break;
+            }
+            Err(e) => {
+                println!("Read error: {}", e);
+                break;
+            }
+        }
+    }
+}
 
-    // TLS 1.3만 허용하도록 설정
-    acceptor.set_min_proto_version(Some(openssl::ssl::SslVersion::TLS1_3))?;
-    acceptor.set_max_proto_version(Some(openssl::ssl::SslVersion::TLS1_3))?;
+/// 핸드셰이크 후 에코, 워커 스레드에서 실행
+fn serve(acceptor: &SslAcceptor, stream: TcpStream, client: Client, early_data: bool) {
+    let _ = stream.set_nodelay(true);
+    let peer = client.peer.to_string();
 
-    acceptor.set_private_key_file(SERVER_KEY_PATH, SslFiletype::PEM)?;
+    // TLS 스트림 생성
+    let (mut ssl_stream, early) = match accept(acceptor, stream, early_data) {
+        Ok(accepted) => accepted,
+        Err(e) if timed_out(&e) => {
+            println!("{}: TLS handshake timed out", peer);
+            return;
+        }
+        Err(e) => {
+            println!("{}: TLS handshake failed: {}", peer, e);
+            return;
+        }
+    };
+    if let Err(e) = client.set_idle_timeout(ssl_stream.get_ref()) {
+        println!("{}: socket error: {}", peer, e);
+        return;
+    }
 
-    acceptor.set_certificate_chain_file(SERVER_CERT_PATH)?;
+    // 협상된 파라미터 표시
+    println!(
+        "{}: TLS connection established: {}",
+        peer,
+        Negotiated::of(ssl_stream.ssl())
+    );
+    if !early.is_empty() {
+        println!("{}: recv&echo {} bytes of 0-RTT data", peer, early.len());
+        if let Err(e) = ssl_stream.write_all(&early) {
+            println!("Write error: {}", e);
+            return;
+        }
+    }
+    let mut ssl_stream = client.rate_limit(ssl_stream);
+    handle_client(&mut ssl_stream, &peer);
+    // close_notify 없이 끊으면 openssl 이 세션을 캐시에서 지워서 재개와
+    // 0-RTT (anti-replay 가 서버 캐시를 씀) 가 되지 않음
+    let _ = ssl_stream.get_mut().shutdown();
+}
+
+fn main() -> Result<(), tls13::Error> {
+    let cli = Cli::parse();
+
+    // TLS acceptor 설정, TLS 1.3만 허용
+    let mut acceptor = SslAcceptor::mozilla_modern_v5(SslMethod::tls())?;
+    cli.tls.apply_server(&mut acceptor)?;
+
+    // 세션 재개: 기본은 티켓, early data 는 openssl 의 anti-replay 캐시가 막아 줌
+    if cli.no_tickets {
+        acceptor.set_options(SslOptions::NO_TICKET);
+    }
+    acceptor.set_max_early_data(cli.early_data)?;
 
+    acceptor.set_private_key_file(&cli.key, SslFiletype::PEM)?;
+    acceptor.set_certificate_chain_file(&cli.cert)?;
     acceptor.check_private_key()?;
-    let acceptor = acceptor.build();
+    let acceptor = Arc::new(acceptor.build());
 
     // TCP 리스너 생성
-    let listener = TcpListener::bind(BIND_ADDRESS)?;
-    println!("Server is running on {}...", BIND_ADDRESS);
+    let listener = TcpListener::bind(&cli.bind)?;
+    println!("Server is running on {}...", cli.bind);
+    let early_data = cli.early_data > 0;
 
+    // 연결은 정해진 수의 워커 스레드가 처리, 넘치면 거부
+    let limiter = Limiter::new(cli.limits);
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                // TLS 스트림 생성
-                let mut ssl_stream = match acceptor.accept(stream) {
-                    Ok(stream) => stream,
-                    Err(e) => {
-                        println!("TLS handshake failed: {}", e);
-                        continue;
-                    }
-                };
-
-                // TLS 버전 정보 표시
-                let tls_version = ssl_stream.ssl().version_str();
-                println!("TLS connection established: Version {}", tls_version);
-
-                // 클라이언트로부터 데이터 수신
-                let mut buf = [0; 1024];
-                loop {
-                    match ssl_stream.read(&mut buf) {
-                        Ok(0) => {
-                            println!("Client connection closed");
-                            break;
-                        }
-                        Ok(size) => {
-                            ssl_stream.write_all(&buf[..size])?;
-                            println!(
-                                "recv&echo {} bytes : {}",
-                                size,
-                                String::from_utf8_lossy(&buf[..size])
-                            );
-                        }
-                        Err(e) => {
-                            println!("Read error: {}", e);
-                            break;
-                        }
-                    }
-                }
+                let acceptor = Arc::clone(&acceptor);
+                limiter.serve(stream, move |stream, client| {
+                    serve(&acceptor, stream, client, early_data)
+                });
             }
             Err(e) => println!("Connection error: {}", e),
         }
@@ -0,0 +1,363 @@
+//! TLS 1.3 settings shared by the `svr` and `cli` binaries, and what a
+//! handshake ended up with.
+//!
+//! Cipher suites and groups use the openssl list syntax, e.g.
+//! `--ciphersuites TLS_AES_256_GCM_SHA384:TLS_CHACHA20_POLY1305_SHA256` and
+//! `--groups X25519:P-256`. Key material is written in the NSS key log
+//! format to `--keylog` or `$SSLKEYLOGFILE`, which Wireshark reads under
+//! Preferences > Protocols > TLS > (Pre)-Master-Secret log filename.
+//!
+//! Sessions are resumed from the tickets the server sends after each
+//! handshake; the client keeps the latest one with [`client_sessions`].
+//! With the server's `--early-data` and the client's `--early-data` the
+//! first message goes out as 0-RTT data. That data can be replayed by
+//! anyone who recorded it: openssl's anti-replay cache only covers one
+//! server process, so only send requests that are safe to repeat.
+
+use clap::Args;
+use openssl::error::ErrorStack;
+use openssl::pkey::Id;
+use openssl::ssl::{
+    AlpnError, SslContextBuilder, SslRef, SslSession, SslSessionCacheMode, SslVersion,
+};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Args, Debug, Clone)]
+pub struct TlsOptions {
+    /// TLS 1.3 cipher suite 목록 (':' 구분), 기본값은 openssl 기본값
+    #[arg(long)]
+    pub ciphersuites: Option<String>,
+
+    /// 키 교환 그룹 목록 (':' 구분), 예: X25519:P-256
+    #[arg(long)]
+    pub groups: Option<String>,
+
+    /// ALPN 프로토콜, 선호 순서대로 (',' 구분)
+    #[arg(long, value_delimiter = ',')]
+    pub alpn: Vec<String>,
+
+    /// Wireshark 용 키 로그 파일
+    #[arg(long, env = "SSLKEYLOGFILE")]
+    pub keylog: Option<PathBuf>,
+}
+
+/// ALPN protocol list in wire format: each name prefixed by its length.
+pub fn alpn_wire(protocols: &[String]) -> Result<Vec<u8>, Error> {
+    let mut wire = Vec::new();
+    for protocol in protocols {
+        let len = u8::try_from(protocol.len())
+            .ok()
+            .filter(|len| *len > 0)
+            .ok_or_else(|| format!("invalid ALPN protocol {:?}", protocol))?;
+        wire.push(len);
+        wire.extend_from_slice(protocol.as_bytes());
+    }
+    Ok(wire)
+}
+
+/// First protocol in the client's wire format list that `server` knows.
+/// The result borrows from `client`, as the ALPN callback has to return.
+pub fn select_alpn<'a>(server: &[String], client: &'a [u8]) -> Option<&'a [u8]> {
+    let mut rest = client;
+    while let Some((&len, tail)) = rest.split_first() {
+        let (protocol, tail) = tail.split_at_checked(len.into())?;
+        if server.iter().any(|name| name.as_bytes() == protocol) {
+            return Some(protocol);
+        }
+        rest = tail;
+    }
+    None
+}
+
+impl TlsOptions {
+    /// TLS 1.3 only, plus the suites, groups and key log.
+    fn apply_common(&self, ctx: &mut SslContextBuilder) -> Result<(), Error> {
+        ctx.set_min_proto_version(Some(SslVersion::TLS1_3))?;
+        ctx.set_max_proto_version(Some(SslVersion::TLS1_3))?;
+        if let Some(suites) = &self.ciphersuites {
+            ctx.set_ciphersuites(suites)
+                .map_err(|e| format!("invalid --ciphersuites {}: {}", suites, e))?;
+        }
+        if let Some(groups) = &self.groups {
+            ctx.set_groups_list(groups)
+                .map_err(|e| format!("invalid --groups {}: {}", groups, e))?;
+        }
+        if let Some(path) = &self.keylog {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("failed to open key log {}: {}", path.display(), e))?;
+            let file: Arc<Mutex<File>> = Arc::new(Mutex::new(file));
+            ctx.set_keylog_callback(move |_, line| {
+                let _ = writeln!(file.lock().unwrap(), "{}", line);
+            });
+            eprintln!(
+                "writing TLS secrets to {}, keep it away from production",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn apply_server(&self, ctx: &mut SslContextBuilder) -> Result<(), Error> {
+        self.apply_common(ctx)?;
+        if !self.alpn.is_empty() {
+            // 서버 선호 순서가 아니라 클라이언트 목록 중 서버가 아는 첫 번째,
+            // 겹치는 것이 없으면 ALPN 없이 계속
+            // 잘못된 이름은 여기서 거름
+            alpn_wire(&self.alpn)?;
+            let server = self.alpn.clone();
+            ctx.set_alpn_select_callback(move |_, client| {
+                select_alpn(&server, client).ok_or(AlpnError::NOACK)
+            });
+        }
+        Ok(())
+    }
+
+    pub fn apply_client(&self, ctx: &mut SslContextBuilder) -> Result<(), Error> {
+        self.apply_common(ctx)?;
+        if !self.alpn.is_empty() {
+            ctx.set_alpn_protos(&alpn_wire(&self.alpn)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Negotiated parameters of an established connection.
+#[derive(Debug, Clone)]
+pub struct Negotiated {
+    pub version: String,
+    pub cipher: Option<String>,
+    /// Key exchange group, from the peer's key share.
+    pub group: Option<String>,
+    pub alpn: Option<String>,
+    pub resumed: bool,
+}
+
+/// Group name of the peer's ephemeral key: `X25519`, `prime256v1`, ...
+fn key_exchange_group(ssl: &SslRef) -> Result<Option<String>, ErrorStack> {
+    let key = ssl.peer_tmp_key()?;
+    Ok(match key.id() {
+        Id::X25519 => Some("X25519".to_string()),
+        Id::X448 => Some("X448".to_string()),
+        Id::EC => key
+            .ec_key()?
+            .group()
+            .curve_name()
+            .and_then(|nid| nid.short_name().ok())
+            .map(str::to_string),
+        _ => None,
+    })
+}
+
+impl Negotiated {
+    pub fn of(ssl: &SslRef) -> Self {
+        Self {
+            version: ssl.version_str().to_string(),
+            cipher: ssl.current_cipher().map(|cipher| cipher.name().to_string()),
+            group: key_exchange_group(ssl).ok().flatten(),
+            alpn: ssl
+                .selected_alpn_protocol()
+                .map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+            resumed: ssl.session_reused(),
+        }
+    }
+}
+
+/// `TLSv1.3 TLS_AES_256_GCM_SHA384 group=X25519 alpn=echo resumed=no`
+impl fmt::Display for Negotiated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unknown = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        write!(
+            f,
+            "{} {} group={} alpn={} resumed={}",
+            self.version,
+            unknown(&self.cipher),
+            unknown(&self.group),
+            self.alpn.as_deref().unwrap_or("none"),
+            if self.resumed { "yes" } else { "no" }
+        )
+    }
+}
+
+/// Latest session the server handed out, for the next connection.
+pub type SessionSlot = Arc<Mutex<Option<SslSession>>>;
+
+/// Keep the sessions from the server's tickets. TLS 1.3 tickets should be
+/// used once, so take the session out of the slot when connecting.
+pub fn client_sessions(ctx: &mut SslContextBuilder) -> SessionSlot {
+    ctx.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+    let slot: SessionSlot = Arc::new(Mutex::new(None));
+    let latest = Arc::clone(&slot);
+    ctx.set_new_session_callback(move |_, session| {
+        *latest.lock().unwrap() = Some(session);
+    });
+    slot
+}
+
+// Neither the openssl crate nor openssl-sys binds SSL_get_early_data_status.
+#[cfg(ossl111)]
+mod ffi {
+    use std::os::raw::c_int;
+
+    pub const SSL_EARLY_DATA_ACCEPTED: c_int = 2;
+
+    unsafe extern "C" {
+        pub fn SSL_get_early_data_status(ssl: *const openssl_sys::SSL) -> c_int;
+    }
+}
+
+/// Whether the server took the 0-RTT data; if not it has to be sent again.
+#[cfg(ossl111)]
+pub fn early_data_accepted(ssl: &SslRef) -> bool {
+    use foreign_types::ForeignTypeRef;
+
+    // SAFETY: a live SSL, the call only reads its state.
+    unsafe { ffi::SSL_get_early_data_status(ssl.as_ptr()) == ffi::SSL_EARLY_DATA_ACCEPTED }
+}
+
+/// Without OpenSSL 1.1.1 there is no early data to accept.
+#[cfg(not(ossl111))]
+pub fn early_data_accepted(_ssl: &SslRef) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cert_store::test_pki::TestCa;
+    use openssl::pkey::PKey;
+    use openssl::ssl::{Ssl, SslAcceptor, SslConnector, SslMethod, SslStream};
+    use openssl::x509::X509;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    #[test]
+    fn alpn_wire_format() {
+        let protocols = vec!["h2".to_string(), "echo/1".to_string()];
+        assert_eq!(alpn_wire(&protocols).unwrap(), b"\x02h2\x06echo/1");
+        assert!(alpn_wire(&["".to_string()]).is_err());
+        assert!(alpn_wire(&["x".repeat(256)]).is_err());
+    }
+
+    #[test]
+    fn alpn_picks_the_clients_first_known_protocol() {
+        let server = vec!["echo/1".to_string(), "h2".to_string()];
+        let client = b"\x08http/1.1\x02h2\x06echo/1";
+        assert_eq!(select_alpn(&server, client), Some(&b"h2"[..]));
+        assert_eq!(select_alpn(&server, b"\x08http/1.1"), None);
+        // a length past the end is not read
+        assert_eq!(select_alpn(&server, b"\x02h2\x09echo"), Some(&b"h2"[..]));
+        assert_eq!(select_alpn(&server, b"\x09echo/1"), None);
+    }
+
+    /// Echo server for `connections` connections that reads 0-RTT data
+    /// before the handshake; returns the early data of each.
+    fn early_data_server(
+        ca: &TestCa,
+        connections: usize,
+    ) -> (String, thread::JoinHandle<Vec<Vec<u8>>>) {
+        let issued = ca.server("localhost", 2);
+        let mut acceptor = SslAcceptor::mozilla_modern_v5(SslMethod::tls()).unwrap();
+        TlsOptions {
+            ciphersuites: None,
+            groups: None,
+            alpn: Vec::new(),
+            keylog: None,
+        }
+        .apply_server(&mut acceptor)
+        .unwrap();
+        acceptor.set_max_early_data(1024).unwrap();
+        acceptor
+            .set_certificate(&X509::from_der(&issued.cert_der).unwrap())
+            .unwrap();
+        acceptor
+            .set_private_key(&PKey::private_key_from_der(&issued.key_der).unwrap())
+            .unwrap();
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let mut received = Vec::new();
+            for _ in 0..connections {
+                let (stream, _) = listener.accept().unwrap();
+                let mut ssl =
+                    SslStream::new(Ssl::new(acceptor.context()).unwrap(), stream).unwrap();
+                let mut early = Vec::new();
+                let mut buf = [0; 1024];
+                loop {
+                    match ssl.read_early_data(&mut buf).unwrap() {
+                        0 => break,
+                        size => early.extend_from_slice(&buf[..size]),
+                    }
+                }
+                ssl.accept().unwrap();
+                ssl.write_all(&early).unwrap();
+                loop {
+                    match ssl.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(size) => ssl.write_all(&buf[..size]).unwrap(),
+                    }
+                }
+                // 끊기 전에 close_notify 를 보내지 않으면 세션이 캐시에서 지워짐
+                let _ = ssl.shutdown();
+                received.push(early);
+            }
+            received
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn early_data_is_accepted_on_the_resumed_connection() {
+        let ca = TestCa::new();
+        let (addr, server) = early_data_server(&ca, 2);
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector
+            .cert_store_mut()
+            .add_cert(X509::from_der(&ca.cert_der()).unwrap())
+            .unwrap();
+        let sessions = client_sessions(&mut connector);
+        let connector = connector.build();
+
+        let connect = |early: Option<&[u8]>| {
+            let stream = TcpStream::connect(&addr).unwrap();
+            let mut config = connector.configure().unwrap();
+            let session = sessions.lock().unwrap().take();
+            if let Some(session) = &session {
+                unsafe { config.set_session(session).unwrap() };
+            }
+            let mut ssl = config.into_ssl("localhost").unwrap();
+            ssl.set_connect_state();
+            let mut ssl = SslStream::new(ssl, stream).unwrap();
+            let mut written = 0;
+            if let Some(early) = early {
+                let session = session.expect("no session from the first connection");
+                assert!(session.max_early_data() > 0);
+                written = ssl.write_early_data(early).unwrap();
+            }
+            ssl.connect().unwrap();
+            let accepted = early_data_accepted(ssl.ssl());
+            let message = if accepted { &b""[..] } else { b"ping" };
+            ssl.write_all(message).unwrap();
+            // 새 세션 티켓도 이 읽기에서 처리됨
+            let mut reply = [0; 4];
+            ssl.read_exact(&mut reply).unwrap();
+            assert_eq!(&reply, b"ping");
+            ssl.shutdown().unwrap();
+            (ssl.ssl().session_reused(), accepted, written)
+        };
+
+        assert_eq!(connect(None), (false, false, 0));
+        assert_eq!(connect(Some(b"ping")), (true, true, 4));
+        assert_eq!(server.join().unwrap(), [Vec::new(), b"ping".to_vec()]);
+    }
+}
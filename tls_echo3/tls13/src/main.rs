@@ -1,3 +0,0 @@
-fn main() {
-    println!("hello tls 1.3");
-}
@@ -1,9 +1,8 @@
-use std::io::{Read, Write};
-use std::os::unix::net::UnixStream;
+use unix_domain_lib::rpcclient::RpcClient;
 
 fn main() -> std::io::Result<()> {
     let sock_path = "/tmp/.rdecho.sock";
-    let mut stream = UnixStream::connect(sock_path)?;
+    let mut client = RpcClient::connect(sock_path).map_err(std::io::Error::other)?;
     println!("서버에 연결되었습니다");
 
     println!("/q 를 입력하면 종료합니다.");
@@ -19,14 +18,10 @@ fn main() -> std::io::Result<()> {
             break;
         }
 
-        stream.write_all(input.as_bytes())?;
         println!("메시지 전송: {}", trimmed);
-
-        let mut buffer = [0; 1024];
-        match stream.read(&mut buffer) {
-            Ok(n) => {
-                let response = String::from_utf8_lossy(&buffer[..n]);
-                println!("서버로부터 받은 응답: {}", response);
+        match client.call::<_, Vec<String>>("echo", [trimmed]) {
+            Ok(response) => {
+                println!("서버로부터 받은 응답: {}", response.join(" "));
             }
             Err(e) => {
                 eprintln!("응답을 읽는 중 오류 발생: {}", e);
@@ -0,0 +1,59 @@
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::Parser;
+use serde_json::Value;
+use unix_domain_lib::rpcclient::{ClientError, RpcClient};
+
+/// Call a JSON-RPC method on the control socket
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// control socket path
+    #[arg(short = 's', long, default_value = "/tmp/.rdecho.sock")]
+    sock: String,
+
+    /// send as a notification and don't wait for a result
+    #[arg(short = 'n', long)]
+    notify: bool,
+
+    /// seconds to wait for the result (0: wait forever)
+    #[arg(short = 't', long, default_value_t = 5)]
+    timeout: u64,
+
+    /// method name
+    method: String,
+
+    /// params as a JSON array or object, e.g. '{"a":1,"b":2}' or '[1,2]'
+    params: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), ClientError> {
+    let params: Option<Value> = match &args.params {
+        Some(params) => Some(serde_json::from_str(params)?),
+        None => None,
+    };
+
+    let mut client = RpcClient::connect(&args.sock)?;
+    if args.notify {
+        return client.notify(&args.method, params);
+    }
+
+    if args.timeout > 0 {
+        client.set_timeout(Some(Duration::from_secs(args.timeout)))?;
+    }
+    let result = client.call_value(&args.method, params)?;
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
@@ -1,25 +1,65 @@
-use log;
+use serde::Deserialize;
+use serde_json::Value;
 use std::thread;
-use unix_domain_lib::{create_unix_domain_server, logger};
+use unix_domain_lib::jsonrpc::{RpcError, RpcServer};
+use unix_domain_lib::{create_unix_domain_server, logger, systemd};
+
+#[derive(Deserialize)]
+struct AddParams {
+    a: i64,
+    b: i64,
+}
+
+fn rpc_methods() -> RpcServer {
+    let mut rpc = RpcServer::new();
+    rpc.register("ping", |_: ()| Ok::<_, RpcError>("pong"));
+    rpc.register("echo", |params: Value| Ok::<_, RpcError>(params));
+    rpc.register("add", |p: AddParams| {
+        p.a.checked_add(p.b)
+            .ok_or_else(|| RpcError::invalid_params("overflow"))
+    });
+
+    let mut names: Vec<String> = rpc.methods().iter().map(|name| name.to_string()).collect();
+    names.push("methods".to_string());
+    rpc.register("methods", move |_: ()| Ok::<_, RpcError>(names.clone()));
+    rpc
+}
 
 fn main() -> std::io::Result<()> {
+    // 스레드가 생기기 전에 systemd 가 넘겨준 소켓을 가져오고 환경 변수를 지움
+    let activation = systemd::init();
     let _logger = logger::start("debug", "/tmp/server", true);
+    if let Err(e) = activation {
+        log::error!("Failed to read activated sockets: {}", e);
+    }
 
     let sock_path = "/tmp/.rdecho.sock";
 
-    // 기존 소켓 파일이 있다면 제거
-    if std::path::Path::new(sock_path).exists() {
+    // 기존 소켓 파일이 있다면 제거 (systemd 가 넘겨준 소켓은 그대로 사용)
+    let path = std::path::Path::new(sock_path);
+    if path.exists() && !systemd::is_activated_unix(path) {
         std::fs::remove_file(sock_path)?;
     }
 
     log::debug!("main loop start");
 
     let server = create_unix_domain_server(sock_path)?;
+    let mut rpc = rpc_methods();
+
+    if let Err(e) = systemd::notify_ready() {
+        log::error!("Failed to notify systemd: {}", e);
+    }
+    let mut watchdog = systemd::Watchdog::from_env();
 
     loop {
-        if let Some(recv_msg) = server.recv() {
+        if let Some(watchdog) = &mut watchdog {
+            watchdog.tick();
+        }
+        if let Some((conn, recv_msg)) = server.recv_from() {
             println!("main: received message: {}", recv_msg);
-            server.send(recv_msg);
+            if let Some(response) = rpc.handle(&recv_msg) {
+                server.send_to(conn, response);
+            }
         }
         // if let Some(msg) = server.get_received_message() {
         //     println!("main: received message: {}", msg);
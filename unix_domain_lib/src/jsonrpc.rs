@@ -0,0 +1,371 @@
+//! JSON-RPC 2.0 on top of the line based stream: one request, response or
+//! batch per line.
+//!
+//! ```
+//! use serde::Deserialize;
+//! use unix_domain_lib::jsonrpc::{RpcError, RpcServer};
+//!
+//! #[derive(Deserialize)]
+//! struct AddParams {
+//!     a: i64,
+//!     b: i64,
+//! }
+//!
+//! let mut rpc = RpcServer::new();
+//! rpc.register("add", |p: AddParams| Ok::<_, RpcError>(p.a + p.b));
+//!
+//! let response = rpc.handle(r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":1}"#);
+//! assert_eq!(response.unwrap(), r#"{"jsonrpc":"2.0","result":3,"id":1}"#);
+//! ```
+
+use std::{collections::HashMap, fmt};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+pub const VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn parse_error(detail: impl fmt::Display) -> Self {
+        Self::new(PARSE_ERROR, "Parse error").with_data(Value::String(detail.to_string()))
+    }
+
+    pub fn invalid_request(detail: &str) -> Self {
+        Self::new(INVALID_REQUEST, "Invalid Request").with_data(Value::String(detail.into()))
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self::new(METHOD_NOT_FOUND, "Method not found").with_data(Value::String(method.into()))
+    }
+
+    pub fn invalid_params(detail: impl fmt::Display) -> Self {
+        Self::new(INVALID_PARAMS, "Invalid params").with_data(Value::String(detail.to_string()))
+    }
+
+    pub fn internal_error(detail: impl fmt::Display) -> Self {
+        Self::new(INTERNAL_ERROR, "Internal error").with_data(Value::String(detail.to_string()))
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)?;
+        if let Some(data) = &self.data {
+            write!(f, ": {}", data)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    /// `None` makes this a notification, which gets no response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+}
+
+impl Request {
+    pub fn new(method: &str, params: Option<Value>, id: Option<Value>) -> Self {
+        Self {
+            jsonrpc: VERSION.to_string(),
+            method: method.to_string(),
+            params,
+            id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: Value,
+}
+
+impl Response {
+    pub fn result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: VERSION.to_string(),
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    pub fn error(id: Value, error: RpcError) -> Self {
+        Self {
+            jsonrpc: VERSION.to_string(),
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+
+    /// The result, or the error the server answered with.
+    pub fn into_result(self) -> Result<Value, RpcError> {
+        match self.error {
+            Some(error) => Err(error),
+            // a null result is skipped by serde's Option handling
+            None => Ok(self.result.unwrap_or(Value::Null)),
+        }
+    }
+}
+
+type Method = Box<dyn FnMut(Value) -> Result<Value, RpcError> + Send>;
+
+/// Method table and request dispatcher.
+#[derive(Default)]
+pub struct RpcServer {
+    methods: HashMap<String, Method>,
+}
+
+impl RpcServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `method`. Params are deserialized into `P`; a missing
+    /// `params` member is passed as `null`, so `()` or `Option<_>` work for
+    /// methods without params.
+    pub fn register<P, R, F>(&mut self, method: &str, mut f: F)
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: FnMut(P) -> Result<R, RpcError> + Send + 'static,
+    {
+        let method_fn: Method = Box::new(move |params| {
+            let params = serde_json::from_value(params).map_err(RpcError::invalid_params)?;
+            let result = f(params)?;
+            serde_json::to_value(result).map_err(RpcError::internal_error)
+        });
+        if self.methods.insert(method.to_string(), method_fn).is_some() {
+            log::warn!("method {} registered twice, keep the last one", method);
+        }
+    }
+
+    pub fn methods(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.methods.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// Handle one line. Returns the line to send back, if any.
+    pub fn handle(&mut self, line: &str) -> Option<String> {
+        let response = match serde_json::from_str::<Value>(line) {
+            Ok(Value::Array(batch)) => self.handle_batch(batch),
+            Ok(value) => self.handle_value(value).map(|r| to_json(&r)),
+            Err(e) => Some(to_json(&Response::error(
+                Value::Null,
+                RpcError::parse_error(e),
+            ))),
+        };
+        if let Some(response) = &response {
+            log::debug!("rpc: {} -> {}", line, response);
+        }
+        response
+    }
+
+    fn handle_batch(&mut self, batch: Vec<Value>) -> Option<String> {
+        if batch.is_empty() {
+            let error = RpcError::invalid_request("empty batch");
+            return Some(to_json(&Response::error(Value::Null, error)));
+        }
+        let responses: Vec<Response> = batch
+            .into_iter()
+            .filter_map(|value| self.handle_value(value))
+            .collect();
+        // a batch of notifications gets no reply at all
+        (!responses.is_empty()).then(|| to_json(&responses))
+    }
+
+    fn handle_value(&mut self, value: Value) -> Option<Response> {
+        let Value::Object(request) = value else {
+            let error = RpcError::invalid_request("request must be an object");
+            return Some(Response::error(Value::Null, error));
+        };
+        let id = request.get("id").cloned();
+        match self.call(&request) {
+            Ok(result) => id.map(|id| Response::result(id, result)),
+            Err(error) => match id {
+                Some(id) if is_valid_id(&id) => Some(Response::error(id, error)),
+                Some(_) => Some(Response::error(Value::Null, error)),
+                None => {
+                    log::warn!("notification failed: {}", error);
+                    None
+                }
+            },
+        }
+    }
+
+    fn call(&mut self, request: &Map<String, Value>) -> Result<Value, RpcError> {
+        if request.get("jsonrpc").and_then(Value::as_str) != Some(VERSION) {
+            return Err(RpcError::invalid_request("jsonrpc must be \"2.0\""));
+        }
+        if let Some(id) = request.get("id") {
+            if !is_valid_id(id) {
+                return Err(RpcError::invalid_request(
+                    "id must be a string, number or null",
+                ));
+            }
+        }
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return Err(RpcError::invalid_request("method must be a string"));
+        };
+        let params = match request.get("params") {
+            None => Value::Null,
+            Some(params @ (Value::Array(_) | Value::Object(_))) => params.clone(),
+            Some(_) => {
+                return Err(RpcError::invalid_request(
+                    "params must be an array or an object",
+                ))
+            }
+        };
+        let method_fn = self
+            .methods
+            .get_mut(method)
+            .ok_or_else(|| RpcError::method_not_found(method))?;
+        method_fn(params)
+    }
+}
+
+fn is_valid_id(id: &Value) -> bool {
+    matches!(id, Value::Null | Value::Number(_) | Value::String(_))
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("responses always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn server() -> RpcServer {
+        let mut rpc = RpcServer::new();
+        rpc.register("sum", |numbers: Vec<i64>| {
+            Ok::<_, RpcError>(numbers.iter().sum::<i64>())
+        });
+        rpc.register("ping", |_: ()| Ok::<_, RpcError>("pong"));
+        rpc
+    }
+
+    fn handle(rpc: &mut RpcServer, request: Value) -> Option<Value> {
+        rpc.handle(&request.to_string())
+            .map(|line| serde_json::from_str(&line).unwrap())
+    }
+
+    #[test]
+    fn call_and_errors() {
+        let mut rpc = server();
+        assert_eq!(
+            handle(
+                &mut rpc,
+                json!({"jsonrpc": "2.0", "method": "sum", "params": [1, 2, 3], "id": "a"})
+            ),
+            Some(json!({"jsonrpc": "2.0", "result": 6, "id": "a"}))
+        );
+        assert_eq!(
+            handle(
+                &mut rpc,
+                json!({"jsonrpc": "2.0", "method": "ping", "id": 1})
+            ),
+            Some(json!({"jsonrpc": "2.0", "result": "pong", "id": 1}))
+        );
+
+        let code = |response: Option<Value>| response.unwrap()["error"]["code"].as_i64().unwrap();
+        assert_eq!(
+            code(handle(
+                &mut rpc,
+                json!({"jsonrpc": "2.0", "method": "nope", "id": 1})
+            )),
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            code(handle(
+                &mut rpc,
+                json!({"jsonrpc": "2.0", "method": "sum", "params": {"a": 1}, "id": 1})
+            )),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(handle(
+                &mut rpc,
+                json!({"jsonrpc": "1.0", "method": "sum", "id": 1})
+            )),
+            INVALID_REQUEST
+        );
+        let parse_error = rpc.handle("{\"jsonrpc\"").unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&parse_error).unwrap()["error"]["code"],
+            json!(PARSE_ERROR)
+        );
+    }
+
+    #[test]
+    fn batches_and_notifications() {
+        let mut rpc = server();
+        assert_eq!(
+            handle(&mut rpc, json!({"jsonrpc": "2.0", "method": "ping"})),
+            None
+        );
+        assert_eq!(
+            handle(
+                &mut rpc,
+                json!([
+                    {"jsonrpc": "2.0", "method": "sum", "params": [1, 1], "id": 1},
+                    {"jsonrpc": "2.0", "method": "ping"},
+                    1,
+                ])
+            ),
+            Some(json!([
+                {"jsonrpc": "2.0", "result": 2, "id": 1},
+                {"jsonrpc": "2.0", "error": {"code": INVALID_REQUEST, "message": "Invalid Request", "data": "request must be an object"}, "id": null},
+            ]))
+        );
+        assert_eq!(
+            handle(&mut rpc, json!([{"jsonrpc": "2.0", "method": "ping"}])),
+            None
+        );
+        assert_eq!(
+            handle(&mut rpc, json!([])).unwrap()["error"]["code"],
+            json!(INVALID_REQUEST)
+        );
+    }
+}
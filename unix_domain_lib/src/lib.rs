@@ -1,7 +1,7 @@
-use log;
 use std::{
     io::{Read, Write},
     os::unix::net::{UnixListener, UnixStream},
+    path::Path,
     sync::{
         mpsc::{Receiver, Sender},
         Arc, Mutex,
@@ -9,26 +9,23 @@ use std::{
     thread,
 };
 
-pub mod logger;
+pub mod jsonrpc;
+pub mod rpcclient;
+
+pub use logger;
+pub use systemd;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+/// Identifies one accepted connection, so replies go back to the client
+/// that asked even if it is gone by the time they are ready.
+pub type ConnId = u64;
 
 pub struct StreamThread {
-    tx: Sender<String>,
-    rx: Receiver<String>,
+    tx: Sender<(Option<ConnId>, String)>,
+    rx: Receiver<(ConnId, String)>,
     exit_flag: Arc<Mutex<bool>>,
     handle: Option<std::thread::JoinHandle<()>>,
 }
@@ -36,8 +33,9 @@ pub struct StreamThread {
 impl StreamThread {
     fn stream_loop(
         mut stream: UnixStream,
-        rx: &Receiver<String>,
-        tx: &Sender<String>,
+        conn: ConnId,
+        rx: &Receiver<(Option<ConnId>, String)>,
+        tx: &Sender<(ConnId, String)>,
         exit_flag: &Arc<Mutex<bool>>,
     ) {
         let mut buf = [0; 2048];
@@ -47,11 +45,23 @@ impl StreamThread {
                 log::debug!("exit flag is true");
                 break;
             }
-            if let Ok(mut msg) = rx.try_recv() {
+            while let Ok((to, mut msg)) = rx.try_recv() {
+                if to.is_some_and(|to| to != conn) {
+                    log::warn!(
+                        "connection {} is closed, drop message: {}",
+                        to.unwrap(),
+                        msg
+                    );
+                    continue;
+                }
                 if !msg.ends_with('\n') {
                     msg.push('\n');
                 }
-                stream.write_all(msg.as_bytes()).unwrap();
+                if let Err(e) = stream.write_all(msg.as_bytes()) {
+                    log::error!("Failed to send data: {}", e);
+                    return;
+                }
+                break;
             }
             match stream
                 .set_nonblocking(true)
@@ -67,7 +77,10 @@ impl StreamThread {
 
                     while let Some(newline_idx) = incomplete_msg.find('\n') {
                         let msg = incomplete_msg[..newline_idx].to_string();
-                        tx.send(msg).unwrap();
+                        if tx.send((conn, msg)).is_err() {
+                            log::debug!("receiver is gone");
+                            return;
+                        }
                         incomplete_msg = incomplete_msg[newline_idx + 1..].to_string();
                     }
                 }
@@ -83,12 +96,30 @@ impl StreamThread {
         }
     }
 
+    /// Send `msg` to whichever client is connected.
     pub fn send(&self, msg: String) {
         log::debug!("send: {}", msg);
-        self.tx.send(msg).unwrap();
+        self.send_message(None, msg);
+    }
+
+    /// Send `msg` to connection `conn` only; dropped if it has gone away.
+    pub fn send_to(&self, conn: ConnId, msg: String) {
+        log::debug!("send to {}: {}", conn, msg);
+        self.send_message(Some(conn), msg);
+    }
+
+    fn send_message(&self, to: Option<ConnId>, msg: String) {
+        if self.tx.send((to, msg)).is_err() {
+            log::error!("stream thread is gone, drop message");
+        }
     }
 
     pub fn recv(&self) -> Option<String> {
+        self.recv_from().map(|(_, msg)| msg)
+    }
+
+    /// Receive a message with the connection it came from.
+    pub fn recv_from(&self) -> Option<(ConnId, String)> {
         self.rx.try_recv().ok()
     }
 
@@ -122,15 +153,23 @@ pub fn create_unix_domain_server(sock_path: &str) -> Result<StreamThread, std::i
     let exit_flag = Arc::new(Mutex::new(false));
     let exit_flag_clone = exit_flag.clone();
 
+    let activated = systemd::take_unix_listener(Path::new(&sock_path));
+
     let handle = thread::spawn(move || {
         log::debug!("stream thread start : {}", sock_path);
-        let listener = UnixListener::bind(sock_path.as_str()).unwrap();
+        let listener = match activated {
+            Some(listener) => {
+                log::info!("use activated socket {}", sock_path);
+                listener
+            }
+            None => UnixListener::bind(sock_path.as_str()).unwrap(),
+        };
 
-        for stream in listener.incoming() {
+        for (conn, stream) in (0..).zip(listener.incoming()) {
             match stream {
                 Ok(stream) => {
                     // handle_client(stream, &tx);
-                    StreamThread::stream_loop(stream, &send_rx, &recv_tx, &exit_flag_clone);
+                    StreamThread::stream_loop(stream, conn, &send_rx, &recv_tx, &exit_flag_clone);
 
                     if *exit_flag_clone.lock().unwrap() {
                         log::debug!("exit flag set, exit stream thread");
@@ -152,3 +191,64 @@ pub fn create_unix_domain_server(sock_path: &str) -> Result<StreamThread, std::i
         handle: Some(handle),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let result = add(2, 2);
+        assert_eq!(result, 4);
+    }
+
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        for _ in 0..50 {
+            if let Some(v) = f() {
+                return v;
+            }
+            thread::sleep(std::time::Duration::from_millis(50));
+        }
+        panic!("timed out");
+    }
+
+    fn read_line(stream: &mut UnixStream) -> String {
+        let mut line = Vec::new();
+        let mut byte = [0];
+        while stream.read(&mut byte).unwrap() == 1 && byte[0] != b'\n' {
+            line.push(byte[0]);
+        }
+        String::from_utf8(line).unwrap()
+    }
+
+    #[test]
+    fn replies_go_to_the_connection_that_asked() {
+        let path =
+            std::env::temp_dir().join(format!("unix_domain_lib_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = create_unix_domain_server(path.to_str().unwrap()).unwrap();
+
+        let mut first = wait_for(|| UnixStream::connect(&path).ok());
+        first.write_all(b"first\n").unwrap();
+        let (first_conn, msg) = wait_for(|| server.recv_from());
+        assert_eq!(msg, "first");
+        drop(first);
+
+        let mut second = UnixStream::connect(&path).unwrap();
+        second.write_all(b"second\n").unwrap();
+        let (second_conn, msg) = wait_for(|| server.recv_from());
+        assert_eq!(msg, "second");
+        assert_ne!(first_conn, second_conn);
+
+        // the late reply to the first client must not reach the second one
+        server.send_to(first_conn, "for first".to_string());
+        server.send_to(second_conn, "for second".to_string());
+        assert_eq!(read_line(&mut second), "for second");
+
+        // stop while the second client is still connected, as a closed
+        // connection leaves the thread waiting in accept
+        drop(server);
+        drop(second);
+        let _ = std::fs::remove_file(&path);
+    }
+}
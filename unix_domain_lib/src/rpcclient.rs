@@ -0,0 +1,308 @@
+//! Blocking JSON-RPC 2.0 client for `jsonrpc::RpcServer` on a Unix socket.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::jsonrpc::{Request, Response, RpcError};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The server answered with an error object.
+    Rpc(RpcError),
+    Closed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "io error: {}", e),
+            ClientError::Json(e) => write!(f, "json error: {}", e),
+            ClientError::Rpc(e) => write!(f, "rpc error: {}", e),
+            ClientError::Closed => write!(f, "connection closed by server"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> Self {
+        ClientError::Json(e)
+    }
+}
+
+/// Answers to [`RpcClient::batch`].
+#[derive(Debug)]
+pub struct BatchResults {
+    /// One per call, in request order. A call the server did not answer
+    /// gets an internal error.
+    pub results: Vec<Result<Value, RpcError>>,
+    /// Errors with `id: null`, which the server could not tie to a call,
+    /// e.g. for a batch it could not parse.
+    pub unmatched: Vec<RpcError>,
+}
+
+pub struct RpcClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl RpcClient {
+    pub fn connect(sock_path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let stream = UnixStream::connect(sock_path)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            next_id: 1,
+        })
+    }
+
+    /// Give up on a response after `timeout`. `None` waits forever.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), ClientError> {
+        self.writer.set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Call `method` and wait for its result.
+    pub fn call<P, R>(&mut self, method: &str, params: P) -> Result<R, ClientError>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        let result = self.call_value(method, params_value(params)?)?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Call with untyped params. `params` must be an array, an object or `None`.
+    /// An error with `id: null` is taken as the answer, as the server could
+    /// not read the request's id.
+    pub fn call_value(
+        &mut self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<Value, ClientError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.write(&Request::new(method, params, Some(Value::from(id))))?;
+        loop {
+            let response: Response = serde_json::from_str(&self.read_line()?)?;
+            if response.id == id || response.id.is_null() {
+                return response.into_result().map_err(ClientError::Rpc);
+            }
+            log::warn!("skip response for another request: {:?}", response.id);
+        }
+    }
+
+    /// Send a notification; the server sends nothing back.
+    pub fn notify<P: Serialize>(&mut self, method: &str, params: P) -> Result<(), ClientError> {
+        self.write(&Request::new(method, params_value(params)?, None))
+    }
+
+    /// Send several calls in one batch. Results come back in request order,
+    /// matched by id.
+    pub fn batch(
+        &mut self,
+        calls: Vec<(String, Option<Value>)>,
+    ) -> Result<BatchResults, ClientError> {
+        let first_id = self.next_id;
+        self.next_id += calls.len() as u64;
+
+        let requests: Vec<Request> = calls
+            .iter()
+            .zip(first_id..)
+            .map(|((method, params), id)| Request::new(method, params.clone(), Some(id.into())))
+            .collect();
+        self.write(&requests)?;
+
+        // a batch the server could not read gets a single error object
+        let responses: Vec<Response> = match serde_json::from_str(&self.read_line()?)? {
+            Value::Array(responses) => responses
+                .into_iter()
+                .map(serde_json::from_value)
+                .collect::<Result<_, _>>()?,
+            response => vec![serde_json::from_value(response)?],
+        };
+
+        // the server may answer in any order
+        let mut results: Vec<Option<Result<Value, RpcError>>> = vec![None; calls.len()];
+        let mut unmatched = Vec::new();
+        for response in responses {
+            let slot = response
+                .id
+                .as_u64()
+                .and_then(|id| id.checked_sub(first_id))
+                .and_then(|i| results.get_mut(i as usize));
+            match slot {
+                Some(slot @ None) => *slot = Some(response.into_result()),
+                _ if response.id.is_null() => {
+                    if let Err(error) = response.into_result() {
+                        unmatched.push(error);
+                    }
+                }
+                _ => log::warn!("skip response for another request: {:?}", response.id),
+            }
+        }
+        let results = results
+            .into_iter()
+            .zip(first_id..)
+            .map(|(result, id)| {
+                result.unwrap_or_else(|| {
+                    Err(RpcError::internal_error(format!(
+                        "no response for id {}",
+                        id
+                    )))
+                })
+            })
+            .collect();
+        Ok(BatchResults { results, unmatched })
+    }
+
+    fn write<T: Serialize>(&mut self, msg: &T) -> Result<(), ClientError> {
+        let mut line = serde_json::to_string(msg)?;
+        log::debug!("rpc send: {}", line);
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String, ClientError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(ClientError::Closed);
+        }
+        log::debug!("rpc recv: {}", line.trim_end());
+        Ok(line)
+    }
+}
+
+fn params_value<P: Serialize>(params: P) -> Result<Option<Value>, ClientError> {
+    Ok(match serde_json::to_value(params)? {
+        Value::Null => None,
+        value => Some(value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::{RpcServer, METHOD_NOT_FOUND};
+
+    #[test]
+    fn call_over_socket() {
+        let sock_path = std::env::temp_dir().join(format!("rpcclient-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&sock_path);
+        let listener = std::os::unix::net::UnixListener::bind(&sock_path).unwrap();
+
+        let server = std::thread::spawn(move || {
+            let mut rpc = RpcServer::new();
+            rpc.register("add", |(a, b): (i64, i64)| Ok::<_, RpcError>(a + b));
+
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                if let Some(response) = rpc.handle(&line.unwrap()) {
+                    writer
+                        .write_all(format!("{}\n", response).as_bytes())
+                        .unwrap();
+                }
+            }
+        });
+
+        let mut client = RpcClient::connect(&sock_path).unwrap();
+        assert_eq!(client.call::<_, i64>("add", (1, 2)).unwrap(), 3);
+        client.notify("add", (0, 0)).unwrap();
+        match client.call::<_, i64>("sub", (1, 2)) {
+            Err(ClientError::Rpc(e)) => assert_eq!(e.code, METHOD_NOT_FOUND),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let results = client
+            .batch(vec![
+                ("add".into(), Some(serde_json::json!([2, 2]))),
+                ("add".into(), Some(serde_json::json!(["x"]))),
+            ])
+            .unwrap();
+        assert_eq!(results.results[0], Ok(Value::from(4)));
+        assert!(results.results[1].is_err());
+        assert!(results.unmatched.is_empty());
+
+        drop(client);
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&sock_path);
+    }
+
+    /// Server that answers each request line with the next of `replies`.
+    fn scripted_server(name: &str, replies: &[&str]) -> (RpcClient, std::thread::JoinHandle<()>) {
+        let sock_path =
+            std::env::temp_dir().join(format!("rpcclient-{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&sock_path);
+        let listener = std::os::unix::net::UnixListener::bind(&sock_path).unwrap();
+        let replies: Vec<String> = replies.iter().map(|reply| format!("{}\n", reply)).collect();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            for reply in replies {
+                lines.next().unwrap().unwrap();
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        let client = RpcClient::connect(&sock_path).unwrap();
+        let _ = std::fs::remove_file(&sock_path);
+        (client, server)
+    }
+
+    #[test]
+    fn call_returns_an_error_without_id() {
+        let (mut client, server) = scripted_server(
+            "null-id",
+            &[r#"{"jsonrpc":"2.0","error":{"code":-32700,"message":"parse error"},"id":null}"#],
+        );
+        match client.call_value("add", None) {
+            Err(ClientError::Rpc(e)) => assert_eq!(e.code, crate::jsonrpc::PARSE_ERROR),
+            other => panic!("unexpected {:?}", other),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn batch_results_are_matched_by_id() {
+        let (mut client, server) = scripted_server(
+            "batch",
+            &[concat!(
+                r#"[{"jsonrpc":"2.0","error":{"code":-32600,"message":"bad"},"id":null},"#,
+                r#"{"jsonrpc":"2.0","result":"c","id":3},"#,
+                r#"{"jsonrpc":"2.0","result":"a","id":1}]"#
+            )],
+        );
+        let calls = ["a", "b", "c"].map(|method| (method.to_string(), None));
+        let batch = client.batch(calls.to_vec()).unwrap();
+        assert_eq!(batch.results[0], Ok(Value::from("a")));
+        assert_eq!(
+            batch.results[1].as_ref().unwrap_err().code,
+            crate::jsonrpc::INTERNAL_ERROR
+        );
+        assert_eq!(batch.results[2], Ok(Value::from("c")));
+        assert_eq!(batch.unmatched.len(), 1);
+        assert_eq!(batch.unmatched[0].code, crate::jsonrpc::INVALID_REQUEST);
+        server.join().unwrap();
+    }
+}